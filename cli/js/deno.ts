@@ -31,6 +31,76 @@ export {
 export type { OpenOptions } from "./files.ts";
 export { read, readSync, write, writeSync } from "./ops/io.ts";
 export { watchRWS, sendRWS } from "./ops/rws_server.ts";
+export { appContext } from "./ops/rws_apps.ts";
+export type { AppContext } from "./ops/rws_apps.ts";
+export { writeLog } from "./ops/rws_log.ts";
+export type { LogLevel } from "./ops/rws_log.ts";
+export { sendMail } from "./ops/rws_mail.ts";
+export type { MailMessage, MailAttachment } from "./ops/rws_mail.ts";
+export { checkLogin, loginFailed, loginSucceeded } from "./ops/rws_security.ts";
+export type { LoginAttempt, LoginCheck } from "./ops/rws_security.ts";
+export {
+  authRegister,
+  authLogin,
+  authLogout,
+  authToken,
+  authSendVerification,
+  authVerify,
+  authRequestReset,
+  authReset,
+} from "./ops/rws_auth.ts";
+export type {
+  AuthUser,
+  AuthToken,
+  AuthLogin,
+  AuthSession,
+  AuthHeaders,
+} from "./ops/rws_auth.ts";
+export { can, listPermissions } from "./ops/rws_rbac.ts";
+export type { PermissionCheck, PermissionInfo } from "./ops/rws_rbac.ts";
+export {
+  registerService,
+  listenServiceCalls,
+  replyServiceCall,
+} from "./ops/rws_services.ts";
+export type { ServiceInfo, ServiceCall } from "./ops/rws_services.ts";
+export { mountRoute, routeUrl } from "./ops/rws_routes.ts";
+export type { RouteMount, RouteSlot } from "./ops/rws_routes.ts";
+export {
+  listenPageRequests,
+  replyPageRequest,
+  renderInline,
+} from "./ops/rws_views.ts";
+export type {
+  PageRequest,
+  Page,
+  ServerModule,
+} from "./ops/rws_views.ts";
+export { listenSockets, AppSocket } from "./ops/rws_ws.ts";
+export type { SocketMessage } from "./ops/rws_ws.ts";
+export {
+  openChannelInbox,
+  nextChannelMessage,
+  subscribeChannel,
+  unsubscribeChannel,
+  publishChannel,
+} from "./ops/rws_channels.ts";
+export type { ChannelName, ChannelMessage } from "./ops/rws_channels.ts";
+export {
+  registerComponent,
+  listComponents,
+  parseComponents,
+  renderComponent,
+  listenComponentRenders,
+  replyComponentRender,
+} from "./ops/rws_components.ts";
+export type {
+  ComponentInfo,
+  ComponentSegment,
+  ComponentRender,
+} from "./ops/rws_components.ts";
+export { getOptions, saveOptions } from "./ops/rws_options.ts";
+export type { Options } from "./ops/rws_options.ts";
 export { watchFs } from "./ops/fs_events.ts";
 export type { FsEvent } from "./ops/fs_events.ts";
 export { internalSymbol as internal } from "./internals.ts";
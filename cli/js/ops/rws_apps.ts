@@ -0,0 +1,14 @@
+// Copyright 2018-2020 the Deno authors. All rights reserved. MIT license.
+import { sendSync } from "./dispatch_json.ts";
+
+/** What an rws isolate runs for. */
+export interface AppContext {
+  host: string;
+  app: string;
+  /** True in the run of `rws app install`, false when serving. */
+  installing: boolean;
+}
+
+export function appContext(): AppContext {
+  return sendSync("op_rws_app_context");
+}
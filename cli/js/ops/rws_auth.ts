@@ -0,0 +1,88 @@
+// Copyright 2018-2020 the Deno authors. All rights reserved. MIT license.
+import { sendSync, sendAsync } from "./dispatch_json.ts";
+
+export interface AuthUser {
+  id: string;
+  email: string;
+  name: string | null;
+  verified: boolean;
+  disabled: boolean;
+  /** Unix time in milliseconds. */
+  created: number;
+}
+
+/** A signed in session, passed to handlers and services as `token`. */
+export interface AuthToken {
+  /** Identifies the session; not the secret. */
+  sessionId: string;
+  kind: "cookie" | "bearer";
+  expires: number;
+  user: AuthUser;
+}
+
+export interface AuthLogin {
+  email: string;
+  password: string;
+}
+
+export interface AuthSession {
+  /** The session secret; send it as `Authorization: Bearer`. */
+  secret: string;
+  token: AuthToken;
+}
+
+export interface AuthHeaders {
+  secret?: string | null;
+  cookie?: string | null;
+  authorization?: string | null;
+}
+
+export function authRegister(
+  email: string,
+  password: string,
+  name?: string,
+): Promise<AuthUser> {
+  return sendAsync("op_rws_auth_register", { email, password, name });
+}
+
+/** Starts a long lived bearer session.  Browsers sign in with
+ * `POST /.rws/login` instead, which sets the session cookie and locks out
+ * addresses that keep failing. */
+export function authLogin(login: AuthLogin): Promise<AuthSession> {
+  return sendAsync("op_rws_auth_login", login);
+}
+
+/** Ends a session; false when there was none.  `POST /.rws/logout` also
+ * clears the session cookie. */
+export function authLogout(secret: string): boolean {
+  return sendSync("op_rws_auth_logout", { secret });
+}
+
+/** The session of a secret or of request headers, `null` when there is no
+ * valid one. */
+export function authToken(from: AuthHeaders): AuthToken | null {
+  return sendSync("op_rws_auth_token", from);
+}
+
+/** Mails a link to `url?token=...`; pass the token to `authVerify`. */
+export function authSendVerification(
+  userId: string,
+  url: string,
+): Promise<boolean> {
+  return sendAsync("op_rws_auth_send_verification", { userId, url });
+}
+
+export function authVerify(ticket: string): AuthUser {
+  return sendSync("op_rws_auth_verify", { ticket });
+}
+
+/** Queues a mail with a reset link to `url?token=...` if the email is
+ * registered.  Returns the same either way. */
+export function authRequestReset(email: string, url: string): boolean {
+  return sendSync("op_rws_auth_request_reset", { email, url });
+}
+
+/** Sets a new password and ends every session of the user. */
+export function authReset(ticket: string, password: string): Promise<boolean> {
+  return sendAsync("op_rws_auth_reset", { ticket, password });
+}
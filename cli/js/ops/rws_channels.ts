@@ -0,0 +1,52 @@
+// Copyright 2018-2020 the Deno authors. All rights reserved. MIT license.
+import { sendSync, sendAsync } from "./dispatch_json.ts";
+
+/** Channels are separate per host and app. */
+export interface ChannelName {
+  host?: string | null;
+  app?: string | null;
+  name: string;
+}
+
+export interface ChannelMessage {
+  channel: ChannelName;
+  /** Position in the channel; every listener sees the same order. */
+  seq: number;
+  data: unknown;
+}
+
+/** Creates the inbox this isolate's channel messages are delivered to. */
+export function openChannelInbox(): number {
+  return sendSync("op_rws_channel_open");
+}
+
+export async function nextChannelMessage(
+  rid: number,
+): Promise<ChannelMessage | null> {
+  const res = await sendAsync("op_rws_channel_next", { rid });
+  return res.done ? null : res.value;
+}
+
+/** Subscribes an inbox, or with `socket` an `AppSocket` rid, to a channel
+ * of this isolate's host and app. */
+export function subscribeChannel(
+  rid: number,
+  name: string,
+  socket = false,
+): void {
+  sendSync("op_rws_channel_subscribe", { rid, socket, name });
+}
+
+export function unsubscribeChannel(
+  rid: number,
+  name: string,
+  socket = false,
+): void {
+  sendSync("op_rws_channel_unsubscribe", { rid, socket, name });
+}
+
+/** Publishes on a channel of this isolate's host and app.  Returns the
+ * message's sequence number, 0 when nobody listens. */
+export function publishChannel(name: string, data: unknown): number {
+  return sendSync("op_rws_channel_publish", { name, data });
+}
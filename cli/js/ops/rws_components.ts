@@ -0,0 +1,90 @@
+// Copyright 2018-2020 the Deno authors. All rights reserved. MIT license.
+import { sendSync, sendAsync } from "./dispatch_json.ts";
+import { close } from "./resources.ts";
+
+export interface ComponentInfo {
+  name: string;
+  title?: string;
+  description?: string;
+  app?: string | null;
+  host?: string | null;
+  hasOptions?: boolean;
+  /** Milliseconds a render may take, when shorter than the page allows. */
+  timeout?: number | null;
+}
+
+export type ComponentSegment =
+  | { kind: "text"; text: string }
+  | { kind: "component"; name: string; args: Record<string, string | boolean> };
+
+/** Claims a component tag name for an app; throws if another app has it. */
+export function registerComponent(info: ComponentInfo): void {
+  sendSync("op_rws_component_register", info);
+}
+
+export function listComponents(): ComponentInfo[] {
+  return sendSync("op_rws_component_list");
+}
+
+/** Splits HTML into text and shortcodes of registered components. */
+export function parseComponents(html: string): ComponentSegment[] {
+  return sendSync("op_rws_component_parse", { html });
+}
+
+/** Renders a component in the isolate of its app; null when it isn't
+ * available.  Rejects when it fails or takes longer than `timeout` ms; an
+ * isolate that is still stuck in synchronous code then is terminated. */
+export function renderComponent(args: {
+  name: string;
+  args: Record<string, unknown>;
+  timeout: number;
+}): Promise<string | null> {
+  return sendAsync("op_rws_component_render", args);
+}
+
+/** A render of one of this app's components; answer it with
+ * `replyComponentRender`. */
+export interface ComponentRender {
+  rid: number;
+  name: string;
+  args: Record<string, unknown>;
+}
+
+class ComponentRenders implements AsyncIterableIterator<ComponentRender> {
+  readonly rid: number;
+
+  constructor() {
+    this.rid = sendSync("op_rws_component_listen");
+  }
+
+  async next(): Promise<IteratorResult<ComponentRender>> {
+    const res = await sendAsync("op_rws_component_next", { rid: this.rid });
+    if (res.done) {
+      return { value: undefined, done: true };
+    }
+    return { value: res.value, done: false };
+  }
+
+  return(value?: ComponentRender): Promise<IteratorResult<ComponentRender>> {
+    close(this.rid);
+    return Promise.resolve({ value, done: true });
+  }
+
+  [Symbol.asyncIterator](): AsyncIterableIterator<ComponentRender> {
+    return this;
+  }
+}
+
+export function listenComponentRenders(): AsyncIterableIterator<
+  ComponentRender
+> {
+  return new ComponentRenders();
+}
+
+/** A null `html` says the component isn't defined here. */
+export function replyComponentRender(
+  rid: number,
+  reply: { html?: string | null; error?: string },
+): void {
+  sendSync("op_rws_component_reply", { rid, ...reply });
+}
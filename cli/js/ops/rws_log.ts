@@ -0,0 +1,14 @@
+// Copyright 2018-2020 the Deno authors. All rights reserved. MIT license.
+import { sendSync } from "./dispatch_json.ts";
+
+export type LogLevel = "error" | "warn" | "info" | "debug";
+
+/** Writes a structured entry to the app's rws log, tagged with the app and
+ * host of the isolate. `requestId` in `fields` is stored as a tag too. */
+export function writeLog(
+  level: LogLevel,
+  msg: string,
+  fields: Record<string, unknown> = {},
+): void {
+  sendSync("op_rws_log", { level, msg, fields });
+}
@@ -0,0 +1,56 @@
+// Copyright 2018-2020 the Deno authors. All rights reserved. MIT license.
+import { sendAsync } from "./dispatch_json.ts";
+
+export interface MailAttachment {
+  filename: string;
+  contentType?: string;
+  content: string | Uint8Array;
+}
+
+export interface MailMessage {
+  from?: string;
+  to: string | string[];
+  cc?: string | string[];
+  bcc?: string | string[];
+  subject: string;
+  text?: string;
+  html?: string;
+  attachments?: MailAttachment[];
+}
+
+export interface MailSendResult {
+  /** Queue id when delivery failed and the message will be retried. */
+  id: string | null;
+  queued: boolean;
+}
+
+function toList(value?: string | string[]): string[] {
+  if (value == null) {
+    return [];
+  }
+  return Array.isArray(value) ? value : [value];
+}
+
+function toBase64(content: string | Uint8Array): string {
+  const bytes =
+    typeof content === "string" ? new TextEncoder().encode(content) : content;
+  let binary = "";
+  for (let i = 0; i < bytes.length; i += 0x8000) {
+    binary += String.fromCharCode(...bytes.subarray(i, i + 0x8000));
+  }
+  return btoa(binary);
+}
+
+export function sendMail(message: MailMessage): Promise<MailSendResult> {
+  return sendAsync("op_rws_mail_send", {
+    ...message,
+    to: toList(message.to),
+    cc: toList(message.cc),
+    bcc: toList(message.bcc),
+    attachments: (message.attachments ?? []).map((a) => ({
+      filename: a.filename,
+      contentType: a.contentType,
+      content: toBase64(a.content),
+    })),
+  });
+}
@@ -0,0 +1,16 @@
+// Copyright 2018-2020 the Deno authors. All rights reserved. MIT license.
+import { sendSync } from "./dispatch_json.ts";
+
+export type Options = Record<string, unknown>;
+
+/** Stored options of this isolate's app, over the defaults in its
+ * manifest. */
+export function getOptions(): Options {
+  return sendSync("op_rws_options_get");
+}
+
+/** Merges `options` into the stored ones (`null` removes a key) and returns
+ * the result. Throws when a value doesn't match the manifest schema. */
+export function saveOptions(options: Options): Options {
+  return sendSync("op_rws_options_save", { options });
+}
@@ -0,0 +1,25 @@
+// Copyright 2018-2020 the Deno authors. All rights reserved. MIT license.
+import { sendSync } from "./dispatch_json.ts";
+
+export interface PermissionCheck {
+  userId?: string | null;
+  permission: string;
+}
+
+export interface PermissionInfo {
+  app: string;
+  name: string;
+  title: string;
+  desc: string;
+}
+
+/** Whether the user's roles on this isolate's host grant its app's
+ * permission. */
+export function can(check: PermissionCheck): boolean {
+  return sendSync("op_rws_can", check);
+}
+
+/** Permissions declared by the apps installed on this isolate's host. */
+export function listPermissions(): PermissionInfo[] {
+  return sendSync("op_rws_permissions");
+}
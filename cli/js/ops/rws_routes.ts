@@ -0,0 +1,35 @@
+// Copyright 2018-2020 the Deno authors. All rights reserved. MIT license.
+import { sendSync } from "./dispatch_json.ts";
+
+/** A nested route of an app put at a path pattern like "/pages/:id". */
+export interface RouteMount {
+  path: string;
+  app: string;
+  /** Name of the route in the app's `nested_routes`. */
+  route: string;
+  /** Arguments fixed by the mount rather than taken from the URL. */
+  args?: Record<string, unknown>;
+}
+
+/** A request path resolved to a nested route. */
+export interface RouteSlot {
+  app: string;
+  route: string;
+  args: Record<string, unknown>;
+}
+
+/** Mounts a route under this app's mount path on its host; its arguments
+ * are checked against the route app's manifest. */
+export function mountRoute(args: { mount: RouteMount }): void {
+  sendSync("op_rws_route_mount", args);
+}
+
+/** The path of a route mounted on this app's host for the given
+ * arguments. */
+export function routeUrl(args: {
+  app: string;
+  route: string;
+  args?: Record<string, unknown>;
+}): string {
+  return sendSync("op_rws_route_url", args);
+}
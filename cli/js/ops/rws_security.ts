@@ -0,0 +1,30 @@
+// Copyright 2018-2020 the Deno authors. All rights reserved. MIT license.
+import { sendSync } from "./dispatch_json.ts";
+
+/** Attempts count against the account.  The per-IP lockout only covers
+ * `POST /.rws/login` and the admin API, where rws sees the client. */
+export interface LoginAttempt {
+  account?: string;
+}
+
+export interface LoginCheck {
+  allowed: boolean;
+  reason?: string;
+  /** Milliseconds until another attempt will be accepted. */
+  retryAfter?: number | null;
+}
+
+/** Charges a login attempt; refuse the login when `allowed` is false. */
+export function checkLogin(attempt: LoginAttempt): LoginCheck {
+  return sendSync("op_rws_login_check", attempt);
+}
+
+/** Reports a failed login so repeated failures lock the account out. */
+export function loginFailed(attempt: LoginAttempt): void {
+  sendSync("op_rws_login_failed", attempt);
+}
+
+/** Clears the account's failure count after a successful login. */
+export function loginSucceeded(attempt: LoginAttempt): void {
+  sendSync("op_rws_login_succeeded", attempt);
+}
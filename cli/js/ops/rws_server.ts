@@ -4,6 +4,10 @@ import { close } from "./resources.ts";
 
 export interface RWSRequest {
   url: string;
+  /** Address of the client, for `RWS.security`. */
+  ip: string | null;
+  /** Headers rws passes through, for `RWS.auth.token`. */
+  headers: { cookie: string | null; authorization: string | null };
   respId: number;
 }
 
@@ -0,0 +1,62 @@
+// Copyright 2018-2020 the Deno authors. All rights reserved. MIT license.
+import { sendSync, sendAsync } from "./dispatch_json.ts";
+import { close } from "./resources.ts";
+import type { AuthToken } from "./rws_auth.ts";
+
+export interface ServiceInfo {
+  name: string;
+  host?: string | null;
+  app?: string | null;
+  /** Manifest permission of `app` that callers need. */
+  permission?: string | null;
+}
+
+/** A `/json` call; answer it with `replyServiceCall`. */
+export interface ServiceCall {
+  rid: number;
+  service: string;
+  host: string;
+  args: unknown;
+  token: AuthToken | null;
+}
+
+export function registerService(info: ServiceInfo): void {
+  sendSync("op_rws_service_register", info);
+}
+
+class ServiceCalls implements AsyncIterableIterator<ServiceCall> {
+  readonly rid: number;
+
+  constructor() {
+    this.rid = sendSync("op_rws_service_listen");
+  }
+
+  async next(): Promise<IteratorResult<ServiceCall>> {
+    const res = await sendAsync("op_rws_service_next", { rid: this.rid });
+    if (res.done) {
+      return { value: undefined, done: true };
+    }
+    return { value: res.value, done: false };
+  }
+
+  return(value?: ServiceCall): Promise<IteratorResult<ServiceCall>> {
+    close(this.rid);
+    return Promise.resolve({ value, done: true });
+  }
+
+  [Symbol.asyncIterator](): AsyncIterableIterator<ServiceCall> {
+    return this;
+  }
+}
+
+/** Calls of this app's services. Permissions are already checked. */
+export function listenServiceCalls(): AsyncIterableIterator<ServiceCall> {
+  return new ServiceCalls();
+}
+
+export function replyServiceCall(
+  rid: number,
+  reply: { result?: unknown; error?: string },
+): void {
+  sendSync("op_rws_service_reply", { rid, ...reply });
+}
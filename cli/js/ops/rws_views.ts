@@ -0,0 +1,90 @@
+// Copyright 2018-2020 the Deno authors. All rights reserved. MIT license.
+import { sendSync, sendAsync } from "./dispatch_json.ts";
+import { close } from "./resources.ts";
+import type { AuthToken } from "./rws_auth.ts";
+import type { RouteSlot } from "./rws_routes.ts";
+
+/** A route of a `multi_page` or `inline` app to render. */
+export interface PageRequest {
+  rid: number;
+  host: string;
+  app: string;
+  /** Path the app is mounted at, "" at the root. */
+  base: string;
+  route: string;
+  query: string;
+  /** Render a fragment to embed rather than a document body. */
+  inline: boolean;
+  /** The nested route the path was resolved to, if any. */
+  slot: RouteSlot | null;
+  /** The app's server build, rendered when it has no view set up. */
+  module: ServerModule | null;
+  token: AuthToken | null;
+}
+
+/** `target/server/html_*/views/index.js` of an app. */
+export interface ServerModule {
+  path: string;
+  /** Section directory its `node_modules` are resolved in. */
+  root: string;
+  /** Modification time of the build. */
+  version: number;
+}
+
+export interface Page {
+  body: string;
+  head?: string;
+  /** Handed to the client as `window.RWS_PAGE.data`. */
+  data?: unknown;
+  status?: number;
+  /** Keeps the page out of the render cache. */
+  nocache?: boolean;
+}
+
+class PageRequests implements AsyncIterableIterator<PageRequest> {
+  readonly rid: number;
+
+  constructor() {
+    this.rid = sendSync("op_rws_page_listen");
+  }
+
+  async next(): Promise<IteratorResult<PageRequest>> {
+    const res = await sendAsync("op_rws_page_next", { rid: this.rid });
+    if (res.done) {
+      return { value: undefined, done: true };
+    }
+    return { value: res.value, done: false };
+  }
+
+  return(value?: PageRequest): Promise<IteratorResult<PageRequest>> {
+    close(this.rid);
+    return Promise.resolve({ value, done: true });
+  }
+
+  [Symbol.asyncIterator](): AsyncIterableIterator<PageRequest> {
+    return this;
+  }
+}
+
+/** Routes rws wants rendered by this app. */
+export function listenPageRequests(): AsyncIterableIterator<PageRequest> {
+  return new PageRequests();
+}
+
+/** Answers a page request; a `null` page serves the plain RequireJS shell. */
+export function replyPageRequest(
+  rid: number,
+  reply: { page?: Page | null; error?: string },
+): void {
+  sendSync("op_rws_page_reply", { rid, ...reply });
+}
+
+/** The fragment another app on this host renders for `route`, rendered
+ * for a signed out visitor. */
+export function renderInline(args: {
+  app: string;
+  route: string;
+  query?: string;
+}): Promise<string> {
+  return sendAsync("op_rws_page_inline", args);
+}
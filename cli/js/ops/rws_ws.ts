@@ -0,0 +1,108 @@
+// Copyright 2018-2020 the Deno authors. All rights reserved. MIT license.
+import { sendSync, sendAsync } from "./dispatch_json.ts";
+import { close } from "./resources.ts";
+import type { AuthToken } from "./rws_auth.ts";
+
+export type SocketMessage =
+  | { kind: "text"; data: string }
+  | { kind: "binary"; data: Uint8Array }
+  | { kind: "close"; code: number | null; reason: string | null };
+
+function fromBase64(data: string): Uint8Array {
+  const binary = atob(data);
+  const bytes = new Uint8Array(binary.length);
+  for (let i = 0; i < binary.length; i++) {
+    bytes[i] = binary.charCodeAt(i);
+  }
+  return bytes;
+}
+
+/** A websocket connected to the host's `/ws` endpoint. Iterating it yields
+ * messages until the peer goes away. */
+export class AppSocket implements AsyncIterable<SocketMessage> {
+  #closed = false;
+
+  constructor(
+    readonly rid: number,
+    readonly id: string,
+    readonly host: string,
+    readonly path: string,
+    readonly ip: string | null,
+    /** Session of the upgrade request, `null` when signed out. */
+    readonly token: AuthToken | null,
+  ) {}
+
+  get closed(): boolean {
+    return this.#closed;
+  }
+
+  async receive(): Promise<SocketMessage> {
+    const msg = await sendAsync("op_rws_ws_receive", { rid: this.rid });
+    if (msg.kind === "binary") {
+      return { kind: "binary", data: fromBase64(msg.data) };
+    }
+    if (msg.kind === "close") {
+      this.#closed = true;
+    }
+    return msg as SocketMessage;
+  }
+
+  send(data: string | Uint8Array): void {
+    if (typeof data === "string") {
+      sendSync("op_rws_ws_send", { rid: this.rid, text: data });
+    } else {
+      sendSync("op_rws_ws_send", { rid: this.rid }, data);
+    }
+  }
+
+  close(code?: number, reason?: string): void {
+    if (!this.#closed) {
+      this.#closed = true;
+      sendSync("op_rws_ws_close", { rid: this.rid, code, reason });
+    }
+  }
+
+  async *[Symbol.asyncIterator](): AsyncIterator<SocketMessage> {
+    while (!this.#closed) {
+      const msg = await this.receive();
+      if (msg.kind === "close") {
+        return;
+      }
+      yield msg;
+    }
+  }
+}
+
+class SocketListener implements AsyncIterableIterator<AppSocket> {
+  readonly rid: number;
+
+  constructor() {
+    this.rid = sendSync("op_rws_ws_listen");
+  }
+
+  async next(): Promise<IteratorResult<AppSocket>> {
+    const res = await sendAsync("op_rws_ws_accept", { rid: this.rid });
+    if (res.done) {
+      return { value: undefined, done: true };
+    }
+    const v = res.value;
+    return {
+      value: new AppSocket(v.rid, v.id, v.host, v.path, v.ip, v.token),
+      done: false,
+    };
+  }
+
+  return(value?: AppSocket): Promise<IteratorResult<AppSocket>> {
+    close(this.rid);
+    return Promise.resolve({ value, done: true });
+  }
+
+  [Symbol.asyncIterator](): AsyncIterableIterator<AppSocket> {
+    return this;
+  }
+}
+
+/** Connections made to `/ws` for this app, in the order they arrive. */
+export function listenSockets(): AsyncIterableIterator<AppSocket> {
+  return new SocketListener();
+}
@@ -132,11 +132,21 @@ pub fn op_rws_server_poll(
     match result {
       Some(req) => {
         let uri = req.0.uri().to_string();
+        let ip = req.0.peer_addr().map(|addr| addr.ip().to_string());
+        let header = |name: &str| {
+          req.0.headers().get(name).and_then(|v| v.to_str().ok()).map(String::from)
+        };
+        let headers = json!({
+          "cookie": header("cookie"),
+          "authorization": header("authorization"),
+        });
         let resp_id = borrow_loop_mut(&resource_table, |mut table| {
           table.add("rwsEvents", Box::new(req))
         });
         Ok(json!({ "value": {
           "url": uri,
+          "ip": ip,
+          "headers": headers,
           "respId": resp_id
         }, "done": false }))
       },
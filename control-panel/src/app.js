@@ -43,8 +43,10 @@ require(["react", "react-dom", "carbon-components-react", "@carbon/icons-react",
                 {title: "Status", href: "#"},
                 {title: "Accounts", href: "#"},
                 {title: "Hosts", href: "#"},
+                {title: "Roles", href: "#"},
                 {title: "Platform", href: "#"},
-                {title: "Settings", href: "#"}
+                {title: "Settings", href: "#"},
+                {title: "Audit", href: "#"}
             ]
         }
     });
@@ -69,6 +71,540 @@ require(["react", "react-dom", "carbon-components-react", "@carbon/icons-react",
         }];
     }
 
+    // CSRF token of the dashboard session, sent with every change.
+    let csrf = null;
+
+    // JSON API of the dashboard; rejects with the server's message.  `file`
+    // is uploaded as the raw body instead of a JSON one.
+    const api = (path, {method = "GET", body, file} = {}) => fetch(path, {
+        method,
+        headers: {
+            ...(body === undefined ? {} : {"content-type": "application/json"}),
+            ...(file === undefined ? {} : {"content-type": "application/octet-stream"}),
+            ...(method == "GET" || !csrf ? {} : {"x-csrf-token": csrf})
+        },
+        body: file !== undefined ? file : body === undefined ? undefined : JSON.stringify(body)
+    }).then(async (res) => {
+        if (res.status == 401) throw new Error("Sign in as an admin to see this.");
+        if (res.status == 403) throw new Error("Your account isn't an admin.");
+        if (!res.ok) {
+            const text = await res.text();
+            let message = text;
+            try {
+                message = JSON.parse(text).error || text;
+            } catch (_) {}
+            throw new Error(message || res.statusText);
+        }
+        return res.status == 204 ? null : res.json();
+    });
+
+    // Loads `path` and reloads it on `reload()`; `error` holds a failed load.
+    const useApi = (path) => {
+        const [data, setData] = React.useState(null);
+        const [error, setError] = React.useState(null);
+        const reload = () => api(path).then((data) => { setData(data); setError(null); }, (e) => setError(e.message));
+        React.useEffect(() => { reload(); }, [path]);
+        return [data, reload, error, setError];
+    };
+
+    const bytes = (n) => n == null ? "-" : n > 1 << 30 ? `${(n / (1 << 30)).toFixed(1)} GiB` : `${(n / (1 << 20)).toFixed(1)} MiB`;
+
+    const status = () => {
+        const [stats, reload, error] = useApi("/api/status");
+        React.useEffect(() => {
+            const timer = setInterval(reload, 5000);
+            return () => clearInterval(timer);
+        }, []);
+
+        const rows = stats ? [
+            ["Process", stats.pid],
+            ["Up since", new Date(stats.started).toLocaleString()],
+            ["Memory", bytes(stats.memoryBytes)],
+            ["Threads", stats.threads == null ? "-" : stats.threads],
+            ["Isolates", stats.isolates],
+            ["Hosts", stats.hosts],
+            ["Users", stats.users],
+            ["Sessions", stats.sessions],
+            ["Services", stats.services],
+            ["Bans", stats.bans]
+        ] : [];
+
+        return h("div", {},
+            h("section", {className: "status"},
+                h("h3", {}, "Status"),
+                error ? h("p", {className: "error"}, error) : null,
+                h("table", {className: "bx--data-table"},
+                    h("tbody", {}, ...rows.map(([name, value]) => h("tr", {key: name}, h("th", {}, name), h("td", {}, value))))
+                )
+            ),
+            h(bans)
+        );
+    };
+
+    const accounts = () => {
+        const [users, reload, error, setError] = useApi("/api/accounts");
+        const [draft, setDraft] = React.useState({email: "", name: "", password: "", verified: true});
+
+        const run = (promise) => promise.then(reload, (e) => setError(e.message));
+        const create = (e) => {
+            e.preventDefault();
+            run(api("/api/accounts", {method: "POST", body: draft}).then(() => setDraft({email: "", name: "", password: "", verified: true})));
+        };
+        const patch = (user, body) => run(api(`/api/accounts/${user.id}`, {method: "PATCH", body}));
+        const remove = (user) => confirm(`Delete ${user.email}?`) && run(api(`/api/accounts/${user.id}`, {method: "DELETE"}));
+        const field = (key, type = "text") => h("input", {type, placeholder: key, value: draft[key], onChange: (e) => setDraft({...draft, [key]: e.target.value})});
+
+        return h("section", {className: "accounts"},
+            h("h3", {}, "Accounts"),
+            error ? h("p", {className: "error"}, error) : null,
+            h("table", {className: "bx--data-table"},
+                h("thead", {}, h("tr", {}, h("th", {}, "Email"), h("th", {}, "Name"), h("th", {}, "Verified"), h("th", {}, "Created"), h("th"))),
+                h("tbody", {}, ...(users || []).map((user) => h("tr", {key: user.id},
+                    h("td", {}, user.email),
+                    h("td", {}, user.name || ""),
+                    h("td", {}, h("input", {type: "checkbox", checked: user.verified, onChange: (e) => patch(user, {verified: e.target.checked})})),
+                    h("td", {}, new Date(user.created).toLocaleString()),
+                    h("td", {},
+                        h(ccr.Button, {kind: "ghost", size: "small", onClick: () => patch(user, {disabled: !user.disabled})}, user.disabled ? "Enable" : "Disable"),
+                        h(ccr.Button, {kind: "danger--ghost", size: "small", onClick: () => remove(user)}, "Delete")
+                    )
+                )))
+            ),
+            h("form", {className: "new-account", onSubmit: create},
+                field("email", "email"), field("name"), field("password", "password"),
+                h(ccr.Button, {type: "submit", size: "small"}, "Add account")
+            )
+        );
+    };
+
+    // A JSON document edited as text; `onSave` gets the parsed value.
+    const jsonEditor = ({value, onSave, label = "Save"}) => {
+        const [text, setText] = React.useState(JSON.stringify(value, null, 2));
+        const [error, setError] = React.useState(null);
+        React.useEffect(() => setText(JSON.stringify(value, null, 2)), [JSON.stringify(value)]);
+
+        const save = () => {
+            let parsed;
+            try {
+                parsed = JSON.parse(text);
+            } catch (e) {
+                return setError(e.message);
+            }
+            Promise.resolve(onSave(parsed)).then(() => setError(null), (e) => setError(e.message));
+        };
+
+        return h("div", {className: "json-editor"},
+            h("textarea", {rows: Math.min(30, text.split("\n").length + 1), value: text, onChange: (e) => setText(e.target.value)}),
+            error ? h("p", {className: "error"}, error) : null,
+            h(ccr.Button, {size: "small", onClick: save}, label)
+        );
+    };
+
+    // Uploads an .rwsapp package to install on `host`.
+    const installApp = ({host, onInstall, onError}) => {
+        const [file, setFile] = React.useState(null);
+        const [path, setPath] = React.useState("");
+        const [force, setForce] = React.useState(false);
+        const [busy, setBusy] = React.useState(false);
+
+        const submit = (e) => {
+            e.preventDefault();
+            const query = new URLSearchParams({...(path ? {path} : {}), force});
+            setBusy(true);
+            api(`/api/hosts/${encodeURIComponent(host)}/apps?${query}`, {method: "POST", file})
+                .then(onInstall, (e) => onError(e.message))
+                .finally(() => setBusy(false));
+        };
+
+        return h("form", {className: "install-app", onSubmit: submit},
+            h("input", {type: "file", accept: ".rwsapp", onChange: (e) => setFile(e.target.files[0] || null)}),
+            h("input", {type: "text", placeholder: "Path, e.g. /forum", value: path, onChange: (e) => setPath(e.target.value)}),
+            h("label", {}, h("input", {type: "checkbox", checked: force, onChange: (e) => setForce(e.target.checked)}), " Replace installed version"),
+            h(ccr.Button, {type: "submit", size: "small", disabled: !file || busy}, busy ? "Installing..." : "Install app")
+        );
+    };
+
+    const hosts = () => {
+        const [list, reload, error, setError] = useApi("/api/hosts");
+        const [name, setName] = React.useState("");
+
+        const url = (name) => `/api/hosts/${encodeURIComponent(name)}`;
+        const save = (host) => api(url(host.name), {method: "PUT", body: host}).then(reload);
+        const remove = (host) => confirm(`Stop serving ${host.name}?`) && api(url(host.name), {method: "DELETE"}).then(reload, (e) => setError(e.message));
+        const add = (e) => {
+            e.preventDefault();
+            save({name}).then(() => setName(""), (e) => setError(e.message));
+        };
+
+        return h("section", {className: "hosts"},
+            h("h3", {}, "Hosts"),
+            error ? h("p", {className: "error"}, error) : null,
+            ...(list || []).map((host) => h("div", {key: host.name, className: "host"},
+                h("h4", {}, host.name, " ", h(ccr.Button, {kind: "danger--ghost", size: "small", onClick: () => remove(host)}, "Remove")),
+                h(jsonEditor, {value: host, onSave: (edited) => save({...edited, name: host.name})}),
+                h(installApp, {host: host.name, onInstall: reload, onError: setError})
+            )),
+            h("form", {onSubmit: add},
+                h("input", {type: "text", placeholder: "example.com", value: name, onChange: (e) => setName(e.target.value)}),
+                h(ccr.Button, {type: "submit", size: "small", disabled: !name}, "Add host")
+            )
+        );
+    };
+
+    // Roles of one host, built from the permissions its apps declare, and
+    // the accounts holding them.
+    const rolesForm = ({host}) => {
+        const url = `/api/rbac/${encodeURIComponent(host)}`;
+        const [data, reload, error, setError] = useApi(url);
+        const [users] = useApi("/api/accounts");
+        const [name, setName] = React.useState("");
+
+        if (data == null) {
+            return error ? h("p", {className: "error"}, error) : h("p", {}, "Loading...");
+        }
+
+        const run = (promise) => promise.then(reload, (e) => setError(e.message));
+        const roleUrl = (role) => `${url}/roles/${encodeURIComponent(role)}`;
+        const saveRole = (role, body) => run(api(roleUrl(role), {method: "PUT", body}));
+        const removeRole = (role) => confirm(`Delete the ${role} role?`) && run(api(roleUrl(role), {method: "DELETE"}));
+        const assign = (user, roles) => run(api(`${url}/members/${encodeURIComponent(user)}`, {method: "PUT", body: roles}));
+        const toggle = (list, item, on) => on ? [...list, item] : list.filter((x) => x != item);
+        const add = (e) => {
+            e.preventDefault();
+            saveRole(name, {title: name, permissions: []}).then(() => setName(""));
+        };
+
+        const roleNames = Object.keys(data.roles);
+        const permissions = [{key: "*", title: "Everything"}, ...data.permissions.map((p) => ({key: `${p.app}.${p.name}`, title: p.title || p.name, desc: p.desc}))];
+
+        return h("div", {className: "roles"},
+            error ? h("p", {className: "error"}, error) : null,
+            h("h4", {}, "Roles"),
+            roleNames.length == 0 ? h("p", {}, "No roles yet.") :
+            h("table", {className: "bx--data-table"},
+                h("thead", {}, h("tr", {}, h("th", {}, "Role"), h("th", {}, "Title"), ...permissions.map((p) => h("th", {key: p.key, title: p.desc || p.key}, p.title)), h("th"))),
+                h("tbody", {}, ...roleNames.map((role) => {
+                    const current = data.roles[role];
+                    return h("tr", {key: role},
+                        h("td", {}, role),
+                        h("td", {}, h("input", {type: "text", defaultValue: current.title, onBlur: (e) => e.target.value != current.title && saveRole(role, {...current, title: e.target.value})})),
+                        ...permissions.map((p) => h("td", {key: p.key}, h("input", {
+                            type: "checkbox",
+                            checked: current.permissions.includes(p.key),
+                            onChange: (e) => saveRole(role, {...current, permissions: toggle(current.permissions, p.key, e.target.checked)})
+                        }))),
+                        h("td", {}, h(ccr.Button, {kind: "danger--ghost", size: "small", onClick: () => removeRole(role)}, "Delete"))
+                    );
+                }))
+            ),
+            h("form", {onSubmit: add},
+                h("input", {type: "text", placeholder: "editor", value: name, onChange: (e) => setName(e.target.value)}),
+                h(ccr.Button, {type: "submit", size: "small", disabled: !name}, "Add role")
+            ),
+            h("h4", {}, "Members"),
+            roleNames.length == 0 || !users ? null :
+            h("table", {className: "bx--data-table"},
+                h("thead", {}, h("tr", {}, h("th", {}, "Account"), ...roleNames.map((role) => h("th", {key: role}, data.roles[role].title || role)))),
+                h("tbody", {}, ...users.map((user) => {
+                    const held = data.members[user.id] || [];
+                    return h("tr", {key: user.id},
+                        h("td", {}, user.email),
+                        ...roleNames.map((role) => h("td", {key: role}, h("input", {
+                            type: "checkbox",
+                            checked: held.includes(role),
+                            onChange: (e) => assign(user.id, toggle(held, role, e.target.checked))
+                        })))
+                    );
+                }))
+            )
+        );
+    };
+
+    const roles = () => {
+        const [list, , error] = useApi("/api/hosts");
+        const [selected, setSelected] = React.useState("");
+
+        return h("section", {className: "rbac"},
+            h("h3", {}, "Roles"),
+            error ? h("p", {className: "error"}, error) : null,
+            h("select", {value: selected, onChange: (e) => setSelected(e.target.value)},
+                h("option", {value: "", disabled: true}, "Choose a host"),
+                ...(list || []).map((host) => h("option", {key: host.name, value: host.name}, host.name))
+            ),
+            selected ? h(rolesForm, {key: selected, host: selected}) : null
+        );
+    };
+
+    const platform = () => {
+        const [info, , error] = useApi("/api/platform");
+
+        return h("section", {className: "platform"},
+            h("h3", {}, "Platform"),
+            error ? h("p", {className: "error"}, error) : null,
+            info ? h("table", {className: "bx--data-table"},
+                h("tbody", {}, ...Object.entries(info.versions).map(([name, version]) => h("tr", {key: name}, h("th", {}, name), h("td", {}, version))))
+            ) : null,
+            h("h4", {}, "Installed apps"),
+            info ? h("table", {className: "bx--data-table"},
+                h("thead", {}, h("tr", {}, h("th", {}, "Host"), h("th", {}, "App"), h("th", {}, "Path"), h("th", {}, "Version"), h("th", {}, "Render type"), h("th", {}, "Native modules"))),
+                h("tbody", {}, ...info.apps.map((app) => h("tr", {key: app.host + app.app},
+                    h("td", {}, app.host),
+                    h("td", {}, app.name || app.app),
+                    h("td", {}, app.path),
+                    h("td", {}, app.error || app.version),
+                    h("td", {}, app.renderType || ""),
+                    h("td", {}, Object.entries(app.nativeDependencies || {}).map(([name, v]) => `${name} ${v}`).join(", "))
+                )))
+            ) : null
+        );
+    };
+
+    // First run setup while rws has no admin, sign in otherwise.
+    const signIn = ({setup, onSignIn}) => {
+        const [form, setForm] = React.useState({email: "", password: "", name: "", code: ""});
+        const [needCode, setNeedCode] = React.useState(false);
+        const [error, setError] = React.useState(null);
+
+        const submit = (e) => {
+            e.preventDefault();
+            const body = setup ? {email: form.email, password: form.password, name: form.name || null}
+                : {email: form.email, password: form.password, code: needCode ? form.code : null};
+            fetch(setup ? "/api/setup" : "/api/login", {method: "POST", headers: {"content-type": "application/json"}, body: JSON.stringify(body)})
+                .then(async (res) => {
+                    const data = await res.json().catch(() => ({}));
+                    if (res.ok) return onSignIn(data);
+                    if (data.totpRequired) setNeedCode(true);
+                    setError(data.error || res.statusText);
+                });
+        };
+        const field = (key, type, label) => h("label", {},
+            h("span", {}, label),
+            h("input", {type, value: form[key], onChange: (e) => setForm({...form, [key]: e.target.value})}));
+
+        return h("form", {className: "sign-in", onSubmit: submit},
+            h("h3", {}, setup ? "Create the first admin" : "Sign in"),
+            setup ? h("p", {}, "This account will manage rws. More admins can be added in Settings.") : null,
+            field("email", "email", "Email"),
+            field("password", "password", "Password"),
+            setup ? field("name", "text", "Name") : null,
+            needCode ? field("code", "text", "Authenticator code") : null,
+            error ? h("p", {className: "error"}, error) : null,
+            h(ccr.Button, {type: "submit"}, setup ? "Create admin" : "Sign in")
+        );
+    };
+
+    // The signed in admin: two-factor sign-in and signing out.
+    const account = ({session, onSignOut, onChange}) => {
+        const [pending, setPending] = React.useState(null);
+        const [code, setCode] = React.useState("");
+        const [error, setError] = React.useState(null);
+
+        const fail = (e) => setError(e.message);
+        const start = () => api("/api/totp", {method: "POST"}).then(setPending, fail);
+        const confirmCode = (path, totp) => (e) => {
+            e.preventDefault();
+            api(path, {method: "POST", body: {code}}).then(() => {
+                setPending(null);
+                setCode("");
+                setError(null);
+                onChange({...session, totp});
+            }, fail);
+        };
+        const signOut = () => api("/api/logout", {method: "POST"}).then(onSignOut, fail);
+        const codeForm = (path, totp, label) => h("form", {onSubmit: confirmCode(path, totp)},
+            h("input", {type: "text", placeholder: "123456", value: code, onChange: (e) => setCode(e.target.value)}),
+            h(ccr.Button, {type: "submit", size: "small"}, label));
+
+        return h("section", {className: "account"},
+            h("h3", {}, session.user.name || session.user.email),
+            h("p", {}, session.user.email),
+            h("h4", {}, "Two-factor sign-in"),
+            session.totp ? h("div", {},
+                h("p", {}, "On. Enter a current code to turn it off."),
+                codeForm("/api/totp/disable", false, "Turn off")
+            ) : pending ? h("div", {},
+                h("p", {}, "Add this key to your authenticator app, then enter the code it shows."),
+                h("code", {}, pending.secret),
+                h("p", {}, h("a", {href: pending.uri}, "Open in authenticator app")),
+                codeForm("/api/totp/enable", true, "Turn on")
+            ) : h(ccr.Button, {size: "small", onClick: start}, "Set up"),
+            error ? h("p", {className: "error"}, error) : null,
+            h(ccr.Button, {kind: "secondary", onClick: signOut}, "Sign out")
+        );
+    };
+
+    // What admins did, newest first.
+    const audit = () => {
+        const [entries, setEntries] = React.useState([]);
+        const [more, setMore] = React.useState(true);
+        const [error, setError] = React.useState(null);
+        const pageSize = 50;
+
+        const load = (before) => api(`/api/audit?limit=${pageSize}` + (before ? `&before=${before}` : ""))
+            .then((page) => {
+                setEntries((entries) => before ? [...entries, ...page] : page);
+                setMore(page.length == pageSize);
+            }, (e) => setError(e.message));
+        React.useEffect(() => { load(); }, []);
+
+        return h("section", {className: "audit"},
+            h("h3", {}, "Audit log"),
+            error ? h("p", {className: "error"}, error) : null,
+            h("table", {className: "bx--data-table"},
+                h("thead", {}, h("tr", {}, h("th", {}, "Time"), h("th", {}, "Admin"), h("th", {}, "Action"), h("th", {}, "Target"), h("th", {}, "Details"), h("th", {}, "IP"))),
+                h("tbody", {}, ...entries.map((entry, i) => h("tr", {key: entry.time + ":" + i},
+                    h("td", {}, new Date(entry.time).toLocaleString()),
+                    h("td", {}, entry.email),
+                    h("td", {}, entry.action),
+                    h("td", {}, entry.target),
+                    h("td", {}, entry.details == null ? "" : h("code", {}, JSON.stringify(entry.details))),
+                    h("td", {}, entry.ip || "")
+                )))
+            ),
+            more && entries.length > 0 ? h(ccr.Button, {kind: "ghost", onClick: () => load(entries[entries.length - 1].time)}, "Older") : null
+        );
+    };
+
+    // Lockouts from failed logins, with a button to lift each one.
+    const bans = () => {
+        const [list, setList] = React.useState([]);
+
+        const load = () => api("/api/bans").then(setList);
+        React.useEffect(() => {
+            load();
+            const timer = setInterval(load, 10000);
+            return () => clearInterval(timer);
+        }, []);
+
+        const unban = (ban) => api(`/api/bans/${ban.kind}/${encodeURIComponent(ban.key)}`, {method: "DELETE"}).then(load);
+
+        return h("section", {className: "bans"},
+            h("h3", {}, "Banned"),
+            list.length == 0 ? h("p", {}, "Nothing is locked out.") :
+            h("table", {className: "bx--data-table"},
+                h("thead", {}, h("tr", {}, h("th", {}, "Kind"), h("th", {}, "Address / Account"), h("th", {}, "Failures"), h("th", {}, "Until"), h("th"))),
+                h("tbody", {}, ...list.map((ban) => h("tr", {key: ban.kind + ban.key},
+                    h("td", {}, ban.kind),
+                    h("td", {}, ban.key),
+                    h("td", {}, ban.failures),
+                    h("td", {}, new Date(ban.until).toLocaleString()),
+                    h("td", {}, h(ccr.Button, {kind: "ghost", size: "small", onClick: () => unban(ban)}, "Unban"))
+                )))
+            )
+        );
+    };
+
+    // Options of one app, with an input per schema entry (or stored key when
+    // the app has no schema).
+    const optionsForm = ({host, app}) => {
+        const [data, setData] = React.useState(null);
+        const [draft, setDraft] = React.useState({});
+        const [error, setError] = React.useState(null);
+        const url = `/api/options/${encodeURIComponent(host)}/${encodeURIComponent(app)}`;
+
+        React.useEffect(() => {
+            api(url).then((data) => {
+                setData(data);
+                setDraft({});
+                setError(null);
+            });
+        }, [url]);
+
+        if (data == null) {
+            return h("p", {}, "Loading...");
+        }
+
+        const keys = [...new Set([...Object.keys(data.schema), ...Object.keys(data.options)])];
+        const value = (key) => key in draft ? draft[key] : data.options[key];
+        const set = (key) => (v) => setDraft({...draft, [key]: v});
+
+        const input = (key) => {
+            const spec = data.schema[key] || {};
+            const v = value(key);
+            if (spec.enum) {
+                return h("select", {value: JSON.stringify(v), onChange: (e) => set(key)(JSON.parse(e.target.value))},
+                    ...spec.enum.map((option) => h("option", {value: JSON.stringify(option)}, String(option))));
+            }
+            switch (spec.type || typeof v) {
+                case "boolean":
+                    return h("input", {type: "checkbox", checked: !!v, onChange: (e) => set(key)(e.target.checked)});
+                case "number":
+                case "integer":
+                    return h("input", {type: "number", value: v == null ? "" : v, onChange: (e) => set(key)(e.target.value === "" ? null : Number(e.target.value))});
+                case "string":
+                    return h("input", {type: "text", value: v == null ? "" : v, onChange: (e) => set(key)(e.target.value)});
+                default:
+                    return h("textarea", {defaultValue: JSON.stringify(v, null, 2), onBlur: (e) => {
+                        try {
+                            set(key)(JSON.parse(e.target.value));
+                        } catch (_) {
+                            setError(`${key} is not valid JSON`);
+                        }
+                    }});
+            }
+        };
+
+        const save = () => api(url, {method: "PUT", body: draft})
+            .then((options) => {
+                setData({...data, options});
+                setDraft({});
+                setError(null);
+            }, (e) => setError(e.message));
+
+        return h("form", {className: "options", onSubmit: (e) => { e.preventDefault(); save(); }},
+            ...keys.map((key) => h("label", {key},
+                h("span", {}, (data.schema[key] || {}).title || key),
+                input(key)
+            )),
+            error ? h("p", {className: "error"}, error) : null,
+            h(ccr.Button, {type: "submit", disabled: Object.keys(draft).length == 0}, "Save")
+        );
+    };
+
+    // config.json apart from the hosts; saving tells when a restart is due.
+    const globalSettings = () => {
+        const [data, reload, error] = useApi("/api/settings");
+        const [notice, setNotice] = React.useState(null);
+
+        const save = (settings) => api("/api/settings", {method: "PUT", body: settings}).then((res) => {
+            setNotice(res.restartRequired ? "Saved. Restart rws for the new addresses to apply." : "Saved.");
+            reload();
+        });
+
+        return h("section", {className: "global-settings"},
+            h("h3", {}, "Server"),
+            error ? h("p", {className: "error"}, error) : null,
+            notice ? h("p", {className: "notice"}, notice) : null,
+            data ? h(jsonEditor, {value: data, onSave: save}) : null
+        );
+    };
+
+    const settings = () => {
+        const [apps, setApps] = React.useState([]);
+        const [selected, setSelected] = React.useState(null);
+
+        React.useEffect(() => {
+            api("/api/options").then(setApps);
+        }, []);
+
+        return h("section", {className: "settings"},
+            h(globalSettings),
+            h("h3", {}, "App settings"),
+            apps.length == 0 ? h("p", {}, "No installed app has options.") :
+            h("select", {value: selected ? JSON.stringify(selected) : "", onChange: (e) => setSelected(JSON.parse(e.target.value))},
+                h("option", {value: "", disabled: true}, "Choose an app"),
+                ...apps.map((a) => h("option", {value: JSON.stringify(a)}, `${a.app} (${a.host})`))
+            ),
+            selected ? h(optionsForm, selected) : null
+        );
+    };
+
+    // The html_admin views of a control_panel app, in a sandboxed frame
+    // with an origin of its own: installed apps get neither the admin
+    // session nor the CSRF token.
+    const panel = ({info}) =>
+        h("iframe", {className: "panel", title: info.title, src: info.frame, sandbox: "allow-scripts allow-forms"});
+
     // console.log(icons);
     const app = () => {
 
@@ -76,6 +612,50 @@ require(["react", "react-dom", "carbon-components-react", "@carbon/icons-react",
 
         const [isSideNavExpanded, toggleExpanded] = useSideBar();
 
+        const [session, setSession] = React.useState(null);
+
+        const changeSession = (next) => {
+            csrf = next && next.user ? next.csrf : null;
+            setSession(next);
+        };
+
+        React.useEffect(() => {
+            fetch("/api/session").then((res) => res.json()).then(changeSession);
+        }, []);
+
+        const signedIn = session && session.user;
+
+        React.useEffect(() => {
+            if (!signedIn) return;
+            api("/api/panels").then((panels) => {
+                const items = panels.map((info) => ({title: info.title, href: "#", panel: info}));
+                setDashNav((nav) => ({...nav, items: [...nav.items.filter((item) => !item.panel), ...items]}));
+            });
+        }, [signedIn]);
+
+        if (session == null) {
+            return h("p", {}, "Loading...");
+        }
+        if (!signedIn) {
+            return h("main", {className: "content"}, h(signIn, {setup: session.setup, onSignIn: (data) => changeSession({setup: false, ...data})}));
+        }
+
+        const go = (item) => (e) => {
+            e.preventDefault();
+            setDashNav({...dashNav, active: item.title});
+        };
+        const pages = {Status: status, Accounts: accounts, Hosts: hosts, Roles: roles, Platform: platform, Settings: settings, Audit: audit};
+        const page = () => {
+            if (dashNav.active == "Account") {
+                return h(account, {session, onChange: changeSession, onSignOut: () => changeSession({setup: false, user: null})});
+            }
+            const item = dashNav.items.find((item) => item.title == dashNav.active);
+            if (item && item.panel) {
+                return h(panel, {key: item.panel.base, info: item.panel});
+            }
+            return pages[dashNav.active] ? h(pages[dashNav.active]) : null;
+        };
+
         return h("div", {className: "container"}, 
             h(ccr.Header, {"aria-label": "Header"}, 
                 h(ccr.HeaderMenuButton, {
@@ -84,9 +664,9 @@ require(["react", "react-dom", "carbon-components-react", "@carbon/icons-react",
                     isActive: isSideNavExpanded
                 }),
                 h(ccr.HeaderName, {prefix: "RWS"}, "DASHBOARD"),
-                h(ccr.HeaderNavigation, {"aria-label": "Navigation"}, ...dashNav.items.map((item) => h(ccr.HeaderMenuItem, {href: item.href, "aria-current": item.title == dashNav.active ? "page" : undefined}, item.title))),
+                h(ccr.HeaderNavigation, {"aria-label": "Navigation"}, ...dashNav.items.map((item) => h(ccr.HeaderMenuItem, {href: item.href, onClick: go(item), "aria-current": item.title == dashNav.active ? "page" : undefined}, item.title))),
                 h(ccr.HeaderGlobalBar, {}, 
-                    h(ccr.HeaderGlobalAction, {title: "Account", "aria-label": "Aaccount"}, h(icons.UserAvatar20)),
+                    h(ccr.HeaderGlobalAction, {title: "Account", "aria-label": "Account", onClick: () => setDashNav({...dashNav, active: "Account"})}, h(icons.UserAvatar20)),
                     h(ccr.HeaderGlobalAction, {title: "Notifications", "aria-label": "Notifications"}, h(icons.Notification20))
                 ),
                 h(ccr.SideNav, {
@@ -95,9 +675,10 @@ require(["react", "react-dom", "carbon-components-react", "@carbon/icons-react",
                     isPersistent: false,
                     "aria-label": "Side Navigation"
                 }, 
-                    h(ccr.SideNavItems, {}, ...dashNav.items.map((item) => h(ccr.SideNavLink, {href: item.href, "aria-label": item.title, "aria-current": item.title == dashNav.active ? "page" : undefined}, item.title)))    
+                    h(ccr.SideNavItems, {}, ...dashNav.items.map((item) => h(ccr.SideNavLink, {href: item.href, onClick: go(item), "aria-label": item.title, "aria-current": item.title == dashNav.active ? "page" : undefined}, item.title)))    
                 )
-            )
+            ),
+            h("main", {className: "content"}, page())
         );
     };
 
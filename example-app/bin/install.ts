@@ -22,6 +22,11 @@ export default (rws: any, db: any, fs: any, mail: any, elasticSearch: any) => {
         }
     });
 
+    // POST /json {"service": "get_user_by_id", "args": {"id": 22}}
+    rws.addService("get_user_by_id", async (args, token) => {
+        return {id: args.id, canEdit: rws.can(token, "can_edit_own_posts")};
+    }, {permission: "can_view_something"});
+
     rws.addAction("route", async (headers, other, args) => {
 
     }, 10);
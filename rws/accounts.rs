@@ -0,0 +1,1013 @@
+//! User accounts, sessions and authentication.
+//!
+//! Users are shared by every host.  Passwords are hashed with argon2.  A
+//! login creates a session, identified by a random token that goes to the
+//! client either as the `rws_session` cookie or as a bearer token; only the
+//! SHA-256 of a token is stored.  Email verification and password reset use
+//! single-use tickets mailed through `mail`.
+//!
+//! Browsers sign in with `POST /.rws/login` on any host, which rws handles
+//! itself: app code sees neither the client's address, which the per-IP
+//! lockout needs, nor the response headers, which the cookie needs.
+//!
+//! Everything is kept in `<rws dir>/accounts/accounts.json`.
+
+use crate::config;
+use crate::mail::{self, MailError};
+use crate::protection::{self, Refusal};
+use actix_web::http::header;
+use actix_web::{web, HttpRequest, HttpResponse};
+use rand::RngCore;
+use ring::digest;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Cookie holding the session token.
+pub const SESSION_COOKIE: &str = "rws_session";
+
+const HOUR: u64 = 60 * 60 * 1000;
+const DAY: u64 = 24 * HOUR;
+const COOKIE_SESSION_MS: u64 = 30 * DAY;
+const BEARER_SESSION_MS: u64 = 365 * DAY;
+const VERIFY_TICKET_MS: u64 = 48 * HOUR;
+const RESET_TICKET_MS: u64 = HOUR;
+const MIN_PASSWORD_LEN: usize = 8;
+
+lazy_static! {
+  static ref STORE: Mutex<AccountStore> =
+    Mutex::new(AccountStore::open(config::rws_dir().join("accounts")));
+  /// Checked against when the email is unknown, so that takes as long as a
+  /// wrong password.
+  static ref DUMMY_HASH: String = argon2::hash_encoded(
+    b"not anyone's password",
+    &[0u8; 16],
+    &argon2::Config::default()
+  )
+  .unwrap();
+}
+
+#[derive(Debug)]
+pub enum AccountError {
+  Invalid(String),
+  EmailTaken,
+  /// Wrong email or password; deliberately doesn't say which.
+  BadCredentials,
+  Refused(Refusal),
+  /// Unknown, expired or already used token.
+  BadToken,
+  Disabled,
+  Io(io::Error),
+  Mail(MailError),
+}
+
+impl fmt::Display for AccountError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      AccountError::Invalid(reason) => f.write_str(reason),
+      AccountError::EmailTaken => write!(f, "email is already registered"),
+      AccountError::BadCredentials => write!(f, "wrong email or password"),
+      AccountError::Refused(refusal) => write!(f, "{}", refusal),
+      AccountError::BadToken => write!(f, "invalid or expired token"),
+      AccountError::Disabled => write!(f, "account is disabled"),
+      AccountError::Io(e) => write!(f, "could not store accounts: {}", e),
+      AccountError::Mail(e) => write!(f, "could not send mail: {}", e),
+    }
+  }
+}
+
+impl std::error::Error for AccountError {}
+
+impl From<io::Error> for AccountError {
+  fn from(e: io::Error) -> Self {
+    AccountError::Io(e)
+  }
+}
+
+impl From<serde_json::Error> for AccountError {
+  fn from(e: serde_json::Error) -> Self {
+    AccountError::Io(e.into())
+  }
+}
+
+impl From<MailError> for AccountError {
+  fn from(e: MailError) -> Self {
+    AccountError::Mail(e)
+  }
+}
+
+type AccountResult<T> = Result<T, AccountError>;
+
+fn now_millis() -> u64 {
+  SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .unwrap()
+    .as_millis() as u64
+}
+
+fn random_token() -> String {
+  let mut bytes = [0u8; 32];
+  rand::thread_rng().fill_bytes(&mut bytes);
+  base64::encode_config(&bytes, base64::URL_SAFE_NO_PAD)
+}
+
+fn token_hash(token: &str) -> String {
+  digest::digest(&digest::SHA256, token.as_bytes())
+    .as_ref()
+    .iter()
+    .map(|b| format!("{:02x}", b))
+    .collect()
+}
+
+fn normalize_email(email: &str) -> AccountResult<String> {
+  let email = email.trim().to_lowercase();
+  let valid = email.len() <= 254
+    && match email.find('@') {
+      Some(at) => at > 0 && email[at + 1..].contains('.'),
+      None => false,
+    }
+    && !email.contains(char::is_whitespace);
+  if valid {
+    Ok(email)
+  } else {
+    Err(AccountError::Invalid("invalid email address".to_string()))
+  }
+}
+
+fn check_password(password: &str) -> AccountResult<()> {
+  if password.chars().count() < MIN_PASSWORD_LEN {
+    return Err(AccountError::Invalid(format!(
+      "password must have at least {} characters",
+      MIN_PASSWORD_LEN
+    )));
+  }
+  Ok(())
+}
+
+/// Hashes on a blocking thread; argon2 is slow on purpose.
+pub async fn hash_password(password: String) -> AccountResult<String> {
+  check_password(&password)?;
+  tokio::task::spawn_blocking(move || {
+    let mut salt = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut salt);
+    let config = argon2::Config::default();
+    argon2::hash_encoded(password.as_bytes(), &salt, &config)
+  })
+  .await
+  .map_err(|e| AccountError::Io(io::Error::new(io::ErrorKind::Other, e)))?
+  .map_err(|e| AccountError::Invalid(e.to_string()))
+}
+
+async fn verify_password(hash: String, password: String) -> bool {
+  tokio::task::spawn_blocking(move || {
+    argon2::verify_encoded(&hash, password.as_bytes()).unwrap_or(false)
+  })
+  .await
+  .unwrap_or(false)
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct User {
+  pub id: String,
+  pub email: String,
+  #[serde(default)]
+  pub name: Option<String>,
+  password_hash: String,
+  #[serde(default)]
+  pub verified: bool,
+  #[serde(default)]
+  pub disabled: bool,
+  pub created: u64,
+}
+
+/// A user as shown to apps and the control panel.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UserInfo {
+  pub id: String,
+  pub email: String,
+  pub name: Option<String>,
+  pub verified: bool,
+  pub disabled: bool,
+  pub created: u64,
+}
+
+impl From<&User> for UserInfo {
+  fn from(user: &User) -> Self {
+    UserInfo {
+      id: user.id.clone(),
+      email: user.email.clone(),
+      name: user.name.clone(),
+      verified: user.verified,
+      disabled: user.disabled,
+      created: user.created,
+    }
+  }
+}
+
+/// Changes an admin makes to a user; unset fields stay as they are.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct UserPatch {
+  pub email: Option<String>,
+  pub name: Option<String>,
+  pub password: Option<String>,
+  pub verified: Option<bool>,
+  pub disabled: Option<bool>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SessionKind {
+  Cookie,
+  Bearer,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Session {
+  user_id: String,
+  kind: SessionKind,
+  created: u64,
+  expires: u64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TicketPurpose {
+  Verify,
+  Reset,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Ticket {
+  user_id: String,
+  purpose: TicketPurpose,
+  expires: u64,
+}
+
+/// What request handlers and services get as `token`.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Token {
+  /// Stable id of the session, safe to show (not the secret token).
+  pub session_id: String,
+  pub kind: SessionKind,
+  pub expires: u64,
+  pub user: UserInfo,
+}
+
+#[derive(Default, Deserialize, Serialize)]
+struct Files {
+  users: HashMap<String, User>,
+  /// Keyed by token hash.
+  sessions: HashMap<String, Session>,
+  /// Keyed by token hash.
+  tickets: HashMap<String, Ticket>,
+}
+
+pub struct AccountStore {
+  path: Option<PathBuf>,
+  data: Files,
+  by_email: HashMap<String, String>,
+}
+
+impl AccountStore {
+  /// Loads `<dir>/accounts.json`, starting empty when there is none.
+  pub fn open(dir: PathBuf) -> Self {
+    let path = dir.join("accounts.json");
+    let data: Files = match fs::read_to_string(&path) {
+      Ok(text) => serde_json::from_str(&text).unwrap_or_else(|e| {
+        error!("invalid {}: {}", path.display(), e);
+        Files::default()
+      }),
+      Err(_) => Files::default(),
+    };
+    let mut store = AccountStore {
+      path: Some(path),
+      data,
+      by_email: HashMap::new(),
+    };
+    store.index();
+    store
+  }
+
+  /// A store that is never written to disk.
+  pub fn in_memory() -> Self {
+    AccountStore {
+      path: None,
+      data: Files::default(),
+      by_email: HashMap::new(),
+    }
+  }
+
+  fn index(&mut self) {
+    self.by_email = self
+      .data
+      .users
+      .values()
+      .map(|u| (u.email.clone(), u.id.clone()))
+      .collect();
+  }
+
+  fn persist(&self) -> AccountResult<()> {
+    let path = match &self.path {
+      Some(path) => path,
+      None => return Ok(()),
+    };
+    fs::create_dir_all(path.parent().unwrap())?;
+    let tmp = path.with_extension("json.tmp");
+    fs::write(&tmp, serde_json::to_vec(&self.data)?)?;
+    fs::rename(&tmp, path)?;
+    Ok(())
+  }
+
+  pub fn create_user(
+    &mut self,
+    email: &str,
+    name: Option<String>,
+    password_hash: String,
+    now: u64,
+  ) -> AccountResult<UserInfo> {
+    let email = normalize_email(email)?;
+    if self.by_email.contains_key(&email) {
+      return Err(AccountError::EmailTaken);
+    }
+    let mut id = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut id);
+    let user = User {
+      id: id.iter().map(|b| format!("{:02x}", b)).collect(),
+      email: email.clone(),
+      name,
+      password_hash,
+      verified: false,
+      disabled: false,
+      created: now,
+    };
+    let info = UserInfo::from(&user);
+    self.by_email.insert(email, user.id.clone());
+    self.data.users.insert(user.id.clone(), user);
+    self.persist()?;
+    Ok(info)
+  }
+
+  pub fn user(&self, id: &str) -> Option<&User> {
+    self.data.users.get(id)
+  }
+
+  pub fn user_by_email(&self, email: &str) -> Option<&User> {
+    let email = normalize_email(email).ok()?;
+    self.by_email.get(&email).and_then(|id| self.user(id))
+  }
+
+  pub fn users(&self) -> Vec<UserInfo> {
+    let mut users: Vec<UserInfo> =
+      self.data.users.values().map(UserInfo::from).collect();
+    users.sort_by(|a, b| a.created.cmp(&b.created));
+    users
+  }
+
+  fn update_user<F: FnOnce(&mut User)>(
+    &mut self,
+    id: &str,
+    f: F,
+  ) -> AccountResult<UserInfo> {
+    let user = self.data.users.get_mut(id).ok_or(AccountError::BadToken)?;
+    f(user);
+    let info = UserInfo::from(&*user);
+    self.persist()?;
+    Ok(info)
+  }
+
+  /// Applies `patch`, with the password already hashed into
+  /// `password_hash`.  Changing the password or disabling the user ends
+  /// their sessions.
+  pub fn patch_user(
+    &mut self,
+    id: &str,
+    patch: UserPatch,
+    password_hash: Option<String>,
+  ) -> AccountResult<UserInfo> {
+    let email = match &patch.email {
+      Some(email) => {
+        let email = normalize_email(email)?;
+        match self.by_email.get(&email) {
+          Some(owner) if owner != id => return Err(AccountError::EmailTaken),
+          _ => Some(email),
+        }
+      }
+      None => None,
+    };
+    let old_email = self.user(id).ok_or(AccountError::BadToken)?.email.clone();
+    let sign_out = password_hash.is_some() || patch.disabled == Some(true);
+    let info = self.update_user(id, |user| {
+      if let Some(email) = email {
+        user.email = email;
+      }
+      if let Some(name) = patch.name {
+        user.name = Some(name).filter(|n| !n.is_empty());
+      }
+      if let Some(hash) = password_hash {
+        user.password_hash = hash;
+      }
+      if let Some(verified) = patch.verified {
+        user.verified = verified;
+      }
+      if let Some(disabled) = patch.disabled {
+        user.disabled = disabled;
+      }
+    })?;
+    self.by_email.remove(&old_email);
+    self.by_email.insert(info.email.clone(), info.id.clone());
+    if sign_out {
+      self.end_sessions_of(id)?;
+    }
+    Ok(info)
+  }
+
+  /// Removes a user with their sessions and tickets.
+  pub fn delete_user(&mut self, id: &str) -> AccountResult<bool> {
+    let user = match self.data.users.remove(id) {
+      Some(user) => user,
+      None => return Ok(false),
+    };
+    self.by_email.remove(&user.email);
+    self.data.sessions.retain(|_, s| s.user_id != id);
+    self.data.tickets.retain(|_, t| t.user_id != id);
+    self.persist()?;
+    Ok(true)
+  }
+
+  /// Number of sessions that haven't expired.
+  pub fn session_count(&self, now: u64) -> usize {
+    self
+      .data
+      .sessions
+      .values()
+      .filter(|s| s.expires > now)
+      .count()
+  }
+
+  pub fn set_disabled(
+    &mut self,
+    id: &str,
+    disabled: bool,
+  ) -> AccountResult<UserInfo> {
+    let info = self.update_user(id, |user| user.disabled = disabled)?;
+    if disabled {
+      self.end_sessions_of(id)?;
+    }
+    Ok(info)
+  }
+
+  /// Starts a session and returns its secret token.
+  pub fn create_session(
+    &mut self,
+    user_id: &str,
+    kind: SessionKind,
+    now: u64,
+  ) -> AccountResult<(String, Token)> {
+    let token = random_token();
+    let lifetime = match kind {
+      SessionKind::Cookie => COOKIE_SESSION_MS,
+      SessionKind::Bearer => BEARER_SESSION_MS,
+    };
+    let session = Session {
+      user_id: user_id.to_string(),
+      kind,
+      created: now,
+      expires: now + lifetime,
+    };
+    let hash = token_hash(&token);
+    self.data.sessions.insert(hash.clone(), session);
+    self.persist()?;
+    let resolved = self.resolve(&hash, now).ok_or(AccountError::BadToken)?;
+    Ok((token, resolved))
+  }
+
+  fn resolve(&self, hash: &str, now: u64) -> Option<Token> {
+    let session = self.data.sessions.get(hash)?;
+    let user = self.user(&session.user_id)?;
+    if session.expires <= now || user.disabled {
+      return None;
+    }
+    Some(Token {
+      session_id: hash[..16].to_string(),
+      kind: session.kind,
+      expires: session.expires,
+      user: UserInfo::from(user),
+    })
+  }
+
+  /// The session a token belongs to, if it is still valid.
+  pub fn session(&self, token: &str, now: u64) -> Option<Token> {
+    self.resolve(&token_hash(token), now)
+  }
+
+  pub fn end_session(&mut self, token: &str) -> AccountResult<bool> {
+    let ended = self.data.sessions.remove(&token_hash(token)).is_some();
+    if ended {
+      self.persist()?;
+    }
+    Ok(ended)
+  }
+
+  pub fn end_sessions_of(&mut self, user_id: &str) -> AccountResult<()> {
+    self.data.sessions.retain(|_, s| s.user_id != user_id);
+    self.persist()
+  }
+
+  pub fn create_ticket(
+    &mut self,
+    user_id: &str,
+    purpose: TicketPurpose,
+    now: u64,
+  ) -> AccountResult<String> {
+    let token = random_token();
+    let lifetime = match purpose {
+      TicketPurpose::Verify => VERIFY_TICKET_MS,
+      TicketPurpose::Reset => RESET_TICKET_MS,
+    };
+    // Only the newest ticket of a kind stays valid.
+    self
+      .data
+      .tickets
+      .retain(|_, t| !(t.user_id == user_id && t.purpose == purpose));
+    self.data.tickets.insert(
+      token_hash(&token),
+      Ticket {
+        user_id: user_id.to_string(),
+        purpose,
+        expires: now + lifetime,
+      },
+    );
+    self.persist()?;
+    Ok(token)
+  }
+
+  /// Uses up a ticket and returns its user id.
+  pub fn take_ticket(
+    &mut self,
+    token: &str,
+    purpose: TicketPurpose,
+    now: u64,
+  ) -> AccountResult<String> {
+    let hash = token_hash(token);
+    match self.data.tickets.get(&hash) {
+      Some(t) if t.purpose == purpose && t.expires > now => {}
+      _ => return Err(AccountError::BadToken),
+    }
+    let ticket = self.data.tickets.remove(&hash).unwrap();
+    self.persist()?;
+    Ok(ticket.user_id)
+  }
+
+  /// Drops expired sessions and tickets.
+  pub fn prune(&mut self, now: u64) -> AccountResult<()> {
+    let sessions = self.data.sessions.len();
+    let tickets = self.data.tickets.len();
+    self.data.sessions.retain(|_, s| s.expires > now);
+    self.data.tickets.retain(|_, t| t.expires > now);
+    let pruned = sessions != self.data.sessions.len()
+      || tickets != self.data.tickets.len();
+    if pruned {
+      self.persist()?;
+    }
+    Ok(())
+  }
+}
+
+/// Pulls the session token out of a request's `Cookie` or `Authorization`
+/// header.
+pub fn request_token(
+  cookie: Option<&str>,
+  authorization: Option<&str>,
+) -> Option<String> {
+  if let Some(auth) = authorization {
+    let mut parts = auth.trim().splitn(2, ' ');
+    if parts.next()?.eq_ignore_ascii_case("bearer") {
+      return parts.next().map(|t| t.trim().to_string());
+    }
+  }
+  cookie?.split(';').find_map(|pair| {
+    let mut kv = pair.trim().splitn(2, '=');
+    if kv.next()? == SESSION_COOKIE {
+      kv.next().map(|v| v.to_string())
+    } else {
+      None
+    }
+  })
+}
+
+/// The session token of a request, from its cookie or bearer token.
+fn request_secret(req: &HttpRequest) -> Option<String> {
+  let value = |name| req.headers().get(name).and_then(|v| v.to_str().ok());
+  request_token(value(header::COOKIE), value(header::AUTHORIZATION))
+}
+
+/// The session of a request, from its cookie or bearer token.
+pub fn request_session(req: &HttpRequest) -> Option<Token> {
+  session(&request_secret(req)?)
+}
+
+/// Whether session cookies are `Secure` unless the app says otherwise:
+/// only when rws serves HTTPS, or browsers would drop them on plain HTTP.
+pub fn secure_cookies() -> bool {
+  config::get().https_addr.is_some()
+}
+
+/// `Set-Cookie` value for a cookie session, or for clearing it when `token`
+/// is `None`.
+pub fn session_cookie(token: Option<&str>, secure: bool) -> String {
+  let secure = if secure { "; Secure" } else { "" };
+  match token {
+    Some(token) => format!(
+      "{}={}; Path=/; Max-Age={}; HttpOnly{}; SameSite=Lax",
+      SESSION_COOKIE,
+      token,
+      COOKIE_SESSION_MS / 1000,
+      secure
+    ),
+    None => format!(
+      "{}=; Path=/; Max-Age=0; HttpOnly{}; SameSite=Lax",
+      SESSION_COOKIE, secure
+    ),
+  }
+}
+
+fn link(url: &str, token: &str) -> String {
+  let separator = if url.contains('?') { '&' } else { '?' };
+  format!("{}{}token={}", url, separator, token)
+}
+
+pub async fn register(
+  email: String,
+  password: String,
+  name: Option<String>,
+) -> AccountResult<UserInfo> {
+  normalize_email(&email)?;
+  let hash = hash_password(password).await?;
+  STORE
+    .lock()
+    .unwrap()
+    .create_user(&email, name, hash, now_millis())
+}
+
+/// Checks the password and starts a session.  Attempts go through the
+/// brute force protection in `protection`.
+pub async fn login(
+  email: String,
+  password: String,
+  kind: SessionKind,
+  ip: Option<IpAddr>,
+) -> AccountResult<(String, Token)> {
+  let email = normalize_email(&email)?;
+  protection::check_login(ip, &email).map_err(AccountError::Refused)?;
+
+  let user = STORE.lock().unwrap().user_by_email(&email).cloned();
+  let ok = match &user {
+    Some(user) => verify_password(user.password_hash.clone(), password).await,
+    None => {
+      // Spend the same time as a real check so unknown emails don't stand
+      // out.
+      let _ = verify_password(DUMMY_HASH.clone(), password).await;
+      false
+    }
+  };
+  let user = match user {
+    Some(user) if ok => user,
+    _ => {
+      protection::record_failure(ip, Some(&email));
+      return Err(AccountError::BadCredentials);
+    }
+  };
+  if user.disabled {
+    return Err(AccountError::Disabled);
+  }
+  protection::record_success(&email);
+  STORE
+    .lock()
+    .unwrap()
+    .create_session(&user.id, kind, now_millis())
+}
+
+#[derive(Deserialize)]
+pub struct LoginRequest {
+  email: String,
+  password: String,
+  /// Return a bearer token as `secret` instead of setting the cookie.
+  #[serde(default)]
+  bearer: bool,
+}
+
+fn error_response(e: AccountError) -> HttpResponse {
+  let (mut response, message) = match e {
+    AccountError::Refused(_) => {
+      (HttpResponse::TooManyRequests(), e.to_string())
+    }
+    // A disabled account doesn't say the password was right.
+    AccountError::BadCredentials | AccountError::Disabled => (
+      HttpResponse::Unauthorized(),
+      AccountError::BadCredentials.to_string(),
+    ),
+    AccountError::Invalid(_) => (HttpResponse::BadRequest(), e.to_string()),
+    _ => {
+      error!("sign in failed: {}", e);
+      (HttpResponse::InternalServerError(), e.to_string())
+    }
+  };
+  response.json(json!({ "error": message }))
+}
+
+/// `POST /.rws/login`: checks the password and starts a session, with the
+/// brute force protection applied to the client's address.
+pub async fn handle_login(
+  req: HttpRequest,
+  body: web::Json<LoginRequest>,
+) -> HttpResponse {
+  if config::get().host(req.connection_info().host()).is_none() {
+    return HttpResponse::NotFound().json(json!({ "error": "unknown host" }));
+  }
+  let LoginRequest {
+    email,
+    password,
+    bearer,
+  } = body.into_inner();
+  let ip = req.peer_addr().map(|addr| addr.ip());
+  let kind = if bearer {
+    SessionKind::Bearer
+  } else {
+    SessionKind::Cookie
+  };
+  match login(email, password, kind, ip).await {
+    Ok((secret, token)) if bearer => {
+      HttpResponse::Ok().json(json!({ "secret": secret, "token": token }))
+    }
+    Ok((secret, token)) => HttpResponse::Ok()
+      .header(
+        header::SET_COOKIE,
+        session_cookie(Some(&secret), secure_cookies()),
+      )
+      .json(json!({ "token": token })),
+    Err(e) => error_response(e),
+  }
+}
+
+/// `POST /.rws/logout`: ends the request's session and clears the cookie.
+pub async fn handle_logout(req: HttpRequest) -> HttpResponse {
+  if let Some(secret) = request_secret(&req) {
+    if let Err(e) = logout(&secret) {
+      return error_response(e);
+    }
+  }
+  HttpResponse::NoContent()
+    .header(header::SET_COOKIE, session_cookie(None, secure_cookies()))
+    .finish()
+}
+
+pub fn session(token: &str) -> Option<Token> {
+  STORE.lock().unwrap().session(token, now_millis())
+}
+
+pub fn logout(token: &str) -> AccountResult<bool> {
+  STORE.lock().unwrap().end_session(token)
+}
+
+pub fn users() -> Vec<UserInfo> {
+  STORE.lock().unwrap().users()
+}
+
+pub fn user(id: &str) -> Option<UserInfo> {
+  STORE.lock().unwrap().user(id).map(UserInfo::from)
+}
+
+pub fn set_disabled(id: &str, disabled: bool) -> AccountResult<UserInfo> {
+  STORE.lock().unwrap().set_disabled(id, disabled)
+}
+
+pub async fn patch_user(
+  id: &str,
+  mut patch: UserPatch,
+) -> AccountResult<UserInfo> {
+  let hash = match patch.password.take() {
+    Some(password) => Some(hash_password(password).await?),
+    None => None,
+  };
+  STORE.lock().unwrap().patch_user(id, patch, hash)
+}
+
+pub fn delete_user(id: &str) -> AccountResult<bool> {
+  STORE.lock().unwrap().delete_user(id)
+}
+
+pub fn session_count() -> usize {
+  STORE.lock().unwrap().session_count(now_millis())
+}
+
+/// Mails a verification link: `url` with `?token=...` appended.
+pub async fn send_verification(user_id: &str, url: &str) -> AccountResult<()> {
+  let (email, token) = {
+    let mut store = STORE.lock().unwrap();
+    let email = match store.user(user_id) {
+      Some(user) => user.email.clone(),
+      None => return Err(AccountError::BadToken),
+    };
+    let token =
+      store.create_ticket(user_id, TicketPurpose::Verify, now_millis())?;
+    (email, token)
+  };
+  let link = link(url, &token);
+  mail::send(mail::Message {
+    from: None,
+    to: vec![email],
+    cc: Vec::new(),
+    bcc: Vec::new(),
+    subject: "Confirm your email address".to_string(),
+    text: Some(format!(
+      "Open this link to confirm your email address:\n\n{}\n\n\
+       It expires in {} hours.\n",
+      link,
+      VERIFY_TICKET_MS / HOUR
+    )),
+    html: None,
+    attachments: Vec::new(),
+  })
+  .await?;
+  Ok(())
+}
+
+pub fn verify_email(token: &str) -> AccountResult<UserInfo> {
+  let mut store = STORE.lock().unwrap();
+  let user_id =
+    store.take_ticket(token, TicketPurpose::Verify, now_millis())?;
+  store.update_user(&user_id, |user| user.verified = true)
+}
+
+/// Queues a reset link for `email` when it has an account.  Succeeds either
+/// way and doesn't wait on SMTP, so neither the answer nor the time it takes
+/// tells who is registered.
+pub fn request_password_reset(email: &str, url: &str) {
+  let found = {
+    let mut store = STORE.lock().unwrap();
+    let user = store.user_by_email(email);
+    match user.map(|u| (u.id.clone(), u.email.clone())) {
+      Some((id, email)) => {
+        match store.create_ticket(&id, TicketPurpose::Reset, now_millis()) {
+          Ok(token) => Some((email, token)),
+          Err(e) => {
+            warn!("could not create a reset ticket: {}", e);
+            None
+          }
+        }
+      }
+      None => None,
+    }
+  };
+  if let Some((email, token)) = found {
+    let queued = mail::queue(mail::Message {
+      from: None,
+      to: vec![email],
+      cc: Vec::new(),
+      bcc: Vec::new(),
+      subject: "Reset your password".to_string(),
+      text: Some(format!(
+        "Open this link to choose a new password:\n\n{}\n\n\
+         It expires in one hour.  If you didn't ask for this, ignore this \
+         email.\n",
+        link(url, &token)
+      )),
+      html: None,
+      attachments: Vec::new(),
+    });
+    if let Err(e) = queued {
+      warn!("could not queue a reset mail: {}", e);
+    }
+  }
+}
+
+/// Sets a new password from a reset ticket and signs the user out
+/// everywhere.
+pub async fn reset_password(
+  token: &str,
+  password: String,
+) -> AccountResult<()> {
+  let hash = hash_password(password).await?;
+  let mut store = STORE.lock().unwrap();
+  let user_id = store.take_ticket(token, TicketPurpose::Reset, now_millis())?;
+  // Following the link proves the address too.
+  store.update_user(&user_id, |user| {
+    user.password_hash = hash;
+    user.verified = true;
+  })?;
+  store.end_sessions_of(&user_id)
+}
+
+/// Periodically drops expired sessions and tickets.
+pub async fn run() {
+  loop {
+    tokio::time::delay_for(std::time::Duration::from_secs(60 * 60)).await;
+    if let Err(e) = STORE.lock().unwrap().prune(now_millis()) {
+      warn!("could not prune sessions: {}", e);
+    }
+  }
+}
+
+#[test]
+fn accounts_test() {
+  let mut store = AccountStore::in_memory();
+  let now = 1_000_000;
+
+  let hash = argon2::hash_encoded(
+    b"correct horse",
+    b"saltsaltsalt",
+    &argon2::Config::default(),
+  )
+  .unwrap();
+  let user = store
+    .create_user(" Alice@Example.com", None, hash.clone(), now)
+    .unwrap();
+  assert_eq!(user.email, "alice@example.com");
+  assert!(matches!(
+    store.create_user("alice@example.com", None, hash, now),
+    Err(AccountError::EmailTaken)
+  ));
+  assert!(matches!(
+    store.create_user("not an email", None, String::new(), now),
+    Err(AccountError::Invalid(_))
+  ));
+  let stored = store.user_by_email("ALICE@example.com").unwrap();
+  assert!(
+    argon2::verify_encoded(&stored.password_hash, b"correct horse").unwrap()
+  );
+
+  let (token, session) = store
+    .create_session(&user.id, SessionKind::Cookie, now)
+    .unwrap();
+  assert_eq!(session.user.id, user.id);
+  assert_eq!(store.session(&token, now + DAY).unwrap().user.id, user.id);
+  assert!(store.session(&token, now + COOKIE_SESSION_MS).is_none());
+  assert!(store.session("forged", now).is_none());
+
+  let cookie = format!("theme=dark; {}={}", SESSION_COOKIE, token);
+  assert_eq!(request_token(Some(&cookie), None), Some(token.clone()));
+  assert_eq!(
+    request_token(Some(&cookie), Some("Bearer abc")),
+    Some("abc".to_string())
+  );
+
+  // Tickets are single use and only the newest one is valid.
+  let old = store
+    .create_ticket(&user.id, TicketPurpose::Reset, now)
+    .unwrap();
+  let reset = store
+    .create_ticket(&user.id, TicketPurpose::Reset, now)
+    .unwrap();
+  assert!(store.take_ticket(&old, TicketPurpose::Reset, now).is_err());
+  assert!(store
+    .take_ticket(&reset, TicketPurpose::Verify, now)
+    .is_err());
+  assert_eq!(
+    store
+      .take_ticket(&reset, TicketPurpose::Reset, now)
+      .unwrap(),
+    user.id
+  );
+  assert!(store
+    .take_ticket(&reset, TicketPurpose::Reset, now)
+    .is_err());
+
+  let patch = UserPatch {
+    email: Some("alice@example.org".to_string()),
+    verified: Some(true),
+    ..UserPatch::default()
+  };
+  let patched = store.patch_user(&user.id, patch, None).unwrap();
+  assert!(patched.verified);
+  assert!(store.user_by_email("alice@example.com").is_none());
+  assert_eq!(
+    store.user_by_email("alice@example.org").unwrap().id,
+    user.id
+  );
+  assert_eq!(store.session_count(now), 1);
+
+  store.set_disabled(&user.id, true).unwrap();
+  assert!(store.session(&token, now).is_none());
+  assert!(!store.end_session(&token).unwrap());
+  assert_eq!(store.session_count(now), 0);
+
+  assert!(store.delete_user(&user.id).unwrap());
+  assert!(store.user_by_email("alice@example.org").is_none());
+  assert!(!store.delete_user(&user.id).unwrap());
+
+  assert!(session_cookie(Some("abc"), true).contains("HttpOnly; Secure;"));
+  assert!(!session_cookie(Some("abc"), false).contains("Secure"));
+  assert!(session_cookie(None, false).contains("Max-Age=0"));
+}
@@ -0,0 +1,654 @@
+//! ACME v2 (RFC 8555) client for automatic virtual host certificates.
+//!
+//! Challenges are answered over HTTP-01 by the main HTTP listener (see
+//! `challenge_response`).  Certificates are kept in
+//! `<rws dir>/certs/<host>/` and served through `RESOLVER`, which the HTTPS
+//! listener uses to pick a certificate by SNI.  Renewals replace the entry in
+//! the resolver, so new handshakes get the new certificate without a restart.
+
+use crate::config::{self, AcmeConfig, Config};
+use ring::rand::SystemRandom;
+use ring::signature::{EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_FIXED_SIGNING};
+use rustls::internal::pemfile;
+use rustls::sign::{self, CertifiedKey};
+use rustls::{ResolvesServerCert, SignatureScheme};
+use serde_derive::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::time::delay_for;
+
+const RENEW_CHECK_INTERVAL: Duration = Duration::from_secs(12 * 60 * 60);
+const CONFIG_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+const POLL_ATTEMPTS: usize = 30;
+
+lazy_static! {
+  /// HTTP-01 token -> key authorization, for challenges in flight.
+  static ref CHALLENGES: RwLock<HashMap<String, String>> =
+    RwLock::new(HashMap::new());
+  pub static ref RESOLVER: Arc<CertResolver> = Arc::new(CertResolver::default());
+}
+
+/// Body to serve at `/.well-known/acme-challenge/<token>`, if that token
+/// belongs to a pending order.
+pub fn challenge_response(token: &str) -> Option<String> {
+  CHALLENGES.read().unwrap().get(token).cloned()
+}
+
+#[derive(Debug)]
+pub struct AcmeError(String);
+
+impl fmt::Display for AcmeError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.write_str(&self.0)
+  }
+}
+
+impl std::error::Error for AcmeError {}
+
+impl From<io::Error> for AcmeError {
+  fn from(e: io::Error) -> Self {
+    AcmeError(e.to_string())
+  }
+}
+
+impl From<reqwest::Error> for AcmeError {
+  fn from(e: reqwest::Error) -> Self {
+    AcmeError(e.to_string())
+  }
+}
+
+impl From<serde_json::Error> for AcmeError {
+  fn from(e: serde_json::Error) -> Self {
+    AcmeError(e.to_string())
+  }
+}
+
+impl From<ring::error::Unspecified> for AcmeError {
+  fn from(_: ring::error::Unspecified) -> Self {
+    AcmeError("signing failed".to_string())
+  }
+}
+
+impl From<rcgen::RcgenError> for AcmeError {
+  fn from(e: rcgen::RcgenError) -> Self {
+    AcmeError(e.to_string())
+  }
+}
+
+type AcmeResult<T> = Result<T, AcmeError>;
+
+/// Picks the certificate for a TLS handshake by server name.
+#[derive(Default)]
+pub struct CertResolver {
+  certs: RwLock<HashMap<String, CertifiedKey>>,
+}
+
+impl CertResolver {
+  pub fn insert(&self, domains: &[String], key: CertifiedKey) {
+    let mut certs = self.certs.write().unwrap();
+    for domain in domains {
+      certs.insert(domain.to_lowercase(), key.clone());
+    }
+  }
+
+  pub fn contains(&self, domain: &str) -> bool {
+    self
+      .certs
+      .read()
+      .unwrap()
+      .contains_key(&domain.to_lowercase())
+  }
+
+  /// Drops the certificates of domains not in `domains`.
+  pub fn retain(&self, domains: &[String]) {
+    let domains: Vec<String> =
+      domains.iter().map(|d| d.to_lowercase()).collect();
+    let mut certs = self.certs.write().unwrap();
+    certs.retain(|domain, _| domains.contains(domain));
+  }
+}
+
+impl ResolvesServerCert for CertResolver {
+  fn resolve(
+    &self,
+    server_name: Option<webpki::DNSNameRef>,
+    _sigschemes: &[SignatureScheme],
+  ) -> Option<CertifiedKey> {
+    let name: &str = server_name?.into();
+    self
+      .certs
+      .read()
+      .unwrap()
+      .get(&name.to_lowercase())
+      .cloned()
+  }
+}
+
+fn b64(data: &[u8]) -> String {
+  base64::encode_config(data, base64::URL_SAFE_NO_PAD)
+}
+
+fn unix_now() -> u64 {
+  SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .unwrap()
+    .as_secs()
+}
+
+#[derive(Deserialize, Serialize)]
+struct CertMeta {
+  domains: Vec<String>,
+  issued_at: u64,
+}
+
+/// Writes a file only its owner can read, for private keys.
+fn write_private(path: &Path, data: &[u8]) -> io::Result<()> {
+  let mut options = fs::OpenOptions::new();
+  options.write(true).create(true).truncate(true);
+  #[cfg(unix)]
+  {
+    use std::os::unix::fs::OpenOptionsExt;
+    options.mode(0o600);
+  }
+  let mut file = options.open(path)?;
+  // `mode` only applies to new files.
+  #[cfg(unix)]
+  {
+    use std::os::unix::fs::PermissionsExt;
+    file.set_permissions(fs::Permissions::from_mode(0o600))?;
+  }
+  file.write_all(data)
+}
+
+/// Where certificates and the ACME account key live on disk.
+pub struct CertStore {
+  dir: PathBuf,
+}
+
+impl CertStore {
+  pub fn new(dir: PathBuf) -> Self {
+    CertStore { dir }
+  }
+
+  pub fn open_default() -> Self {
+    CertStore::new(config::rws_dir())
+  }
+
+  fn host_dir(&self, host: &str) -> PathBuf {
+    self.dir.join("certs").join(config::file_name(host))
+  }
+
+  fn account_key_path(&self) -> PathBuf {
+    self.dir.join("acme").join("account.pk8")
+  }
+
+  fn save(
+    &self,
+    host: &str,
+    domains: &[String],
+    chain_pem: &str,
+    key_pem: &str,
+  ) -> io::Result<()> {
+    let dir = self.host_dir(host);
+    fs::create_dir_all(&dir)?;
+    write_private(&dir.join("key.pem"), key_pem.as_bytes())?;
+    fs::write(dir.join("cert.pem"), chain_pem)?;
+    let meta = CertMeta {
+      domains: domains.to_vec(),
+      issued_at: unix_now(),
+    };
+    fs::write(dir.join("meta.json"), serde_json::to_vec(&meta)?)
+  }
+
+  fn meta(&self, host: &str) -> Option<CertMeta> {
+    let bytes = fs::read(self.host_dir(host).join("meta.json")).ok()?;
+    serde_json::from_slice(&bytes).ok()
+  }
+
+  pub fn load(&self, host: &str) -> AcmeResult<CertifiedKey> {
+    let dir = self.host_dir(host);
+    let certs = pemfile::certs(&mut io::BufReader::new(fs::File::open(
+      dir.join("cert.pem"),
+    )?))
+    .map_err(|_| AcmeError(format!("bad certificate for {}", host)))?;
+    let key = pemfile::pkcs8_private_keys(&mut io::BufReader::new(
+      fs::File::open(dir.join("key.pem"))?,
+    ))
+    .ok()
+    .and_then(|keys| keys.into_iter().next())
+    .ok_or_else(|| AcmeError(format!("bad private key for {}", host)))?;
+    let signing_key = sign::any_supported_type(&key)
+      .map_err(|_| AcmeError(format!("unsupported key type for {}", host)))?;
+    Ok(CertifiedKey::new(certs, Arc::new(signing_key)))
+  }
+
+  /// True when there is no certificate yet, it doesn't cover `domains`, or
+  /// it is older than the renewal window.
+  pub fn needs_renewal(
+    &self,
+    host: &str,
+    domains: &[String],
+    config: &AcmeConfig,
+  ) -> bool {
+    match self.meta(host) {
+      Some(meta) => {
+        meta.domains != domains
+          || unix_now() >= meta.issued_at + config.renew_after_days * 86400
+      }
+      None => true,
+    }
+  }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Directory {
+  new_nonce: String,
+  new_account: String,
+  new_order: String,
+}
+
+struct Account {
+  client: reqwest::Client,
+  directory: Directory,
+  key: EcdsaKeyPair,
+  rng: SystemRandom,
+  kid: Option<String>,
+  nonce: Option<String>,
+}
+
+impl Account {
+  /// Loads (or creates) the account key and registers it with the server.
+  async fn open(config: &AcmeConfig, store: &CertStore) -> AcmeResult<Self> {
+    let mut client = reqwest::Client::builder();
+    if let Some(ca_file) = &config.ca_file {
+      let pem = fs::read(ca_file)?;
+      client =
+        client.add_root_certificate(reqwest::Certificate::from_pem(&pem)?);
+    }
+    let client = client.build()?;
+
+    let body = client
+      .get(&config.directory_url)
+      .send()
+      .await?
+      .bytes()
+      .await?;
+    let directory: Directory = serde_json::from_slice(&body)?;
+
+    let rng = SystemRandom::new();
+    let key_path = store.account_key_path();
+    let pkcs8 = match fs::read(&key_path) {
+      Ok(pkcs8) => pkcs8,
+      Err(_) => {
+        let pkcs8 =
+          EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &rng)?;
+        fs::create_dir_all(key_path.parent().unwrap())?;
+        write_private(&key_path, pkcs8.as_ref())?;
+        pkcs8.as_ref().to_vec()
+      }
+    };
+    let key =
+      EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &pkcs8)
+        .map_err(|_| {
+          AcmeError(format!("bad account key {}", key_path.display()))
+        })?;
+
+    let mut account = Account {
+      client,
+      directory,
+      key,
+      rng,
+      kid: None,
+      nonce: None,
+    };
+
+    let mut payload = json!({ "termsOfServiceAgreed": true });
+    if let Some(email) = &config.email {
+      payload["contact"] = json!([format!("mailto:{}", email)]);
+    }
+    let new_account = account.directory.new_account.clone();
+    let res = account.post(&new_account, Some(&payload)).await?;
+    account.kid = Some(location(&res)?);
+    Ok(account)
+  }
+
+  fn jwk(&self) -> Value {
+    // Uncompressed point: 0x04 || x || y
+    let point = self.key.public_key().as_ref();
+    json!({
+      "crv": "P-256",
+      "kty": "EC",
+      "x": b64(&point[1..33]),
+      "y": b64(&point[33..65]),
+    })
+  }
+
+  /// RFC 7638 thumbprint of the account key, used in key authorizations.
+  fn thumbprint(&self) -> String {
+    let jwk = self.jwk();
+    // Members in lexicographic order, no whitespace.
+    let canonical = format!(
+      r#"{{"crv":"P-256","kty":"EC","x":{},"y":{}}}"#,
+      jwk["x"], jwk["y"]
+    );
+    b64(
+      ring::digest::digest(&ring::digest::SHA256, canonical.as_bytes())
+        .as_ref(),
+    )
+  }
+
+  async fn nonce(&mut self) -> AcmeResult<String> {
+    if let Some(nonce) = self.nonce.take() {
+      return Ok(nonce);
+    }
+    let res = self.client.head(&self.directory.new_nonce).send().await?;
+    replay_nonce(&res).ok_or_else(|| AcmeError("server sent no nonce".into()))
+  }
+
+  /// Signed POST; `None` as payload is a POST-as-GET.
+  async fn post(
+    &mut self,
+    url: &str,
+    payload: Option<&Value>,
+  ) -> AcmeResult<reqwest::Response> {
+    let mut retried = false;
+    loop {
+      let mut protected = json!({
+        "alg": "ES256",
+        "nonce": self.nonce().await?,
+        "url": url,
+      });
+      match &self.kid {
+        Some(kid) => protected["kid"] = json!(kid),
+        None => protected["jwk"] = self.jwk(),
+      }
+      let protected = b64(&serde_json::to_vec(&protected)?);
+      let payload = match payload {
+        Some(payload) => b64(&serde_json::to_vec(payload)?),
+        None => String::new(),
+      };
+      let signature = self
+        .key
+        .sign(&self.rng, format!("{}.{}", protected, payload).as_bytes())?;
+      let body = json!({
+        "protected": protected,
+        "payload": payload,
+        "signature": b64(signature.as_ref()),
+      });
+
+      let res = self
+        .client
+        .post(url)
+        .header("Content-Type", "application/jose+json")
+        .body(serde_json::to_vec(&body)?)
+        .send()
+        .await?;
+      self.nonce = replay_nonce(&res);
+      if res.status().is_success() {
+        return Ok(res);
+      }
+
+      let status = res.status();
+      let problem: Value =
+        serde_json::from_slice(&res.bytes().await?).unwrap_or(Value::Null);
+      if !retried && problem["type"] == "urn:ietf:params:acme:error:badNonce" {
+        retried = true;
+        continue;
+      }
+      return Err(AcmeError(format!("{} {}: {}", url, status, problem)));
+    }
+  }
+
+  async fn get_json(&mut self, url: &str) -> AcmeResult<Value> {
+    let res = self.post(url, None).await?;
+    Ok(serde_json::from_slice(&res.bytes().await?)?)
+  }
+
+  /// Polls an order or authorization until it reaches `status`.
+  async fn poll(&mut self, url: &str, status: &str) -> AcmeResult<Value> {
+    for _ in 0..POLL_ATTEMPTS {
+      let object = self.get_json(url).await?;
+      if object["status"] == status {
+        return Ok(object);
+      }
+      if object["status"] == "invalid" {
+        return Err(AcmeError(format!("{} is invalid: {}", url, object)));
+      }
+      delay_for(POLL_INTERVAL).await;
+    }
+    Err(AcmeError(format!("timed out waiting for {}", url)))
+  }
+}
+
+fn replay_nonce(res: &reqwest::Response) -> Option<String> {
+  res
+    .headers()
+    .get("Replay-Nonce")
+    .and_then(|v| v.to_str().ok())
+    .map(String::from)
+}
+
+fn location(res: &reqwest::Response) -> AcmeResult<String> {
+  res
+    .headers()
+    .get("Location")
+    .and_then(|v| v.to_str().ok())
+    .map(String::from)
+    .ok_or_else(|| AcmeError("response has no Location header".into()))
+}
+
+/// Orders a certificate for `domains` and saves it under `host`.
+pub async fn issue(
+  config: &AcmeConfig,
+  store: &CertStore,
+  host: &str,
+  domains: &[String],
+) -> AcmeResult<()> {
+  let mut account = Account::open(config, store).await?;
+
+  let identifiers: Vec<Value> = domains
+    .iter()
+    .map(|d| json!({ "type": "dns", "value": d }))
+    .collect();
+  let new_order = account.directory.new_order.clone();
+  let res = account
+    .post(&new_order, Some(&json!({ "identifiers": identifiers })))
+    .await?;
+  let order_url = location(&res)?;
+  let order: Value = serde_json::from_slice(&res.bytes().await?)?;
+
+  let authorizations: Vec<String> =
+    serde_json::from_value(order["authorizations"].clone())?;
+  for authz_url in authorizations {
+    let authz = account.get_json(&authz_url).await?;
+    if authz["status"] == "valid" {
+      continue;
+    }
+    let challenge = authz["challenges"]
+      .as_array()
+      .and_then(|c| c.iter().find(|c| c["type"] == "http-01"))
+      .cloned()
+      .ok_or_else(|| AcmeError(format!("no http-01 challenge: {}", authz)))?;
+    let token = challenge["token"].as_str().unwrap_or_default().to_string();
+    let url = challenge["url"].as_str().unwrap_or_default().to_string();
+
+    let key_authorization = format!("{}.{}", token, account.thumbprint());
+    CHALLENGES
+      .write()
+      .unwrap()
+      .insert(token.clone(), key_authorization);
+    let result = async {
+      account.post(&url, Some(&json!({}))).await?;
+      account.poll(&authz_url, "valid").await
+    }
+    .await;
+    CHALLENGES.write().unwrap().remove(&token);
+    result?;
+  }
+
+  let mut params = rcgen::CertificateParams::new(domains.to_vec());
+  params.distinguished_name = rcgen::DistinguishedName::new();
+  let cert = rcgen::Certificate::from_params(params)?;
+  let csr = cert.serialize_request_der()?;
+  let finalize = order["finalize"].as_str().unwrap_or_default().to_string();
+  account
+    .post(&finalize, Some(&json!({ "csr": b64(&csr) })))
+    .await?;
+
+  let order = account.poll(&order_url, "valid").await?;
+  let cert_url = order["certificate"]
+    .as_str()
+    .ok_or_else(|| AcmeError("order has no certificate".into()))?
+    .to_string();
+  let chain_pem = account.post(&cert_url, None).await?.text().await?;
+
+  store.save(host, domains, &chain_pem, &cert.serialize_private_key_pem())?;
+  Ok(())
+}
+
+/// Loads certificates already on disk into the resolver.
+pub fn load_all(store: &CertStore) {
+  for host in &config::get().hosts {
+    if RESOLVER.contains(&host.name) {
+      continue;
+    }
+    if let Ok(key) = store.load(&host.name) {
+      RESOLVER.insert(&host.domains(), key);
+    }
+  }
+}
+
+async fn renew_all(store: &CertStore) {
+  let config = config::get();
+  let acme = match &config.acme {
+    Some(acme) => acme,
+    None => return,
+  };
+
+  for host in config.hosts.iter().filter(|h| h.acme) {
+    let domains = host.domains();
+    if !store.needs_renewal(&host.name, &domains, acme) {
+      continue;
+    }
+    info!("requesting certificate for {}", domains.join(", "));
+    let loaded = match issue(acme, store, &host.name, &domains).await {
+      Ok(()) => store.load(&host.name),
+      Err(e) => Err(e),
+    };
+    match loaded {
+      Ok(key) => RESOLVER.insert(&domains, key),
+      Err(e) => error!("certificate for {} failed: {}", host.name, e),
+    }
+  }
+}
+
+/// Keeps host certificates issued and fresh for the life of the process.
+/// Hosts added to or removed from config.json are picked up within
+/// `CONFIG_CHECK_INTERVAL`.
+pub async fn run() {
+  let store = CertStore::open_default();
+  let mut seen: Option<Arc<Config>> = None;
+  let mut renewed: Option<Instant> = None;
+  loop {
+    let config = config::get();
+    let changed = match &seen {
+      Some(seen) => !Arc::ptr_eq(seen, &config),
+      None => true,
+    };
+    if changed {
+      let domains: Vec<String> =
+        config.hosts.iter().flat_map(|h| h.domains()).collect();
+      RESOLVER.retain(&domains);
+      load_all(&store);
+    }
+    let due = renewed.map_or(true, |at| at.elapsed() >= RENEW_CHECK_INTERVAL);
+    if changed || due {
+      renew_all(&store).await;
+      renewed = Some(Instant::now());
+    }
+    seen = Some(config);
+    delay_for(CONFIG_CHECK_INTERVAL).await;
+  }
+}
+
+/// Issues a certificate from a local pebble
+/// (https://github.com/letsencrypt/pebble) when `RWS_TEST_PEBBLE` holds its
+/// directory URL and `RWS_TEST_PEBBLE_CA` its root certificate.  Start
+/// pebble with `PEBBLE_VA_ALWAYS_VALID=1` so it doesn't have to reach back.
+#[tokio::test]
+async fn acme_pebble_test() {
+  let directory_url = match std::env::var("RWS_TEST_PEBBLE") {
+    Ok(url) => url,
+    Err(_) => return,
+  };
+  let dir = tempfile::TempDir::new().unwrap();
+  let store = CertStore::new(dir.path().to_path_buf());
+  let config = AcmeConfig {
+    directory_url,
+    email: Some("admin@example.com".to_string()),
+    ca_file: std::env::var("RWS_TEST_PEBBLE_CA").ok().map(PathBuf::from),
+    renew_after_days: 60,
+  };
+  let domains = vec!["example.com".to_string(), "www.example.com".to_string()];
+
+  assert!(store.needs_renewal("example.com", &domains, &config));
+  issue(&config, &store, "example.com", &domains)
+    .await
+    .unwrap();
+  assert!(!store.needs_renewal("example.com", &domains, &config));
+
+  let resolver = CertResolver::default();
+  resolver.insert(&domains, store.load("example.com").unwrap());
+  assert!(resolver.contains("WWW.example.com"));
+}
+
+#[test]
+fn cert_store_test() {
+  let dir = tempfile::TempDir::new().unwrap();
+  let store = CertStore::new(dir.path().to_path_buf());
+  let config = AcmeConfig {
+    directory_url: String::new(),
+    email: None,
+    ca_file: None,
+    renew_after_days: 60,
+  };
+  let domains = vec!["example.com".to_string(), "www.example.com".to_string()];
+  assert!(store.needs_renewal("example.com", &domains, &config));
+
+  let cert = rcgen::generate_simple_self_signed(domains.clone()).unwrap();
+  let chain_pem = cert.serialize_pem().unwrap();
+  let key_pem = cert.serialize_private_key_pem();
+  store
+    .save("example.com", &domains, &chain_pem, &key_pem)
+    .unwrap();
+  assert!(!store.needs_renewal("example.com", &domains, &config));
+  assert!(store.needs_renewal("example.com", &domains[..1], &config));
+
+  let resolver = CertResolver::default();
+  resolver.insert(&domains, store.load("example.com").unwrap());
+  assert!(resolver.contains("WWW.example.com"));
+  resolver.retain(&domains[..1]);
+  assert!(resolver.contains("example.com"));
+  assert!(!resolver.contains("www.example.com"));
+
+  // Host names stay inside the store.
+  let certs = dir.path().join("certs");
+  assert_eq!(store.host_dir("../../etc"), certs.join("_.._etc"));
+  assert_eq!(store.host_dir("a\\b"), certs.join("a_b"));
+
+  #[cfg(unix)]
+  {
+    use std::os::unix::fs::PermissionsExt;
+    let key = store.host_dir("example.com").join("key.pem");
+    let mode = fs::metadata(key).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
+  }
+}
@@ -0,0 +1,1081 @@
+//! The control panel's JSON API, one group of routes per dashboard section:
+//!
+//! - Status: `GET /api/status`, process and isolate stats.
+//! - Accounts: `GET|POST /api/accounts`, `GET|PATCH|DELETE
+//!   /api/accounts/{id}`.
+//! - Hosts: `GET /api/hosts`, `PUT|DELETE /api/hosts/{name}`.
+//! - Platform: `GET /api/platform`, installed apps and runtime versions.
+//! - Settings: `GET|PUT /api/settings`, everything in config.json but the
+//!   hosts.
+//!
+//! - Audit: `GET /api/audit`, the admin actions recorded by `audit`.
+//!
+//! Admins are the users listed in config.json's `admins`.  On first run,
+//! while there are none, `POST /api/setup` creates the first one.  They sign
+//! in with `POST /api/login`, which starts a dashboard session: the
+//! `rws_admin` cookie, or a bearer token for scripts.  Sessions of app
+//! sign-ins don't reach the API, so the optional TOTP second factor
+//! (`/api/totp`) can't be sidestepped.  Requests authenticated by the cookie
+//! that change anything must echo the session's CSRF token, from
+//! `GET /api/session`, in `X-CSRF-Token`.  That only protects the API as
+//! long as no other code runs on the dashboard's origin, which is why the
+//! views of `control_panel` apps are kept in a sandboxed origin of their
+//! own (see `control_panel::panel_frame`): installing an app doesn't make
+//! it an admin.
+//!
+//! Changes are written to config.json and take effect right away, hosts
+//! included (proxy routes, protection and certificates follow config.json);
+//! only listen addresses wait for a restart.
+
+use crate::accounts::{self, AccountError, SessionKind, Token, UserPatch};
+use crate::audit;
+use crate::config::{self, Config, HostConfig};
+use crate::manifest::Manifest;
+use crate::package::{self, InstallOptions, Package, PackageError};
+use crate::protection;
+use crate::services;
+use crate::totp;
+use actix_web::dev::Payload;
+use actix_web::error::{BlockingError, ErrorForbidden, ErrorUnauthorized};
+use actix_web::http::{header, Method};
+use actix_web::{web, FromRequest, HttpMessage, HttpRequest, HttpResponse};
+use futures::future::{ready, Ready};
+use ring::digest;
+use serde_derive::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+/// Cookie holding the dashboard session.
+pub const ADMIN_COOKIE: &str = "rws_admin";
+/// Header carrying the CSRF token of a cookie session.
+pub const CSRF_HEADER: &str = "x-csrf-token";
+/// Largest `.rwsapp` package that can be uploaded.
+const MAX_PACKAGE: usize = 256 << 20;
+
+lazy_static! {
+  static ref STORE: Mutex<AdminStore> =
+    Mutex::new(AdminStore::open(config::rws_dir().join("admin")));
+}
+
+lazy_static! {
+  static ref STARTED: (Instant, u64) = (
+    Instant::now(),
+    SystemTime::now()
+      .duration_since(UNIX_EPOCH)
+      .unwrap()
+      .as_millis() as u64,
+  );
+}
+
+static ISOLATES: AtomicUsize = AtomicUsize::new(0);
+
+/// Records the time rws started, for the uptime in `/api/status`.
+pub fn mark_started() {
+  lazy_static::initialize(&STARTED);
+}
+
+/// Counts a running isolate for as long as it is alive.
+pub struct IsolateGuard(());
+
+impl IsolateGuard {
+  pub fn start() -> Self {
+    ISOLATES.fetch_add(1, Ordering::SeqCst);
+    IsolateGuard(())
+  }
+}
+
+impl Drop for IsolateGuard {
+  fn drop(&mut self) {
+    ISOLATES.fetch_sub(1, Ordering::SeqCst);
+  }
+}
+
+fn now_millis() -> u64 {
+  SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .unwrap()
+    .as_millis() as u64
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct TotpState {
+  secret: String,
+  /// Off until a first code confirms the authenticator app has the secret.
+  enabled: bool,
+  /// Codes of this step and earlier can't be used again.
+  last_step: u64,
+}
+
+#[derive(Default, Deserialize, Serialize)]
+#[serde(default)]
+struct AdminFiles {
+  /// Expiry of the dashboard sessions, by session id.
+  sessions: HashMap<String, u64>,
+  /// Second factors, by user id.
+  totp: HashMap<String, TotpState>,
+}
+
+/// Dashboard sessions and second factors, kept in
+/// `<rws dir>/admin/admin.json`.
+pub struct AdminStore {
+  path: Option<PathBuf>,
+  data: AdminFiles,
+}
+
+impl AdminStore {
+  pub fn open(dir: PathBuf) -> Self {
+    let path = dir.join("admin.json");
+    let data = match fs::read_to_string(&path) {
+      Ok(text) => serde_json::from_str(&text).unwrap_or_else(|e| {
+        error!("invalid {}: {}", path.display(), e);
+        AdminFiles::default()
+      }),
+      Err(_) => AdminFiles::default(),
+    };
+    AdminStore {
+      path: Some(path),
+      data,
+    }
+  }
+
+  pub fn in_memory() -> Self {
+    AdminStore {
+      path: None,
+      data: AdminFiles::default(),
+    }
+  }
+
+  fn persist(&self) -> io::Result<()> {
+    let path = match &self.path {
+      Some(path) => path,
+      None => return Ok(()),
+    };
+    fs::create_dir_all(path.parent().unwrap())?;
+    let tmp = path.with_extension("json.tmp");
+    fs::write(&tmp, serde_json::to_vec(&self.data)?)?;
+    fs::rename(&tmp, path)
+  }
+
+  pub fn add_session(&mut self, token: &Token, now: u64) -> io::Result<()> {
+    self.data.sessions.retain(|_, expires| *expires > now);
+    let id = token.session_id.clone();
+    self.data.sessions.insert(id, token.expires);
+    self.persist()
+  }
+
+  pub fn has_session(&self, token: &Token, now: u64) -> bool {
+    match self.data.sessions.get(&token.session_id) {
+      Some(expires) => *expires > now,
+      None => false,
+    }
+  }
+
+  pub fn end_session(&mut self, token: &Token) -> io::Result<()> {
+    self.data.sessions.remove(&token.session_id);
+    self.persist()
+  }
+
+  pub fn totp_enabled(&self, user_id: &str) -> bool {
+    self.data.totp.get(user_id).map_or(false, |t| t.enabled)
+  }
+
+  /// Starts setting up a second factor and returns its secret.  An enabled
+  /// one stays in place until it is disabled.
+  pub fn start_totp(&mut self, user_id: &str) -> io::Result<Option<String>> {
+    if self.totp_enabled(user_id) {
+      return Ok(None);
+    }
+    let secret = totp::new_secret();
+    let state = TotpState {
+      secret: secret.clone(),
+      ..TotpState::default()
+    };
+    self.data.totp.insert(user_id.to_string(), state);
+    self.persist()?;
+    Ok(Some(secret))
+  }
+
+  /// Checks a code of the user's second factor, enabled or being set up.
+  /// Each code works once.
+  pub fn check_totp(
+    &mut self,
+    user_id: &str,
+    code: &str,
+    now: u64,
+  ) -> io::Result<bool> {
+    let state = match self.data.totp.get_mut(user_id) {
+      Some(state) => state,
+      None => return Ok(false),
+    };
+    match totp::verify(&state.secret, code, now / 1000) {
+      Some(step) if step > state.last_step => {
+        state.last_step = step;
+        self.persist()?;
+        Ok(true)
+      }
+      _ => Ok(false),
+    }
+  }
+
+  pub fn set_totp_enabled(
+    &mut self,
+    user_id: &str,
+    enabled: bool,
+  ) -> io::Result<()> {
+    if enabled {
+      if let Some(state) = self.data.totp.get_mut(user_id) {
+        state.enabled = true;
+      }
+    } else {
+      self.data.totp.remove(user_id);
+    }
+    self.persist()
+  }
+}
+
+/// The session secret of a request and whether it came in the cookie.
+fn admin_secret(req: &HttpRequest) -> Option<(String, bool)> {
+  let authorization = req
+    .headers()
+    .get(header::AUTHORIZATION)
+    .and_then(|v| v.to_str().ok());
+  if let Some(secret) = accounts::request_token(None, authorization) {
+    return Some((secret, false));
+  }
+  req
+    .cookie(ADMIN_COOKIE)
+    .map(|cookie| (cookie.value().to_string(), true))
+}
+
+/// The CSRF token of a session, derived from its secret.
+pub fn csrf_token(secret: &str) -> String {
+  let input = format!("rws-csrf:{}", secret);
+  digest::digest(&digest::SHA256, input.as_bytes())
+    .as_ref()
+    .iter()
+    .take(16)
+    .map(|b| format!("{:02x}", b))
+    .collect()
+}
+
+fn is_safe(method: &Method) -> bool {
+  *method == Method::GET
+    || *method == Method::HEAD
+    || *method == Method::OPTIONS
+}
+
+fn peer_ip(req: &HttpRequest) -> Option<String> {
+  req.peer_addr().map(|addr| addr.ip().to_string())
+}
+
+/// A request made by a control panel admin.
+pub struct Admin {
+  pub token: Token,
+  pub ip: Option<String>,
+  secret: String,
+}
+
+impl Admin {
+  /// Records an action in the audit log.
+  pub fn audit(&self, action: &str, target: &str, details: Value) {
+    audit::record(&self.token, self.ip.clone(), action, target, details);
+  }
+
+  fn authenticate(req: &HttpRequest) -> Result<Admin, actix_web::Error> {
+    let (secret, from_cookie) =
+      admin_secret(req).ok_or_else(|| ErrorUnauthorized("sign in required"))?;
+    let token = accounts::session(&secret)
+      .filter(|token| STORE.lock().unwrap().has_session(token, now_millis()))
+      .ok_or_else(|| ErrorUnauthorized("sign in required"))?;
+    if !config::get().admins.contains(&token.user.id) {
+      return Err(ErrorForbidden("admins only"));
+    }
+    if from_cookie && !is_safe(req.method()) {
+      let sent = req.headers().get(CSRF_HEADER).and_then(|v| v.to_str().ok());
+      if sent != Some(csrf_token(&secret).as_str()) {
+        return Err(ErrorForbidden("missing or wrong CSRF token"));
+      }
+    }
+    Ok(Admin {
+      token,
+      ip: peer_ip(req),
+      secret,
+    })
+  }
+}
+
+impl FromRequest for Admin {
+  type Error = actix_web::Error;
+  type Future = Ready<Result<Self, Self::Error>>;
+  type Config = ();
+
+  fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+    ready(Admin::authenticate(req))
+  }
+}
+
+pub fn routes(cfg: &mut web::ServiceConfig) {
+  cfg
+    .route("/api/session", web::get().to(session))
+    .route("/api/setup", web::post().to(setup))
+    .route("/api/login", web::post().to(login))
+    .route("/api/logout", web::post().to(logout))
+    .route("/api/totp", web::post().to(start_totp))
+    .route("/api/totp/enable", web::post().to(enable_totp))
+    .route("/api/totp/disable", web::post().to(disable_totp))
+    .route("/api/audit", web::get().to(audit_log))
+    .route("/api/status", web::get().to(status))
+    .route("/api/accounts", web::get().to(account_list))
+    .route("/api/accounts", web::post().to(create_account))
+    .route("/api/accounts/{id}", web::get().to(account))
+    .route("/api/accounts/{id}", web::patch().to(update_account))
+    .route("/api/accounts/{id}", web::delete().to(delete_account))
+    .route("/api/hosts", web::get().to(host_list))
+    .route("/api/hosts/{name}", web::put().to(save_host))
+    .route("/api/hosts/{name}", web::delete().to(delete_host))
+    .service(
+      web::resource("/api/hosts/{name}/apps")
+        .data(web::PayloadConfig::new(MAX_PACKAGE))
+        .route(web::post().to(install_app)),
+    )
+    .route("/api/platform", web::get().to(platform))
+    .route("/api/settings", web::get().to(settings))
+    .route("/api/settings", web::put().to(save_settings));
+}
+
+fn bad_request(message: impl ToString) -> HttpResponse {
+  HttpResponse::BadRequest().json(json!({ "error": message.to_string() }))
+}
+
+fn server_error(message: impl ToString) -> HttpResponse {
+  let body = json!({ "error": message.to_string() });
+  HttpResponse::InternalServerError().json(body)
+}
+
+fn account_error(e: AccountError) -> HttpResponse {
+  match e {
+    AccountError::Invalid(_) | AccountError::EmailTaken => bad_request(e),
+    AccountError::BadToken => HttpResponse::NotFound().finish(),
+    e => server_error(e),
+  }
+}
+
+fn unauthorized(message: &str, totp_required: bool) -> HttpResponse {
+  HttpResponse::Unauthorized().json(json!({
+    "error": message,
+    "totpRequired": totp_required,
+  }))
+}
+
+/// Checks a second factor code of `user_id`.  Codes are limited and locked
+/// out by account, whatever address they come from, under a key of their
+/// own: a right password clears the account's password failures.
+fn check_code(
+  store: &mut AdminStore,
+  ip: Option<IpAddr>,
+  user_id: &str,
+  code: &str,
+) -> Result<bool, HttpResponse> {
+  let key = format!("totp:{}", user_id);
+  if let Err(refusal) = protection::check_login(ip, &key) {
+    let body = json!({ "error": refusal.to_string() });
+    return Err(HttpResponse::TooManyRequests().json(body));
+  }
+  match store.check_totp(user_id, code, now_millis()) {
+    Ok(true) => {
+      protection::record_success(&key);
+      Ok(true)
+    }
+    Ok(false) => {
+      protection::record_failure(ip, Some(&key));
+      Ok(false)
+    }
+    Err(e) => Err(server_error(e)),
+  }
+}
+
+/// `Set-Cookie` value for a dashboard session, or for clearing it.  The
+/// dashboard is served over plain HTTP on loopback, so it isn't `Secure`.
+fn admin_cookie(secret: Option<&str>, max_age_secs: u64) -> String {
+  format!(
+    "{}={}; Path=/; Max-Age={}; HttpOnly; SameSite=Strict",
+    ADMIN_COOKIE,
+    secret.unwrap_or(""),
+    if secret.is_some() { max_age_secs } else { 0 }
+  )
+}
+
+/// Answers a successful sign-in: the cookie, or the secret for bearer use.
+fn signed_in(secret: &str, token: &Token) -> HttpResponse {
+  let mut body = json!({
+    "user": token.user,
+    "csrf": csrf_token(secret),
+    "totp": STORE.lock().unwrap().totp_enabled(&token.user.id),
+  });
+  if token.kind == SessionKind::Bearer {
+    body["secret"] = json!(secret);
+    return HttpResponse::Ok().json(body);
+  }
+  let max_age = token.expires.saturating_sub(now_millis()) / 1000;
+  HttpResponse::Ok()
+    .header(header::SET_COOKIE, admin_cookie(Some(secret), max_age))
+    .json(body)
+}
+
+/// Who is signed in, the CSRF token to send, and whether rws still needs
+/// its first admin.
+async fn session(req: HttpRequest) -> HttpResponse {
+  let setup = config::get().admins.is_empty();
+  match Admin::authenticate(&req) {
+    Ok(admin) => HttpResponse::Ok().json(json!({
+      "setup": setup,
+      "user": admin.token.user,
+      "csrf": csrf_token(&admin.secret),
+      "totp": STORE.lock().unwrap().totp_enabled(&admin.token.user.id),
+    })),
+    Err(_) => HttpResponse::Ok().json(json!({
+      "setup": setup,
+      "user": null,
+    })),
+  }
+}
+
+#[derive(Deserialize)]
+struct SetupRequest {
+  email: String,
+  password: String,
+  #[serde(default)]
+  name: Option<String>,
+}
+
+/// Creates the first admin.  Only works while there is none.
+async fn setup(
+  req: HttpRequest,
+  body: web::Json<SetupRequest>,
+) -> HttpResponse {
+  if !config::get().admins.is_empty() {
+    return HttpResponse::Forbidden().json(json!({
+      "error": "rws already has an admin",
+    }));
+  }
+  let SetupRequest {
+    email,
+    password,
+    name,
+  } = body.into_inner();
+  let user = match accounts::register(email, password.clone(), name).await {
+    Ok(user) => user,
+    Err(e) => return account_error(e),
+  };
+  let verified = UserPatch {
+    verified: Some(true),
+    ..UserPatch::default()
+  };
+  if let Err(e) = accounts::patch_user(&user.id, verified).await {
+    return account_error(e);
+  }
+  let mut config = (*config::get()).clone();
+  config.admins.push(user.id.clone());
+  if let Err(e) = config::save(config) {
+    return server_error(e);
+  }
+
+  let ip = req.peer_addr().map(|addr| addr.ip());
+  let kind = SessionKind::Cookie;
+  let (secret, token) =
+    match accounts::login(user.email.clone(), password, kind, ip).await {
+      Ok(session) => session,
+      Err(e) => return account_error(e),
+    };
+  if let Err(e) = STORE.lock().unwrap().add_session(&token, now_millis()) {
+    return server_error(e);
+  }
+  audit::record(&token, peer_ip(&req), "admin.setup", &user.email, json!({}));
+  signed_in(&secret, &token)
+}
+
+#[derive(Deserialize)]
+struct LoginRequest {
+  email: String,
+  password: String,
+  /// Code of the second factor, when the admin has one.
+  #[serde(default)]
+  code: Option<String>,
+  /// Start a bearer session for scripts rather than a cookie one.
+  #[serde(default)]
+  bearer: bool,
+}
+
+async fn login(
+  req: HttpRequest,
+  body: web::Json<LoginRequest>,
+) -> HttpResponse {
+  let LoginRequest {
+    email,
+    password,
+    code,
+    bearer,
+  } = body.into_inner();
+  let ip = req.peer_addr().map(|addr| addr.ip());
+  let kind = if bearer {
+    SessionKind::Bearer
+  } else {
+    SessionKind::Cookie
+  };
+  let (secret, token) =
+    match accounts::login(email.clone(), password, kind, ip).await {
+      Ok(session) => session,
+      Err(AccountError::Refused(refusal)) => {
+        let body = json!({ "error": refusal.to_string() });
+        return HttpResponse::TooManyRequests().json(body);
+      }
+      Err(AccountError::BadCredentials) | Err(AccountError::Disabled) => {
+        return unauthorized("wrong email or password", false)
+      }
+      Err(e) => return account_error(e),
+    };
+  // The password was right but this session isn't to be used unless the
+  // rest checks out too.
+  let refuse = |response: HttpResponse| {
+    let _ = accounts::logout(&secret);
+    response
+  };
+  if !config::get().admins.contains(&token.user.id) {
+    return refuse(HttpResponse::Forbidden().json(json!({
+      "error": "admins only",
+    })));
+  }
+
+  let mut store = STORE.lock().unwrap();
+  if store.totp_enabled(&token.user.id) {
+    let code = match code {
+      Some(code) => code,
+      None => return refuse(unauthorized("enter the code", true)),
+    };
+    match check_code(&mut store, ip, &token.user.id, &code) {
+      Ok(true) => {}
+      Ok(false) => return refuse(unauthorized("wrong code", true)),
+      Err(response) => return refuse(response),
+    }
+  }
+  if let Err(e) = store.add_session(&token, now_millis()) {
+    return refuse(server_error(e));
+  }
+  drop(store);
+
+  audit::record(&token, peer_ip(&req), "admin.login", &email, json!({}));
+  signed_in(&secret, &token)
+}
+
+async fn logout(admin: Admin) -> HttpResponse {
+  admin.audit("admin.logout", &admin.token.user.email, json!({}));
+  let _ = STORE.lock().unwrap().end_session(&admin.token);
+  if let Err(e) = accounts::logout(&admin.secret) {
+    return account_error(e);
+  }
+  HttpResponse::NoContent()
+    .header(header::SET_COOKIE, admin_cookie(None, 0))
+    .finish()
+}
+
+/// Starts setting up a second factor; `/api/totp/enable` with a code from
+/// the authenticator app turns it on.
+async fn start_totp(admin: Admin) -> HttpResponse {
+  let user = &admin.token.user;
+  match STORE.lock().unwrap().start_totp(&user.id) {
+    Ok(Some(secret)) => HttpResponse::Ok().json(json!({
+      "uri": totp::uri("RWS", &user.email, &secret),
+      "secret": secret,
+    })),
+    Ok(None) => bad_request("two-factor sign-in is already on"),
+    Err(e) => server_error(e),
+  }
+}
+
+#[derive(Deserialize)]
+struct CodeRequest {
+  code: String,
+}
+
+async fn set_totp(
+  admin: Admin,
+  body: web::Json<CodeRequest>,
+  enabled: bool,
+) -> HttpResponse {
+  let user_id = &admin.token.user.id;
+  let mut store = STORE.lock().unwrap();
+  if store.totp_enabled(user_id) == enabled {
+    let state = if enabled { "on" } else { "off" };
+    return bad_request(format!("two-factor sign-in is already {}", state));
+  }
+  let ip = admin.ip.as_deref().and_then(|ip| ip.parse().ok());
+  match check_code(&mut store, ip, user_id, &body.code) {
+    Ok(true) => {}
+    Ok(false) => return bad_request("wrong code"),
+    Err(response) => return response,
+  }
+  if let Err(e) = store.set_totp_enabled(user_id, enabled) {
+    return server_error(e);
+  }
+  drop(store);
+  let action = if enabled {
+    "totp.enable"
+  } else {
+    "totp.disable"
+  };
+  admin.audit(action, &admin.token.user.email, json!({}));
+  HttpResponse::NoContent().finish()
+}
+
+async fn enable_totp(
+  admin: Admin,
+  body: web::Json<CodeRequest>,
+) -> HttpResponse {
+  set_totp(admin, body, true).await
+}
+
+async fn disable_totp(
+  admin: Admin,
+  body: web::Json<CodeRequest>,
+) -> HttpResponse {
+  set_totp(admin, body, false).await
+}
+
+#[derive(Deserialize)]
+struct AuditQuery {
+  #[serde(default)]
+  limit: Option<usize>,
+  /// Only entries older than this time, for paging.
+  #[serde(default)]
+  before: Option<u64>,
+}
+
+async fn audit_log(_: Admin, query: web::Query<AuditQuery>) -> HttpResponse {
+  let limit = query.limit.unwrap_or(100).min(1000);
+  HttpResponse::Ok().json(audit::list(limit, query.before))
+}
+
+/// Resident memory and thread count from `/proc`, where there is one.
+fn process_stats() -> (Option<u64>, Option<u64>) {
+  let status = fs::read_to_string("/proc/self/status").unwrap_or_default();
+  let field = |name: &str| {
+    status
+      .lines()
+      .find(|line| line.starts_with(name))
+      .and_then(|line| line[name.len()..].split_whitespace().next())
+      .and_then(|value| value.parse::<u64>().ok())
+  };
+  (field("VmRSS:").map(|kb| kb * 1024), field("Threads:"))
+}
+
+async fn status(_: Admin) -> HttpResponse {
+  let (rss, threads) = process_stats();
+  let config = config::get();
+  HttpResponse::Ok().json(json!({
+    "pid": std::process::id(),
+    "started": STARTED.1,
+    "uptimeSecs": STARTED.0.elapsed().as_secs(),
+    "memoryBytes": rss,
+    "threads": threads,
+    "isolates": ISOLATES.load(Ordering::SeqCst),
+    "hosts": config.hosts.len(),
+    "users": accounts::users().len(),
+    "sessions": accounts::session_count(),
+    "services": services::list().len(),
+    "bans": protection::bans().len(),
+  }))
+}
+
+async fn account_list(_: Admin) -> HttpResponse {
+  HttpResponse::Ok().json(accounts::users())
+}
+
+async fn account(_: Admin, id: web::Path<String>) -> HttpResponse {
+  match accounts::user(&id) {
+    Some(user) => HttpResponse::Ok().json(user),
+    None => HttpResponse::NotFound().finish(),
+  }
+}
+
+#[derive(Deserialize)]
+struct NewAccount {
+  email: String,
+  password: String,
+  #[serde(default)]
+  name: Option<String>,
+  /// Skips email verification.
+  #[serde(default)]
+  verified: bool,
+}
+
+async fn create_account(
+  admin: Admin,
+  body: web::Json<NewAccount>,
+) -> HttpResponse {
+  let NewAccount {
+    email,
+    password,
+    name,
+    verified,
+  } = body.into_inner();
+  let user = match accounts::register(email, password, name).await {
+    Ok(user) => user,
+    Err(e) => return account_error(e),
+  };
+  admin.audit("account.create", &user.email, json!({ "id": user.id }));
+  if !verified {
+    return HttpResponse::Created().json(user);
+  }
+  let patch = UserPatch {
+    verified: Some(true),
+    ..UserPatch::default()
+  };
+  match accounts::patch_user(&user.id, patch).await {
+    Ok(user) => HttpResponse::Created().json(user),
+    Err(e) => account_error(e),
+  }
+}
+
+async fn update_account(
+  admin: Admin,
+  id: web::Path<String>,
+  patch: web::Json<UserPatch>,
+) -> HttpResponse {
+  let patch = patch.into_inner();
+  // Which fields changed, never the password itself.
+  let details = json!({
+    "id": *id,
+    "email": patch.email,
+    "name": patch.name,
+    "password": patch.password.is_some(),
+    "verified": patch.verified,
+    "disabled": patch.disabled,
+  });
+  match accounts::patch_user(&id, patch).await {
+    Ok(user) => {
+      admin.audit("account.update", &user.email, details);
+      HttpResponse::Ok().json(user)
+    }
+    Err(e) => account_error(e),
+  }
+}
+
+async fn delete_account(admin: Admin, id: web::Path<String>) -> HttpResponse {
+  if admin.token.user.id == *id {
+    return bad_request("admins can't delete their own account");
+  }
+  let email = accounts::user(&id).map(|u| u.email).unwrap_or_default();
+  match accounts::delete_user(&id) {
+    Ok(true) => {
+      admin.audit("account.delete", &email, json!({ "id": *id }));
+      let config = config::get();
+      if config.admins.contains(&id) {
+        // Otherwise a new account that got the same id would be an admin.
+        let mut config = (*config).clone();
+        config.admins.retain(|admin| *admin != *id);
+        return save_config(config);
+      }
+      HttpResponse::NoContent().finish()
+    }
+    Ok(false) => HttpResponse::NotFound().finish(),
+    Err(e) => account_error(e),
+  }
+}
+
+async fn host_list(_: Admin) -> HttpResponse {
+  HttpResponse::Ok().json(&config::get().hosts)
+}
+
+/// Checks that no other host answers for the names of `host`, its docs
+/// hosts included.
+fn check_host(config: &Config, host: &HostConfig) -> Result<(), String> {
+  if host.name.trim().is_empty() {
+    return Err("host name is empty".to_string());
+  }
+  let others = config.hosts.iter().filter(|h| h.name != host.name);
+  for other in others {
+    let taken = other.domains();
+    let conflict = host
+      .domains()
+      .into_iter()
+      .find(|d| taken.iter().any(|t| t.eq_ignore_ascii_case(d)));
+    if let Some(name) = conflict {
+      return Err(format!("{} is already served by {}", name, other.name));
+    }
+  }
+  Ok(())
+}
+
+fn save_config(config: Config) -> HttpResponse {
+  match config::save(config) {
+    Ok(()) => HttpResponse::NoContent().finish(),
+    Err(e) => server_error(e),
+  }
+}
+
+/// Adds the host or replaces the one of the same name.
+async fn save_host(
+  admin: Admin,
+  name: web::Path<String>,
+  host: web::Json<HostConfig>,
+) -> HttpResponse {
+  let mut host = host.into_inner();
+  host.name = name.into_inner();
+  let mut config = (*config::get()).clone();
+  if let Err(e) = check_host(&config, &host) {
+    return bad_request(e);
+  }
+  let details = json!(host);
+  let name = host.name.clone();
+  match config.hosts.iter_mut().find(|h| h.name == host.name) {
+    Some(existing) => *existing = host,
+    None => config.hosts.push(host),
+  }
+  let response = save_config(config);
+  if response.status().is_success() {
+    admin.audit("host.save", &name, details);
+  }
+  response
+}
+
+async fn delete_host(admin: Admin, name: web::Path<String>) -> HttpResponse {
+  let mut config = (*config::get()).clone();
+  let before = config.hosts.len();
+  config.hosts.retain(|h| h.name != *name);
+  if config.hosts.len() == before {
+    return HttpResponse::NotFound().finish();
+  }
+  let response = save_config(config);
+  if response.status().is_success() {
+    admin.audit("host.delete", &name, json!({}));
+  }
+  response
+}
+
+#[derive(Deserialize)]
+struct InstallQuery {
+  path: Option<String>,
+  #[serde(default)]
+  force: bool,
+}
+
+/// Installs the `.rwsapp` package in the body (see `package`).
+async fn install_app(
+  admin: Admin,
+  host: web::Path<String>,
+  query: web::Query<InstallQuery>,
+  body: web::Bytes,
+) -> HttpResponse {
+  let query = query.into_inner();
+  let options = InstallOptions {
+    host: host.into_inner(),
+    path: query.path,
+    force: query.force,
+  };
+  let result = web::block(move || {
+    let package = Package::read(&body)?;
+    package::install(&package, &options)
+  })
+  .await;
+  match result {
+    Ok(installed) => {
+      admin.audit("app.install", &installed.app, json!(installed));
+      HttpResponse::Ok().json(installed)
+    }
+    Err(BlockingError::Error(e)) => match e {
+      PackageError::Io(e) => server_error(e),
+      PackageError::AlreadyInstalled(_) | PackageError::PathTaken { .. } => {
+        HttpResponse::Conflict().json(json!({ "error": e.to_string() }))
+      }
+      e => bad_request(e),
+    },
+    Err(BlockingError::Canceled) => server_error("install canceled"),
+  }
+}
+
+async fn platform(_: Admin) -> HttpResponse {
+  let mut apps = Vec::new();
+  for host in &config::get().hosts {
+    for mount in &host.apps {
+      let dir = config::app_dir(&host.name, &mount.app);
+      let mut app = json!({
+        "host": host.name,
+        "app": mount.app,
+        "path": mount.path,
+      });
+      match Manifest::load(&dir.join("manifest.json")) {
+        Ok(manifest) => {
+          app["name"] = json!(manifest.name);
+          app["version"] = json!(manifest.version);
+          app["renderType"] = json!(manifest.render_type);
+          app["nativeDependencies"] = json!(manifest.native_dependencies);
+        }
+        Err(e) => app["error"] = json!(e.to_string()),
+      }
+      apps.push(app);
+    }
+  }
+  HttpResponse::Ok().json(json!({
+    "versions": {
+      "rws": env!("CARGO_PKG_VERSION"),
+      "deno": deno_cli::version::DENO,
+      "v8": deno_cli::version::v8(),
+      "typescript": deno_cli::version::TYPESCRIPT,
+    },
+    "apps": apps,
+  }))
+}
+
+/// The settings as shown to the dashboard: config.json without the hosts
+/// and the SMTP password.
+fn settings_json(config: &Config) -> Map<String, Value> {
+  let mut settings = match serde_json::to_value(config) {
+    Ok(Value::Object(settings)) => settings,
+    _ => Map::new(),
+  };
+  settings.remove("hosts");
+  if let Some(Value::Object(smtp)) = settings.get_mut("smtp") {
+    let set = smtp.get("password").map_or(false, |p| !p.is_null());
+    smtp.remove("password");
+    smtp.insert("passwordSet".to_string(), json!(set));
+  }
+  settings
+}
+
+/// Applies the top level keys of `patch` to `config`.  An SMTP password
+/// left out keeps the stored one.
+pub fn patch_settings(
+  config: &Config,
+  patch: Map<String, Value>,
+) -> Result<Config, serde_json::Error> {
+  let mut value = serde_json::to_value(config)?;
+  let old_password = config.smtp.as_ref().and_then(|s| s.password.clone());
+  for (key, mut new) in patch {
+    if key == "hosts" {
+      continue;
+    }
+    if let Value::Object(smtp) = &mut new {
+      if key == "smtp" {
+        smtp.remove("passwordSet");
+        let missing = smtp.get("password").map_or(true, Value::is_null);
+        if missing {
+          smtp.insert("password".to_string(), json!(old_password));
+        }
+      }
+    }
+    value[key.as_str()] = new;
+  }
+  serde_json::from_value(value)
+}
+
+async fn settings(_: Admin) -> HttpResponse {
+  HttpResponse::Ok().json(settings_json(&config::get()))
+}
+
+async fn save_settings(
+  admin: Admin,
+  patch: web::Json<Map<String, Value>>,
+) -> HttpResponse {
+  let old = config::get();
+  let patch = patch.into_inner();
+  let changed: Vec<&String> = patch.keys().collect();
+  let details = json!({ "keys": changed });
+  let config = match patch_settings(&old, patch.clone()) {
+    Ok(config) => config,
+    Err(e) => return bad_request(e),
+  };
+  let restart =
+    config.http_addr != old.http_addr || config.https_addr != old.https_addr;
+  let settings = settings_json(&config);
+  match config::save(config) {
+    Ok(()) => {
+      admin.audit("settings.save", "config.json", details);
+      HttpResponse::Ok().json(json!({
+        "settings": settings,
+        "restartRequired": restart,
+      }))
+    }
+    Err(e) => server_error(e),
+  }
+}
+
+#[test]
+fn admin_settings_test() {
+  let config: Config = serde_json::from_value(json!({
+    "hosts": [{"name": "example.com"}],
+    "smtp": {"host": "mail", "from": "rws@example.com", "password": "pw"},
+  }))
+  .unwrap();
+
+  let shown = settings_json(&config);
+  assert!(shown.get("hosts").is_none());
+  assert_eq!(shown["smtp"]["passwordSet"], json!(true));
+  assert!(shown["smtp"].get("password").is_none());
+
+  let mut patch = Map::new();
+  patch.insert("http_addr".to_string(), json!("0.0.0.0:80"));
+  patch.insert("smtp".to_string(), shown["smtp"].clone());
+  patch.insert("hosts".to_string(), json!([]));
+  let patched = patch_settings(&config, patch).unwrap();
+  assert_eq!(patched.http_addr, "0.0.0.0:80");
+  assert_eq!(patched.hosts.len(), 1);
+  assert_eq!(patched.smtp.unwrap().password.as_deref(), Some("pw"));
+
+  let mut bad = Map::new();
+  bad.insert("http_addr".to_string(), json!(80));
+  assert!(patch_settings(&config, bad).is_err());
+
+  let taken: HostConfig = serde_json::from_value(json!({
+    "name": "www.example.org",
+    "aliases": ["example.com"],
+  }))
+  .unwrap();
+  assert!(check_host(&config, &taken).is_err());
+}
+
+#[test]
+fn admin_store_test() {
+  let mut store = AdminStore::in_memory();
+  let token = Token {
+    session_id: "s1".to_string(),
+    kind: SessionKind::Cookie,
+    expires: 2_000,
+    user: accounts::UserInfo {
+      id: "u1".to_string(),
+      email: "admin@example.com".to_string(),
+      name: None,
+      verified: true,
+      disabled: false,
+      created: 0,
+    },
+  };
+  store.add_session(&token, 1_000).unwrap();
+  assert!(store.has_session(&token, 1_500));
+  assert!(!store.has_session(&token, 2_000));
+  store.end_session(&token).unwrap();
+  assert!(!store.has_session(&token, 1_500));
+
+  let secret = store.start_totp("u1").unwrap().unwrap();
+  assert!(!store.totp_enabled("u1"));
+  let now = 1_600_000_000_000;
+  let key = totp::base32_decode(&secret).unwrap();
+  let code = format!("{:06}", totp::code(&key, now / 1000 / totp::STEP_SECS));
+  assert!(store.check_totp("u1", &code, now).unwrap());
+  // A code only works once.
+  assert!(!store.check_totp("u1", &code, now).unwrap());
+  store.set_totp_enabled("u1", true).unwrap();
+  assert!(store.totp_enabled("u1"));
+  assert!(store.start_totp("u1").unwrap().is_none());
+
+  assert_eq!(csrf_token("secret"), csrf_token("secret"));
+  assert_ne!(csrf_token("secret"), csrf_token("other"));
+  assert_eq!(csrf_token("secret").len(), 32);
+}
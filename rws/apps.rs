@@ -0,0 +1,312 @@
+//! App isolates.
+//!
+//! Every app mounted in config.json gets an isolate of its own, on a thread
+//! of its own, with `RWS.context` set to its host and app.  The isolate
+//! runs `js/runtime.js` and then the default export of the app's
+//! `bin/install.ts`, called with `RWS` as on `rws app install` but with
+//! `RWS.installing` false; that is where apps register their services,
+//! views, components and channel listeners.  Requests reach the isolate
+//! through the listeners it opens, which are keyed by its `AppContext` (see
+//! `render`, `services`, `components` and `websocket`), and ops acting for
+//! an app take it from the context rather than from JS.
+//!
+//! `run` compares the running isolates with config.json every few seconds.
+//! New mounts are started, removed ones stopped, and apps whose manifest or
+//! install script changed are restarted.  An isolate that died is started
+//! again once `RESTART_DELAY` has passed since its last start.
+
+use crate::admin::IsolateGuard;
+use crate::config;
+use crate::ops;
+use deno_cli::flags::Flags;
+use deno_cli::global_state::GlobalState;
+use deno_cli::worker::MainWorker;
+use deno_core::v8;
+use deno_core::{ErrBox, ModuleSpecifier};
+use futures::future::{self, Either};
+use serde_derive::Serialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, UNIX_EPOCH};
+use tokio::sync::{mpsc, oneshot};
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(3);
+const RESTART_DELAY: Duration = Duration::from_secs(30);
+/// How long an isolate has to answer a probe before it is taken to be stuck.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// The host and app an isolate runs for.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize)]
+pub struct AppContext {
+  pub host: String,
+  pub app: String,
+}
+
+impl AppContext {
+  pub fn new(host: &str, app: &str) -> Self {
+    AppContext {
+      host: host.to_string(),
+      app: app.to_string(),
+    }
+  }
+}
+
+/// Where app isolates take one kind of request, such as page renders or
+/// service calls.
+pub struct Listeners<T>(Mutex<HashMap<AppContext, mpsc::UnboundedSender<T>>>);
+
+impl<T> Listeners<T> {
+  pub fn new() -> Self {
+    Listeners(Mutex::new(HashMap::new()))
+  }
+
+  /// Starts taking the requests for `context`; a later call replaces the
+  /// earlier listener.
+  pub fn listen(&self, context: &AppContext) -> mpsc::UnboundedReceiver<T> {
+    let (tx, rx) = mpsc::unbounded_channel();
+    self.0.lock().unwrap().insert(context.clone(), tx);
+    rx
+  }
+
+  /// Hands `request` to the app's isolate, or back when it isn't listening.
+  pub fn send(&self, context: &AppContext, request: T) -> Result<(), T> {
+    let mut listeners = self.0.lock().unwrap();
+    let listener = match listeners.get(context) {
+      Some(listener) => listener,
+      None => return Err(request),
+    };
+    match listener.send(request) {
+      Ok(()) => Ok(()),
+      Err(mpsc::error::SendError(request)) => {
+        listeners.remove(context);
+        Err(request)
+      }
+    }
+  }
+
+  /// Whether the isolate of `context` no longer gets back to its event
+  /// loop, as when a view or component loops synchronously: it can't even
+  /// answer `probe`, a request asking for no work, on `answer`.
+  pub async fn stuck<R>(
+    &self,
+    context: &AppContext,
+    probe: T,
+    answer: oneshot::Receiver<R>,
+  ) -> bool {
+    if self.send(context, probe).is_err() {
+      return false;
+    }
+    tokio::time::timeout(PROBE_TIMEOUT, answer).await.is_err()
+  }
+}
+
+type IsolateSlot = Arc<Mutex<Option<v8::IsolateHandle>>>;
+
+/// How `run` stops a serving isolate.
+pub struct Control {
+  stop: oneshot::Receiver<()>,
+  isolate: IsolateSlot,
+}
+
+/// An isolate started by `start`.
+pub struct Running {
+  stop: Option<oneshot::Sender<()>>,
+  isolate: IsolateSlot,
+  exited: Arc<AtomicBool>,
+  version: u64,
+  started: Instant,
+}
+
+impl Running {
+  pub fn stop(&mut self) {
+    if let Some(stop) = self.stop.take() {
+      let _ = stop.send(());
+    }
+    // In case it is stuck in a loop and never gets to see `stop`.
+    if let Some(isolate) = &*self.isolate.lock().unwrap() {
+      isolate.terminate_execution();
+    }
+  }
+}
+
+lazy_static! {
+  static ref RUNNING: Mutex<HashMap<AppContext, Running>> =
+    Mutex::new(HashMap::new());
+}
+
+/// Stops the isolate of `context` even in the middle of a script; `run`
+/// starts it again like any isolate that died.
+pub fn terminate(context: &AppContext) {
+  if let Some(running) = RUNNING.lock().unwrap().get_mut(context) {
+    running.stop();
+  }
+}
+
+/// Runs an app's isolate: `runtime.js`, then `bin/install.ts` if the app
+/// has one.  Without `control` this is the install run, which ends when
+/// the event loop runs dry and fails if the install script did.  A serving
+/// isolate runs until it is stopped or its script fails.
+pub fn run_isolate(
+  context: &AppContext,
+  dir: &Path,
+  control: Option<Control>,
+) -> Result<(), ErrBox> {
+  let installing = control.is_none();
+  let install = dir.join("bin").join("install.ts");
+  let main_module = ModuleSpecifier::resolve_url_or_path("./__$rws$app.js")?;
+  let flags = Flags {
+    allow_net: true,
+    read_allowlist: vec![dir.to_path_buf()],
+    write_allowlist: vec![dir.to_path_buf()],
+    // Type checked when the app was built, and `RWS` isn't declared.
+    no_check: true,
+    ..Flags::default()
+  };
+  let code = if !install.is_file() {
+    String::new()
+  } else {
+    let script =
+      ModuleSpecifier::resolve_url_or_path(&install.to_string_lossy())?;
+    // Install failures are kept for after the event loop has run dry; in a
+    // serving isolate they are unhandled and end it.
+    let catch = if installing {
+      ".catch((e) => { globalThis.installError = e; })"
+    } else {
+      ""
+    };
+    format!(
+      "import({})\n  \
+         .then((m) => typeof m.default == \"function\" && m.default(RWS))\n  \
+         {};\n",
+      json!(script.to_string()),
+      catch
+    )
+  };
+
+  let mut runtime = tokio::runtime::Builder::new()
+    .basic_scheduler()
+    .enable_all()
+    .build()?;
+  let local = tokio::task::LocalSet::new();
+  local.block_on(&mut runtime, async {
+    let global_state = GlobalState::new(flags)?;
+    let mut worker = MainWorker::create(global_state, main_module.clone())?;
+    {
+      let state = worker.state.clone();
+      ops::init(&mut worker.isolate, &state, context, installing);
+    }
+    worker.execute(include_str!("js/runtime.js"))?;
+    worker.execute_module_from_code(&main_module, code).await?;
+    match control {
+      None => {
+        (&mut *worker).await?;
+        worker.execute("if (globalThis.installError) throw installError;")
+      }
+      Some(Control { stop, isolate }) => {
+        let _isolate = IsolateGuard::start();
+        *isolate.lock().unwrap() = Some(worker.isolate.thread_safe_handle());
+        match future::select(&mut *worker, stop).await {
+          Either::Left((result, _)) => result,
+          Either::Right(_) => Ok(()),
+        }
+      }
+    }
+  })
+}
+
+/// Changes when the app is reinstalled or its install script edited.
+fn version(dir: &Path) -> u64 {
+  ["manifest.json", "bin/install.ts"]
+    .iter()
+    .filter_map(|file| fs::metadata(dir.join(file)).ok()?.modified().ok())
+    .filter_map(|time| time.duration_since(UNIX_EPOCH).ok())
+    .map(|since| since.as_millis() as u64)
+    .max()
+    .unwrap_or(0)
+}
+
+/// The installed apps config.json mounts, with their directories.
+fn mounted() -> Vec<(AppContext, PathBuf)> {
+  let config = config::get();
+  let mut apps: Vec<(AppContext, PathBuf)> = vec![];
+  for host in &config.hosts {
+    for mount in &host.apps {
+      let context = AppContext::new(&host.name, &mount.app);
+      let dir = config::app_dir(&host.name, &mount.app);
+      if dir.join("manifest.json").is_file()
+        && !apps.iter().any(|(known, _)| *known == context)
+      {
+        apps.push((context, dir));
+      }
+    }
+  }
+  apps
+}
+
+/// Runs the isolate of the app in `dir` on a thread of its own.
+pub fn start(context: AppContext, dir: PathBuf) -> Running {
+  let (stop, stopped) = oneshot::channel();
+  let isolate = IsolateSlot::default();
+  let exited = Arc::new(AtomicBool::new(false));
+  let running = Running {
+    stop: Some(stop),
+    isolate: isolate.clone(),
+    exited: exited.clone(),
+    version: version(&dir),
+    started: Instant::now(),
+  };
+  let control = Control {
+    stop: stopped,
+    isolate,
+  };
+  let name = format!("{} on {}", context.app, context.host);
+  let spawned = thread::Builder::new().name(name).spawn({
+    let exited = exited.clone();
+    move || {
+      if let Err(e) = run_isolate(&context, &dir, Some(control)) {
+        error!("app {} on {} stopped: {}", context.app, context.host, e);
+      }
+      exited.store(true, Ordering::SeqCst);
+    }
+  });
+  if let Err(e) = spawned {
+    error!("could not start an isolate: {}", e);
+    exited.store(true, Ordering::SeqCst);
+  }
+  running
+}
+
+/// Brings the running isolates in line with config.json.
+fn check() {
+  let mounted = mounted();
+  let mut running = RUNNING.lock().unwrap();
+  running.retain(|context, app| {
+    let current = mounted
+      .iter()
+      .any(|(known, dir)| known == context && version(dir) == app.version);
+    let dead = app.exited.load(Ordering::SeqCst)
+      && app.started.elapsed() >= RESTART_DELAY;
+    let keep = current && !dead;
+    if !keep {
+      app.stop();
+    }
+    keep
+  });
+  for (context, dir) in mounted {
+    if !running.contains_key(&context) {
+      running.insert(context.clone(), start(context, dir));
+    }
+  }
+}
+
+/// Keeps an isolate running for every mounted app.
+pub async fn run() {
+  loop {
+    check();
+    tokio::time::delay_for(CHECK_INTERVAL).await;
+  }
+}
@@ -0,0 +1,133 @@
+//! Audit log of control panel actions.
+//!
+//! Every change an admin makes (host added, app installed, setting changed,
+//! sign-ins...) is appended as one JSON line to
+//! `<rws dir>/admin/audit.log`.  The log is only ever appended to; the
+//! control panel reads it newest first.
+
+use crate::accounts::Token;
+use crate::config;
+use serde_derive::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fs::{self, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+lazy_static! {
+  static ref LOG: Mutex<AuditLog> = {
+    let path = config::rws_dir().join("admin").join("audit.log");
+    Mutex::new(AuditLog::new(path))
+  };
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditEntry {
+  /// Milliseconds since the epoch.
+  pub time: u64,
+  pub user_id: String,
+  pub email: String,
+  /// What was done, e.g. "host.save" or "settings.save".
+  pub action: String,
+  /// What it was done to, e.g. the host name.
+  pub target: String,
+  #[serde(default)]
+  pub details: Value,
+  #[serde(default)]
+  pub ip: Option<String>,
+}
+
+pub struct AuditLog {
+  path: PathBuf,
+}
+
+impl AuditLog {
+  pub fn new(path: PathBuf) -> Self {
+    AuditLog { path }
+  }
+
+  pub fn append(&self, entry: &AuditEntry) -> io::Result<()> {
+    fs::create_dir_all(self.path.parent().unwrap())?;
+    let mut line = serde_json::to_vec(entry)?;
+    line.push(b'\n');
+    let mut file = OpenOptions::new()
+      .create(true)
+      .append(true)
+      .open(&self.path)?;
+    file.write_all(&line)
+  }
+
+  /// Up to `limit` entries older than `before`, newest first.
+  pub fn read(&self, limit: usize, before: Option<u64>) -> Vec<AuditEntry> {
+    let file = match fs::File::open(&self.path) {
+      Ok(file) => file,
+      Err(_) => return Vec::new(),
+    };
+    let mut entries: Vec<AuditEntry> = BufReader::new(file)
+      .lines()
+      .filter_map(|line| serde_json::from_str(&line.ok()?).ok())
+      .filter(|e: &AuditEntry| before.map_or(true, |before| e.time < before))
+      .collect();
+    entries.reverse();
+    entries.truncate(limit);
+    entries
+  }
+}
+
+/// Records an action of the admin signed in with `token`.  Failing to write
+/// the log is reported but doesn't undo the action.
+pub fn record(
+  token: &Token,
+  ip: Option<String>,
+  action: &str,
+  target: &str,
+  details: Value,
+) {
+  let entry = AuditEntry {
+    time: SystemTime::now()
+      .duration_since(UNIX_EPOCH)
+      .unwrap()
+      .as_millis() as u64,
+    user_id: token.user.id.clone(),
+    email: token.user.email.clone(),
+    action: action.to_string(),
+    target: target.to_string(),
+    details,
+    ip,
+  };
+  if let Err(e) = LOG.lock().unwrap().append(&entry) {
+    error!("could not write audit log: {}", e);
+  }
+}
+
+pub fn list(limit: usize, before: Option<u64>) -> Vec<AuditEntry> {
+  LOG.lock().unwrap().read(limit, before)
+}
+
+#[test]
+fn audit_test() {
+  let dir = tempfile::TempDir::new().unwrap();
+  let log = AuditLog::new(dir.path().join("admin").join("audit.log"));
+  assert!(log.read(10, None).is_empty());
+
+  for time in 1..=3 {
+    log
+      .append(&AuditEntry {
+        time,
+        user_id: "u1".to_string(),
+        email: "admin@example.com".to_string(),
+        action: "host.save".to_string(),
+        target: format!("host{}", time),
+        details: Value::Null,
+        ip: None,
+      })
+      .unwrap();
+  }
+  let targets = |entries: Vec<AuditEntry>| {
+    entries.into_iter().map(|e| e.target).collect::<Vec<_>>()
+  };
+  assert_eq!(targets(log.read(2, None)), vec!["host3", "host2"]);
+  assert_eq!(targets(log.read(10, Some(3))), vec!["host2", "host1"]);
+}
@@ -0,0 +1,81 @@
+// Embeds the control panel into the binary.  The files of control-panel/src
+// (without the TypeScript sources) are overlaid with the built ones in
+// control-panel/dst, where an empty file is one that hasn't been built yet.
+// The result is `control_panel_assets.rs` in OUT_DIR, included by
+// `control_panel`.
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+fn collect(
+  root: &Path,
+  dir: &Path,
+  built: bool,
+  files: &mut BTreeMap<String, PathBuf>,
+) {
+  let entries = match fs::read_dir(dir) {
+    Ok(entries) => entries,
+    Err(_) => return,
+  };
+  for entry in entries.filter_map(|e| e.ok()) {
+    let path = entry.path();
+    if path.is_dir() {
+      collect(root, &path, built, files);
+      continue;
+    }
+    let name = path.to_string_lossy();
+    if !built && (name.ends_with(".ts") || name.ends_with(".tsx")) {
+      continue;
+    }
+    if built && fs::metadata(&path).map(|m| m.len() == 0).unwrap_or(true) {
+      continue;
+    }
+    println!("cargo:rerun-if-changed={}", path.display());
+    let relative = path.strip_prefix(root).unwrap();
+    let key = relative
+      .components()
+      .map(|c| c.as_os_str().to_string_lossy().into_owned())
+      .collect::<Vec<_>>()
+      .join("/");
+    files.insert(key, path);
+  }
+}
+
+/// FNV-1a, for ETags.
+fn hash(bytes: &[u8]) -> u64 {
+  bytes.iter().fold(0xcbf2_9ce4_8422_2325, |h, b| {
+    (h ^ u64::from(*b)).wrapping_mul(0x0100_0000_01b3)
+  })
+}
+
+fn main() {
+  let manifest_dir = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap());
+  let panel = manifest_dir.join("..").join("control-panel");
+  println!("cargo:rerun-if-changed={}", panel.join("src").display());
+  println!("cargo:rerun-if-changed={}", panel.join("dst").display());
+
+  let mut files = BTreeMap::new();
+  collect(&panel.join("src"), &panel.join("src"), false, &mut files);
+  collect(&panel.join("dst"), &panel.join("dst"), true, &mut files);
+
+  let mut out = String::from(
+    "/// `(path, contents, hash)` of every dashboard file.\n\
+     pub static ASSETS: &[(&str, &[u8], u64)] = &[\n",
+  );
+  for (key, path) in &files {
+    let bytes = fs::read(path).unwrap();
+    let path = path.canonicalize().unwrap();
+    out.push_str(&format!(
+      "  ({:?}, include_bytes!({:?}), {:#x}),\n",
+      key,
+      path,
+      hash(&bytes)
+    ));
+  }
+  out.push_str("];\n");
+
+  let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
+  fs::write(out_dir.join("control_panel_assets.rs"), out).unwrap();
+}
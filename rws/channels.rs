@@ -0,0 +1,265 @@
+//! In-process pub/sub for apps.
+//!
+//! Channels are named per host and app, so two apps can both use "orders"
+//! without hearing each other.  Subscribers are isolate inboxes (one per
+//! app isolate, see `ops::channels`) and `/ws` clients.  Publishing
+//! numbers the message and hands it to every subscriber while holding the
+//! bus lock, so all subscribers see a channel's messages in the same order.
+
+use crate::websocket::{AppSocket, Outgoing};
+use actix::Addr;
+use serde_derive::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use tokio::sync::mpsc;
+
+lazy_static! {
+  static ref BUS: Mutex<Bus> = Mutex::new(Bus::default());
+}
+
+static NEXT_INBOX: AtomicUsize = AtomicUsize::new(1);
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub struct ChannelKey {
+  #[serde(default)]
+  pub host: Option<String>,
+  #[serde(default)]
+  pub app: Option<String>,
+  pub name: String,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Publication {
+  pub channel: ChannelKey,
+  /// Position in the channel, starting at 1.
+  pub seq: u64,
+  pub data: Value,
+}
+
+/// Where an isolate receives the messages of every channel it listens to.
+pub struct Inbox {
+  pub id: usize,
+  pub messages: mpsc::UnboundedReceiver<Publication>,
+}
+
+impl Inbox {
+  pub fn new() -> (Inbox, mpsc::UnboundedSender<Publication>) {
+    let (tx, rx) = mpsc::unbounded_channel();
+    let inbox = Inbox {
+      id: NEXT_INBOX.fetch_add(1, Ordering::Relaxed),
+      messages: rx,
+    };
+    (inbox, tx)
+  }
+}
+
+enum Subscriber {
+  Inbox(usize, mpsc::UnboundedSender<Publication>),
+  Socket(String, Addr<AppSocket>),
+}
+
+impl Subscriber {
+  /// False once the subscriber is gone for good.
+  fn deliver(&self, publication: &Publication) -> bool {
+    match self {
+      Subscriber::Inbox(_, tx) => tx.send(publication.clone()).is_ok(),
+      Subscriber::Socket(_, addr) => {
+        let text = json!({
+          "channel": publication.channel.name,
+          "seq": publication.seq,
+          "data": publication.data,
+        })
+        .to_string();
+        if !addr.connected() {
+          return false;
+        }
+        // Queued past the mailbox capacity rather than dropped, as replies
+        // from the isolate are.
+        addr.do_send(Outgoing::Text(text));
+        true
+      }
+    }
+  }
+}
+
+#[derive(Default)]
+struct Channel {
+  seq: u64,
+  subscribers: Vec<Subscriber>,
+}
+
+#[derive(Default)]
+pub struct Bus {
+  channels: HashMap<ChannelKey, Channel>,
+}
+
+impl Bus {
+  fn channel(&mut self, key: &ChannelKey) -> &mut Channel {
+    self.channels.entry(key.clone()).or_default()
+  }
+
+  pub fn subscribe_inbox(
+    &mut self,
+    key: &ChannelKey,
+    inbox: usize,
+    tx: &mpsc::UnboundedSender<Publication>,
+  ) {
+    let channel = self.channel(key);
+    let present = channel.subscribers.iter().any(|s| match s {
+      Subscriber::Inbox(id, _) => *id == inbox,
+      _ => false,
+    });
+    if !present {
+      channel
+        .subscribers
+        .push(Subscriber::Inbox(inbox, tx.clone()));
+    }
+  }
+
+  pub fn subscribe_socket(
+    &mut self,
+    key: &ChannelKey,
+    socket_id: &str,
+    addr: Addr<AppSocket>,
+  ) {
+    let channel = self.channel(key);
+    let present = channel.subscribers.iter().any(|s| match s {
+      Subscriber::Socket(id, _) => id == socket_id,
+      _ => false,
+    });
+    if !present {
+      let socket_id = socket_id.to_string();
+      channel
+        .subscribers
+        .push(Subscriber::Socket(socket_id, addr));
+    }
+  }
+
+  pub fn unsubscribe_inbox(&mut self, key: &ChannelKey, inbox: usize) {
+    self.retain(key, |s| match s {
+      Subscriber::Inbox(id, _) => *id != inbox,
+      _ => true,
+    });
+  }
+
+  pub fn unsubscribe_socket(&mut self, key: &ChannelKey, socket_id: &str) {
+    self.retain(key, |s| match s {
+      Subscriber::Socket(id, _) => id != socket_id,
+      _ => true,
+    });
+  }
+
+  fn retain<F: FnMut(&Subscriber) -> bool>(&mut self, key: &ChannelKey, f: F) {
+    let now_empty = match self.channels.get_mut(key) {
+      Some(channel) => {
+        channel.subscribers.retain(f);
+        channel.subscribers.is_empty()
+      }
+      None => false,
+    };
+    if now_empty {
+      self.channels.remove(key);
+    }
+  }
+
+  /// Delivers `data` to the channel's subscribers and returns its sequence
+  /// number, or 0 when nobody is listening.
+  pub fn publish(&mut self, key: &ChannelKey, data: Value) -> u64 {
+    let channel = match self.channels.get_mut(key) {
+      Some(channel) => channel,
+      None => return 0,
+    };
+    channel.seq += 1;
+    let publication = Publication {
+      channel: key.clone(),
+      seq: channel.seq,
+      data,
+    };
+    channel.subscribers.retain(|s| s.deliver(&publication));
+    if channel.subscribers.is_empty() {
+      self.channels.remove(key);
+    }
+    publication.seq
+  }
+}
+
+pub fn subscribe_inbox(
+  key: &ChannelKey,
+  inbox: usize,
+  tx: &mpsc::UnboundedSender<Publication>,
+) {
+  BUS.lock().unwrap().subscribe_inbox(key, inbox, tx);
+}
+
+pub fn unsubscribe_inbox(key: &ChannelKey, inbox: usize) {
+  BUS.lock().unwrap().unsubscribe_inbox(key, inbox);
+}
+
+pub fn subscribe_socket(
+  key: &ChannelKey,
+  socket_id: &str,
+  addr: Addr<AppSocket>,
+) {
+  BUS.lock().unwrap().subscribe_socket(key, socket_id, addr);
+}
+
+pub fn unsubscribe_socket(key: &ChannelKey, socket_id: &str) {
+  BUS.lock().unwrap().unsubscribe_socket(key, socket_id);
+}
+
+pub fn publish(key: &ChannelKey, data: Value) -> u64 {
+  BUS.lock().unwrap().publish(key, data)
+}
+
+#[test]
+fn channels_test() {
+  let key = |app: &str, name: &str| ChannelKey {
+    host: Some("example.com".to_string()),
+    app: Some(app.to_string()),
+    name: name.to_string(),
+  };
+  let mut bus = Bus::default();
+  let (mut a, a_tx) = Inbox::new();
+  let (mut b, b_tx) = Inbox::new();
+
+  assert_eq!(bus.publish(&key("forum", "posts"), json!("nobody")), 0);
+
+  bus.subscribe_inbox(&key("forum", "posts"), a.id, &a_tx);
+  bus.subscribe_inbox(&key("forum", "posts"), a.id, &a_tx);
+  bus.subscribe_inbox(&key("forum", "posts"), b.id, &b_tx);
+  bus.subscribe_inbox(&key("shop", "posts"), b.id, &b_tx);
+
+  for i in 1..=3 {
+    assert_eq!(bus.publish(&key("forum", "posts"), json!(i)), i);
+  }
+  bus.publish(&key("shop", "posts"), json!("shop"));
+
+  // Subscribed twice, delivered once.
+  let seen: Vec<Value> = std::iter::from_fn(|| a.messages.try_recv().ok())
+    .map(|p| p.data)
+    .collect();
+  assert_eq!(seen, vec![json!(1), json!(2), json!(3)]);
+
+  let seen: Vec<(String, u64)> =
+    std::iter::from_fn(|| b.messages.try_recv().ok())
+      .map(|p| (p.channel.app.clone().unwrap(), p.seq))
+      .collect();
+  assert_eq!(
+    seen,
+    vec![
+      ("forum".to_string(), 1),
+      ("forum".to_string(), 2),
+      ("forum".to_string(), 3),
+      ("shop".to_string(), 1)
+    ]
+  );
+
+  bus.unsubscribe_inbox(&key("forum", "posts"), a.id);
+  drop(b);
+  // b's receiver is gone, so it is dropped on the next publish.
+  assert_eq!(bus.publish(&key("forum", "posts"), json!(4)), 4);
+  assert_eq!(bus.publish(&key("forum", "posts"), json!(5)), 0);
+  assert!(a.messages.try_recv().is_err());
+}
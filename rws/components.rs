@@ -0,0 +1,333 @@
+//! Registry of server-rendered components.
+//!
+//! Apps register components by tag name (`RWS.addComponent`) and any page
+//! can embed one with a shortcode:
+//!
+//! ```text
+//! [g-form id="3" ajax]
+//! [notice type=warn]Content passed as args.content[/notice]
+//! [[g-form]]   <- escaped, renders as the literal text "[g-form]"
+//! ```
+//!
+//! Only registered names are treated as shortcodes, so ordinary brackets in
+//! page text are left alone.  A component is rendered in the isolate of the
+//! app that registered it (see `render`), and `js/runtime.js` turns
+//! failures into comments so they never fail the page.  A page stops
+//! waiting for a component once its timeout has passed; if the app's
+//! isolate is stuck in synchronous code by then, it is terminated and
+//! started again by `apps::run`.
+
+use crate::apps::{self, AppContext, Listeners};
+use serde_derive::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::sync::RwLock;
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot};
+
+lazy_static! {
+  static ref REGISTRY: RwLock<HashMap<String, ComponentInfo>> =
+    RwLock::new(HashMap::new());
+  static ref RENDERS: Listeners<RenderRequest> = Listeners::new();
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ComponentInfo {
+  pub name: String,
+  #[serde(default)]
+  pub title: String,
+  #[serde(default)]
+  pub description: String,
+  /// App that registered the component.
+  #[serde(default)]
+  pub app: Option<String>,
+  #[serde(default)]
+  pub host: Option<String>,
+  /// The component has an options form.
+  #[serde(default)]
+  pub has_options: bool,
+  /// Milliseconds a render may take, when shorter than the page allows.
+  #[serde(default)]
+  pub timeout: Option<u64>,
+}
+
+/// Registers `info`; returns false when another app already owns the name.
+/// An app mounted on several hosts registers its components once per host.
+pub fn register(info: ComponentInfo) -> bool {
+  let mut registry = REGISTRY.write().unwrap();
+  if let Some(existing) = registry.get(&info.name) {
+    if existing.app != info.app {
+      warn!(
+        "component {} is already registered by {:?}",
+        info.name, existing.app
+      );
+      return false;
+    }
+  }
+  registry.insert(info.name.clone(), info);
+  true
+}
+
+pub fn get(name: &str) -> Option<ComponentInfo> {
+  REGISTRY.read().unwrap().get(name).cloned()
+}
+
+pub fn list() -> Vec<ComponentInfo> {
+  let mut list: Vec<ComponentInfo> =
+    REGISTRY.read().unwrap().values().cloned().collect();
+  list.sort_by(|a, b| a.name.cmp(&b.name));
+  list
+}
+
+/// A component for its app's isolate to render.
+pub struct RenderRequest {
+  pub name: String,
+  pub args: Map<String, Value>,
+  pub reply: oneshot::Sender<Result<Option<String>, String>>,
+}
+
+/// Starts taking the component renders of an app; a later call replaces the
+/// earlier listener.
+pub fn listen(context: &AppContext) -> mpsc::UnboundedReceiver<RenderRequest> {
+  RENDERS.listen(context)
+}
+
+/// Renders component `name` for a page on `host`, in the isolate of its
+/// app on that host, or else on the host that registered it.  `None` means
+/// the component isn't available.
+pub async fn render(
+  host: &str,
+  name: &str,
+  args: Map<String, Value>,
+  timeout: Duration,
+) -> Result<Option<String>, String> {
+  let info = match get(name) {
+    Some(info) => info,
+    None => return Ok(None),
+  };
+  // The page's timeout is a limit: a component can only ask for less.
+  let timeout = match info.timeout {
+    Some(ms) => timeout.min(Duration::from_millis(ms)),
+    None => timeout,
+  };
+  let app = info.app.unwrap_or_default();
+  let mut contexts = vec![AppContext::new(host, &app)];
+  if let Some(registered) = info.host {
+    contexts.push(AppContext::new(&registered, &app));
+  }
+
+  let (reply, result) = oneshot::channel();
+  let mut request = RenderRequest {
+    name: name.to_string(),
+    args,
+    reply,
+  };
+  let mut owner = None;
+  for context in contexts {
+    match RENDERS.send(&context, request) {
+      Ok(()) => {
+        owner = Some(context);
+        break;
+      }
+      Err(back) => request = back,
+    }
+  }
+  let owner = match owner {
+    Some(owner) => owner,
+    None => return Ok(None),
+  };
+
+  match tokio::time::timeout(timeout, result).await {
+    Ok(Ok(html)) => html,
+    Ok(Err(_)) => Err("the renderer went away".to_string()),
+    Err(_) => {
+      if stuck(&owner).await {
+        warn!(
+          "component {} is stuck, terminating {} on {}",
+          name, owner.app, owner.host
+        );
+        apps::terminate(&owner);
+      }
+      Err(format!(
+        "component {} timed out after {}ms",
+        name,
+        timeout.as_millis()
+      ))
+    }
+  }
+}
+
+/// Whether the isolate of `context` is stuck; it is probed with a render
+/// of a component it doesn't have.
+async fn stuck(context: &AppContext) -> bool {
+  let (reply, result) = oneshot::channel();
+  let probe = RenderRequest {
+    name: String::new(),
+    args: Map::new(),
+    reply,
+  };
+  RENDERS.stuck(context, probe, result).await
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum Segment {
+  Text {
+    text: String,
+  },
+  Component {
+    name: String,
+    args: Map<String, Value>,
+  },
+}
+
+fn is_name_char(c: char) -> bool {
+  c.is_ascii_alphanumeric() || c == '-' || c == '_'
+}
+
+/// Parses the attributes of an opening shortcode, e.g. `id="3" ajax`.
+fn parse_args(mut s: &str) -> Option<Map<String, Value>> {
+  let mut args = Map::new();
+  loop {
+    s = s.trim_start();
+    if s.is_empty() {
+      return Some(args);
+    }
+    let key_len = s.find(|c: char| !is_name_char(c)).unwrap_or(s.len());
+    if key_len == 0 {
+      return None;
+    }
+    let key = s[..key_len].to_string();
+    s = &s[key_len..];
+
+    if !s.starts_with('=') {
+      args.insert(key, Value::Bool(true));
+      continue;
+    }
+    s = &s[1..];
+    let value = match s.chars().next() {
+      Some(quote) if quote == '"' || quote == '\'' => {
+        let end = s[1..].find(quote)? + 1;
+        let value = &s[1..end];
+        s = &s[end + 1..];
+        value
+      }
+      _ => {
+        let end = s.find(char::is_whitespace).unwrap_or(s.len());
+        let value = &s[..end];
+        s = &s[end..];
+        value
+      }
+    };
+    args.insert(key, Value::String(value.to_string()));
+  }
+}
+
+/// Splits `html` into text and the shortcodes of components `known` accepts.
+pub fn parse<F: Fn(&str) -> bool>(html: &str, known: F) -> Vec<Segment> {
+  let mut segments = Vec::new();
+  let mut text = String::new();
+  let mut rest = html;
+
+  while let Some(start) = rest.find('[') {
+    text.push_str(&rest[..start]);
+    rest = &rest[start..];
+
+    // `[[name ...]]` is an escaped shortcode.
+    if rest.starts_with("[[") {
+      if let Some(end) = rest.find("]]") {
+        let inner = &rest[2..end];
+        let name_len = inner.find(|c: char| !is_name_char(c));
+        let name = &inner[..name_len.unwrap_or(inner.len())];
+        if known(name) {
+          text.push('[');
+          text.push_str(inner);
+          text.push(']');
+          rest = &rest[end + 2..];
+          continue;
+        }
+      }
+    }
+
+    let shortcode = rest.find(']').and_then(|end| {
+      let inner = &rest[1..end];
+      let name_len = inner.find(|c: char| !is_name_char(c));
+      let name = &inner[..name_len.unwrap_or(inner.len())];
+      if name.is_empty() || !known(name) {
+        return None;
+      }
+      let mut args = parse_args(&inner[name.len()..])?;
+
+      let mut consumed = end + 1;
+      let closing = format!("[/{}]", name);
+      if let Some(close) = rest[consumed..].find(&closing) {
+        let content = &rest[consumed..consumed + close];
+        args.insert("content".to_string(), Value::String(content.to_string()));
+        consumed += close + closing.len();
+      }
+      Some((name.to_string(), args, consumed))
+    });
+
+    match shortcode {
+      Some((name, args, consumed)) => {
+        if !text.is_empty() {
+          segments.push(Segment::Text {
+            text: std::mem::replace(&mut text, String::new()),
+          });
+        }
+        segments.push(Segment::Component { name, args });
+        rest = &rest[consumed..];
+      }
+      None => {
+        text.push('[');
+        rest = &rest[1..];
+      }
+    }
+  }
+
+  text.push_str(rest);
+  if !text.is_empty() {
+    segments.push(Segment::Text { text });
+  }
+  segments
+}
+
+/// `parse` against the registry.
+pub fn parse_registered(html: &str) -> Vec<Segment> {
+  let registry = REGISTRY.read().unwrap();
+  parse(html, |name| registry.contains_key(name))
+}
+
+#[test]
+fn components_parse_test() {
+  let known = |name: &str| name == "g-form" || name == "notice";
+  let component = |name: &str, args: Value| Segment::Component {
+    name: name.to_string(),
+    args: args.as_object().unwrap().clone(),
+  };
+  let text = |text: &str| Segment::Text {
+    text: text.to_string(),
+  };
+
+  assert_eq!(
+    parse(r#"<p>[g-form id="3" ajax theme=dark]</p>"#, known),
+    vec![
+      text("<p>"),
+      component("g-form", json!({"id": "3", "ajax": true, "theme": "dark"})),
+      text("</p>"),
+    ]
+  );
+  assert_eq!(
+    parse("[notice type='warn']Careful [b][/notice] after", known),
+    vec![
+      component("notice", json!({"type": "warn", "content": "Careful [b]"})),
+      text(" after"),
+    ]
+  );
+  // Unknown names, escapes and malformed attributes stay text.
+  assert_eq!(
+    parse(r#"a[0] [other] [[g-form id=1]] [g-form id="3]"#, known),
+    vec![text(r#"a[0] [other] [g-form id=1] [g-form id="3]"#)]
+  );
+}
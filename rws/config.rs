@@ -0,0 +1,360 @@
+use serde_derive::{Deserialize, Serialize};
+use std::env;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+
+lazy_static! {
+  static ref CONFIG: RwLock<Arc<Config>> =
+    RwLock::new(Arc::new(Config::load()));
+}
+
+/// Directory rws keeps its configuration and data in.  Defaults to `~/.rws`,
+/// `RWS_DIR` overrides it.
+pub fn rws_dir() -> PathBuf {
+  if let Ok(dir) = env::var("RWS_DIR") {
+    return PathBuf::from(dir);
+  }
+  let mut dir = match env::var("HOME") {
+    Ok(home) => PathBuf::from(home),
+    Err(_) => env::current_dir().unwrap(),
+  };
+  dir.push(".rws");
+  dir
+}
+
+/// Makes a host or app name safe to use as a file name under `rws_dir`.
+pub fn file_name(name: &str) -> String {
+  let name = name
+    .chars()
+    .map(|c| {
+      if c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.' {
+        c
+      } else {
+        '_'
+      }
+    })
+    .collect::<String>()
+    .trim_start_matches('.')
+    .to_string();
+  if name.is_empty() {
+    "_".to_string()
+  } else {
+    name
+  }
+}
+
+/// Where an installed app lives: `<rws dir>/apps/<host>/<app>`.
+pub fn app_dir(host: &str, app: &str) -> PathBuf {
+  rws_dir()
+    .join("apps")
+    .join(file_name(host))
+    .join(file_name(app))
+}
+
+/// Returns the currently loaded configuration.
+pub fn get() -> Arc<Config> {
+  Arc::clone(&CONFIG.read().unwrap())
+}
+
+/// Writes `config.json` and makes `config` the current configuration.
+/// Listen addresses only change on restart.
+pub fn save(config: Config) -> io::Result<()> {
+  let path = Config::path();
+  fs::create_dir_all(path.parent().unwrap())?;
+  let tmp = path.with_extension("json.tmp");
+  fs::write(&tmp, serde_json::to_vec_pretty(&config)?)?;
+  fs::rename(&tmp, &path)?;
+  *CONFIG.write().unwrap() = Arc::new(config);
+  Ok(())
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SmtpTls {
+  /// Plain text, only meant for local relays and tests.
+  None,
+  /// Connect in plain text and upgrade with STARTTLS before authenticating.
+  StartTls,
+  /// TLS from the first byte (usually port 465).
+  Implicit,
+}
+
+impl Default for SmtpTls {
+  fn default() -> Self {
+    SmtpTls::StartTls
+  }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct SmtpConfig {
+  pub host: String,
+  #[serde(default = "default_smtp_port")]
+  pub port: u16,
+  #[serde(default)]
+  pub tls: SmtpTls,
+  #[serde(default)]
+  pub username: Option<String>,
+  #[serde(default)]
+  pub password: Option<String>,
+  /// Sender used when a message doesn't set `from`.
+  pub from: String,
+  /// Name sent with EHLO, defaults to "localhost".
+  #[serde(default)]
+  pub hello_name: Option<String>,
+}
+
+fn default_smtp_port() -> u16 {
+  587
+}
+
+/// A virtual host served by rws.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct HostConfig {
+  pub name: String,
+  /// Other names answering for this host, e.g. "www.example.com".
+  #[serde(default)]
+  pub aliases: Vec<String>,
+  /// Get a certificate for the host and its aliases from the ACME server.
+  #[serde(default = "default_true")]
+  pub acme: bool,
+  /// Paths handed to upstream servers instead of the host's apps.
+  #[serde(default)]
+  pub proxy: Vec<ProxyRoute>,
+  /// Installed apps and the paths they are served at.
+  #[serde(default)]
+  pub apps: Vec<AppMount>,
+  /// Nested routes of apps mounted into the host's pages (see `routes`).
+  #[serde(default)]
+  pub routes: Vec<RouteMount>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct AppMount {
+  /// App name, as installed under `app_dir`.
+  pub app: String,
+  /// Path prefix, e.g. "/" or "/shop".
+  #[serde(default = "default_mount_path")]
+  pub path: String,
+  /// Host name the app's documentation is served on (see `docs`), e.g.
+  /// "docs.example.com".
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub docs: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct RouteMount {
+  /// Path pattern; `:name` segments capture route arguments, e.g.
+  /// "/pages/:template".
+  pub path: String,
+  pub app: String,
+  /// Name of the route in the app's `nested_routes`.
+  pub route: String,
+  /// Arguments fixed by the mount rather than taken from the URL.
+  #[serde(default)]
+  pub args: serde_json::Map<String, serde_json::Value>,
+}
+
+fn default_mount_path() -> String {
+  "/".to_string()
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct ProxyRoute {
+  /// Path prefix, e.g. "/api".
+  pub prefix: String,
+  /// Base URLs, e.g. "http://127.0.0.1:3000", used round-robin.
+  pub upstreams: Vec<String>,
+  /// Remove `prefix` from the path before forwarding.
+  #[serde(default)]
+  pub strip_prefix: bool,
+  /// Path polled on each upstream; failing upstreams are skipped until they
+  /// recover.
+  #[serde(default)]
+  pub health_check: Option<String>,
+}
+
+impl HostConfig {
+  pub fn domains(&self) -> Vec<String> {
+    let mut domains = vec![self.name.clone()];
+    domains.extend(self.aliases.iter().cloned());
+    domains.extend(self.apps.iter().filter_map(|a| a.docs.clone()));
+    domains
+  }
+
+  pub fn matches(&self, host: &str) -> bool {
+    let host = without_port(host);
+    self.name.eq_ignore_ascii_case(host)
+      || self.aliases.iter().any(|a| a.eq_ignore_ascii_case(host))
+  }
+
+  /// The app whose documentation `host` serves.
+  pub fn docs(&self, host: &str) -> Option<&AppMount> {
+    let host = without_port(host);
+    self.apps.iter().find(|a| {
+      a.docs
+        .as_ref()
+        .map_or(false, |d| d.eq_ignore_ascii_case(host))
+    })
+  }
+}
+
+/// Drops any port from a Host header.
+fn without_port(host: &str) -> &str {
+  host.rsplitn(2, ':').last().unwrap_or(host)
+}
+
+fn default_true() -> bool {
+  true
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct AcmeConfig {
+  #[serde(default = "default_acme_directory")]
+  pub directory_url: String,
+  /// Contact address registered with the ACME account.
+  #[serde(default)]
+  pub email: Option<String>,
+  /// Extra root certificate (PEM) for the ACME server, e.g. pebble's.
+  #[serde(default)]
+  pub ca_file: Option<PathBuf>,
+  /// Certificates older than this are renewed.  Let's Encrypt issues them
+  /// for 90 days.
+  #[serde(default = "default_renew_after_days")]
+  pub renew_after_days: u64,
+}
+
+fn default_acme_directory() -> String {
+  "https://acme-v02.api.letsencrypt.org/directory".to_string()
+}
+
+fn default_renew_after_days() -> u64 {
+  60
+}
+
+/// Rate limits and brute force lockouts (see `protection`).
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ProtectionConfig {
+  /// Requests per second one IP may sustain.
+  pub ip_rate: f64,
+  /// Requests one IP may make in a burst.
+  pub ip_burst: f64,
+  /// Login attempts per second one account may sustain.
+  pub account_rate: f64,
+  pub account_burst: f64,
+  /// Failed logins tolerated before an IP or account is locked out.
+  pub max_failures: u32,
+  /// Length of the first lockout, doubled by every further failure.
+  pub lockout_secs: u64,
+  pub max_lockout_secs: u64,
+  /// Addresses or CIDR ranges that are never limited by IP; the accounts
+  /// they log in to still are.
+  pub allow: Vec<String>,
+  /// Addresses or CIDR ranges that are always refused.
+  pub deny: Vec<String>,
+}
+
+impl Default for ProtectionConfig {
+  fn default() -> Self {
+    ProtectionConfig {
+      ip_rate: 20.0,
+      ip_burst: 100.0,
+      account_rate: 0.2,
+      account_burst: 5.0,
+      max_failures: 5,
+      lockout_secs: 60,
+      max_lockout_secs: 24 * 60 * 60,
+      allow: vec!["127.0.0.1/32".to_string(), "::1/128".to_string()],
+      deny: Vec::new(),
+    }
+  }
+}
+
+/// Server side rendering of app pages (see `render`).
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct RenderConfig {
+  /// How long a render may take before the bare shell is served instead.
+  pub timeout_ms: u64,
+  /// How long rendered pages of signed out visitors are reused; 0 turns
+  /// the cache off.
+  pub cache_secs: u64,
+  pub cache_entries: usize,
+}
+
+impl Default for RenderConfig {
+  fn default() -> Self {
+    RenderConfig {
+      timeout_ms: 2000,
+      cache_secs: 60,
+      cache_entries: 1000,
+    }
+  }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Config {
+  pub http_addr: String,
+  /// HTTPS is only served when this is set.
+  pub https_addr: Option<String>,
+  pub hosts: Vec<HostConfig>,
+  pub acme: Option<AcmeConfig>,
+  pub smtp: Option<SmtpConfig>,
+  pub protection: ProtectionConfig,
+  pub render: RenderConfig,
+  /// Ids of the users who may use the control panel.
+  pub admins: Vec<String>,
+  /// Serve apps' development builds: unminified library packages, without
+  /// SRI checks.
+  pub dev: bool,
+}
+
+impl Default for Config {
+  fn default() -> Self {
+    Config {
+      http_addr: "127.0.0.1:8083".to_string(),
+      https_addr: None,
+      hosts: Vec::new(),
+      acme: None,
+      smtp: None,
+      protection: ProtectionConfig::default(),
+      render: RenderConfig::default(),
+      admins: Vec::new(),
+      dev: false,
+    }
+  }
+}
+
+impl Config {
+  pub fn path() -> PathBuf {
+    rws_dir().join("config.json")
+  }
+
+  pub fn host(&self, host: &str) -> Option<&HostConfig> {
+    self.hosts.iter().find(|h| h.matches(host))
+  }
+
+  /// The host and app mount whose documentation `host` serves.
+  pub fn docs_host(&self, host: &str) -> Option<(&HostConfig, &AppMount)> {
+    self
+      .hosts
+      .iter()
+      .find_map(|h| h.docs(host).map(|mount| (h, mount)))
+  }
+
+  /// Reads `config.json` from the rws directory, falling back to defaults
+  /// when it is missing or invalid.
+  pub fn load() -> Config {
+    let path = Config::path();
+    match fs::read_to_string(&path) {
+      Ok(text) => serde_json::from_str(&text).unwrap_or_else(|e| {
+        error!("invalid config {}: {}", path.display(), e);
+        Config::default()
+      }),
+      Err(_) => Config::default(),
+    }
+  }
+}
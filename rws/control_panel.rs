@@ -1,9 +1,9 @@
 use actix_rt::System;
-use actix_web::{web, App, HttpResponse, HttpServer, HttpRequest, http::header::{DispositionType, ContentDisposition}, Error};
+use actix_web::{web, App, HttpResponse, HttpServer, HttpRequest, http::header::{self, DispositionType, ContentDisposition}, Error};
 use tokio::runtime::Builder;
 use actix_files as fs;
 use std::env;
-use std::path::PathBuf;
+use std::path::{Component, Path, PathBuf};
 use actix_web_actors::ws;
 use actix::{Actor, StreamHandler, Recipient};
 use std::thread_local;
@@ -15,6 +15,17 @@ use tokio::sync::watch;
 use notify::{Watcher, RecommendedWatcher, RecursiveMode, Error as nError};
 use lazy_static::lazy_static;
 use std::sync::{Arc, Mutex};
+use futures::{future, StreamExt};
+use crate::admin::{self, Admin};
+use crate::components;
+use crate::config;
+use crate::manifest::{Manifest, RenderType};
+use crate::render;
+use crate::logging::{self, LogQuery};
+use crate::options;
+use crate::protection;
+use crate::rbac;
+use crate::services;
 
 lazy_static! {
     static ref FILE_WATCHER: Arc<(Mutex<watch::Sender<String>>, Mutex<watch::Receiver<String>>)> = make_channel();
@@ -204,40 +215,293 @@ async fn index_ws(
     resp
 }
 
-async fn index(req: HttpRequest) -> Result<fs::NamedFile, Error> {
-    let mut root = env::current_dir().unwrap();
-    root.push("control-panel");
-    root.push("src");
+async fn logs(_: Admin, query: web::Query<LogQuery>) -> HttpResponse {
+    HttpResponse::Ok().json(logging::query(&query))
+}
+
+/// Server-sent events stream of new log entries matching the query.
+async fn logs_tail(_: Admin, query: web::Query<LogQuery>) -> HttpResponse {
+    let query = query.into_inner();
+    let events = logging::subscribe().filter_map(move |entry| {
+        let event = match entry {
+            Ok(entry) if query.matches(&entry) => {
+                let json = serde_json::to_string(&entry).unwrap();
+                Some(Ok::<_, Error>(web::Bytes::from(format!("data: {}\n\n", json))))
+            },
+            _ => None,
+        };
+        future::ready(event)
+    });
+    HttpResponse::Ok().content_type("text/event-stream").streaming(events)
+}
+
+async fn component_list(_: Admin) -> HttpResponse {
+    HttpResponse::Ok().json(components::list())
+}
+
+async fn option_apps(_: Admin) -> HttpResponse {
+    let apps: Vec<_> = options::list()
+        .into_iter()
+        .map(|(host, app)| json!({"host": host, "app": app}))
+        .collect();
+    HttpResponse::Ok().json(apps)
+}
 
-    let path: PathBuf = req.match_info().query("filename").parse().unwrap();
-    if path.to_str().unwrap().len() == 0 {
-        root.push("index.html");
+async fn app_options(_: Admin, path: web::Path<(String, String)>) -> HttpResponse {
+    let schema: serde_json::Map<String, serde_json::Value> = options::schema(&path.0, &path.1)
+        .into_iter()
+        .map(|(key, spec)| (key, json!(spec)))
+        .collect();
+    HttpResponse::Ok().json(json!({
+        "schema": schema,
+        "options": options::get(&path.0, &path.1),
+    }))
+}
+
+async fn save_app_options(
+    admin: Admin,
+    path: web::Path<(String, String)>,
+    patch: web::Json<serde_json::Map<String, serde_json::Value>>,
+) -> HttpResponse {
+    let patch = patch.into_inner();
+    let keys: Vec<&String> = patch.keys().collect();
+    let details = json!({"host": path.0, "keys": keys});
+    match options::save(&path.0, &path.1, patch.clone()) {
+        Ok(saved) => {
+            admin.audit("options.save", &path.1, details);
+            HttpResponse::Ok().json(saved)
+        },
+        Err(e @ options::OptionsError::Invalid { .. }) => HttpResponse::BadRequest().body(e.to_string()),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+async fn bans(_: Admin) -> HttpResponse {
+    HttpResponse::Ok().json(protection::bans())
+}
+
+async fn unban(admin: Admin, path: web::Path<(String, String)>) -> HttpResponse {
+    if protection::unban(&path.0, &path.1) {
+        admin.audit("ban.lift", &path.1, json!({"kind": path.0}));
+        HttpResponse::NoContent().finish()
     } else {
-        root.push(path);
+        HttpResponse::NotFound().finish()
     }
-    
-    let file = fs::NamedFile::open(root);
-    match file {
-        Ok(f) => {
-            Ok(f.use_last_modified(true)
-                .set_content_disposition(ContentDisposition {
-                    disposition: DispositionType::Inline,
-                    parameters: vec![],
-                }))
+}
+
+async fn service_list(_: Admin) -> HttpResponse {
+    HttpResponse::Ok().json(services::list())
+}
+
+/// Roles, members and the permissions roles can be built from.
+async fn host_rbac(_: Admin, host: web::Path<String>) -> HttpResponse {
+    let policy = rbac::policy(&host);
+    HttpResponse::Ok().json(json!({
+        "permissions": rbac::permissions(&host),
+        "roles": policy.roles,
+        "members": policy.members,
+    }))
+}
+
+/// Answers an RBAC change, auditing it when it went through.
+fn rbac_response(
+    admin: &Admin,
+    action: &str,
+    target: &str,
+    details: serde_json::Value,
+    result: Result<(), rbac::RbacError>,
+) -> HttpResponse {
+    match result {
+        Ok(()) => {
+            admin.audit(action, target, details);
+            HttpResponse::NoContent().finish()
         },
-        Err(_) => {
-            let mut root = env::current_dir().unwrap();
-            root.push("control-panel");
-            root.push("src");
-            root.push("index.html");
-            Ok(fs::NamedFile::open(root).unwrap().use_last_modified(true)
+        Err(e @ rbac::RbacError::Invalid(_)) => HttpResponse::BadRequest().body(e.to_string()),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+async fn save_role(admin: Admin, path: web::Path<(String, String)>, role: web::Json<rbac::Role>) -> HttpResponse {
+    let role = role.into_inner();
+    let details = json!({"host": path.0, "role": role});
+    let result = rbac::save_role(&path.0, &path.1, role);
+    rbac_response(&admin, "role.save", &path.1, details, result)
+}
+
+async fn delete_role(admin: Admin, path: web::Path<(String, String)>) -> HttpResponse {
+    let details = json!({"host": path.0});
+    match rbac::delete_role(&path.0, &path.1) {
+        Ok(true) => rbac_response(&admin, "role.delete", &path.1, details, Ok(())),
+        Ok(false) => HttpResponse::NotFound().finish(),
+        Err(e) => rbac_response(&admin, "role.delete", &path.1, details, Err(e)),
+    }
+}
+
+async fn assign_roles(
+    admin: Admin,
+    path: web::Path<(String, String)>,
+    roles: web::Json<std::collections::BTreeSet<String>>,
+) -> HttpResponse {
+    let roles = roles.into_inner();
+    let details = json!({"host": path.0, "roles": roles});
+    let result = rbac::assign(&path.0, &path.1, roles);
+    rbac_response(&admin, "member.assign", &path.1, details, result)
+}
+
+/// Installed `control_panel` apps, which the dashboard shows as pages.
+async fn panels(_: Admin) -> HttpResponse {
+    let mut found = Vec::new();
+    for host in &config::get().hosts {
+        for mount in &host.apps {
+            let dir = config::app_dir(&host.name, &mount.app);
+            let manifest = match Manifest::load(&dir.join("manifest.json")) {
+                Ok(manifest) if manifest.render_type == RenderType::ControlPanel => manifest,
+                _ => continue,
+            };
+            found.push(json!({
+                "host": host.name,
+                "app": mount.app,
+                "title": manifest.name,
+                "base": format!("/apps/{}/{}", host.name, mount.app),
+                "frame": format!("/panels/{}/{}", host.name, mount.app),
+            }));
+        }
+    }
+    HttpResponse::Ok().json(found)
+}
+
+/// Keeps `control_panel` app code out of the dashboard's origin, wherever
+/// its files are opened: in an opaque origin it gets neither the admin
+/// cookie nor the CSRF token.
+const PANEL_SANDBOX: &str = "sandbox allow-scripts allow-forms";
+
+/// The page the html_admin views of a `control_panel` app run in.  The
+/// dashboard shows it in a sandboxed frame.
+async fn panel_frame(path: web::Path<(String, String)>) -> HttpResponse {
+    let (host, app) = path.into_inner();
+    let config = config::get();
+    let mounted = config.hosts.iter().any(|h| h.name == host && h.apps.iter().any(|m| m.app == app));
+    let dir = config::app_dir(&host, &app);
+    match Manifest::load(&dir.join("manifest.json")) {
+        Ok(manifest) if mounted && manifest.render_type == RenderType::ControlPanel => {}
+        _ => return HttpResponse::NotFound().finish(),
+    }
+    let setup = json!({
+        "base": format!("/apps/{}/{}", host, app),
+        "paths": render::require_paths(&dir, render::section(RenderType::ControlPanel)),
+    });
+    // So the JSON can't end the script element.
+    let setup = setup.to_string().replace('<', "\\u003c");
+    let html = format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+         <script src=\"/require.js\"></script>\n</head>\n<body>\n<div id=\"panel\"></div>\n\
+         <script>\nconst setup = {};\n\
+         requirejs.config({{baseUrl: setup.base + \"/\", paths: setup.paths}});\n\
+         requirejs([\"views/index\"], (view) =>\n  \
+         (view.default || view)(document.getElementById(\"panel\"), setup.base, \"/\"));\n\
+         </script>\n</body>\n</html>\n",
+        setup
+    );
+    HttpResponse::Ok()
+        .header(header::CONTENT_SECURITY_POLICY, PANEL_SANDBOX)
+        .content_type("text/html; charset=utf-8")
+        .body(html)
+}
+
+/// Client files of a `control_panel` app, laid out like on its host.  Not
+/// behind the admin session: the sandboxed frame loading them has none to
+/// send, and they are the app's public client code anyway.
+async fn panel_file(req: HttpRequest, path: web::Path<(String, String, String)>) -> HttpResponse {
+    let (host, app, route) = path.into_inner();
+    let dir = config::app_dir(&host, &app);
+    let section = render::section(RenderType::ControlPanel);
+    let mut response = match render::static_file(&dir, section, &route) {
+        Some(file) => match fs::NamedFile::open(file) {
+            Ok(file) => file.into_response(&req).unwrap_or_else(HttpResponse::from_error),
+            Err(_) => return HttpResponse::NotFound().finish(),
+        },
+        None => return HttpResponse::NotFound().finish(),
+    };
+    response.headers_mut().insert(header::CONTENT_SECURITY_POLICY, header::HeaderValue::from_static(PANEL_SANDBOX));
+    response
+}
+
+/// The dashboard's files, embedded by build.rs.
+mod embedded {
+    include!(concat!(env!("OUT_DIR"), "/control_panel_assets.rs"));
+}
+
+/// A control-panel checkout to serve the dashboard from instead of the
+/// embedded copy, for working on it.  Set with `RWS_CONTROL_PANEL_DIR`.
+fn dev_dir() -> Option<PathBuf> {
+    env::var_os("RWS_CONTROL_PANEL_DIR").map(PathBuf::from)
+}
+
+/// A dashboard file in a checkout: the built one in `dst`, else the one in
+/// `src`.  Paths can't leave the checkout.
+fn disk_asset(root: &Path, path: &str) -> Option<PathBuf> {
+    let relative = Path::new(path);
+    if relative.components().any(|c| !matches!(c, Component::Normal(_))) {
+        return None;
+    }
+    let built = root.join("dst").join(relative);
+    if std::fs::metadata(&built).map_or(false, |m| m.is_file() && m.len() > 0) {
+        return Some(built);
+    }
+    Some(root.join("src").join(relative)).filter(|file| file.is_file())
+}
+
+fn embedded_asset(path: &str) -> Option<&'static (&'static str, &'static [u8], u64)> {
+    embedded::ASSETS.iter().find(|(name, _, _)| *name == path)
+}
+
+/// Library packages change rarely; everything else is revalidated.
+fn cache_control(path: &str) -> &'static str {
+    if path.starts_with("libs/") {
+        "public, max-age=86400"
+    } else {
+        "no-cache"
+    }
+}
+
+/// Serves a dashboard file; unknown paths get index.html so client side
+/// routes load the app.
+async fn index(req: HttpRequest) -> HttpResponse {
+    let path = req.match_info().query("filename").trim_start_matches('/');
+    let path = if path.is_empty() { "index.html" } else { path };
+
+    if let Some(root) = dev_dir() {
+        let file = disk_asset(&root, path).or_else(|| disk_asset(&root, "index.html"));
+        return match file.map(fs::NamedFile::open) {
+            Some(Ok(file)) => file
+                .use_last_modified(true)
                 .set_content_disposition(ContentDisposition {
                     disposition: DispositionType::Inline,
                     parameters: vec![],
-                }))
-        }
+                })
+                .into_response(&req)
+                .unwrap_or_else(HttpResponse::from_error),
+            _ => HttpResponse::NotFound().finish(),
+        };
     }
-    
+
+    let (name, body, hash) = match embedded_asset(path).or_else(|| embedded_asset("index.html")) {
+        Some(asset) => asset,
+        None => return HttpResponse::NotFound().finish(),
+    };
+    let etag = format!("\"{:016x}\"", hash);
+    let fresh = req.headers().get(header::IF_NONE_MATCH).map_or(false, |tag| tag == etag.as_str());
+    let mut response = if fresh { HttpResponse::NotModified() } else { HttpResponse::Ok() };
+    response
+        .header(header::ETAG, etag)
+        .header(header::CACHE_CONTROL, cache_control(name));
+    if fresh {
+        return response.finish();
+    }
+    let extension = Path::new(name).extension().and_then(|e| e.to_str()).unwrap_or("");
+    response
+        .content_type(fs::file_extension_to_mime(extension).to_string())
+        .body(*body)
 }
 
 pub fn server() {
@@ -263,9 +527,12 @@ pub fn server() {
         }
     }).unwrap();
 
-    // Add a path to be watched. All files and directories at that path and
-    // below will be monitored for changes.
-    watcher.watch("./control-panel", RecursiveMode::Recursive).unwrap();
+    // Reload open dashboards when the checkout being served changes.
+    if let Some(root) = dev_dir() {
+        if let Err(e) = watcher.watch(&root, RecursiveMode::Recursive) {
+            warn!("can't watch {}: {}", root.display(), e);
+        }
+    }
     
   
     let local = tokio::task::LocalSet::new();
@@ -280,6 +547,23 @@ pub fn server() {
             App::new()
             .data(server.clone())
             .route("/livereload", web::get().to(index_ws))
+            .route("/api/logs", web::get().to(logs))
+            .route("/api/logs/tail", web::get().to(logs_tail))
+            .route("/api/bans", web::get().to(bans))
+            .route("/api/components", web::get().to(component_list))
+            .route("/api/options", web::get().to(option_apps))
+            .route("/api/options/{host}/{app}", web::get().to(app_options))
+            .route("/api/options/{host}/{app}", web::put().to(save_app_options))
+            .route("/api/bans/{kind}/{key}", web::delete().to(unban))
+            .route("/api/services", web::get().to(service_list))
+            .route("/api/panels", web::get().to(panels))
+            .route("/panels/{host}/{app}", web::get().to(panel_frame))
+            .route("/apps/{host}/{app}/{path:.*}", web::get().to(panel_file))
+            .route("/api/rbac/{host}", web::get().to(host_rbac))
+            .route("/api/rbac/{host}/roles/{role}", web::put().to(save_role))
+            .route("/api/rbac/{host}/roles/{role}", web::delete().to(delete_role))
+            .route("/api/rbac/{host}/members/{user}", web::put().to(assign_roles))
+            .configure(admin::routes)
             .route("/{filename:.*}", web::get().to(index))
         })
         .workers(1)
@@ -288,4 +572,23 @@ pub fn server() {
         .run()
         .await;
     });
-}
\ No newline at end of file
+}
+
+#[test]
+fn control_panel_assets_test() {
+    assert!(embedded_asset("index.html").is_some());
+    assert!(embedded_asset("pages/index.tsx").is_none());
+    assert_eq!(cache_control("libs/react/index.js"), "public, max-age=86400");
+    assert_eq!(cache_control("app.js"), "no-cache");
+
+    let dir = tempfile::TempDir::new().unwrap();
+    std::fs::create_dir_all(dir.path().join("src/pages")).unwrap();
+    std::fs::create_dir_all(dir.path().join("dst/pages")).unwrap();
+    std::fs::write(dir.path().join("src/app.js"), "source").unwrap();
+    std::fs::write(dir.path().join("dst/app.js"), "").unwrap();
+    std::fs::write(dir.path().join("dst/pages/index.js"), "built").unwrap();
+    assert_eq!(disk_asset(dir.path(), "app.js"), Some(dir.path().join("src/app.js")));
+    assert_eq!(disk_asset(dir.path(), "pages/index.js"), Some(dir.path().join("dst/pages/index.js")));
+    assert_eq!(disk_asset(dir.path(), "../src/app.js"), None);
+    assert_eq!(disk_asset(dir.path(), "missing.js"), None);
+}
@@ -0,0 +1,1262 @@
+//! Documentation sites.
+//!
+//! An app mount with a `docs` host (see `config::AppMount`) serves the
+//! app's documentation on that host:
+//!
+//! - `html_docs/**/*.md` are pages at their path without the extension,
+//!   `index.md` at its directory.  `html_docs/static` is served at
+//!   `/static`.
+//! - The service modules, `html_*/views/services/**/*.ts`, and the `bin`
+//!   modules get API pages under `/api/`, e.g. `/api/bin/install`, made with
+//!   deno's doc parser and printer.  The `export default {...}` of a service
+//!   module is listed as its services.
+//! - `/search?q=` searches every page and API entry; `/search.json?q=`
+//!   returns the hits.
+//!
+//! Links to `.md` files are rewritten to their pages.  `[[name]]` and
+//! `{@link name}` link to the page with that path or title, or to the API
+//! entry or module with that name, e.g. `[[bin/install]]`, and so does
+//! inline code naming an API entry.  A site is built on the first request
+//! and rebuilt when one of its files changes.
+
+use crate::config;
+use crate::manifest::Manifest;
+use crate::render::{self, escape_html};
+use crate::search::{SearchIndex, SearchOptions};
+use crate::swc_common::{Span, Spanned};
+use crate::swc_ecma_ast::{
+  Expr, ModuleDecl, ModuleItem, Prop, PropName, PropOrSpread,
+};
+use actix_files::NamedFile;
+use actix_web::http::Method;
+use actix_web::{web, HttpRequest, HttpResponse};
+use deno_cli::colors;
+use deno_cli::doc::parser::DocFileLoader;
+use deno_cli::doc::{DocNode, DocNodeKind, DocParser, DocPrinter};
+use deno_cli::file_fetcher::map_file_extension;
+use deno_cli::op_error::OpError;
+use futures::Future;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+use url::Url;
+
+const MODULE_EXTENSIONS: &[&str] = &["ts", "tsx", "js"];
+
+const STYLE: &str = "
+body { display: flex; margin: 0; font: 16px/1.5 sans-serif; color: #222; }
+nav { width: 16rem; padding: 1rem; background: #f5f5f5; min-height: 100vh; }
+nav ul { list-style: none; padding: 0; }
+nav .site { font-weight: bold; }
+main { flex: 1; max-width: 50rem; padding: 1rem 2rem; }
+pre { background: #f5f5f5; padding: 0.75rem; overflow-x: auto; }
+section { border-top: 1px solid #ddd; }
+.kind, .source { color: #777; font-size: 0.85rem; }
+";
+
+/// A file the site is built from and when it last changed.
+type Source = (PathBuf, Option<SystemTime>);
+
+lazy_static! {
+  static ref SITES: Mutex<HashMap<PathBuf, Arc<Site>>> =
+    Mutex::new(HashMap::new());
+}
+
+/// An app whose docs host a request came in on.
+#[derive(Clone, Debug)]
+pub struct DocsMount {
+  pub host: String,
+  pub app: String,
+  pub dir: PathBuf,
+}
+
+pub fn mount_for(req: &HttpRequest) -> Option<DocsMount> {
+  let config = config::get();
+  let host = req.connection_info().host().to_string();
+  let (host_config, mount) = config.docs_host(&host)?;
+  Some(DocsMount {
+    host: host_config.name.clone(),
+    app: mount.app.clone(),
+    dir: config::app_dir(&host_config.name, &mount.app),
+  })
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Page {
+  pub url: String,
+  pub title: String,
+  /// HTML placed in `<main>`.
+  pub body: String,
+}
+
+/// A page or API entry that searches can find.
+#[derive(Clone, Debug, Serialize)]
+pub struct Hit {
+  pub url: String,
+  pub title: String,
+  pub summary: String,
+}
+
+pub struct Site {
+  sources: Vec<Source>,
+  pub title: String,
+  /// By URL.
+  pages: BTreeMap<String, Page>,
+  guides: Vec<(String, String)>,
+  modules: Vec<(String, String)>,
+  index: SearchIndex,
+  /// By URL, with the anchor of API entries.
+  entries: HashMap<String, Hit>,
+}
+
+/// Loads the modules the doc parser follows re-exports into.  Only files
+/// inside the app are read.
+struct AppLoader {
+  dir: PathBuf,
+}
+
+impl DocFileLoader for AppLoader {
+  fn load_source_code(
+    &self,
+    specifier: &str,
+  ) -> Pin<Box<dyn Future<Output = Result<String, OpError>>>> {
+    let path = Url::parse(specifier)
+      .ok()
+      .and_then(|url| url.to_file_path().ok())
+      .filter(|path| path.starts_with(&self.dir));
+    let result = match path {
+      // Imports in apps may leave out the extension.
+      Some(path) => std::iter::once(path.clone())
+        .chain(MODULE_EXTENSIONS.iter().map(|e| path.with_extension(e)))
+        .find(|path| path.is_file())
+        .ok_or_else(|| OpError::not_found(format!("{} not found", specifier)))
+        .and_then(|path| fs::read_to_string(path).map_err(OpError::from)),
+      None => Err(OpError::permission_denied(format!(
+        "{} is outside the app",
+        specifier
+      ))),
+    };
+    Box::pin(futures::future::ready(result))
+  }
+}
+
+/// A service in the `export default {...}` of a service module.  Nested
+/// objects give dotted names, e.g. `user_services.add_user`.
+#[derive(Clone, Debug)]
+struct Service {
+  name: String,
+  /// The parameters of the function, when the value is one.
+  params: Option<String>,
+  js_doc: Option<String>,
+  line: usize,
+}
+
+/// A service or bin module, parsed for its API page.
+struct Module {
+  /// Relative to the app, e.g. "bin/install.ts".
+  path: String,
+  url: String,
+  nodes: Result<Vec<DocNode>, String>,
+  services: Vec<Service>,
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+  fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+fn has_extension(path: &Path, extensions: &[&str]) -> bool {
+  match path.extension().and_then(|e| e.to_str()) {
+    Some(ext) => extensions.contains(&ext),
+    None => false,
+  }
+}
+
+/// Files under `dir` with one of the extensions, skipping `node_modules`
+/// and `static`.
+fn walk(
+  dir: &Path,
+  extensions: &[&str],
+  recursive: bool,
+  out: &mut Vec<PathBuf>,
+) {
+  let entries = match fs::read_dir(dir) {
+    Ok(entries) => entries,
+    Err(_) => return,
+  };
+  for entry in entries.filter_map(|e| e.ok()) {
+    let path = entry.path();
+    let name = entry.file_name();
+    if path.is_dir() {
+      if recursive && name != "node_modules" && name != "static" {
+        walk(&path, extensions, recursive, out);
+      }
+    } else if has_extension(&path, extensions) {
+      out.push(path);
+    }
+  }
+}
+
+/// The markdown pages and the modules of an app.
+fn files(dir: &Path) -> (Vec<PathBuf>, Vec<PathBuf>) {
+  let mut pages = Vec::new();
+  walk(&dir.join("html_docs"), &["md"], true, &mut pages);
+
+  let mut modules = Vec::new();
+  if let Ok(entries) = fs::read_dir(dir) {
+    for entry in entries.filter_map(|e| e.ok()) {
+      let name = entry.file_name().to_string_lossy().into_owned();
+      if name.starts_with("html_") {
+        let services = entry.path().join("views").join("services");
+        walk(&services, MODULE_EXTENSIONS, true, &mut modules);
+      }
+    }
+  }
+  walk(&dir.join("bin"), MODULE_EXTENSIONS, false, &mut modules);
+
+  pages.sort();
+  modules.sort();
+  (pages, modules)
+}
+
+/// `path` relative to `dir`, with forward slashes.
+fn relative(dir: &Path, path: &Path) -> String {
+  let relative = path.strip_prefix(dir).unwrap_or(path);
+  let parts: Vec<_> = relative
+    .components()
+    .map(|c| c.as_os_str().to_string_lossy().into_owned())
+    .collect();
+  parts.join("/")
+}
+
+fn without_extension(path: &str) -> &str {
+  match path.rfind('.') {
+    Some(dot) if !path[dot..].contains('/') => &path[..dot],
+    _ => path,
+  }
+}
+
+/// The URL of a page in `html_docs`, e.g. "guide/index.md" is "/guide".
+fn page_url(path: &str) -> String {
+  let path = without_extension(path);
+  let path = if path == "index" {
+    ""
+  } else if path.ends_with("/index") {
+    &path[..path.len() - "/index".len()]
+  } else {
+    path
+  };
+  format!("/{}", path)
+}
+
+/// Resolves `.` and `..` in a relative path.
+fn normalize(path: &str) -> String {
+  let mut parts: Vec<&str> = Vec::new();
+  for part in path.split('/') {
+    match part {
+      "" | "." => {}
+      ".." => {
+        parts.pop();
+      }
+      part => parts.push(part),
+    }
+  }
+  parts.join("/")
+}
+
+/// A heading anchor: lowercase words joined by dashes.
+fn slug(text: &str) -> String {
+  let mut slug = String::new();
+  for c in text.chars() {
+    if c.is_alphanumeric() {
+      slug.extend(c.to_lowercase());
+    } else if !slug.is_empty() && !slug.ends_with('-') {
+      slug.push('-');
+    }
+  }
+  slug.trim_end_matches('-').to_string()
+}
+
+fn title_of(markdown: &str) -> Option<String> {
+  markdown
+    .lines()
+    .find(|line| line.starts_with("# "))
+    .map(|line| line[2..].trim().to_string())
+}
+
+fn default_services(
+  parser: &DocParser,
+  file_name: &str,
+  source: &str,
+) -> Vec<Service> {
+  let media_type = map_file_extension(Path::new(file_name));
+  let ast = &parser.ast_parser;
+  ast.parse_module(file_name, media_type, source, |module| {
+    let mut found = Vec::new();
+    for item in module.map(|m| m.body).unwrap_or_default() {
+      let export = match item {
+        ModuleItem::ModuleDecl(ModuleDecl::ExportDefaultExpr(export)) => export,
+        _ => continue,
+      };
+      if let Expr::Object(object) = &*export.expr {
+        collect_services(parser, &object.props, "", &mut found);
+      }
+    }
+    found
+  })
+}
+
+fn collect_services(
+  parser: &DocParser,
+  props: &[PropOrSpread],
+  prefix: &str,
+  out: &mut Vec<Service>,
+) {
+  for prop in props {
+    let prop = match prop {
+      PropOrSpread::Prop(prop) => &**prop,
+      PropOrSpread::Spread(_) => continue,
+    };
+    let (key, params) = match prop {
+      Prop::KeyValue(kv) => match &*kv.value {
+        Expr::Object(object) => {
+          if let Some(key) = prop_name(&kv.key) {
+            let prefix = dotted(prefix, &key);
+            collect_services(parser, &object.props, &prefix, out);
+          }
+          continue;
+        }
+        value => (prop_name(&kv.key), function_params(parser, value)),
+      },
+      Prop::Method(method) => (
+        prop_name(&method.key),
+        snippet(parser, prop.span()).and_then(|source| params(&source)),
+      ),
+      Prop::Shorthand(ident) => (Some(ident.sym.to_string()), None),
+      _ => continue,
+    };
+    let key = match key {
+      Some(key) => key,
+      None => continue,
+    };
+    out.push(Service {
+      name: dotted(prefix, &key),
+      params,
+      js_doc: parser.js_doc_for_span(prop.span()),
+      line: parser.ast_parser.get_span_location(prop.span()).line,
+    });
+  }
+}
+
+fn prop_name(key: &PropName) -> Option<String> {
+  match key {
+    PropName::Ident(ident) => Some(ident.sym.to_string()),
+    PropName::Str(text) => Some(text.value.to_string()),
+    _ => None,
+  }
+}
+
+fn dotted(prefix: &str, name: &str) -> String {
+  if prefix.is_empty() {
+    name.to_string()
+  } else {
+    format!("{}.{}", prefix, name)
+  }
+}
+
+/// The parameters of a function value.  Wrappers such as
+/// `auth(check, async (args) => ...)` give those of their last argument.
+fn function_params(parser: &DocParser, expr: &Expr) -> Option<String> {
+  match expr {
+    Expr::Arrow(_) | Expr::Fn(_) => params(&snippet(parser, expr.span())?),
+    Expr::Paren(paren) => function_params(parser, &paren.expr),
+    Expr::Call(call) => function_params(parser, &call.args.last()?.expr),
+    _ => None,
+  }
+}
+
+fn snippet(parser: &DocParser, span: Span) -> Option<String> {
+  parser.ast_parser.source_map.span_to_snippet(span).ok()
+}
+
+/// The parameter list at the start of a function's source, types included.
+fn params(source: &str) -> Option<String> {
+  let source = source.trim_start();
+  let source = if source.starts_with("async") {
+    source[5..].trim_start()
+  } else {
+    source
+  };
+  let open = source.find('(');
+  // `arg => ...`
+  if let Some(arrow) = source.find("=>") {
+    if open.map_or(true, |open| arrow < open) {
+      return Some(source[..arrow].trim().to_string());
+    }
+  }
+  let open = open?;
+  let mut depth = 0;
+  for (i, c) in source[open..].char_indices() {
+    match c {
+      '(' | '[' | '{' => depth += 1,
+      ')' | ']' | '}' => {
+        depth -= 1;
+        if depth == 0 {
+          return Some(source[open + 1..open + i].trim().to_string());
+        }
+      }
+      _ => {}
+    }
+  }
+  None
+}
+
+fn kind_name(kind: &DocNodeKind) -> &'static str {
+  match kind {
+    DocNodeKind::Function => "function",
+    DocNodeKind::Variable => "variable",
+    DocNodeKind::Class => "class",
+    DocNodeKind::Enum => "enum",
+    DocNodeKind::Interface => "interface",
+    DocNodeKind::TypeAlias => "type",
+    DocNodeKind::Namespace => "namespace",
+  }
+}
+
+/// The printer's signature of a node, with its members but without its
+/// JSDoc, which is rendered as markdown instead.
+fn signature(node: &DocNode) -> String {
+  let mut node = node.clone();
+  node.js_doc = None;
+  let printed = DocPrinter::new(&[node], true, false).to_string();
+  let printed = colors::strip_ansi_codes(&printed);
+  let lines: Vec<_> = printed
+    .lines()
+    .skip_while(|line| !line.starts_with("Defined in"))
+    .skip(1)
+    .skip_while(|line| line.trim().is_empty())
+    .collect();
+  lines.join("\n").trim_end().to_string()
+}
+
+impl Module {
+  fn parse(dir: &Path, path: &Path) -> Module {
+    let relative = relative(dir, path);
+    let url = format!("/api/{}", without_extension(&relative));
+    let specifier = match Url::from_file_path(path) {
+      Ok(url) => url.to_string(),
+      Err(_) => relative.clone(),
+    };
+    let loader = AppLoader {
+      dir: dir.to_path_buf(),
+    };
+    let parser = DocParser::new(Box::new(loader), false);
+    let nodes =
+      futures::executor::block_on(parser.parse_with_reexports(&specifier))
+        .map_err(|e| e.to_string());
+    let services = if relative.contains("/views/services/") {
+      match fs::read_to_string(path) {
+        Ok(source) => default_services(&parser, &specifier, &source),
+        Err(_) => Vec::new(),
+      }
+    } else {
+      Vec::new()
+    };
+    Module {
+      path: relative,
+      url,
+      nodes,
+      services,
+    }
+  }
+
+  /// API entries by name, and the module by its path without the
+  /// extension, with their URL.
+  fn symbols(&self) -> Vec<(String, String)> {
+    let name = without_extension(&self.path).to_string();
+    let mut symbols = vec![(name, self.url.clone())];
+    for service in &self.services {
+      let url = format!("{}#service-{}", self.url, service.name);
+      symbols.push((service.name.clone(), url));
+    }
+    for node in self.nodes.iter().flatten() {
+      symbols.push((node.name.clone(), format!("{}#{}", self.url, node.name)));
+    }
+    symbols
+  }
+
+  fn render(&self, links: &Links) -> String {
+    let mut body = format!("<h1>{}</h1>\n", escape_html(&self.path));
+    let line = |line: usize| {
+      format!(
+        "<p class=\"source\">{}:{}</p>\n",
+        escape_html(&self.path),
+        line
+      )
+    };
+
+    if !self.services.is_empty() {
+      body.push_str("<h2 id=\"services\">Services</h2>\n");
+      for service in &self.services {
+        let call = match &service.params {
+          Some(params) => format!("{}({})", service.name, params),
+          None => service.name.clone(),
+        };
+        body.push_str(&format!(
+          "<section id=\"service-{}\">\n<h3><code>{}</code></h3>\n",
+          escape_html(&service.name),
+          escape_html(&call)
+        ));
+        if let Some(js_doc) = &service.js_doc {
+          body.push_str(&markdown(js_doc, links));
+        }
+        body.push_str(&line(service.line));
+        body.push_str("</section>\n");
+      }
+    }
+
+    match &self.nodes {
+      Ok(nodes) if nodes.is_empty() => {}
+      Ok(nodes) => {
+        body.push_str("<h2 id=\"exports\">Exports</h2>\n");
+        for node in nodes {
+          body.push_str(&format!(
+            "<section id=\"{name}\">\n<h3><code>{name}</code> \
+             <span class=\"kind\">{kind}</span></h3>\n\
+             <pre><code>{signature}</code></pre>\n",
+            name = escape_html(&node.name),
+            kind = kind_name(&node.kind),
+            signature = escape_html(&signature(node)),
+          ));
+          if let Some(js_doc) = &node.js_doc {
+            body.push_str(&markdown(js_doc, links));
+          }
+          body.push_str(&line(node.location.line));
+          body.push_str("</section>\n");
+        }
+      }
+      Err(e) => body.push_str(&format!(
+        "<p>The module could not be parsed: {}</p>\n",
+        escape_html(e)
+      )),
+    }
+    body
+  }
+}
+
+/// Schemes links may use; others, such as `javascript:`, are made inert.
+const LINK_SCHEMES: &[&str] = &["http", "https", "mailto"];
+
+/// The lowercase scheme of a URL, or `None` for a relative one.  Browsers
+/// ignore whitespace and control characters in it, so this does too.
+fn scheme(url: &str) -> Option<String> {
+  let end = url.find(|c: char| c == ':' || c == '/' || c == '?' || c == '#')?;
+  if !url[end..].starts_with(':') {
+    return None;
+  }
+  let scheme = url[..end]
+    .chars()
+    .filter(|c| !c.is_ascii_whitespace() && !c.is_control())
+    .collect::<String>();
+  Some(scheme.to_ascii_lowercase())
+}
+
+/// What links in a page can point to.
+struct Links<'a> {
+  /// Directory of the page's markdown file, relative to `html_docs`.
+  dir: &'a str,
+  /// Page URLs by markdown path, e.g. "guide/install.md".
+  pages: &'a HashMap<String, String>,
+  /// Page URLs by lowercase title.
+  titles: &'a HashMap<String, String>,
+  /// API entry URLs by name.
+  symbols: &'a HashMap<String, String>,
+}
+
+impl<'a> Links<'a> {
+  /// Rewrites links to markdown files to their pages, and links with a
+  /// scheme not in `LINK_SCHEMES` to "#".
+  fn href(&self, target: &str) -> String {
+    match scheme(target) {
+      Some(scheme) if LINK_SCHEMES.contains(&scheme.as_str()) => {
+        return target.to_string()
+      }
+      Some(_) => return "#".to_string(),
+      None => {}
+    }
+    if target.starts_with('/') || target.starts_with('#') {
+      return target.to_string();
+    }
+    let (path, fragment) = match target.find('#') {
+      Some(hash) => (&target[..hash], &target[hash..]),
+      None => (target, ""),
+    };
+    if !path.ends_with(".md") {
+      return target.to_string();
+    }
+    let path = normalize(&format!("{}/{}", self.dir, path));
+    match self.pages.get(&path) {
+      Some(url) => format!("{}{}", url, fragment),
+      None => target.to_string(),
+    }
+  }
+
+  /// The URL `[[name]]` and `{@link name}` point to.
+  fn named(&self, name: &str) -> Option<&String> {
+    let path = normalize(name.trim_end_matches(".md"));
+    self
+      .pages
+      .get(&format!("{}.md", path))
+      .or_else(|| self.pages.get(&format!("{}/index.md", path)))
+      .or_else(|| self.titles.get(&name.to_lowercase()))
+      .or_else(|| self.symbols.get(name))
+  }
+
+  fn reference(&self, name: &str) -> String {
+    let text = escape_html(name);
+    match self.named(name) {
+      Some(url) if self.symbols.get(name) == Some(url) => {
+        format!("<a href=\"{}\"><code>{}</code></a>", escape_html(url), text)
+      }
+      Some(url) => format!("<a href=\"{}\">{}</a>", escape_html(url), text),
+      None => text,
+    }
+  }
+
+  /// Inline code links to the API entry it names, with or without `()`.
+  fn code(&self, code: &str) -> String {
+    let text = escape_html(code);
+    match self.symbols.get(code.trim_end_matches("()")) {
+      Some(url) => {
+        format!("<a href=\"{}\"><code>{}</code></a>", escape_html(url), text)
+      }
+      None => format!("<code>{}</code>", text),
+    }
+  }
+}
+
+/// `[label](target)` at the start of `text`: the label, the target and the
+/// length of the link.
+fn link(text: &str) -> Option<(&str, &str, usize)> {
+  if !text.starts_with('[') {
+    return None;
+  }
+  let close = text.find(']')?;
+  if !text[close + 1..].starts_with('(') {
+    return None;
+  }
+  let end = close + 1 + text[close + 1..].find(')')?;
+  Some((&text[1..close], text[close + 2..end].trim(), end + 1))
+}
+
+/// Inline markdown: code, `[[name]]` and `{@link name}` references, links,
+/// images, and `*emphasis*` and `**strong**` text.
+fn inline(text: &str, links: &Links) -> String {
+  let mut out = String::new();
+  let mut rest = text;
+  while let Some(c) = rest.chars().next() {
+    if c == '`' {
+      if let Some(end) = rest[1..].find('`') {
+        out.push_str(&links.code(&rest[1..end + 1]));
+        rest = &rest[end + 2..];
+        continue;
+      }
+    } else if rest.starts_with("[[") {
+      if let Some(end) = rest.find("]]") {
+        out.push_str(&links.reference(rest[2..end].trim()));
+        rest = &rest[end + 2..];
+        continue;
+      }
+    } else if rest.starts_with("{@link ") {
+      if let Some(end) = rest.find('}') {
+        out.push_str(&links.reference(rest[7..end].trim()));
+        rest = &rest[end + 1..];
+        continue;
+      }
+    } else if rest.starts_with("![") {
+      if let Some((alt, src, len)) = link(&rest[1..]) {
+        out.push_str(&format!(
+          "<img src=\"{}\" alt=\"{}\">",
+          escape_html(src),
+          escape_html(alt)
+        ));
+        rest = &rest[len + 1..];
+        continue;
+      }
+    } else if c == '[' {
+      if let Some((label, target, len)) = link(rest) {
+        out.push_str(&format!(
+          "<a href=\"{}\">{}</a>",
+          escape_html(&links.href(target)),
+          inline(label, links)
+        ));
+        rest = &rest[len..];
+        continue;
+      }
+    } else if rest.starts_with("**") {
+      if let Some(end) = rest[2..].find("**") {
+        let strong = inline(&rest[2..end + 2], links);
+        out.push_str(&format!("<strong>{}</strong>", strong));
+        rest = &rest[end + 4..];
+        continue;
+      }
+    } else if c == '*' {
+      if let Some(end) = rest[1..].find('*').filter(|end| *end > 0) {
+        out.push_str(&format!("<em>{}</em>", inline(&rest[1..end + 1], links)));
+        rest = &rest[end + 2..];
+        continue;
+      }
+    }
+    out.push_str(&escape_html(&rest[..c.len_utf8()]));
+    rest = &rest[c.len_utf8()..];
+  }
+  out
+}
+
+fn heading_level(line: &str) -> Option<usize> {
+  let level = line.chars().take_while(|c| *c == '#').count();
+  if (1..=6).contains(&level) && line[level..].starts_with(' ') {
+    Some(level)
+  } else {
+    None
+  }
+}
+
+fn is_rule(line: &str) -> bool {
+  let line = line.trim();
+  let first = line.chars().next();
+  line.len() >= 3
+    && matches!(first, Some('-') | Some('*') | Some('_'))
+    && line.chars().all(|c| Some(c) == first)
+}
+
+/// The list marker a line starts with and whether the list is ordered.
+fn list_marker(line: &str) -> Option<(usize, bool)> {
+  if line.starts_with("- ") || line.starts_with("* ") || line.starts_with("+ ")
+  {
+    return Some((2, false));
+  }
+  let digits = line.chars().take_while(|c| c.is_ascii_digit()).count();
+  if digits > 0 && line[digits..].starts_with(". ") {
+    Some((digits + 2, true))
+  } else {
+    None
+  }
+}
+
+fn starts_block(line: &str) -> bool {
+  heading_level(line).is_some()
+    || line.trim_start().starts_with("```")
+    || line.starts_with('>')
+    || list_marker(line).is_some()
+    || is_rule(line)
+}
+
+/// Renders the markdown docs are written in: ATX headings, paragraphs,
+/// fenced code, lists, block quotes and rules, with the inline markup of
+/// `inline`.  HTML in the source is escaped.
+fn markdown(text: &str, links: &Links) -> String {
+  let lines: Vec<&str> = text.lines().collect();
+  let mut out = String::new();
+  let mut i = 0;
+  while i < lines.len() {
+    let line = lines[i];
+    if line.trim().is_empty() {
+      i += 1;
+    } else if line.trim_start().starts_with("```") {
+      let language = line.trim_start()[3..].trim();
+      let mut code = String::new();
+      i += 1;
+      while i < lines.len() && !lines[i].trim_start().starts_with("```") {
+        code.push_str(lines[i]);
+        code.push('\n');
+        i += 1;
+      }
+      i += 1;
+      let class = if language.is_empty() {
+        String::new()
+      } else {
+        format!(" class=\"language-{}\"", escape_html(language))
+      };
+      out.push_str(&format!(
+        "<pre><code{}>{}</code></pre>\n",
+        class,
+        escape_html(&code)
+      ));
+    } else if let Some(level) = heading_level(line) {
+      let text = line[level..].trim().trim_end_matches('#').trim();
+      out.push_str(&format!(
+        "<h{level} id=\"{id}\">{text}</h{level}>\n",
+        level = level,
+        id = slug(text),
+        text = inline(text, links)
+      ));
+      i += 1;
+    } else if is_rule(line) {
+      out.push_str("<hr>\n");
+      i += 1;
+    } else if line.starts_with('>') {
+      let mut quote = String::new();
+      while i < lines.len() && lines[i].starts_with('>') {
+        quote.push_str(lines[i][1..].trim_start());
+        quote.push('\n');
+        i += 1;
+      }
+      out.push_str(&format!(
+        "<blockquote>\n{}</blockquote>\n",
+        markdown(&quote, links)
+      ));
+    } else if let Some((_, ordered)) = list_marker(line) {
+      let mut items: Vec<String> = Vec::new();
+      while i < lines.len() {
+        let line = lines[i];
+        match list_marker(line) {
+          Some((width, o)) if o == ordered => {
+            items.push(format!("{}\n", &line[width..]));
+          }
+          _ if line.starts_with(' ') && !line.trim().is_empty() => {
+            let item = items.last_mut().unwrap();
+            item.push_str(line.trim_start_matches(' '));
+            item.push('\n');
+          }
+          // A blank line only ends the list if no item follows.
+          _ if line.trim().is_empty()
+            && i + 1 < lines.len()
+            && (list_marker(lines[i + 1]).is_some()
+              || lines[i + 1].starts_with("  ")) =>
+          {
+            items.last_mut().unwrap().push('\n');
+          }
+          _ => break,
+        }
+        i += 1;
+      }
+      let tag = if ordered { "ol" } else { "ul" };
+      out.push_str(&format!("<{}>\n", tag));
+      for item in items {
+        let html = markdown(&item, links);
+        let html = html.trim_end();
+        // Items of a single paragraph aren't wrapped in <p>.
+        let html = if html.starts_with("<p>")
+          && html.ends_with("</p>")
+          && html.matches("<p>").count() == 1
+        {
+          &html[3..html.len() - 4]
+        } else {
+          html
+        };
+        out.push_str(&format!("<li>{}</li>\n", html));
+      }
+      out.push_str(&format!("</{}>\n", tag));
+    } else {
+      let mut paragraph = Vec::new();
+      while i < lines.len()
+        && !lines[i].trim().is_empty()
+        && (paragraph.is_empty() || !starts_block(lines[i]))
+      {
+        paragraph.push(lines[i].trim());
+        i += 1;
+      }
+      out.push_str(&format!(
+        "<p>{}</p>\n",
+        inline(&paragraph.join("\n"), links)
+      ));
+    }
+  }
+  out
+}
+
+/// The first line of a text, for search results.
+fn summary(text: &str) -> String {
+  let line = text
+    .lines()
+    .map(str::trim)
+    .find(|line| !line.is_empty() && !line.starts_with('#'))
+    .unwrap_or("");
+  match line.char_indices().nth(160) {
+    Some((end, _)) => format!("{}…", &line[..end]),
+    None => line.to_string(),
+  }
+}
+
+impl Site {
+  fn build(dir: &Path, sources: Vec<Source>) -> io::Result<Site> {
+    let name = match Manifest::load(&dir.join("manifest.json")) {
+      Ok(manifest) if !manifest.name.is_empty() => manifest.name,
+      _ => dir
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default(),
+    };
+    let docs_dir = dir.join("html_docs");
+    let (page_files, module_files) = files(dir);
+
+    let mut site = Site {
+      sources,
+      title: format!("{} documentation", name),
+      pages: BTreeMap::new(),
+      guides: Vec::new(),
+      modules: Vec::new(),
+      index: SearchIndex::new(),
+      entries: HashMap::new(),
+    };
+
+    let modules: Vec<Module> = module_files
+      .iter()
+      .map(|path| Module::parse(dir, path))
+      .collect();
+    let mut symbols = HashMap::new();
+    for module in &modules {
+      for (name, url) in module.symbols() {
+        symbols.entry(name).or_insert(url);
+      }
+    }
+
+    let mut texts = Vec::new();
+    let mut pages = HashMap::new();
+    let mut titles = HashMap::new();
+    for path in &page_files {
+      let relative = relative(&docs_dir, path);
+      let text = fs::read_to_string(path)?;
+      let url = page_url(&relative);
+      let title = title_of(&text)
+        .unwrap_or_else(|| without_extension(&relative).to_string());
+      pages.insert(relative.clone(), url.clone());
+      titles.insert(title.to_lowercase(), url.clone());
+      texts.push((relative, url, title, text));
+    }
+
+    for (relative, url, title, text) in texts {
+      let dir = match relative.rfind('/') {
+        Some(slash) => &relative[..slash],
+        None => "",
+      };
+      let links = Links {
+        dir,
+        pages: &pages,
+        titles: &titles,
+        symbols: &symbols,
+      };
+      site.add(&url, &title, &text);
+      site.guides.push((url.clone(), title.clone()));
+      site.pages.insert(
+        url.clone(),
+        Page {
+          url,
+          title,
+          body: markdown(&text, &links),
+        },
+      );
+    }
+
+    let links = Links {
+      dir: "",
+      pages: &pages,
+      titles: &titles,
+      symbols: &symbols,
+    };
+    for module in &modules {
+      for service in &module.services {
+        let text = format!(
+          "{} {}",
+          service.name,
+          service.js_doc.as_deref().unwrap_or("")
+        );
+        let url = format!("{}#service-{}", module.url, service.name);
+        site.add(&url, &format!("{} service", service.name), &text);
+      }
+      for node in module.nodes.iter().flatten() {
+        let text =
+          format!("{} {}", node.name, node.js_doc.as_deref().unwrap_or(""));
+        let url = format!("{}#{}", module.url, node.name);
+        let title = format!("{} {}", kind_name(&node.kind), node.name);
+        site.add(&url, &title, &text);
+      }
+      site.modules.push((module.url.clone(), module.path.clone()));
+      site.pages.insert(
+        module.url.clone(),
+        Page {
+          url: module.url.clone(),
+          title: module.path.clone(),
+          body: module.render(&links),
+        },
+      );
+    }
+
+    if !site.pages.contains_key("/") {
+      let title = escape_html(&site.title);
+      let body = format!("<h1>{}</h1>\n{}", title, site.nav());
+      let page = Page {
+        url: "/".to_string(),
+        title: site.title.clone(),
+        body,
+      };
+      site.pages.insert(page.url.clone(), page);
+    }
+    Ok(site)
+  }
+
+  fn add(&mut self, url: &str, title: &str, text: &str) {
+    self.index.upsert_text(url, &format!("{}\n{}", title, text));
+    let hit = Hit {
+      url: url.to_string(),
+      title: title.to_string(),
+      summary: summary(text),
+    };
+    self.entries.insert(url.to_string(), hit);
+  }
+
+  /// The page at a path; trailing slashes are ignored.
+  pub fn page(&self, path: &str) -> Option<&Page> {
+    let path = path.trim_end_matches('/');
+    self.pages.get(if path.is_empty() { "/" } else { path })
+  }
+
+  pub fn search(&self, query: &str) -> Vec<Hit> {
+    self
+      .index
+      .search(query, &SearchOptions::default())
+      .into_iter()
+      .filter_map(|hit| self.entries.get(&hit.id).cloned())
+      .collect()
+  }
+
+  fn nav(&self) -> String {
+    let list = |heading: &str, items: &[(String, String)]| {
+      if items.is_empty() {
+        return String::new();
+      }
+      let items: String = items
+        .iter()
+        .map(|(url, title)| {
+          format!(
+            "<li><a href=\"{}\">{}</a></li>",
+            escape_html(url),
+            escape_html(title)
+          )
+        })
+        .collect();
+      format!("<h2>{}</h2>\n<ul>{}</ul>\n", heading, items)
+    };
+    format!(
+      "{}{}",
+      list("Guide", &self.guides),
+      list("API", &self.modules)
+    )
+  }
+
+  /// A page of the site, with the navigation and search form.
+  pub fn layout(&self, title: &str, query: &str, body: &str) -> String {
+    format!(
+      r#"<!DOCTYPE html>
+<html>
+  <head>
+    <meta charset="utf-8">
+    <meta name="viewport" content="width=device-width, initial-scale=1.0">
+    <title>{title}</title>
+    <style>{style}</style>
+  </head>
+  <body>
+    <nav>
+      <a class="site" href="/">{site}</a>
+      <form action="/search">
+        <input type="search" name="q" value="{query}" placeholder="Search">
+      </form>
+      {nav}
+    </nav>
+    <main>{body}</main>
+  </body>
+</html>
+"#,
+      title = escape_html(title),
+      style = STYLE,
+      site = escape_html(&self.title),
+      query = escape_html(query),
+      nav = self.nav(),
+      body = body,
+    )
+  }
+
+  fn search_page(&self, query: &str) -> String {
+    let hits = self.search(query);
+    let mut body = format!("<h1>Search: {}</h1>\n", escape_html(query));
+    if hits.is_empty() {
+      body.push_str("<p>Nothing found.</p>\n");
+    }
+    for hit in hits {
+      body.push_str(&format!(
+        "<p><a href=\"{}\">{}</a><br>{}</p>\n",
+        escape_html(&hit.url),
+        escape_html(&hit.title),
+        escape_html(&hit.summary)
+      ));
+    }
+    self.layout(&format!("Search: {}", query), query, &body)
+  }
+}
+
+fn sources(dir: &Path) -> Vec<Source> {
+  let (pages, modules) = files(dir);
+  std::iter::once(dir.join("manifest.json"))
+    .chain(pages)
+    .chain(modules)
+    .map(|path| {
+      let modified = modified(&path);
+      (path, modified)
+    })
+    .collect()
+}
+
+/// The docs site of the app in `dir`, built again when its files changed.
+pub fn site(dir: &Path) -> io::Result<Arc<Site>> {
+  let sources = sources(dir);
+  if let Some(site) = SITES.lock().unwrap().get(dir) {
+    if site.sources == sources {
+      return Ok(Arc::clone(site));
+    }
+  }
+  let site = Arc::new(Site::build(dir, sources)?);
+  SITES
+    .lock()
+    .unwrap()
+    .insert(dir.to_path_buf(), Arc::clone(&site));
+  Ok(site)
+}
+
+#[derive(Deserialize)]
+struct SearchQuery {
+  #[serde(default)]
+  q: String,
+}
+
+pub async fn serve(mount: DocsMount, req: HttpRequest) -> HttpResponse {
+  if req.method() != Method::GET && req.method() != Method::HEAD {
+    return HttpResponse::MethodNotAllowed().finish();
+  }
+  let path = req.path().to_string();
+  if path.starts_with("/static/") {
+    return match render::static_file(&mount.dir, "html_docs", &path)
+      .map(NamedFile::open)
+    {
+      Some(Ok(file)) => file
+        .use_last_modified(true)
+        .into_response(&req)
+        .unwrap_or_else(HttpResponse::from_error),
+      _ => HttpResponse::NotFound().finish(),
+    };
+  }
+
+  let dir = mount.dir.clone();
+  let site = match web::block(move || site(&dir)).await {
+    Ok(site) => site,
+    Err(e) => {
+      error!("docs of {} on {}: {}", mount.app, mount.host, e);
+      return HttpResponse::InternalServerError().finish();
+    }
+  };
+  let query = web::Query::<SearchQuery>::from_query(req.query_string())
+    .map(|q| q.into_inner().q)
+    .unwrap_or_default();
+  match path.as_str() {
+    "/search.json" => HttpResponse::Ok().json(site.search(&query)),
+    "/search" => render::html(None, site.search_page(&query)),
+    _ => match site.page(&path) {
+      Some(page) => {
+        render::html(None, site.layout(&page.title, "", &page.body))
+      }
+      None => render::html(
+        Some(404),
+        site.layout("Not found", "", "<h1>Not found</h1>"),
+      ),
+    },
+  }
+}
+
+#[test]
+fn docs_test() {
+  let dir = tempfile::TempDir::new().unwrap();
+  let app = dir.path();
+  let write = |path: &str, text: &str| {
+    let path = app.join(path);
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::write(path, text).unwrap();
+  };
+  write("manifest.json", r#"{"name": "shop"}"#);
+  write(
+    "html_docs/index.md",
+    "# Shop\n\nSee [installing](guide/install.md#steps), [[Installing]], \
+     `get_user` and `Greeter`.\n\n- one\n- *two*\n\n```ts\na < b\n```\n",
+  );
+  write(
+    "html_docs/guide/install.md",
+    "# Installing\n\n## Steps\n\nRun [[bin/install]], then [[shop]].\n\n\
+     [Site](https://shop.test/), [x](JavaScript:alert) and \
+     [y](java\tscript:alert).\n",
+  );
+  write(
+    "bin/install.ts",
+    "/** Sets the app up. Uses {@link Greeter}. */\n\
+     export function install(rws: any): void {}\n\
+     export class Greeter {\n\
+     greet(name: string): string { return name; }\n}\n",
+  );
+  write(
+    "html_public/views/services/index.ts",
+    "export default {\n\
+     /** Looks up a user by id. */\n\
+     get_user: async (args: {id: number}, token) => {},\n\
+     users: {\n  add: auth(check, async (args) => {}),\n},\n};\n",
+  );
+
+  let site = site(app).unwrap();
+  assert!(Arc::ptr_eq(&site, &self::site(app).unwrap()));
+  assert_eq!(site.title, "shop documentation");
+
+  let index = &site.page("/").unwrap().body;
+  assert!(index.contains("<h1 id=\"shop\">Shop</h1>"));
+  assert!(index.contains("<a href=\"/guide/install#steps\">installing</a>"));
+  assert!(index.contains("<a href=\"/guide/install\">Installing</a>"));
+  assert!(index.contains(
+    "<a href=\"/api/html_public/views/services/index#service-get_user\">\
+     <code>get_user</code></a>"
+  ));
+  assert!(index.contains("<a href=\"/api/bin/install#Greeter\">"));
+  assert!(index.contains("<li>one</li>\n<li><em>two</em></li>"));
+  assert!(index.contains("<code class=\"language-ts\">a &lt; b\n</code>"));
+
+  let install = &site.page("/guide/install/").unwrap().body;
+  assert!(install.contains("<h2 id=\"steps\">Steps</h2>"));
+  assert!(install.contains("<a href=\"/\">shop</a>"));
+  assert!(install.contains("<a href=\"https://shop.test/\">Site</a>"));
+  assert!(install.contains("<a href=\"#\">x</a> and <a href=\"#\">y</a>"));
+  assert!(install
+    .contains("<a href=\"/api/bin/install\"><code>bin/install</code></a>"));
+
+  let bin = &site.page("/api/bin/install").unwrap().body;
+  assert!(bin.contains("<section id=\"install\">"));
+  assert!(bin.contains("function install(rws: any): void"));
+  assert!(bin.contains("<a href=\"/api/bin/install#Greeter\">"));
+  assert!(bin.contains("greet(name: string): string"));
+
+  let services = &site.page("/api/html_public/views/services/index").unwrap();
+  assert!(services
+    .body
+    .contains("<code>get_user(args: {id: number}, token)</code>"));
+  assert!(services.body.contains("<p>Looks up a user by id.</p>"));
+  assert!(services.body.contains("<code>users.add(args)</code>"));
+
+  let hits = site.search("instal");
+  assert!(hits.iter().any(|h| h.url == "/guide/install"));
+  assert!(hits.iter().any(|h| h.url == "/api/bin/install#install"));
+  let hits = site.search("user");
+  assert!(hits.iter().any(|h| h.url.ends_with("#service-get_user")));
+  assert!(site.page("/missing").is_none());
+
+  assert_eq!(page_url("guide/index.md"), "/guide");
+  assert_eq!(normalize("guide/../a/./b.md"), "a/b.md");
+  assert_eq!(slug("Hello, World!"), "hello-world");
+}
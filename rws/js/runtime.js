@@ -0,0 +1,482 @@
+// Runs in every rws isolate before app code.  `RWS` is the handle apps get
+// (install.ts receives it as `rws`, along with the sub-APIs below).  Each
+// isolate runs one app; `installing` is set in the run of `rws app install`,
+// which ends when its work is done, so nothing listens for requests there.
+((window) => {
+  const { host, app, installing } = Deno.appContext();
+
+  // `tags` (requestId and anything else) are merged into the fields of
+  // every entry; `child` binds more of them.  Entries always carry this
+  // isolate's app and host.
+  const makeLogger = (tags) => {
+    const write = (level) => (msg, fields = {}) =>
+      Deno.writeLog(level, String(msg), { ...tags, ...fields });
+    return {
+      debug: write("debug"),
+      info: write("info"),
+      warn: write("warn"),
+      error: write("error"),
+      child: (more) => makeLogger({ ...tags, ...more }),
+    };
+  };
+
+  const mail = {
+    send: (message) => Deno.sendMail(message),
+  };
+
+  // Apps with a login of their own call `checkLogin` before verifying a
+  // password and report the outcome, so repeated failures lock the account
+  // out.  Isolates don't see client addresses, so the per-IP lockout only
+  // covers `POST /.rws/login` and the admin API.
+  const security = {
+    checkLogin: (attempt) => Deno.checkLogin(attempt),
+    loginFailed: (attempt) => Deno.loginFailed(attempt),
+    loginSucceeded: (attempt) => Deno.loginSucceeded(attempt),
+  };
+
+  // Accounts shared by every host.  `token(req)` resolves the session of a
+  // request (cookie or bearer) and is what handlers and services receive as
+  // `token`; it is null when nobody is signed in.  Browsers sign in with
+  // `POST /.rws/login`, which sets the cookie; `login` here issues bearer
+  // tokens, with the account lockout but no per-IP one.
+  const auth = {
+    register: (email, password, name) =>
+      Deno.authRegister(email, password, name),
+    login: (login) => Deno.authLogin(login),
+    logout: (secret) => Deno.authLogout(secret),
+    token: (req) =>
+      Deno.authToken({
+        cookie: req?.headers?.cookie ?? null,
+        authorization: req?.headers?.authorization ?? null,
+      }),
+    session: (secret) => Deno.authToken({ secret }),
+    sendVerification: (userId, url) => Deno.authSendVerification(userId, url),
+    verify: (ticket) => Deno.authVerify(ticket),
+    requestReset: (email, url) => Deno.authRequestReset(email, url),
+    reset: (ticket, password) => Deno.authReset(ticket, password),
+  };
+
+  // `can(token, "can_edit_own_posts")` checks a permission of the current
+  // app against the roles of the token's user on this host.
+  const can = (token, permission) =>
+    Deno.can({ userId: token?.user?.id ?? null, permission });
+
+  // Services are called as `fn(args, token)`, from `/json` or through
+  // `call`.  One with a `permission` refuses callers without it; for `/json`
+  // rws checks that before the call gets here.
+  const services = (() => {
+    const defs = new Map();
+    let listening = false;
+
+    const run = async (name, args, token) => {
+      const def = defs.get(name);
+      if (!def) throw new Error(`unknown service ${name}`);
+      return await def.fn(args, token);
+    };
+
+    const start = () => {
+      if (listening || installing) return;
+      listening = true;
+      (async () => {
+        for await (const call of Deno.listenServiceCalls()) {
+          run(call.service, call.args, call.token).then(
+            (result) => Deno.replyServiceCall(call.rid, { result }),
+            (e) => {
+              window.RWS.log.error("service failed", {
+                service: call.service,
+                error: String(e && e.stack || e),
+              });
+              Deno.replyServiceCall(call.rid, { error: String(e?.message ?? e) });
+            },
+          );
+        }
+      })();
+    };
+
+    return {
+      add(name, fn, { permission = null } = {}) {
+        if (typeof fn !== "function") {
+          throw new TypeError(`service ${name} needs a function`);
+        }
+        Deno.registerService({ name, ...window.RWS.context, permission });
+        defs.set(name, { fn, permission });
+        start();
+      },
+      async call(name, args = {}, token = null) {
+        const def = defs.get(name);
+        if (def?.permission && !can(token, def.permission)) {
+          throw new Deno.errors.PermissionDenied(`${name} needs ${def.permission}`);
+        }
+        return run(name, args, token);
+      },
+    };
+  })();
+
+  // A minimal AMD loader for server builds of app views.  Dependencies are
+  // `require`, `exports`, `module`, paths relative to the requiring module,
+  // or packages in the app's `node_modules`, e.g. `fb::react` or
+  // `fb::react-dom/server`.  Each loader evaluates a module once.
+  const amd = (() => {
+    const normalize = (path) => {
+      const parts = [];
+      for (const part of path.split("/")) {
+        if (part === "..") parts.pop();
+        else if (part !== ".") parts.push(part);
+      }
+      return parts.join("/");
+    };
+    const dirname = (path) => path.slice(0, path.lastIndexOf("/"));
+    const withJs = (path) => (path.endsWith(".js") ? path : `${path}.js`);
+
+    const loader = (root) => {
+      const modules = new Map();
+
+      const resolve = (dep, from) => {
+        if (dep.startsWith("./") || dep.startsWith("../")) {
+          return withJs(normalize(`${dirname(from)}/${dep}`));
+        }
+        const slash = dep.indexOf("/");
+        const pkg = slash < 0 ? dep : dep.slice(0, slash);
+        const sub = slash < 0 ? "index" : dep.slice(slash + 1);
+        return withJs(`${root}/node_modules/${pkg}/${sub}`);
+      };
+
+      const load = (path) => {
+        if (modules.has(path)) return modules.get(path).exports;
+        const module = { id: path, exports: {} };
+        modules.set(path, module);
+        const define = (...args) => {
+          const factory = args.pop();
+          const deps = Array.isArray(args[args.length - 1])
+            ? args.pop()
+            : ["require", "exports", "module"];
+          const values = deps.map((dep) => {
+            if (dep === "require") return (d) => load(resolve(d, path));
+            if (dep === "exports") return module.exports;
+            if (dep === "module") return module;
+            return load(resolve(dep, path));
+          });
+          const result = typeof factory === "function"
+            ? factory(...values)
+            : factory;
+          if (result !== undefined) module.exports = result;
+        };
+        define.amd = {};
+        const source = Deno.readTextFileSync(path);
+        new Function("define", "module", "exports", source)(
+          define,
+          module,
+          module.exports,
+        );
+        return module.exports;
+      };
+
+      return { load, require: (dep) => load(resolve(dep, `${root}/`)) };
+    };
+
+    return { loader };
+  })();
+
+  // Renders the server build of an app's React views (`module` of a page
+  // request).  The build's default export is the page component, called
+  // with `{route, base, query, slot, data}`; its optional `getData(route,
+  // ctx)` supplies `data`, which the client hydrates from, and `head(route,
+  // data)` extra tags.  Loaders are rebuilt when the build changes.
+  const react = (() => {
+    const loaders = new Map();
+
+    const loaderOf = (module) => {
+      const known = loaders.get(module.path);
+      if (known?.version === module.version) return known.loader;
+      const loader = amd.loader(module.root);
+      loaders.set(module.path, { version: module.version, loader });
+      return loader;
+    };
+
+    return (module) => async (route, ctx) => {
+      const loader = loaderOf(module);
+      const view = loader.load(module.path);
+      const React = loader.require("fb::react");
+      const { renderToString } = loader.require("fb::react-dom/server");
+      const Page = view.default ?? view;
+      const data = view.getData ? await view.getData(route, ctx) : null;
+      const { base, query, slot } = ctx;
+      const props = { route, base, query, slot, data };
+      return {
+        body: renderToString(React.createElement(Page, props)),
+        head: view.head ? view.head(route, data) : "",
+        data,
+      };
+    };
+  })();
+
+  // Server side views of `multi_page` and `inline` apps: `fn(route, ctx)`
+  // returns the HTML (or `{body, head, data, status}`) of a route, with
+  // `ctx` holding `token`, `query`, `base`, `inline` and, for nested
+  // routes, `slot` (`{app, route, args}`).  `inline(app, route)` renders
+  // an app's fragment into a page being built here; other apps render it
+  // in their own isolate, for a signed out visitor.
+  const views = (() => {
+    const defs = new Map();
+    let listening = false;
+    const keyOf = (host, app) => JSON.stringify([host ?? null, app ?? null]);
+    const find = (host, app) =>
+      defs.get(keyOf(host, app)) ?? defs.get(keyOf(null, app));
+
+    const renderPage = async (fn, route, ctx) => {
+      const page = await fn(route, ctx);
+      if (page == null) return null;
+      return typeof page === "string" ? { body: page } : page;
+    };
+
+    const start = () => {
+      if (listening || installing) return;
+      listening = true;
+      (async () => {
+        for await (const req of Deno.listenPageRequests()) {
+          const fn = find(req.host, req.app) ??
+            (req.module ? react(req.module) : null);
+          if (!fn) {
+            Deno.replyPageRequest(req.rid, { page: null });
+            continue;
+          }
+          const { route, token, query, base, inline, slot } = req;
+          renderPage(fn, route, { token, query, base, inline, slot }).then(
+            (page) => Deno.replyPageRequest(req.rid, { page }),
+            (e) => {
+              window.RWS.log.error("view failed", {
+                app: req.app,
+                route,
+                error: String(e && e.stack || e),
+              });
+              Deno.replyPageRequest(req.rid, { error: String(e?.message ?? e) });
+            },
+          );
+        }
+      })();
+    };
+
+    // Apps with a server build of their views need no view of their own.
+    start();
+
+    return {
+      set(fn) {
+        if (typeof fn !== "function") {
+          throw new TypeError("a view needs a render function");
+        }
+        const { host, app } = window.RWS.context;
+        defs.set(keyOf(host, app), fn);
+      },
+      async inline(app, route = "/", { token = null, query = "" } = {}) {
+        const { host } = window.RWS.context;
+        const fn = app === window.RWS.context.app ? find(host, app) : null;
+        if (!fn) return Deno.renderInline({ app, route, query });
+        const page = await renderPage(fn, route, {
+          token,
+          query,
+          base: "",
+          inline: true,
+        });
+        return page?.body ?? "";
+      },
+    };
+  })();
+
+  // Nested routes declared in the manifest's `nested_routes`.  `mount`
+  // puts a route of an app (this one by default) at a path pattern like
+  // "/pages/:template" under this app's mount path; `url` builds the path
+  // of a mounted route, e.g.
+  // `RWS.routes.url("main-page", { template: "home" })`.
+  const routes = {
+    mount(path, route, args = {}, app = window.RWS.context.app) {
+      Deno.mountRoute({ mount: { path, app, route, args } });
+    },
+    url(route, args = {}, app = window.RWS.context.app) {
+      return Deno.routeUrl({ app, route, args });
+    },
+  };
+
+  // `for await (const socket of RWS.ws.connections())` gets every
+  // connection made to `/ws` for this app.
+  const ws = {
+    connections: () => Deno.listenSockets(),
+  };
+
+  // One inbox per isolate receives every channel it listens to.  Listeners
+  // of a channel run one message at a time, in publish order; different
+  // channels don't wait on each other.  Channels are those of this
+  // isolate's host and app, so they are known by name alone.
+  const channels = (() => {
+    let inbox = null;
+    const listeners = new Map();
+    const queues = new Map();
+
+    const deliver = (msg) => {
+      const key = msg.channel.name;
+      const fns = [...(listeners.get(key) ?? [])];
+      const run = () => Promise.all(fns.map(async (fn) => {
+        try {
+          await fn(msg.data, msg);
+        } catch (e) {
+          window.RWS.log.error("channel listener failed", {
+            channel: msg.channel.name,
+            error: String(e && e.stack || e),
+          });
+        }
+      }));
+      const done = (queues.get(key) ?? Promise.resolve()).then(run);
+      queues.set(key, done);
+      done.then(() => {
+        if (queues.get(key) === done) queues.delete(key);
+      });
+    };
+
+    const start = () => {
+      if (inbox !== null) return;
+      inbox = Deno.openChannelInbox();
+      (async () => {
+        for (;;) {
+          const msg = await Deno.nextChannelMessage(inbox);
+          if (msg === null) return;
+          deliver(msg);
+        }
+      })();
+    };
+
+    return {
+      listen(name, fn) {
+        start();
+        if (!listeners.has(name)) {
+          listeners.set(name, new Set());
+          Deno.subscribeChannel(inbox, name);
+        }
+        listeners.get(name).add(fn);
+        return () => {
+          const fns = listeners.get(name);
+          if (fns && fns.delete(fn) && fns.size === 0) {
+            listeners.delete(name);
+            Deno.unsubscribeChannel(inbox, name);
+          }
+        };
+      },
+      publish: (name, data) => Deno.publishChannel(name, data),
+      // Messages go to the client as `{channel, seq, data}` text frames.
+      subscribeSocket: (socket, name) =>
+        Deno.subscribeChannel(socket.rid, name, true),
+      unsubscribeSocket: (socket, name) =>
+        Deno.unsubscribeChannel(socket.rid, name, true),
+    };
+  })();
+
+  // Components registered by this app.  Any page renders a component in
+  // the isolate of the app it belongs to.  rws stops waiting once the
+  // timeout has passed, and terminates the isolate if a render keeps it
+  // stuck in synchronous code; it is restarted after a while.
+  const components = (() => {
+    const defs = new Map();
+    let listening = false;
+    const DEFAULT_TIMEOUT = 2000;
+
+    const escape = (s) => String(s).replace(/[&<>"']/g, (c) => ({
+      "&": "&amp;", "<": "&lt;", ">": "&gt;", '"': "&quot;", "'": "&#39;",
+    })[c]);
+
+    const start = () => {
+      if (listening || installing) return;
+      listening = true;
+      (async () => {
+        for await (const req of Deno.listenComponentRenders()) {
+          const def = defs.get(req.name);
+          if (!def) {
+            Deno.replyComponentRender(req.rid, { html: null });
+            continue;
+          }
+          Promise.resolve().then(() => def.render(req.args)).then(
+            (html) =>
+              Deno.replyComponentRender(req.rid, {
+                html: html == null ? "" : String(html),
+              }),
+            (e) =>
+              Deno.replyComponentRender(req.rid, {
+                error: String(e && e.stack || e),
+              }),
+          );
+        }
+      })();
+    };
+
+    // A failing component renders as a comment and never fails the page.
+    const renderOne = async (name, args, timeout) => {
+      try {
+        const html = await Deno.renderComponent({ name, args, timeout });
+        return html ?? `<!-- component ${escape(name)} is not available -->`;
+      } catch (e) {
+        window.RWS.log.error("component render failed", {
+          component: name,
+          error: String(e?.message ?? e),
+        });
+        return `<!-- component ${escape(name)} failed -->`;
+      }
+    };
+
+    return {
+      add(name, def) {
+        if (typeof def.render !== "function") {
+          throw new TypeError(`component ${name} needs a render function`);
+        }
+        Deno.registerComponent({
+          name,
+          title: def.title ?? name,
+          description: def.description ?? "",
+          hasOptions: typeof def.options === "function",
+          timeout: def.timeout ?? null,
+        });
+        defs.set(name, def);
+        start();
+      },
+      list: () => Deno.listComponents(),
+      render: (name, args = {}, { timeout = DEFAULT_TIMEOUT } = {}) =>
+        renderOne(name, args, timeout),
+      // Renders every shortcode in `html`, concurrently.
+      async renderAll(html, { timeout = DEFAULT_TIMEOUT } = {}) {
+        const parts = Deno.parseComponents(html).map((segment) =>
+          segment.kind === "text"
+            ? segment.text
+            : renderOne(segment.name, segment.args, timeout)
+        );
+        return (await Promise.all(parts)).join("");
+      },
+    };
+  })();
+
+  window.RWS = {
+    // Host and app the code in this isolate runs for; channels are scoped
+    // to them.
+    context: { host, app },
+    installing,
+    log: makeLogger({}),
+    mail,
+    security,
+    auth,
+    can,
+    permissions: () => Deno.listPermissions(),
+    services,
+    addService: services.add,
+    views,
+    setView: views.set,
+    routes,
+    ws,
+    channels,
+    addChannelListener: channels.listen,
+    publish: channels.publish,
+    components,
+    addComponent: components.add,
+    // `defaults` fill in keys neither stored nor defaulted by the manifest.
+    getOptions: async (defaults = {}) => ({
+      ...defaults,
+      ...Deno.getOptions(),
+    }),
+    saveOptions: async (options) => Deno.saveOptions(options),
+    onOptionsChange: (fn) => channels.listen("rws:options", fn),
+  };
+})(globalThis);
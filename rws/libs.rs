@@ -0,0 +1,778 @@
+//! Vendored client library packages.
+//!
+//! `webpack-amd/run.js` builds an npm package into a directory with a named
+//! AMD module, `index.js` for development and `index.min.js` for
+//! production, and a `lib.js` describing it:
+//!
+//! ```js
+//! export default {
+//!     api: 1,
+//!     name: "react-dom",
+//!     version: "16.13.1",
+//!     sri: "sha512-...", // of index.min.js
+//!     dependencies: {"react": "16.13.1"},
+//!     head: (h, isDev) => [h("link", {rel: "stylesheet", href: "./a.css"})]
+//! }
+//! ```
+//!
+//! The control panel's copies pass the same object to `new_library(...)`,
+//! with `production: {src, sri}` and `development: {src}` in place of
+//! `sri`.  lib.js is read without running it, so the object has to be a
+//! literal and `head` an arrow function returning `h(tag, attrs,
+//! ...children)` calls, optionally behind `isDev ? a : b` or `isDev && a`.
+//!
+//! Apps keep their packages in `html_*/node_modules/<scope>::<name>`.  Their
+//! pages load every package with a `<script integrity>` tag, dependencies
+//! first, after the packages' head tags (see `render::shell`).
+
+use crate::swc_ecma_ast::{
+  ArrowExpr, BinaryOp, BlockStmtOrExpr, Expr, ExprOrSpread, ExprOrSuper, Lit,
+  ModuleDecl, ModuleItem, Pat, Prop, PropName, PropOrSpread, Stmt, UnaryOp,
+};
+use deno_cli::msg::MediaType;
+use deno_cli::swc_util::AstParser;
+use serde_json::{Map, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+/// Version of the lib.js format (`api`).
+pub const API_VERSION: u64 = 1;
+
+lazy_static! {
+  /// Parsed lib.js files by path, with their modification time.
+  static ref CACHE: Mutex<HashMap<PathBuf, (SystemTime, Arc<Library>)>> =
+    Mutex::new(HashMap::new());
+}
+
+#[derive(Debug)]
+pub enum LibError {
+  Io(io::Error),
+  /// lib.js isn't JavaScript, or not in the shape described above.
+  Syntax(String),
+  MissingDependency {
+    lib: String,
+    dependency: String,
+  },
+  Cycle(String),
+}
+
+impl fmt::Display for LibError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      LibError::Io(e) => write!(f, "{}", e),
+      LibError::Syntax(e) => write!(f, "invalid lib.js: {}", e),
+      LibError::MissingDependency { lib, dependency } => {
+        write!(
+          f,
+          "{} depends on {}, which isn't installed",
+          lib, dependency
+        )
+      }
+      LibError::Cycle(lib) => write!(f, "{} depends on itself", lib),
+    }
+  }
+}
+
+impl std::error::Error for LibError {}
+
+impl From<io::Error> for LibError {
+  fn from(e: io::Error) -> Self {
+    LibError::Io(e)
+  }
+}
+
+fn syntax(message: impl ToString) -> LibError {
+  LibError::Syntax(message.to_string())
+}
+
+/// One build of a package.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Build {
+  /// File in the package directory, e.g. "index.min.js".
+  pub src: String,
+  pub sri: Option<String>,
+}
+
+/// A tag returned by `head`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Node {
+  Text(String),
+  Element {
+    tag: String,
+    attrs: Vec<(String, String)>,
+    children: Vec<Node>,
+  },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Library {
+  /// Directory of the package, e.g. "fb::react".
+  pub dir: String,
+  /// Name of the AMD module, e.g. "react".
+  pub name: String,
+  pub version: String,
+  pub production: Option<Build>,
+  pub development: Option<Build>,
+  /// Packages the module requires, by name, with the version it was built
+  /// against.
+  pub dependencies: BTreeMap<String, String>,
+  head_dev: Vec<Node>,
+  head_prod: Vec<Node>,
+}
+
+/// Parameter names of `head` and the value of `isDev`.
+struct Scope<'a> {
+  h: &'a str,
+  is_dev: Option<&'a str>,
+  dev: bool,
+}
+
+impl Scope<'_> {
+  fn is_h(&self, expr: &Expr) -> bool {
+    matches!(expr, Expr::Ident(ident) if &*ident.sym == self.h)
+  }
+
+  fn condition(&self, expr: &Expr) -> Result<bool, LibError> {
+    match expr {
+      Expr::Ident(ident) if Some(&*ident.sym) == self.is_dev => Ok(self.dev),
+      Expr::Lit(Lit::Bool(value)) => Ok(value.value),
+      Expr::Unary(unary) if unary.op == UnaryOp::Bang => {
+        Ok(!self.condition(&unary.arg)?)
+      }
+      Expr::Paren(paren) => self.condition(&paren.expr),
+      _ => Err(syntax("head conditions can only test isDev")),
+    }
+  }
+
+  /// Nodes of an array, with spread arrays flattened.
+  fn nodes(&self, expr: &Expr) -> Result<Vec<Node>, LibError> {
+    match expr {
+      Expr::Array(array) => {
+        let mut nodes = Vec::new();
+        for elem in array.elems.iter().flatten() {
+          if elem.spread.is_some() {
+            nodes.extend(self.nodes(&elem.expr)?);
+          } else {
+            nodes.extend(self.child(&elem.expr)?);
+          }
+        }
+        Ok(nodes)
+      }
+      Expr::Paren(paren) => self.nodes(&paren.expr),
+      Expr::Cond(cond) => {
+        let branch = if self.condition(&cond.test)? {
+          &cond.cons
+        } else {
+          &cond.alt
+        };
+        self.nodes(branch)
+      }
+      _ => Err(syntax("head has to return an array")),
+    }
+  }
+
+  /// An element, text, or nothing for `null` and `false`.
+  fn child(&self, expr: &Expr) -> Result<Vec<Node>, LibError> {
+    match expr {
+      Expr::Call(call) => match &call.callee {
+        ExprOrSuper::Expr(callee) if self.is_h(callee) => {
+          Ok(vec![self.element(&call.args)?])
+        }
+        _ => Err(syntax("head can only call h")),
+      },
+      Expr::Lit(Lit::Str(text)) => Ok(vec![Node::Text(text.value.to_string())]),
+      Expr::Lit(Lit::Null(_)) | Expr::Lit(Lit::Bool(_)) => Ok(vec![]),
+      Expr::Bin(bin) if bin.op == BinaryOp::LogicalAnd => {
+        if self.condition(&bin.left)? {
+          self.child(&bin.right)
+        } else {
+          Ok(vec![])
+        }
+      }
+      Expr::Cond(cond) => {
+        let branch = if self.condition(&cond.test)? {
+          &cond.cons
+        } else {
+          &cond.alt
+        };
+        self.child(branch)
+      }
+      Expr::Paren(paren) => self.child(&paren.expr),
+      Expr::Array(_) => self.nodes(expr),
+      _ => Err(syntax("unsupported expression in head")),
+    }
+  }
+
+  fn element(&self, args: &[ExprOrSpread]) -> Result<Node, LibError> {
+    let tag = match args.first().map(|a| &*a.expr) {
+      Some(Expr::Lit(Lit::Str(tag))) => tag.value.to_string(),
+      _ => return Err(syntax("h needs a tag name")),
+    };
+    if !valid_name(&tag) {
+      return Err(syntax(format!("invalid tag {:?}", tag)));
+    }
+    let mut attrs = Vec::new();
+    let props = match args.get(1).map(|a| &*a.expr) {
+      Some(Expr::Object(object)) => object.props.as_slice(),
+      Some(Expr::Lit(Lit::Null(_))) | None => &[],
+      _ => return Err(syntax("h attributes have to be an object")),
+    };
+    for prop in props {
+      let (name, value) = key_value(prop)?;
+      if !valid_name(&name) {
+        return Err(syntax(format!("invalid attribute {:?}", name)));
+      }
+      match literal(value, Some(self))? {
+        Value::Null | Value::Bool(false) => {}
+        Value::Bool(true) => attrs.push((name, String::new())),
+        Value::String(value) => attrs.push((name, value)),
+        value => attrs.push((name, value.to_string())),
+      }
+    }
+    let mut children = Vec::new();
+    for arg in args.iter().skip(2) {
+      children.extend(self.child(&arg.expr)?);
+    }
+    Ok(Node::Element {
+      tag,
+      attrs,
+      children,
+    })
+  }
+}
+
+fn valid_name(name: &str) -> bool {
+  !name.is_empty()
+    && name
+      .chars()
+      .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == ':')
+}
+
+/// Key and value of a `key: value` property, the only kind lib.js may use.
+fn key_value(prop: &PropOrSpread) -> Result<(String, &Expr), LibError> {
+  let kv = match prop {
+    PropOrSpread::Prop(prop) => match &**prop {
+      Prop::KeyValue(kv) => kv,
+      _ => return Err(syntax("only key: value properties")),
+    },
+    PropOrSpread::Spread(_) => return Err(syntax("spreads aren't supported")),
+  };
+  let key = match &kv.key {
+    PropName::Ident(ident) => ident.sym.to_string(),
+    PropName::Str(text) => text.value.to_string(),
+    _ => return Err(syntax("computed keys aren't supported")),
+  };
+  Ok((key, &kv.value))
+}
+
+/// The JSON value of a literal.  Inside `head`, conditions on `isDev` are
+/// evaluated as well.
+fn literal(expr: &Expr, scope: Option<&Scope>) -> Result<Value, LibError> {
+  match expr {
+    Expr::Lit(Lit::Str(text)) => Ok(Value::String(text.value.to_string())),
+    Expr::Lit(Lit::Bool(value)) => Ok(Value::Bool(value.value)),
+    Expr::Lit(Lit::Num(number)) if number.value.fract() == 0.0 => {
+      Ok(json!(number.value as i64))
+    }
+    Expr::Lit(Lit::Num(number)) => Ok(json!(number.value)),
+    Expr::Lit(Lit::Null(_)) => Ok(Value::Null),
+    Expr::Paren(paren) => literal(&paren.expr, scope),
+    Expr::Array(array) => array
+      .elems
+      .iter()
+      .map(|elem| match elem {
+        Some(elem) if elem.spread.is_none() => literal(&elem.expr, scope),
+        _ => Err(syntax("holes and spreads aren't supported")),
+      })
+      .collect::<Result<Vec<_>, _>>()
+      .map(Value::Array),
+    Expr::Object(object) => {
+      let mut map = Map::new();
+      for prop in &object.props {
+        let (key, value) = key_value(prop)?;
+        map.insert(key, literal(value, scope)?);
+      }
+      Ok(Value::Object(map))
+    }
+    Expr::Cond(cond) => match scope {
+      Some(scope) => {
+        let branch = if scope.condition(&cond.test)? {
+          &cond.cons
+        } else {
+          &cond.alt
+        };
+        literal(branch, Some(scope))
+      }
+      None => Err(syntax("conditions are only supported in head")),
+    },
+    _ => Err(syntax("lib.js has to be a literal object")),
+  }
+}
+
+fn param_name(pat: &Pat) -> Option<&str> {
+  match pat {
+    Pat::Ident(ident) => Some(&*ident.sym),
+    _ => None,
+  }
+}
+
+/// Evaluates `head(h, isDev)`.
+fn head(arrow: &ArrowExpr, dev: bool) -> Result<Vec<Node>, LibError> {
+  let scope = Scope {
+    h: arrow.params.get(0).and_then(param_name).unwrap_or("h"),
+    is_dev: arrow.params.get(1).and_then(param_name),
+    dev,
+  };
+  match &arrow.body {
+    BlockStmtOrExpr::Expr(expr) => scope.nodes(expr),
+    BlockStmtOrExpr::BlockStmt(block) => match block.stmts.as_slice() {
+      [Stmt::Return(ret)] => match &ret.arg {
+        Some(expr) => scope.nodes(expr),
+        None => Ok(vec![]),
+      },
+      _ => Err(syntax("head has to be a single return")),
+    },
+  }
+}
+
+/// The described object: `export default {...}` or `new_library({...})`.
+fn described(module_body: &[ModuleItem]) -> Option<&Expr> {
+  module_body.iter().find_map(|item| match item {
+    ModuleItem::ModuleDecl(ModuleDecl::ExportDefaultExpr(export)) => {
+      Some(&*export.expr)
+    }
+    ModuleItem::Stmt(Stmt::Expr(stmt)) => match &*stmt.expr {
+      Expr::Call(call) => call.args.first().map(|arg| &*arg.expr),
+      _ => None,
+    },
+    _ => None,
+  })
+}
+
+fn build(value: Option<&Value>, default: &str) -> Build {
+  let src = value
+    .and_then(|b| b.get("src"))
+    .and_then(Value::as_str)
+    .unwrap_or(default);
+  let src = if src.starts_with("./") {
+    &src[2..]
+  } else {
+    src
+  };
+  Build {
+    src: src.to_string(),
+    sri: value
+      .and_then(|b| b.get("sri"))
+      .and_then(Value::as_str)
+      .map(String::from),
+  }
+}
+
+impl Library {
+  /// Reads the lib.js of the package in directory `dir`.
+  pub fn parse(dir: &str, source: &str) -> Result<Library, LibError> {
+    let parser = AstParser::new();
+    parser.parse_module("lib.js", MediaType::JavaScript, source, |module| {
+      let module = module.map_err(syntax)?;
+      let object = match described(&module.body) {
+        Some(Expr::Object(object)) => object,
+        _ => return Err(syntax("no library object")),
+      };
+
+      let mut fields = Map::new();
+      let mut head_fn = None;
+      for prop in &object.props {
+        let (key, value) = key_value(prop)?;
+        match (key.as_str(), value) {
+          ("head", Expr::Arrow(arrow)) => head_fn = Some(arrow),
+          ("head", _) => return Err(syntax("head has to be an arrow")),
+          _ => {
+            fields.insert(key, literal(value, None)?);
+          }
+        }
+      }
+
+      let api = fields.get("api").and_then(Value::as_u64);
+      if api != Some(API_VERSION) {
+        return Err(syntax(format!("unsupported api {:?}", api)));
+      }
+      let text = |key: &str| {
+        fields
+          .get(key)
+          .and_then(Value::as_str)
+          .map(String::from)
+          .ok_or_else(|| syntax(format!("{} is missing", key)))
+      };
+      let mut production = build(fields.get("production"), "index.min.js");
+      if let Some(sri) = fields.get("sri").and_then(Value::as_str) {
+        production.sri = Some(sri.to_string());
+      }
+      let dependencies = match fields.get("dependencies") {
+        Some(Value::Object(deps)) => deps
+          .iter()
+          .map(|(name, version)| {
+            let version = version.as_str().unwrap_or_default().to_string();
+            (name.clone(), version)
+          })
+          .collect(),
+        _ => BTreeMap::new(),
+      };
+      let (head_dev, head_prod) = match head_fn {
+        Some(arrow) => (head(arrow, true)?, head(arrow, false)?),
+        None => (vec![], vec![]),
+      };
+
+      Ok(Library {
+        dir: dir.to_string(),
+        name: text("name")?,
+        version: text("version")?,
+        production: Some(production),
+        development: Some(build(fields.get("development"), "index.js")),
+        dependencies,
+        head_dev,
+        head_prod,
+      })
+    })
+  }
+
+  /// The package in `dir`, without the builds that aren't there.
+  pub fn load(dir: &Path) -> Result<Library, LibError> {
+    let name = dir.file_name().unwrap_or_default().to_string_lossy();
+    let source = fs::read_to_string(dir.join("lib.js"))?;
+    let mut lib = Library::parse(&name, &source)?;
+    let exists = |build: &Option<Build>| {
+      build.as_ref().map_or(false, |b| dir.join(&b.src).is_file())
+    };
+    if !exists(&lib.production) {
+      lib.production = None;
+    }
+    if !exists(&lib.development) {
+      lib.development = None;
+    }
+    Ok(lib)
+  }
+
+  /// The build to serve, preferring the unminified one in development.
+  /// Only the production build is checked against its SRI hash.
+  pub fn build(&self, dev: bool) -> Option<&Build> {
+    if dev {
+      self
+        .development
+        .as_ref()
+        .or_else(|| self.production.as_ref())
+    } else {
+      self
+        .production
+        .as_ref()
+        .or_else(|| self.development.as_ref())
+    }
+  }
+
+  pub fn head(&self, dev: bool) -> &[Node] {
+    if dev {
+      &self.head_dev
+    } else {
+      &self.head_prod
+    }
+  }
+}
+
+/// The packages in a `node_modules` directory.  Packages without a lib.js
+/// are left out; broken ones are logged and left out.
+pub fn load(node_modules: &Path) -> Vec<Arc<Library>> {
+  let entries = match fs::read_dir(node_modules) {
+    Ok(entries) => entries,
+    Err(_) => return Vec::new(),
+  };
+  let mut dirs: Vec<PathBuf> = entries
+    .filter_map(|e| e.ok())
+    .map(|e| e.path())
+    .filter(|p| p.join("lib.js").is_file())
+    .collect();
+  dirs.sort();
+
+  let mut cache = CACHE.lock().unwrap();
+  let mut libs = Vec::new();
+  for dir in dirs {
+    let path = dir.join("lib.js");
+    let modified = fs::metadata(&path)
+      .and_then(|m| m.modified())
+      .unwrap_or(SystemTime::UNIX_EPOCH);
+    match cache.get(&path) {
+      Some((time, lib)) if *time == modified => libs.push(Arc::clone(lib)),
+      _ => match Library::load(&dir) {
+        Ok(lib) => {
+          let lib = Arc::new(lib);
+          cache.insert(path, (modified, Arc::clone(&lib)));
+          libs.push(lib);
+        }
+        Err(e) => error!("{}: {}", path.display(), e),
+      },
+    }
+  }
+  libs
+}
+
+/// Orders `libs` so every package comes after its dependencies.
+/// Dependencies built against another version than the installed one are
+/// logged, they usually still work.
+pub fn resolve(libs: &[Arc<Library>]) -> Result<Vec<Arc<Library>>, LibError> {
+  let by_name: HashMap<&str, &Arc<Library>> =
+    libs.iter().map(|lib| (lib.name.as_str(), lib)).collect();
+  // 0 unvisited, 1 in progress, 2 done.
+  let mut state: HashMap<&str, u8> = HashMap::new();
+  let mut ordered = Vec::new();
+
+  fn visit<'a>(
+    lib: &'a Arc<Library>,
+    by_name: &HashMap<&'a str, &'a Arc<Library>>,
+    state: &mut HashMap<&'a str, u8>,
+    ordered: &mut Vec<Arc<Library>>,
+  ) -> Result<(), LibError> {
+    match state.get(lib.name.as_str()) {
+      Some(2) => return Ok(()),
+      Some(1) => return Err(LibError::Cycle(lib.name.clone())),
+      _ => {}
+    }
+    state.insert(&lib.name, 1);
+    for (name, version) in &lib.dependencies {
+      let dependency: &'a Arc<Library> = by_name
+        .get(name.as_str())
+        .ok_or_else(|| LibError::MissingDependency {
+          lib: lib.name.clone(),
+          dependency: name.clone(),
+        })?;
+      if !version.is_empty() && dependency.version != *version {
+        warn!(
+          "{} was built against {} {}, {} is installed",
+          lib.name, name, version, dependency.version
+        );
+      }
+      visit(dependency, by_name, state, ordered)?;
+    }
+    state.insert(&lib.name, 2);
+    ordered.push(Arc::clone(lib));
+    Ok(())
+  }
+
+  for lib in libs {
+    visit(lib, &by_name, &mut state, &mut ordered)?;
+  }
+  Ok(ordered)
+}
+
+fn escape(text: &str) -> String {
+  text
+    .replace('&', "&amp;")
+    .replace('<', "&lt;")
+    .replace('>', "&gt;")
+    .replace('"', "&quot;")
+}
+
+/// Elements whose text isn't parsed for entities, so it can't be escaped.
+const RAW_TEXT: &[&str] = &["script", "style"];
+
+/// The text of a `script` or `style` element as is, except that its end tag
+/// can't appear in it: `</script` becomes `<\/script`, which means the same
+/// in a JS string or CSS.
+fn raw_text(tag: &str, text: &str) -> String {
+  let end = format!("</{}", tag);
+  let lower = text.to_ascii_lowercase();
+  let mut out = String::new();
+  let mut last = 0;
+  for (at, _) in lower.match_indices(&end) {
+    out.push_str(&text[last..at + 1]);
+    out.push('\\');
+    last = at + 1;
+  }
+  out.push_str(&text[last..]);
+  out
+}
+
+/// `./` URLs in head tags are relative to the package.
+fn render_node(node: &Node, package_url: &str, out: &mut String) {
+  match node {
+    Node::Text(text) => out.push_str(&escape(text)),
+    Node::Element {
+      tag,
+      attrs,
+      children,
+    } => {
+      out.push('<');
+      out.push_str(tag);
+      for (name, value) in attrs {
+        let value = if value.starts_with("./") {
+          format!("{}{}", package_url, &value[2..])
+        } else {
+          value.clone()
+        };
+        out.push_str(&format!(" {}=\"{}\"", name, escape(&value)));
+      }
+      out.push('>');
+      let tag = tag.to_ascii_lowercase();
+      let raw = RAW_TEXT.contains(&tag.as_str());
+      for child in children {
+        match child {
+          Node::Text(text) if raw => out.push_str(&raw_text(&tag, text)),
+          _ => render_node(child, package_url, out),
+        }
+      }
+      let void = ["link", "meta", "base"].contains(&tag.as_str());
+      if !void {
+        out.push_str(&format!("</{}>", tag));
+      }
+    }
+  }
+}
+
+/// The head tags and scripts of `libs` (in order), for a page served at
+/// `base`, e.g. "/shop/".
+pub fn tags(libs: &[Arc<Library>], base: &str, dev: bool) -> String {
+  let mut out = String::new();
+  for lib in libs {
+    let url = format!("{}node_modules/{}/", base, lib.dir);
+    for node in lib.head(dev) {
+      render_node(node, &url, &mut out);
+      out.push('\n');
+    }
+    if let Some(build) = lib.build(dev) {
+      let src = format!("{}{}", url, build.src);
+      out.push_str(&format!("<script src=\"{}\"", escape(&src)));
+      if let (Some(sri), false) = (&build.sri, dev) {
+        out.push_str(&format!(
+          " integrity=\"{}\" crossorigin=\"anonymous\"",
+          escape(sri)
+        ));
+      }
+      out.push_str("></script>\n");
+    }
+  }
+  out
+}
+
+/// RequireJS `paths` of the packages, and a `map` from their directory
+/// names (what app code imports, e.g. "fb::react") to the module names.
+pub fn require_config(
+  libs: &[Arc<Library>],
+  dev: bool,
+) -> (Map<String, Value>, Map<String, Value>) {
+  let mut paths = Map::new();
+  let mut map = Map::new();
+  for lib in libs {
+    if let Some(build) = lib.build(dev) {
+      let src = build.src.trim_end_matches(".js");
+      let path = format!("node_modules/{}/{}", lib.dir, src);
+      paths.insert(lib.name.clone(), Value::String(path));
+    }
+    if lib.dir != lib.name {
+      map.insert(lib.dir.clone(), Value::String(lib.name.clone()));
+    }
+  }
+  (paths, map)
+}
+
+#[test]
+fn libs_test() {
+  let react = Library::parse(
+    "fb::react",
+    include_str!("../example-app/html_public/node_modules/fb::react/lib.js"),
+  )
+  .unwrap();
+  assert_eq!(react.name, "react");
+  assert_eq!(react.version, "16.13.1");
+  assert_eq!(react.dependencies["object-assign"], "4.1.1");
+  let production = react.production.clone().unwrap();
+  assert_eq!(production.src, "index.min.js");
+  assert!(production.sri.unwrap().starts_with("sha512-"));
+  assert_eq!(react.development.clone().unwrap().src, "index.js");
+
+  // The control panel's format, with a stylesheet in head.
+  let carbon = Library::parse(
+    "carbon-components",
+    include_str!("../control-panel/src/libs/carbon-components/lib.js"),
+  )
+  .unwrap();
+  assert_eq!(carbon.name, "carbon-components");
+  assert_eq!(carbon.head(false).len(), 1);
+  let html = tags(&[Arc::new(carbon)], "/", false);
+  assert!(html.starts_with(
+    "<link rel=\"stylesheet\" \
+     href=\"/node_modules/carbon-components/carbon-components.min.css\" \
+     integrity=\"sha512-BZWQ"
+  ));
+  assert!(html.contains(
+    "<script src=\"/node_modules/carbon-components/index.min.js\" \
+     integrity=\"sha512-Hj1N"
+  ));
+
+  let lib = Library::parse(
+    "x",
+    r#"export default {
+      api: 1, name: "x", version: "1", dependencies: {},
+      head: (h, isDev) => [
+        isDev ? h("script", {src: "./debug.js"}) : null,
+        ...(isDev ? [] : [h("meta", {name: "x", content: "prod"})]),
+        h("style", {}, "body > p { margin: 0 }"),
+        h("script", {}, "if (a < b && c) x = '</SCRIPT>';"),
+      ],
+    }"#,
+  )
+  .unwrap();
+  let dev = tags(&[Arc::new(lib.clone())], "/shop/", true);
+  assert!(dev.contains("<script src=\"/shop/node_modules/x/debug.js\">"));
+  assert!(!dev.contains("<meta"));
+  let prod = tags(&[Arc::new(lib)], "/shop/", false);
+  assert!(prod.contains("<meta name=\"x\" content=\"prod\">"));
+  assert!(prod.contains("<style>body > p { margin: 0 }</style>"));
+  assert!(prod.contains("<script>if (a < b && c) x = '<\\/SCRIPT>';</script>"));
+  assert!(Library::parse("x", "export default {api: 2}").is_err());
+  assert!(Library::parse(
+    "x",
+    r#"export default {api: 1, name: "x", version: "1",
+       head: (h) => [fetch("http://example.com")]}"#
+  )
+  .is_err());
+
+  let lib = |name: &str, deps: &[&str]| {
+    Arc::new(Library {
+      dir: format!("fb::{}", name),
+      name: name.to_string(),
+      version: "1".to_string(),
+      production: None,
+      development: None,
+      dependencies: deps
+        .iter()
+        .map(|d| (d.to_string(), "1".to_string()))
+        .collect(),
+      head_dev: vec![],
+      head_prod: vec![],
+    })
+  };
+  let libs = vec![
+    lib("react-dom", &["react", "object-assign"]),
+    lib("react", &["object-assign"]),
+    lib("object-assign", &[]),
+  ];
+  let names = |libs: Vec<Arc<Library>>| {
+    libs.iter().map(|l| l.name.clone()).collect::<Vec<_>>()
+  };
+  assert_eq!(
+    names(resolve(&libs).unwrap()),
+    vec!["object-assign", "react", "react-dom"]
+  );
+  assert!(matches!(
+    resolve(&libs[..2]),
+    Err(LibError::MissingDependency { .. })
+  ));
+  let cycle = vec![lib("a", &["b"]), lib("b", &["a"])];
+  assert!(matches!(resolve(&cycle), Err(LibError::Cycle(_))));
+
+  let (paths, map) = require_config(&libs, false);
+  assert!(paths.is_empty());
+  assert_eq!(map["fb::react"], json!("react"));
+}
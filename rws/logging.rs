@@ -0,0 +1,389 @@
+//! Structured log storage.
+//!
+//! Every entry is a JSON line tagged with app, host and request id where
+//! known.  Entries are appended to `<rws dir>/logs/<app>/current.log`, which
+//! is rotated once it grows past `MAX_FILE_BYTES`; only the newest
+//! `MAX_FILES` files are kept per app.  Appending is left to a thread of
+//! its own, so logging never waits on the disk.  Entries are also broadcast
+//! so the control panel can tail them live.
+
+use crate::config;
+use serde_derive::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Mutex};
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::broadcast;
+
+const MAX_FILE_BYTES: u64 = 10 * 1024 * 1024;
+const MAX_FILES: usize = 5;
+const CURRENT_FILE: &str = "current.log";
+const DEFAULT_QUERY_LIMIT: usize = 200;
+
+/// App name used for entries that don't belong to an app.
+pub const SYSTEM_APP: &str = "rws";
+
+lazy_static! {
+  static ref WRITER: Mutex<mpsc::Sender<LogEntry>> = Mutex::new(writer());
+  static ref TAIL: broadcast::Sender<LogEntry> = broadcast::channel(1024).0;
+}
+
+static ROTATIONS: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(
+  Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+  Error,
+  Warn,
+  Info,
+  Debug,
+}
+
+impl From<log::Level> for LogLevel {
+  fn from(level: log::Level) -> Self {
+    match level {
+      log::Level::Error => LogLevel::Error,
+      log::Level::Warn => LogLevel::Warn,
+      log::Level::Info => LogLevel::Info,
+      log::Level::Debug | log::Level::Trace => LogLevel::Debug,
+    }
+  }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LogEntry {
+  /// Milliseconds since the unix epoch.
+  pub time: u64,
+  pub level: LogLevel,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub app: Option<String>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub host: Option<String>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub request_id: Option<String>,
+  pub msg: String,
+  #[serde(default, skip_serializing_if = "Map::is_empty")]
+  pub fields: Map<String, Value>,
+}
+
+impl LogEntry {
+  pub fn new(level: LogLevel, msg: String) -> Self {
+    LogEntry {
+      time: now_millis(),
+      level,
+      app: None,
+      host: None,
+      request_id: None,
+      msg,
+      fields: Map::new(),
+    }
+  }
+
+  /// Builds an entry from app supplied fields, lifting the `app`, `host`
+  /// and `requestId` tags out of `fields`.
+  pub fn with_fields(
+    level: LogLevel,
+    msg: String,
+    mut fields: Map<String, Value>,
+  ) -> Self {
+    let mut take = |key: &str| match fields.remove(key) {
+      Some(Value::String(s)) => Some(s),
+      Some(other) => Some(other.to_string()),
+      None => None,
+    };
+    let app = take("app");
+    let host = take("host");
+    let request_id = take("requestId");
+    LogEntry {
+      app,
+      host,
+      request_id,
+      fields,
+      ..LogEntry::new(level, msg)
+    }
+  }
+}
+
+fn now_millis() -> u64 {
+  let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+  now.as_secs() * 1000 + u64::from(now.subsec_millis())
+}
+
+/// Filters for `query` and live tails; every set field must match.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct LogQuery {
+  pub app: Option<String>,
+  pub host: Option<String>,
+  pub request_id: Option<String>,
+  /// Most verbose level to include, e.g. "warn" returns warnings and errors.
+  pub level: Option<LogLevel>,
+  /// Case sensitive substring of the message.
+  pub contains: Option<String>,
+  /// Only entries at or after this time (milliseconds).
+  pub since: Option<u64>,
+  pub limit: Option<usize>,
+}
+
+impl LogQuery {
+  pub fn matches(&self, entry: &LogEntry) -> bool {
+    let tag = |want: &Option<String>, have: &Option<String>| match want {
+      Some(want) => have.as_ref() == Some(want),
+      None => true,
+    };
+    tag(&self.app, &entry.app)
+      && tag(&self.host, &entry.host)
+      && tag(&self.request_id, &entry.request_id)
+      && self.level.map_or(true, |level| entry.level <= level)
+      && self
+        .contains
+        .as_ref()
+        .map_or(true, |text| entry.msg.contains(text.as_str()))
+      && self.since.map_or(true, |since| entry.time >= since)
+  }
+}
+
+pub struct LogStore {
+  dir: PathBuf,
+  max_bytes: u64,
+  max_files: usize,
+  /// `current.log` of the apps written to so far, with its length.
+  open: HashMap<PathBuf, (File, u64)>,
+}
+
+fn open_current(dir: &Path) -> io::Result<(File, u64)> {
+  fs::create_dir_all(dir)?;
+  let file = OpenOptions::new()
+    .create(true)
+    .append(true)
+    .open(dir.join(CURRENT_FILE))?;
+  let len = file.metadata()?.len();
+  Ok((file, len))
+}
+
+impl LogStore {
+  pub fn new(dir: PathBuf) -> Self {
+    LogStore {
+      dir,
+      max_bytes: MAX_FILE_BYTES,
+      max_files: MAX_FILES,
+      open: HashMap::new(),
+    }
+  }
+
+  fn app_dir(&self, app: &str) -> PathBuf {
+    self.dir.join(config::file_name(app))
+  }
+
+  /// Appends `entry` to its app's file, which is kept open from then on.
+  pub fn append(&mut self, entry: &LogEntry) -> io::Result<()> {
+    let dir = self.app_dir(entry.app.as_deref().unwrap_or(SYSTEM_APP));
+    if !self.open.contains_key(&dir) {
+      let current = open_current(&dir)?;
+      self.open.insert(dir.clone(), current);
+    }
+    if self.open[&dir].1 >= self.max_bytes {
+      self.open.remove(&dir);
+      self.rotate(&dir)?;
+      let current = open_current(&dir)?;
+      self.open.insert(dir.clone(), current);
+    }
+
+    let mut line = serde_json::to_vec(entry)?;
+    line.push(b'\n');
+    let (file, len) = self.open.get_mut(&dir).unwrap();
+    if let Err(e) = file.write_all(&line) {
+      // Opened again next time, in case the file went away.
+      self.open.remove(&dir);
+      return Err(e);
+    }
+    *len += line.len() as u64;
+    Ok(())
+  }
+
+  fn rotate(&self, dir: &Path) -> io::Result<()> {
+    let seq = ROTATIONS.fetch_add(1, Ordering::SeqCst);
+    fs::rename(
+      dir.join(CURRENT_FILE),
+      dir.join(format!("{:013}-{:06}.log", now_millis(), seq)),
+    )?;
+    for old in self.files(dir).into_iter().skip(self.max_files) {
+      fs::remove_file(old)?;
+    }
+    Ok(())
+  }
+
+  /// Log files of one app, newest first.
+  fn files(&self, dir: &Path) -> Vec<PathBuf> {
+    let mut rotated: Vec<PathBuf> = match fs::read_dir(dir) {
+      Ok(entries) => entries
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| {
+          p.extension().map_or(false, |ext| ext == "log")
+            && p.file_name().map_or(false, |name| name != CURRENT_FILE)
+        })
+        .collect(),
+      Err(_) => return Vec::new(),
+    };
+    // Rotated files are named by time and sequence, so name order is age
+    // order.
+    rotated.sort();
+    rotated.reverse();
+
+    let current = dir.join(CURRENT_FILE);
+    let mut files = Vec::with_capacity(rotated.len() + 1);
+    if current.exists() {
+      files.push(current);
+    }
+    files.extend(rotated);
+    files
+  }
+
+  fn apps(&self) -> Vec<String> {
+    match fs::read_dir(&self.dir) {
+      Ok(entries) => entries
+        .filter_map(|e| e.ok())
+        .filter(|e| e.path().is_dir())
+        .filter_map(|e| e.file_name().into_string().ok())
+        .collect(),
+      Err(_) => Vec::new(),
+    }
+  }
+
+  /// Newest matching entries first.
+  pub fn query(&self, query: &LogQuery) -> Vec<LogEntry> {
+    let limit = query.limit.unwrap_or(DEFAULT_QUERY_LIMIT);
+    let apps = match &query.app {
+      Some(app) => vec![config::file_name(app)],
+      None => self.apps(),
+    };
+
+    let mut found = Vec::new();
+    for app in apps {
+      let mut from_app = 0;
+      'files: for file in self.files(&self.dir.join(app)) {
+        let text = match fs::read_to_string(&file) {
+          Ok(text) => text,
+          Err(_) => continue,
+        };
+        for line in text.lines().rev() {
+          let entry: LogEntry = match serde_json::from_str(line) {
+            Ok(entry) => entry,
+            Err(_) => continue,
+          };
+          if query.since.map_or(false, |since| entry.time < since) {
+            break 'files;
+          }
+          if query.matches(&entry) {
+            found.push(entry);
+            from_app += 1;
+            if from_app == limit {
+              break 'files;
+            }
+          }
+        }
+      }
+    }
+
+    found.sort_by(|a, b| b.time.cmp(&a.time));
+    found.truncate(limit);
+    found
+  }
+}
+
+fn logs_dir() -> PathBuf {
+  config::rws_dir().join("logs")
+}
+
+/// Starts the thread that appends entries to the log files.
+fn writer() -> mpsc::Sender<LogEntry> {
+  let (tx, rx) = mpsc::channel::<LogEntry>();
+  let mut store = LogStore::new(logs_dir());
+  let spawned =
+    thread::Builder::new()
+      .name("log writer".to_string())
+      .spawn(move || {
+        for entry in rx {
+          if let Err(e) = store.append(&entry) {
+            // Can't go through `log` here, it would come straight back.
+            eprintln!("could not write log entry: {}", e);
+          }
+        }
+      });
+  if let Err(e) = spawned {
+    eprintln!("could not start the log writer: {}", e);
+  }
+  tx
+}
+
+/// Queues `entry` for storage and hands it to live tails.
+pub fn write(entry: LogEntry) {
+  let _ = WRITER.lock().unwrap().send(entry.clone());
+  let _ = TAIL.send(entry);
+}
+
+/// Entries written so far; the newest few may still be on their way to
+/// the disk.
+pub fn query(query: &LogQuery) -> Vec<LogEntry> {
+  LogStore::new(logs_dir()).query(query)
+}
+
+/// Receives every entry written from now on.
+pub fn subscribe() -> broadcast::Receiver<LogEntry> {
+  TAIL.subscribe()
+}
+
+#[test]
+fn log_store_test() {
+  let dir = tempfile::TempDir::new().unwrap();
+  let mut store = LogStore::new(dir.path().to_path_buf());
+  store.max_bytes = 400;
+  store.max_files = 2;
+
+  for i in 0..40 {
+    let mut fields = Map::new();
+    fields.insert("app".to_string(), json!(if i % 2 == 0 { "a" } else { "b" }));
+    fields.insert("requestId".to_string(), json!(format!("req-{}", i)));
+    fields.insert("n".to_string(), json!(i));
+    let level = if i % 10 == 0 {
+      LogLevel::Error
+    } else {
+      LogLevel::Info
+    };
+    let mut entry =
+      LogEntry::with_fields(level, format!("entry {}", i), fields);
+    entry.time = i;
+    store.append(&entry).unwrap();
+  }
+
+  // Rotation keeps the current file plus `max_files` old ones.
+  let files = store.files(&dir.path().join("a"));
+  assert_eq!(files.len(), 3);
+
+  let newest = store.query(&LogQuery {
+    app: Some("a".to_string()),
+    limit: Some(3),
+    ..LogQuery::default()
+  });
+  let msgs: Vec<&str> = newest.iter().map(|e| e.msg.as_str()).collect();
+  assert_eq!(msgs, vec!["entry 38", "entry 36", "entry 34"]);
+  assert_eq!(newest[0].request_id.as_deref(), Some("req-38"));
+  assert_eq!(newest[0].fields["n"], json!(38));
+
+  let errors = store.query(&LogQuery {
+    level: Some(LogLevel::Error),
+    ..LogQuery::default()
+  });
+  let msgs: Vec<&str> = errors.iter().map(|e| e.msg.as_str()).collect();
+  // "entry 0" went out with the oldest rotated file.
+  assert_eq!(msgs, vec!["entry 30", "entry 20", "entry 10"]);
+}
@@ -0,0 +1,874 @@
+//! SMTP client behind `RWS.mail.send`.
+//!
+//! Messages are delivered straight away when possible.  Transient failures
+//! (network errors, 4xx replies) are written to a queue on disk and retried
+//! with exponential backoff by `run_queue`.
+
+use crate::config::{self, SmtpConfig, SmtpTls};
+use percent_encoding::{utf8_percent_encode, AsciiSet};
+use serde_derive::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::io::{
+  AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader,
+};
+use tokio::net::TcpStream;
+use tokio_rustls::{client::TlsStream, rustls::ClientConfig, TlsConnector};
+use webpki::DNSNameRef;
+
+const MAX_ATTEMPTS: u32 = 10;
+const QUEUE_INTERVAL: Duration = Duration::from_secs(30);
+/// What RFC 2231 parameter values can't carry unencoded.
+const PARAM_VALUE: &AsciiSet = &percent_encoding::NON_ALPHANUMERIC
+  .remove(b'!')
+  .remove(b'#')
+  .remove(b'$')
+  .remove(b'&')
+  .remove(b'+')
+  .remove(b'-')
+  .remove(b'.')
+  .remove(b'^')
+  .remove(b'_')
+  .remove(b'`')
+  .remove(b'|')
+  .remove(b'~');
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Attachment {
+  pub filename: String,
+  #[serde(default)]
+  pub content_type: Option<String>,
+  /// Base64 encoded file content.
+  pub content: String,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Message {
+  #[serde(default)]
+  pub from: Option<String>,
+  pub to: Vec<String>,
+  #[serde(default)]
+  pub cc: Vec<String>,
+  #[serde(default)]
+  pub bcc: Vec<String>,
+  pub subject: String,
+  #[serde(default)]
+  pub text: Option<String>,
+  #[serde(default)]
+  pub html: Option<String>,
+  #[serde(default)]
+  pub attachments: Vec<Attachment>,
+}
+
+impl Message {
+  fn recipients(&self) -> impl Iterator<Item = &String> {
+    self.to.iter().chain(self.cc.iter()).chain(self.bcc.iter())
+  }
+
+  /// Refuses line breaks in anything that ends up in an SMTP command or a
+  /// header, where they would start a command or header of their own.
+  fn check_lines(&self, config: &SmtpConfig) -> MailResult<()> {
+    let from = self.from.as_ref().unwrap_or(&config.from);
+    let single_line = |field: &str, value: &str| {
+      if value.contains(|c: char| c == '\r' || c == '\n') {
+        Err(MailError::permanent(format!(
+          "{} contains a line break",
+          field
+        )))
+      } else {
+        Ok(())
+      }
+    };
+    single_line("from", from)?;
+    for rcpt in self.recipients() {
+      single_line("recipient", rcpt)?;
+    }
+    single_line("subject", &self.subject)?;
+    for attachment in &self.attachments {
+      single_line("attachment filename", &attachment.filename)?;
+      if let Some(content_type) = &attachment.content_type {
+        single_line("attachment content type", content_type)?;
+      }
+    }
+    Ok(())
+  }
+}
+
+#[derive(Debug)]
+pub struct MailError {
+  pub message: String,
+  /// Retrying won't help (5xx reply, bad input or configuration).
+  pub permanent: bool,
+}
+
+impl MailError {
+  fn transient<S: Into<String>>(message: S) -> Self {
+    MailError {
+      message: message.into(),
+      permanent: false,
+    }
+  }
+
+  fn permanent<S: Into<String>>(message: S) -> Self {
+    MailError {
+      message: message.into(),
+      permanent: true,
+    }
+  }
+
+  fn from_reply(reply: &Reply) -> Self {
+    MailError {
+      message: format!("{} {}", reply.code, reply.lines.join(" ")),
+      permanent: reply.code >= 500,
+    }
+  }
+}
+
+impl fmt::Display for MailError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.write_str(&self.message)
+  }
+}
+
+impl std::error::Error for MailError {}
+
+impl From<io::Error> for MailError {
+  fn from(error: io::Error) -> Self {
+    MailError::transient(error.to_string())
+  }
+}
+
+type MailResult<T> = Result<T, MailError>;
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SendStatus {
+  /// Queue id, set when delivery was deferred.
+  pub id: Option<String>,
+  pub queued: bool,
+}
+
+/// Sends `message` with the configured SMTP server, queueing it for retry
+/// when delivery fails for a transient reason.
+pub async fn send(message: Message) -> MailResult<SendStatus> {
+  let smtp = match config::get().smtp.clone() {
+    Some(smtp) => smtp,
+    None => return Err(MailError::permanent("SMTP is not configured")),
+  };
+
+  match send_with(&smtp, &message).await {
+    Ok(()) => Ok(SendStatus {
+      id: None,
+      queued: false,
+    }),
+    Err(error) if error.permanent => Err(error),
+    Err(error) => {
+      warn!("mail to {:?} deferred: {}", message.to, error);
+      let id = MailQueue::open_default().push(message, &error)?;
+      Ok(SendStatus {
+        id: Some(id),
+        queued: true,
+      })
+    }
+  }
+}
+
+/// Leaves `message` for `run_queue` to send on its next pass, for callers
+/// that mustn't wait on the SMTP server.
+pub fn queue(message: Message) -> io::Result<String> {
+  MailQueue::open_default().push_new(message)
+}
+
+struct Reply {
+  code: u16,
+  lines: Vec<String>,
+}
+
+struct Connection<S> {
+  stream: BufReader<S>,
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> Connection<S> {
+  fn new(stream: S) -> Self {
+    Connection {
+      stream: BufReader::new(stream),
+    }
+  }
+
+  async fn read_reply(&mut self) -> MailResult<Reply> {
+    let mut lines = Vec::new();
+    loop {
+      let mut line = String::new();
+      if self.stream.read_line(&mut line).await? == 0 {
+        return Err(MailError::transient("connection closed by server"));
+      }
+      let line = line.trim_end();
+      let code = match line.get(..3).and_then(|c| c.parse::<u16>().ok()) {
+        Some(code) => code,
+        None => {
+          return Err(MailError::transient(format!("bad reply: {}", line)))
+        }
+      };
+      lines.push(line.get(4..).unwrap_or("").to_string());
+      if line.as_bytes().get(3) != Some(&b'-') {
+        return Ok(Reply { code, lines });
+      }
+    }
+  }
+
+  async fn expect(&mut self, codes: &[u16]) -> MailResult<Reply> {
+    let reply = self.read_reply().await?;
+    if codes.contains(&reply.code) {
+      Ok(reply)
+    } else {
+      Err(MailError::from_reply(&reply))
+    }
+  }
+
+  async fn command(&mut self, line: &str, codes: &[u16]) -> MailResult<Reply> {
+    self.stream.write_all(line.as_bytes()).await?;
+    self.stream.write_all(b"\r\n").await?;
+    self.stream.flush().await?;
+    self.expect(codes).await
+  }
+
+  /// Sends EHLO and returns the capabilities the server advertised.
+  async fn ehlo(&mut self, config: &SmtpConfig) -> MailResult<Vec<String>> {
+    let name = config.hello_name.as_deref().unwrap_or("localhost");
+    let reply = self.command(&format!("EHLO {}", name), &[250]).await?;
+    Ok(
+      reply
+        .lines
+        .into_iter()
+        .skip(1)
+        .map(|cap| cap.to_uppercase())
+        .collect(),
+    )
+  }
+
+  async fn auth(
+    &mut self,
+    caps: &[String],
+    username: &str,
+    password: &str,
+  ) -> MailResult<()> {
+    let methods: Vec<&str> = caps
+      .iter()
+      .filter(|cap| cap.starts_with("AUTH"))
+      .flat_map(|cap| cap[4..].split(|c| c == ' ' || c == '='))
+      .collect();
+
+    if methods.contains(&"PLAIN") {
+      let token = base64::encode(format!("\0{}\0{}", username, password));
+      self
+        .command(&format!("AUTH PLAIN {}", token), &[235])
+        .await?;
+    } else if methods.contains(&"LOGIN") {
+      self.command("AUTH LOGIN", &[334]).await?;
+      self.command(&base64::encode(username), &[334]).await?;
+      self.command(&base64::encode(password), &[235]).await?;
+    } else {
+      return Err(MailError::permanent(
+        "server offers neither AUTH PLAIN nor AUTH LOGIN",
+      ));
+    }
+    Ok(())
+  }
+
+  async fn deliver(
+    &mut self,
+    config: &SmtpConfig,
+    caps: &[String],
+    message: &Message,
+    body: &str,
+  ) -> MailResult<()> {
+    if let (Some(username), Some(password)) =
+      (&config.username, &config.password)
+    {
+      self.auth(caps, username, password).await?;
+    }
+
+    let from = message.from.as_ref().unwrap_or(&config.from);
+    self
+      .command(&format!("MAIL FROM:<{}>", envelope_address(from)), &[250])
+      .await?;
+    for rcpt in message.recipients() {
+      self
+        .command(
+          &format!("RCPT TO:<{}>", envelope_address(rcpt)),
+          &[250, 251],
+        )
+        .await?;
+    }
+    self.command("DATA", &[354]).await?;
+    self.stream.write_all(dot_stuff(body).as_bytes()).await?;
+    self.command(".", &[250]).await?;
+    // The message is accepted at this point, a failed QUIT doesn't matter.
+    let _ = self.command("QUIT", &[221]).await;
+    Ok(())
+  }
+}
+
+async fn tls_connect(
+  host: &str,
+  tcp: TcpStream,
+) -> MailResult<TlsStream<TcpStream>> {
+  let mut config = ClientConfig::new();
+  config
+    .root_store
+    .add_server_trust_anchors(&webpki_roots::TLS_SERVER_ROOTS);
+  let connector = TlsConnector::from(Arc::new(config));
+  let dnsname = DNSNameRef::try_from_ascii_str(host).map_err(|_| {
+    MailError::permanent(format!("invalid TLS server name: {}", host))
+  })?;
+  Ok(connector.connect(dnsname, tcp).await?)
+}
+
+/// Delivers one message with the given server settings, without queueing.
+pub async fn send_with(
+  config: &SmtpConfig,
+  message: &Message,
+) -> MailResult<()> {
+  if message.recipients().next().is_none() {
+    return Err(MailError::permanent("message has no recipients"));
+  }
+  message.check_lines(config)?;
+  let body = build_mime(config, message)?;
+  let tcp = TcpStream::connect((config.host.as_str(), config.port)).await?;
+
+  if config.tls == SmtpTls::Implicit {
+    let mut conn = Connection::new(tls_connect(&config.host, tcp).await?);
+    conn.expect(&[220]).await?;
+    let caps = conn.ehlo(config).await?;
+    return conn.deliver(config, &caps, message, &body).await;
+  }
+
+  let mut conn = Connection::new(tcp);
+  conn.expect(&[220]).await?;
+  let caps = conn.ehlo(config).await?;
+  if config.tls == SmtpTls::None {
+    return conn.deliver(config, &caps, message, &body).await;
+  }
+
+  if !caps.iter().any(|cap| cap == "STARTTLS") {
+    return Err(MailError::permanent("server does not support STARTTLS"));
+  }
+  conn.command("STARTTLS", &[220]).await?;
+  let tcp = conn.stream.into_inner();
+  let mut conn = Connection::new(tls_connect(&config.host, tcp).await?);
+  let caps = conn.ehlo(config).await?;
+  conn.deliver(config, &caps, message, &body).await
+}
+
+/// "Name <user@host>" -> "user@host"
+fn envelope_address(address: &str) -> &str {
+  match (address.rfind('<'), address.rfind('>')) {
+    (Some(start), Some(end)) if start < end => &address[start + 1..end],
+    _ => address.trim(),
+  }
+}
+
+/// Escapes lines starting with "." and makes sure the data ends in CRLF.
+fn dot_stuff(body: &str) -> String {
+  let mut out = String::with_capacity(body.len() + 2);
+  for line in body.split("\r\n") {
+    if line.starts_with('.') {
+      out.push('.');
+    }
+    out.push_str(line);
+    out.push_str("\r\n");
+  }
+  if body.ends_with("\r\n") {
+    out.truncate(out.len() - 2);
+  }
+  out
+}
+
+/// RFC 2047 encoded word for header values that aren't printable ASCII.
+fn encode_header(value: &str) -> String {
+  if value.chars().all(|c| c.is_ascii() && !c.is_ascii_control()) {
+    value.to_string()
+  } else {
+    format!("=?UTF-8?B?{}?=", base64::encode(value))
+  }
+}
+
+/// An address for the From, To or Cc header, its display name RFC 2047
+/// encoded like the subject when it isn't printable ASCII.
+fn encode_address(address: &str) -> String {
+  let start = match address.rfind('<') {
+    Some(start) => start,
+    None => return address.to_string(),
+  };
+  let name = address[..start].trim();
+  if name.chars().all(|c| c.is_ascii() && !c.is_ascii_control()) {
+    return address.to_string();
+  }
+  let name = name.trim_matches('"');
+  format!("{} {}", encode_header(name), &address[start..])
+}
+
+/// `name="value"` as a MIME parameter: a quoted string when the value is
+/// printable ASCII, RFC 2231 `name*=UTF-8''...` otherwise.
+fn mime_param(name: &str, value: &str) -> String {
+  if value.chars().all(|c| c.is_ascii() && !c.is_ascii_control()) {
+    let quoted = value.replace('\\', "\\\\").replace('"', "\\\"");
+    format!("{}=\"{}\"", name, quoted)
+  } else {
+    let encoded = utf8_percent_encode(value, PARAM_VALUE);
+    format!("{}*=UTF-8''{}", name, encoded)
+  }
+}
+
+fn wrap_base64(data: &[u8]) -> String {
+  let encoded = base64::encode(data);
+  let lines: Vec<&str> = encoded
+    .as_bytes()
+    .chunks(76)
+    .map(|chunk| std::str::from_utf8(chunk).unwrap())
+    .collect();
+  lines.join("\r\n")
+}
+
+fn text_part(subtype: &str, body: &str) -> String {
+  format!(
+    "Content-Type: text/{}; charset=utf-8\r\n\
+     Content-Transfer-Encoding: base64\r\n\r\n{}\r\n",
+    subtype,
+    wrap_base64(body.as_bytes())
+  )
+}
+
+fn attachment_part(attachment: &Attachment) -> MailResult<String> {
+  let content = base64::decode(&attachment.content).map_err(|_| {
+    MailError::permanent(format!(
+      "attachment {} is not valid base64",
+      attachment.filename
+    ))
+  })?;
+  let content_type = attachment
+    .content_type
+    .as_deref()
+    .unwrap_or("application/octet-stream");
+  Ok(format!(
+    "Content-Type: {}; {}\r\n\
+     Content-Transfer-Encoding: base64\r\n\
+     Content-Disposition: attachment; {}\r\n\r\n{}\r\n",
+    content_type,
+    mime_param("name", &attachment.filename),
+    mime_param("filename", &attachment.filename),
+    wrap_base64(&content)
+  ))
+}
+
+fn multipart(kind: &str, parts: Vec<String>) -> String {
+  let boundary = format!("rws-{:016x}", rand::random::<u64>());
+  let mut out = format!(
+    "Content-Type: multipart/{}; boundary=\"{}\"\r\n\r\n",
+    kind, boundary
+  );
+  for part in parts {
+    out.push_str(&format!("--{}\r\n{}", boundary, part));
+  }
+  out.push_str(&format!("--{}--\r\n", boundary));
+  out
+}
+
+/// Renders the full RFC 5322 message, headers included.
+fn build_mime(config: &SmtpConfig, message: &Message) -> MailResult<String> {
+  let from = message.from.as_ref().unwrap_or(&config.from);
+  let domain = envelope_address(from)
+    .rsplit('@')
+    .next()
+    .unwrap_or("localhost");
+
+  let mut out = String::new();
+  let addresses = |list: &[String]| {
+    let encoded: Vec<String> = list.iter().map(|a| encode_address(a)).collect();
+    encoded.join(", ")
+  };
+  out.push_str(&format!("From: {}\r\n", encode_address(from)));
+  out.push_str(&format!("To: {}\r\n", addresses(&message.to)));
+  if !message.cc.is_empty() {
+    out.push_str(&format!("Cc: {}\r\n", addresses(&message.cc)));
+  }
+  out.push_str(&format!("Subject: {}\r\n", encode_header(&message.subject)));
+  out.push_str(&format!(
+    "Date: {}\r\n",
+    httpdate::fmt_http_date(SystemTime::now())
+  ));
+  out.push_str(&format!(
+    "Message-ID: <{:016x}@{}>\r\n",
+    rand::random::<u64>(),
+    domain
+  ));
+  out.push_str("MIME-Version: 1.0\r\n");
+
+  let content = match (&message.text, &message.html) {
+    (Some(text), Some(html)) => multipart(
+      "alternative",
+      vec![text_part("plain", text), text_part("html", html)],
+    ),
+    (None, Some(html)) => text_part("html", html),
+    (Some(text), None) => text_part("plain", text),
+    (None, None) => text_part("plain", ""),
+  };
+
+  if message.attachments.is_empty() {
+    out.push_str(&content);
+  } else {
+    let mut parts = vec![content];
+    for attachment in &message.attachments {
+      parts.push(attachment_part(attachment)?);
+    }
+    out.push_str(&multipart("mixed", parts));
+  }
+  Ok(out)
+}
+
+fn unix_now() -> u64 {
+  SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .unwrap()
+    .as_secs()
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct QueuedMail {
+  id: String,
+  message: Message,
+  attempts: u32,
+  next_attempt: u64,
+  last_error: String,
+}
+
+/// Messages waiting for another delivery attempt, one JSON file each.
+/// Messages that run out of attempts are moved to the `failed` directory
+/// next to the queue.
+pub struct MailQueue {
+  dir: PathBuf,
+}
+
+impl MailQueue {
+  pub fn new(dir: PathBuf) -> Self {
+    MailQueue { dir }
+  }
+
+  pub fn open_default() -> Self {
+    MailQueue::new(config::rws_dir().join("mail").join("queue"))
+  }
+
+  fn failed_dir(&self) -> PathBuf {
+    self.dir.with_file_name("failed")
+  }
+
+  fn entry_path(&self, id: &str) -> PathBuf {
+    self.dir.join(format!("{}.json", id))
+  }
+
+  fn write(&self, entry: &QueuedMail) -> io::Result<()> {
+    fs::create_dir_all(&self.dir)?;
+    let tmp = self.dir.join(format!(".{}.tmp", entry.id));
+    fs::write(&tmp, serde_json::to_vec(entry)?)?;
+    fs::rename(tmp, self.entry_path(&entry.id))
+  }
+
+  fn move_to_failed(&self, entry: &QueuedMail) -> io::Result<()> {
+    let failed = self.failed_dir();
+    fs::create_dir_all(&failed)?;
+    fs::write(
+      failed.join(format!("{}.json", entry.id)),
+      serde_json::to_vec(entry)?,
+    )?;
+    fs::remove_file(self.entry_path(&entry.id))
+  }
+
+  /// Adds a message that hasn't been tried yet, due straight away.
+  pub fn push_new(&self, message: Message) -> io::Result<String> {
+    let id = format!("{}-{:08x}", unix_now(), rand::random::<u32>());
+    self.write(&QueuedMail {
+      id: id.clone(),
+      message,
+      attempts: 0,
+      next_attempt: unix_now(),
+      last_error: String::new(),
+    })?;
+    Ok(id)
+  }
+
+  /// Adds a message that just failed its first attempt.
+  pub fn push(
+    &self,
+    message: Message,
+    error: &MailError,
+  ) -> io::Result<String> {
+    let id = format!("{}-{:08x}", unix_now(), rand::random::<u32>());
+    self.write(&QueuedMail {
+      id: id.clone(),
+      message,
+      attempts: 1,
+      next_attempt: unix_now() + backoff(1),
+      last_error: error.message.clone(),
+    })?;
+    Ok(id)
+  }
+
+  fn entries(&self) -> Vec<QueuedMail> {
+    let dir = match fs::read_dir(&self.dir) {
+      Ok(dir) => dir,
+      Err(_) => return Vec::new(),
+    };
+    let mut entries: Vec<QueuedMail> = dir
+      .filter_map(|entry| entry.ok())
+      .map(|entry| entry.path())
+      .filter(|path| path.extension().map_or(false, |ext| ext == "json"))
+      .filter_map(|path| fs::read(path).ok())
+      .filter_map(|bytes| serde_json::from_slice(&bytes).ok())
+      .collect();
+    entries.sort_by(|a, b| a.id.cmp(&b.id));
+    entries
+  }
+
+  pub fn len(&self) -> usize {
+    self.entries().len()
+  }
+
+  /// Retries every message that is due at `now`, returns how many went out.
+  pub async fn flush_at(&self, config: &SmtpConfig, now: u64) -> usize {
+    let mut delivered = 0;
+    for mut entry in self.entries() {
+      if entry.next_attempt > now {
+        continue;
+      }
+      let path = self.entry_path(&entry.id);
+      match send_with(config, &entry.message).await {
+        Ok(()) => {
+          delivered += 1;
+          let _ = fs::remove_file(path);
+        }
+        Err(error) => {
+          entry.attempts += 1;
+          entry.last_error = error.message.clone();
+          if error.permanent || entry.attempts >= MAX_ATTEMPTS {
+            error!("mail {} failed for good: {}", entry.id, error);
+            if let Err(e) = self.move_to_failed(&entry) {
+              error!("could not move mail {} to failed: {}", entry.id, e);
+            }
+          } else {
+            entry.next_attempt = now + backoff(entry.attempts);
+            if let Err(e) = self.write(&entry) {
+              error!("could not update mail queue entry {}: {}", entry.id, e);
+            }
+          }
+        }
+      }
+    }
+    delivered
+  }
+
+  pub async fn flush(&self, config: &SmtpConfig) -> usize {
+    self.flush_at(config, unix_now()).await
+  }
+}
+
+/// Seconds to wait after `attempts` failures: 1m, 2m, 4m, ... capped at 6h.
+fn backoff(attempts: u32) -> u64 {
+  (60u64 << attempts.saturating_sub(1).min(10)).min(6 * 60 * 60)
+}
+
+/// Periodically retries queued mail for the life of the process.
+pub async fn run_queue() {
+  let queue = MailQueue::open_default();
+  loop {
+    tokio::time::delay_for(QUEUE_INTERVAL).await;
+    if let Some(smtp) = config::get().smtp.clone() {
+      queue.flush(&smtp).await;
+    }
+  }
+}
+
+#[cfg(test)]
+async fn fake_smtp_session(
+  mut listener: tokio::net::TcpListener,
+) -> Vec<String> {
+  let (socket, _) = listener.accept().await.unwrap();
+  let mut socket = BufReader::new(socket);
+  socket.write_all(b"220 fake ESMTP\r\n").await.unwrap();
+
+  let mut transcript = Vec::new();
+  let mut data: Option<String> = None;
+  loop {
+    let mut line = String::new();
+    if socket.read_line(&mut line).await.unwrap() == 0 {
+      break;
+    }
+    if data.is_some() {
+      if line == ".\r\n" {
+        transcript.push(data.take().unwrap());
+        socket.write_all(b"250 queued\r\n").await.unwrap();
+      } else {
+        data.as_mut().unwrap().push_str(&line);
+      }
+      continue;
+    }
+
+    let command = line.trim_end().to_string();
+    let verb = command.split(' ').next().unwrap().to_uppercase();
+    transcript.push(command);
+    let reply: &[u8] = match verb.as_str() {
+      "EHLO" => b"250-fake\r\n250 AUTH PLAIN LOGIN\r\n",
+      "AUTH" => b"235 accepted\r\n",
+      "MAIL" | "RCPT" => b"250 ok\r\n",
+      "DATA" => {
+        data = Some(String::new());
+        b"354 go ahead\r\n"
+      }
+      "QUIT" => {
+        socket.write_all(b"221 bye\r\n").await.unwrap();
+        break;
+      }
+      _ => b"502 unknown command\r\n",
+    };
+    socket.write_all(reply).await.unwrap();
+  }
+  transcript
+}
+
+#[cfg(test)]
+fn test_config(port: u16) -> SmtpConfig {
+  SmtpConfig {
+    host: "127.0.0.1".to_string(),
+    port,
+    tls: SmtpTls::None,
+    username: Some("user".to_string()),
+    password: Some("secret".to_string()),
+    from: "RWS <rws@example.com>".to_string(),
+    hello_name: None,
+  }
+}
+
+#[cfg(test)]
+fn test_message() -> Message {
+  Message {
+    from: None,
+    to: vec!["Bob <bob@example.com>".to_string()],
+    cc: vec![],
+    bcc: vec!["carol@example.com".to_string()],
+    subject: "Hello".to_string(),
+    text: Some("plain body".to_string()),
+    html: Some("<p>html body</p>".to_string()),
+    attachments: vec![Attachment {
+      filename: "a.txt".to_string(),
+      content_type: None,
+      content: base64::encode("attached"),
+    }],
+  }
+}
+
+#[tokio::test]
+async fn mail_send_test() {
+  let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+  let port = listener.local_addr().unwrap().port();
+  let server = tokio::spawn(fake_smtp_session(listener));
+
+  send_with(&test_config(port), &test_message())
+    .await
+    .unwrap();
+  let transcript = server.await.unwrap();
+
+  let has = |line: &str| transcript.iter().any(|l| l == line);
+  assert!(has(&format!(
+    "AUTH PLAIN {}",
+    base64::encode("\0user\0secret")
+  )));
+  assert!(has("MAIL FROM:<rws@example.com>"));
+  assert!(has("RCPT TO:<bob@example.com>"));
+  assert!(has("RCPT TO:<carol@example.com>"));
+
+  let data = transcript.iter().find(|l| l.contains("MIME-")).unwrap();
+  assert!(data.contains("Subject: Hello\r\n"));
+  assert!(data.contains("multipart/mixed"));
+  assert!(data.contains("multipart/alternative"));
+  assert!(data.contains("filename=\"a.txt\""));
+  assert!(data.contains(&base64::encode("attached")));
+  assert!(!data.contains("carol"));
+}
+
+#[test]
+fn mail_header_test() {
+  let config = test_config(25);
+  assert!(test_message().check_lines(&config).is_ok());
+
+  let mut message = test_message();
+  message.subject = "Hi\r\nBcc: eve@example.com".to_string();
+  assert!(message.check_lines(&config).unwrap_err().permanent);
+  let mut message = test_message();
+  message
+    .to
+    .push("eve@example.com>\r\nRCPT TO:<mallory".to_string());
+  assert!(message.check_lines(&config).is_err());
+  let mut message = test_message();
+  message.from = Some("rws@example.com\nX-Evil: 1".to_string());
+  assert!(message.check_lines(&config).is_err());
+  let mut message = test_message();
+  message.attachments[0].filename = "a.txt\r\nX-Evil: 1".to_string();
+  assert!(message.check_lines(&config).is_err());
+
+  assert_eq!(encode_header("Hello"), "Hello");
+  assert_eq!(encode_header("H\u{e9}"), "=?UTF-8?B?SMOp?=");
+  assert_eq!(encode_header("a\tb"), "=?UTF-8?B?YQli?=");
+  assert_eq!(encode_address("zoe@example.com"), "zoe@example.com");
+  assert_eq!(
+    encode_address("\"Zoe Z\" <zoe@example.com>"),
+    "\"Zoe Z\" <zoe@example.com>"
+  );
+  assert_eq!(
+    encode_address("\"Zo\u{eb}\" <zoe@example.com>"),
+    "=?UTF-8?B?Wm/Dqw==?= <zoe@example.com>"
+  );
+  assert_eq!(
+    mime_param("filename", r#"a "b".txt"#),
+    r#"filename="a \"b\".txt""#
+  );
+  assert_eq!(mime_param("name", r"a\b"), r#"name="a\\b""#);
+  assert_eq!(
+    mime_param("filename", "r\u{e9}sum\u{e9}.pdf"),
+    "filename*=UTF-8''r%C3%A9sum%C3%A9.pdf"
+  );
+}
+
+#[tokio::test]
+async fn mail_queue_test() {
+  let dir = tempfile::TempDir::new().unwrap();
+  let queue = MailQueue::new(dir.path().join("queue"));
+
+  let closed_port = {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    listener.local_addr().unwrap().port()
+  };
+  let error = send_with(&test_config(closed_port), &test_message())
+    .await
+    .unwrap_err();
+  assert!(!error.permanent);
+  queue.push(test_message(), &error).unwrap();
+  assert_eq!(queue.len(), 1);
+
+  // Not due yet.
+  assert_eq!(queue.flush(&test_config(closed_port)).await, 0);
+
+  let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+  let port = listener.local_addr().unwrap().port();
+  let server = tokio::spawn(fake_smtp_session(listener));
+  assert_eq!(
+    queue.flush_at(&test_config(port), u64::max_value()).await,
+    1
+  );
+  server.await.unwrap();
+  assert_eq!(queue.len(), 0);
+}
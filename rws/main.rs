@@ -30,11 +30,10 @@ pub use deno_lint::swc_ecma_parser;
 pub use deno_lint::swc_ecma_visit;
 
 use deno_cli::doc::parser::DocFileLoader;
-use deno_cli::file_fetcher::SourceFile;
 use deno_cli::file_fetcher::SourceFileFetcher;
 use deno_cli::fs as deno_fs;
 use deno_cli::global_state::GlobalState;
-use deno_cli::msg::{self, MediaType};
+use deno_cli::msg;
 use deno_cli::op_error::OpError;
 use deno_cli::permissions::Permissions;
 use deno_cli::tsc::TargetLib;
@@ -55,6 +54,7 @@ use std::io::Read;
 use std::io::Write;
 use std::path::PathBuf;
 use std::{rc::Rc, pin::Pin, cell::RefCell, time::Duration, thread};
+use std::sync::Arc;
 use deno_cli::{colors, upgrade::upgrade_command};
 use url::Url;
 use tokio::sync::{oneshot, mpsc};
@@ -64,7 +64,32 @@ use actix_web::HttpRequest;
 use actix_web::*;
 use futures::{StreamExt};
 
+mod accounts;
+mod acme;
+mod admin;
+mod apps;
+mod audit;
+mod channels;
+mod components;
+mod config;
 mod control_panel;
+mod docs;
+mod libs;
+mod logging;
+mod mail;
+mod manifest;
+mod ops;
+mod options;
+mod package;
+mod protection;
+mod proxy;
+mod rbac;
+mod render;
+mod routes;
+mod search;
+mod services;
+mod totp;
+mod websocket;
 
 static LOGGER: Logger = Logger;
 
@@ -81,7 +106,8 @@ fn wrap_channel() -> (SuperUnsafeCell<tokio::sync::mpsc::Sender<ChannelRx>>, Sup
   (SuperUnsafeCell::new(sender), SuperUnsafeCell::new(receiver))
 }
 
-// TODO(ry) Switch to env_logger or other standard crate.
+/// Prints like Deno's logger and also keeps each record in the rws system
+/// log (see `logging`).
 struct Logger;
 
 impl log::Log for Logger {
@@ -103,6 +129,13 @@ impl log::Log for Logger {
       } else {
         eprintln!("{} RS - {} - {}", record.level(), target, record.args());
       }
+
+      let mut entry = logging::LogEntry::new(
+        record.level().into(),
+        record.args().to_string(),
+      );
+      entry.fields.insert("target".to_string(), json!(target));
+      logging::write(entry);
     }
   }
   fn flush(&self) {}
@@ -308,7 +341,7 @@ async fn send() -> u32 {
 
 async fn main_handler(
   req: HttpRequest,
-  mut body: actix_web::web::Payload,
+  body: actix_web::web::Payload,
 ) -> actix_web::HttpResponse {
 /*
   let mut bytes = actix_web::web::BytesMut::new();
@@ -327,25 +360,47 @@ async fn main_handler(
   .body("<h2>hello</h2>")
   */
   
-  let mut sender = THREAD_CHANNEL.with(|channel| {
-    Rc::clone(channel)
-  });
-  let (resp_tx, resp_rx) = oneshot::channel::<HttpResponse>();
-  sender.0.borrow_mut().send((req, resp_tx)).await.ok().unwrap();
-  let res = resp_rx.await;
-  match res {
-    Ok(result) => result,
-    Err(error) => {
-      println!("{:?}", error);
-      actix_web::HttpResponse::Ok()
-      .content_type("text/html")
-      .body("<h2>error</h2>")
-    }
+  let request_id = format!("{:016x}", rand::random::<u64>());
+  let host = req.connection_info().host().to_string();
+  let access = format!("{} {}", req.method(), req.uri());
+
+  let response = respond(req, body).await;
+
+  let mut entry = logging::LogEntry::new(logging::LogLevel::Info, access);
+  entry.host = Some(host);
+  entry.request_id = Some(request_id);
+  let status = response.status().as_u16();
+  entry.fields.insert("status".to_string(), json!(status));
+  logging::write(entry);
+  response
+}
+
+/// Proxy routes come first, then docs sites, then mounted apps.
+async fn respond(
+  req: HttpRequest,
+  body: actix_web::web::Payload,
+) -> HttpResponse {
+  if let Some(route) = proxy::route_for(&req) {
+    return proxy::forward(route, req, body).await;
+  }
+  if let Some(mount) = docs::mount_for(&req) {
+    return docs::serve(mount, req).await;
   }
+  match render::mount_for(&req) {
+    Some(Ok(mount)) => render::serve(mount, req).await,
+    Some(Err(e)) => HttpResponse::NotFound().body(e.to_string()),
+    None => HttpResponse::NotFound().finish(),
+  }
+}
 
-  /*actix_web::HttpResponse::Ok()
-  .content_type("text/html")
-  .body("<h2>hello</h2>")*/
+/// HTTP-01 challenges for certificates being issued (see `acme`).
+async fn acme_challenge(token: actix_web::web::Path<String>) -> HttpResponse {
+  match acme::challenge_response(&token) {
+    Some(key_authorization) => actix_web::HttpResponse::Ok()
+      .content_type("application/octet-stream")
+      .body(key_authorization),
+    None => actix_web::HttpResponse::NotFound().finish(),
+  }
 }
 
 pub struct SuperUnsafeCell<T> {
@@ -368,69 +423,6 @@ impl<T> SuperUnsafeCell<T> {
   }
 }
 
-//tokio::task::JoinHandle<Result<(), ErrBox>> 
-
-fn new_js_context() -> tokio::task::JoinHandle<Result<(), ErrBox>> {
-/*
-  tokio::task::spawn_local(async {
-
-    let channel = THREAD_CHANNEL.with(|channel| {
-       Rc::clone(channel)
-    });
-
-    while let Some(res) = channel.1.borrow_mut().recv().await {
-      res.1.send(actix_web::HttpResponse::Ok()
-      .content_type("text/html")
-      .body("<h2>hello</h2>")).unwrap();
-    }
-
-    Ok(())
-  })
-  */
-  tokio::task::spawn_local(async {
-    let args: Vec<String> = env::args().collect();
-    let mut flags = deno_cli::flags::flags_from_vec(args);
-
-    flags.subcommand = DenoSubcommand::Repl;
-  
-    let main_module = ModuleSpecifier::resolve_url_or_path("./__$deno$eval.ts").unwrap();
-    let global_state = GlobalState::new(flags)?;
-    let mut worker = MainWorker::create(global_state, main_module.clone())?;
-    let main_module_url = main_module.as_url().to_owned();
-    // Create a dummy source file.
-    let source_code = r###"
-      const s = Deno.watchRWS();
-      for await (const req of s) {
-        console.log(req.respId);
-        Deno.sendRWS(req.respId, "<h1>hello</h1>");
-      }
-    "###.to_owned().into_bytes();
-
-    let source_file = SourceFile {
-      filename: main_module_url.to_file_path().unwrap(),
-      url: main_module_url,
-      types_header: None,
-      media_type: MediaType::JavaScript,
-      source_code,
-    };
-    // Save our fake file into file fetcher cache
-    // to allow module access by TS compiler (e.g. op_fetch_source_files)
-    worker
-      .state
-      .borrow()
-      .global_state
-      .file_fetcher
-      .save_source_file_in_cache(&main_module, source_file);
-    debug!("main_module {}", &main_module);
-    worker.execute_module(&main_module).await?;
-    worker.execute("window.dispatchEvent(new Event('load'))")?;
-    (&mut *worker).await?;
-    worker.execute("window.dispatchEvent(new Event('unload'))")?;
-    
-    Ok(())
-  })
-}
-
 pub fn main() {
   #[cfg(windows)]
   colors::enable_ansi(); // For Windows 10
@@ -438,6 +430,9 @@ pub fn main() {
   log::set_logger(&LOGGER).unwrap();
 
   let args: Vec<String> = env::args().collect();
+  if let Some(code) = package::app_command(&args) {
+    std::process::exit(code);
+  }
   let mut flags = deno_cli::flags::flags_from_vec(args);
 
   flags.subcommand = DenoSubcommand::Run { script: "/eval.js".to_string() };
@@ -454,6 +449,7 @@ pub fn main() {
   };
   log::set_max_level(log_level.to_level_filter());
 
+  admin::mark_started();
   thread::spawn(control_panel::server);
 
   let mut single_rt = Builder::new()
@@ -466,16 +462,36 @@ pub fn main() {
   let system_fut = actix_rt::System::run_in_tokio("main", &local);
   local.block_on(&mut single_rt, async {
     tokio::task::spawn_local(system_fut);
-
-    let _ = actix_web::HttpServer::new(|| {
+    tokio::task::spawn_local(mail::run_queue());
+    tokio::task::spawn_local(acme::run());
+    tokio::task::spawn_local(proxy::run_health_checks());
+    tokio::task::spawn_local(protection::run());
+    tokio::task::spawn_local(accounts::run());
+    tokio::task::spawn_local(apps::run());
+
+    let config = config::get();
+    let mut server = actix_web::HttpServer::new(|| {
         // actix_web::App::new().service(actix_web::web::resource("/").to(|| async { "<h1>Hello world!</h1>" }))
         actix_web::App::new()
-            .data(new_js_context())
+            .wrap(protection::Protection)
+            .route("/.well-known/acme-challenge/{token}", actix_web::web::get().to(acme_challenge))
+            .route("/ws", actix_web::web::get().to(websocket::index))
+            .route("/json", actix_web::web::post().to(services::handle))
+            .route("/.rws/login", actix_web::web::post().to(accounts::handle_login))
+            .route("/.rws/logout", actix_web::web::post().to(accounts::handle_logout))
+            .route("/.rws/require.js", actix_web::web::get().to(render::require_js))
             .service(actix_web::web::resource("*").to(main_handler))
     })
-    .bind("127.0.0.1:8083")
-    .unwrap()
-    .run()
-    .await;
+    .bind(&config.http_addr)
+    .unwrap();
+
+    if let Some(https_addr) = &config.https_addr {
+      let resolver: Arc<dyn rustls::ResolvesServerCert> = acme::RESOLVER.clone();
+      let mut tls = rustls::ServerConfig::new(rustls::NoClientAuth::new());
+      tls.cert_resolver = resolver;
+      server = server.bind_rustls(https_addr, tls).unwrap();
+    }
+
+    let _ = server.run().await;
   });
 }
@@ -0,0 +1,318 @@
+//! App `manifest.json`.
+//!
+//! Manifests are written by hand and carry `//` comments (see
+//! `example-app/manifest.json`), so comments are stripped before parsing.
+//! Fields rws doesn't use yet are kept in `extra`.
+
+use serde_derive::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OptionType {
+  String,
+  Number,
+  Integer,
+  Boolean,
+  Object,
+  Array,
+}
+
+impl OptionType {
+  pub fn accepts(self, value: &Value) -> bool {
+    match self {
+      OptionType::String => value.is_string(),
+      OptionType::Number => value.is_number(),
+      OptionType::Integer => value.is_i64() || value.is_u64(),
+      OptionType::Boolean => value.is_boolean(),
+      OptionType::Object => value.is_object(),
+      OptionType::Array => value.is_array(),
+    }
+  }
+}
+
+/// One typed argument of a nested route, written `name:type`.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct RouteArg {
+  pub name: String,
+  #[serde(rename = "type")]
+  pub kind: OptionType,
+}
+
+impl RouteArg {
+  /// Parses `name:type`; the type defaults to string.
+  pub fn parse(spec: &str) -> Option<RouteArg> {
+    let mut parts = spec.splitn(2, ':');
+    let name = parts.next()?.trim();
+    if name.is_empty() {
+      return None;
+    }
+    let kind = match parts.next() {
+      Some(kind) => {
+        serde_json::from_value(Value::String(kind.trim().to_string())).ok()?
+      }
+      None => OptionType::String,
+    };
+    Some(RouteArg {
+      name: name.to_string(),
+      kind,
+    })
+  }
+}
+
+/// A named route slot the app exposes for others to mount (see `routes`).
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct RouteSpec {
+  pub name: String,
+  pub args: Vec<RouteArg>,
+}
+
+/// How the app's pages are served (see `render`).
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RenderType {
+  /// The RequireJS shell for every route; the app routes on the client.
+  SinglePage,
+  /// Each route rendered on the server by the isolate.
+  MultiPage,
+  /// A fragment other apps' pages embed.
+  Inline,
+  /// A page of the RWS dashboard.
+  ControlPanel,
+}
+
+impl Default for RenderType {
+  fn default() -> Self {
+    RenderType::SinglePage
+  }
+}
+
+/// One entry of the manifest's `options` schema.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct OptionSpec {
+  #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
+  pub kind: Option<OptionType>,
+  pub title: String,
+  pub desc: String,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub default: Option<Value>,
+  /// Allowed values.
+  #[serde(rename = "enum", skip_serializing_if = "Option::is_none")]
+  pub one_of: Option<Vec<Value>>,
+}
+
+/// One entry of the manifest's `permissions`.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct PermissionSpec {
+  pub title: String,
+  pub desc: String,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Manifest {
+  pub api: u32,
+  pub name: String,
+  pub version: String,
+  pub render_type: RenderType,
+  /// Native modules the app needs and their API versions, e.g.
+  /// `{"rws-db": 1}`.
+  pub native_dependencies: Map<String, Value>,
+  /// Settings the app stores with `RWS.saveOptions`; when present, only
+  /// these keys are accepted.
+  pub options: Map<String, Value>,
+  /// Permissions admins can grant through roles (see `rbac`).
+  pub permissions: Map<String, Value>,
+  /// Route slots, e.g. `{"main-page": {"args": ["template:string"]}}`.
+  pub nested_routes: Map<String, Value>,
+  #[serde(flatten)]
+  pub extra: Map<String, Value>,
+}
+
+#[derive(Debug)]
+pub enum ManifestError {
+  Io(io::Error),
+  Parse(serde_json::Error),
+}
+
+impl fmt::Display for ManifestError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      ManifestError::Io(e) => write!(f, "could not read manifest: {}", e),
+      ManifestError::Parse(e) => write!(f, "invalid manifest: {}", e),
+    }
+  }
+}
+
+impl std::error::Error for ManifestError {}
+
+impl From<io::Error> for ManifestError {
+  fn from(e: io::Error) -> Self {
+    ManifestError::Io(e)
+  }
+}
+
+impl From<serde_json::Error> for ManifestError {
+  fn from(e: serde_json::Error) -> Self {
+    ManifestError::Parse(e)
+  }
+}
+
+/// Removes `//` and `/* */` comments that are outside strings.
+fn strip_comments(text: &str) -> String {
+  let mut out = String::with_capacity(text.len());
+  let mut chars = text.chars().peekable();
+  let mut in_string = false;
+
+  while let Some(c) = chars.next() {
+    if in_string {
+      out.push(c);
+      match c {
+        '\\' => {
+          if let Some(escaped) = chars.next() {
+            out.push(escaped);
+          }
+        }
+        '"' => in_string = false,
+        _ => {}
+      }
+      continue;
+    }
+    match (c, chars.peek()) {
+      ('"', _) => {
+        in_string = true;
+        out.push(c);
+      }
+      ('/', Some('/')) => {
+        while let Some(&next) = chars.peek() {
+          if next == '\n' {
+            break;
+          }
+          chars.next();
+        }
+      }
+      ('/', Some('*')) => {
+        chars.next();
+        let mut prev = '\0';
+        for next in &mut chars {
+          if prev == '*' && next == '/' {
+            break;
+          }
+          prev = next;
+        }
+      }
+      _ => out.push(c),
+    }
+  }
+  out
+}
+
+impl Manifest {
+  pub fn parse(text: &str) -> Result<Manifest, ManifestError> {
+    Ok(serde_json::from_str(&strip_comments(text))?)
+  }
+
+  pub fn load(path: &Path) -> Result<Manifest, ManifestError> {
+    Manifest::parse(&fs::read_to_string(path)?)
+  }
+
+  /// The `options` schema; malformed entries are skipped.
+  pub fn option_specs(&self) -> Vec<(String, OptionSpec)> {
+    self
+      .options
+      .iter()
+      .filter_map(|(key, spec)| {
+        let spec = serde_json::from_value(spec.clone()).ok()?;
+        Some((key.clone(), spec))
+      })
+      .collect()
+  }
+
+  /// The nested route slots; ones with a malformed argument are skipped.
+  pub fn route_specs(&self) -> Vec<RouteSpec> {
+    self
+      .nested_routes
+      .iter()
+      .filter_map(|(name, spec)| {
+        let args = match spec.get("args") {
+          Some(Value::Array(args)) => args
+            .iter()
+            .map(|arg| RouteArg::parse(arg.as_str()?))
+            .collect::<Option<Vec<_>>>()?,
+          Some(_) => return None,
+          None => Vec::new(),
+        };
+        Some(RouteSpec {
+          name: name.clone(),
+          args,
+        })
+      })
+      .collect()
+  }
+
+  /// The declared permissions; malformed entries are skipped.
+  pub fn permission_specs(&self) -> Vec<(String, PermissionSpec)> {
+    self
+      .permissions
+      .iter()
+      .filter_map(|(name, spec)| {
+        let spec = serde_json::from_value(spec.clone()).ok()?;
+        Some((name.clone(), spec))
+      })
+      .collect()
+  }
+}
+
+#[test]
+fn manifest_test() {
+  let manifest =
+    Manifest::parse(include_str!("../example-app/manifest.json")).unwrap();
+  assert_eq!(manifest.name, "my-app");
+  assert_eq!(manifest.render_type, RenderType::SinglePage);
+  assert_eq!(manifest.extra["license"], json!("MIT"));
+  let permissions = manifest.permission_specs();
+  assert_eq!(permissions.len(), 2);
+  let (_, spec) = permissions
+    .iter()
+    .find(|(name, _)| name == "can_view_something")
+    .unwrap();
+  assert_eq!(spec.title, "Some view");
+  assert_eq!(
+    manifest.route_specs(),
+    vec![RouteSpec {
+      name: "main-page".to_string(),
+      args: vec![RouteArg {
+        name: "template".to_string(),
+        kind: OptionType::String,
+      }],
+    }]
+  );
+  let page = RouteArg::parse("page:integer").unwrap();
+  assert_eq!(page.kind, OptionType::Integer);
+  assert!(RouteArg::parse("page:colour").is_none());
+
+  let manifest = Manifest::parse(
+    r#"{
+      "name": "a // not a comment", /* block
+      comment */
+      "render_type": "control_panel",
+      "options": {
+        "color": {"type": "string", "default": "blue", "enum": ["blue", "red"]},
+        "broken": {"type": "colour"}
+      }
+    }"#,
+  )
+  .unwrap();
+  assert_eq!(manifest.name, "a // not a comment");
+  assert_eq!(manifest.render_type, RenderType::ControlPanel);
+  let specs = manifest.option_specs();
+  assert_eq!(specs.len(), 1);
+  assert_eq!(specs[0].1.kind, Some(OptionType::String));
+  assert_eq!(specs[0].1.default, Some(json!("blue")));
+}
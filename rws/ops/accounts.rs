@@ -0,0 +1,217 @@
+use crate::accounts::{self, AccountError, SessionKind};
+use deno_cli::op_error::OpError;
+use deno_cli::ops::JsonOp;
+use deno_cli::state::State;
+use deno_core::CoreIsolate;
+use deno_core::ZeroCopyBuf;
+use futures::future::FutureExt;
+use serde_derive::Deserialize;
+use serde_json::Value;
+
+pub fn init(i: &mut CoreIsolate, s: &State) {
+  i.register_op(
+    "op_rws_auth_register",
+    s.stateful_json_op(op_rws_auth_register),
+  );
+  i.register_op("op_rws_auth_login", s.stateful_json_op(op_rws_auth_login));
+  i.register_op("op_rws_auth_logout", s.stateful_json_op(op_rws_auth_logout));
+  i.register_op("op_rws_auth_token", s.stateful_json_op(op_rws_auth_token));
+  i.register_op(
+    "op_rws_auth_send_verification",
+    s.stateful_json_op(op_rws_auth_send_verification),
+  );
+  i.register_op("op_rws_auth_verify", s.stateful_json_op(op_rws_auth_verify));
+  i.register_op(
+    "op_rws_auth_request_reset",
+    s.stateful_json_op(op_rws_auth_request_reset),
+  );
+  i.register_op("op_rws_auth_reset", s.stateful_json_op(op_rws_auth_reset));
+}
+
+fn op_error(e: AccountError) -> OpError {
+  match e {
+    AccountError::Invalid(_) | AccountError::EmailTaken => {
+      OpError::type_error(e.to_string())
+    }
+    AccountError::BadCredentials
+    | AccountError::Refused(_)
+    | AccountError::BadToken
+    | AccountError::Disabled => OpError::permission_denied(e.to_string()),
+    AccountError::Io(_) | AccountError::Mail(_) => {
+      OpError::other(e.to_string())
+    }
+  }
+}
+
+#[derive(Deserialize)]
+struct RegisterArgs {
+  email: String,
+  password: String,
+  #[serde(default)]
+  name: Option<String>,
+}
+
+fn op_rws_auth_register(
+  _state: &State,
+  args: Value,
+  _zero_copy: &mut [ZeroCopyBuf],
+) -> Result<JsonOp, OpError> {
+  let RegisterArgs {
+    email,
+    password,
+    name,
+  } = serde_json::from_value(args)?;
+
+  let fut = async move {
+    let user = accounts::register(email, password, name)
+      .await
+      .map_err(op_error)?;
+    Ok(json!(user))
+  };
+  Ok(JsonOp::Async(fut.boxed_local()))
+}
+
+#[derive(Deserialize)]
+struct LoginArgs {
+  email: String,
+  password: String,
+}
+
+/// Starts a bearer session.  App code can't set the cookie, and doesn't know
+/// the client's address, so only the account lockout applies; browsers sign
+/// in with `POST /.rws/login` (see `accounts`).
+fn op_rws_auth_login(
+  _state: &State,
+  args: Value,
+  _zero_copy: &mut [ZeroCopyBuf],
+) -> Result<JsonOp, OpError> {
+  let LoginArgs { email, password } = serde_json::from_value(args)?;
+
+  let fut = async move {
+    let kind = SessionKind::Bearer;
+    let (secret, token) = accounts::login(email, password, kind, None)
+      .await
+      .map_err(op_error)?;
+    Ok(json!({ "secret": secret, "token": token }))
+  };
+  Ok(JsonOp::Async(fut.boxed_local()))
+}
+
+#[derive(Deserialize)]
+struct SecretArgs {
+  secret: String,
+}
+
+fn op_rws_auth_logout(
+  _state: &State,
+  args: Value,
+  _zero_copy: &mut [ZeroCopyBuf],
+) -> Result<JsonOp, OpError> {
+  let SecretArgs { secret } = serde_json::from_value(args)?;
+  let ended = accounts::logout(&secret).map_err(op_error)?;
+  Ok(JsonOp::Sync(json!(ended)))
+}
+
+#[derive(Deserialize)]
+struct TokenArgs {
+  #[serde(default)]
+  secret: Option<String>,
+  #[serde(default)]
+  cookie: Option<String>,
+  #[serde(default)]
+  authorization: Option<String>,
+}
+
+/// Resolves a session from a secret or from request headers; `null` when
+/// there is no valid session.
+fn op_rws_auth_token(
+  _state: &State,
+  args: Value,
+  _zero_copy: &mut [ZeroCopyBuf],
+) -> Result<JsonOp, OpError> {
+  let TokenArgs {
+    secret,
+    cookie,
+    authorization,
+  } = serde_json::from_value(args)?;
+  let secret = secret.or_else(|| {
+    accounts::request_token(cookie.as_deref(), authorization.as_deref())
+  });
+  let token = secret.and_then(|secret| accounts::session(&secret));
+  Ok(JsonOp::Sync(json!(token)))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SendVerificationArgs {
+  user_id: String,
+  url: String,
+}
+
+fn op_rws_auth_send_verification(
+  _state: &State,
+  args: Value,
+  _zero_copy: &mut [ZeroCopyBuf],
+) -> Result<JsonOp, OpError> {
+  let SendVerificationArgs { user_id, url } = serde_json::from_value(args)?;
+
+  let fut = async move {
+    accounts::send_verification(&user_id, &url)
+      .await
+      .map_err(op_error)?;
+    Ok(json!(true))
+  };
+  Ok(JsonOp::Async(fut.boxed_local()))
+}
+
+#[derive(Deserialize)]
+struct TicketArgs {
+  ticket: String,
+  #[serde(default)]
+  password: Option<String>,
+}
+
+fn op_rws_auth_verify(
+  _state: &State,
+  args: Value,
+  _zero_copy: &mut [ZeroCopyBuf],
+) -> Result<JsonOp, OpError> {
+  let TicketArgs { ticket, .. } = serde_json::from_value(args)?;
+  let user = accounts::verify_email(&ticket).map_err(op_error)?;
+  Ok(JsonOp::Sync(json!(user)))
+}
+
+#[derive(Deserialize)]
+struct RequestResetArgs {
+  email: String,
+  url: String,
+}
+
+fn op_rws_auth_request_reset(
+  _state: &State,
+  args: Value,
+  _zero_copy: &mut [ZeroCopyBuf],
+) -> Result<JsonOp, OpError> {
+  let RequestResetArgs { email, url } = serde_json::from_value(args)?;
+
+  accounts::request_password_reset(&email, &url);
+  Ok(JsonOp::Sync(json!(true)))
+}
+
+fn op_rws_auth_reset(
+  _state: &State,
+  args: Value,
+  _zero_copy: &mut [ZeroCopyBuf],
+) -> Result<JsonOp, OpError> {
+  let TicketArgs { ticket, password } = serde_json::from_value(args)?;
+  let password = password
+    .ok_or_else(|| OpError::type_error("password is required".to_string()))?;
+
+  let fut = async move {
+    accounts::reset_password(&ticket, password)
+      .await
+      .map_err(op_error)?;
+    Ok(json!(true))
+  };
+  Ok(JsonOp::Async(fut.boxed_local()))
+}
@@ -0,0 +1,38 @@
+use crate::apps::AppContext;
+use deno_cli::op_error::OpError;
+use deno_cli::ops::JsonOp;
+use deno_cli::state::State;
+use deno_core::CoreIsolate;
+use deno_core::ZeroCopyBuf;
+use serde_json::Value;
+
+pub fn init(
+  i: &mut CoreIsolate,
+  s: &State,
+  context: &AppContext,
+  installing: bool,
+) {
+  let context = context.clone();
+  i.register_op(
+    "op_rws_app_context",
+    s.stateful_json_op(move |state, args, zero_copy| {
+      op_rws_app_context(&context, installing, state, args, zero_copy)
+    }),
+  );
+}
+
+/// The host and app the isolate runs for, and whether this is the run of
+/// `rws app install` rather than a serving isolate.
+fn op_rws_app_context(
+  context: &AppContext,
+  installing: bool,
+  _state: &State,
+  _args: Value,
+  _zero_copy: &mut [ZeroCopyBuf],
+) -> Result<JsonOp, OpError> {
+  Ok(JsonOp::Sync(json!({
+    "host": context.host,
+    "app": context.app,
+    "installing": installing,
+  })))
+}
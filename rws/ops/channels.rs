@@ -0,0 +1,191 @@
+use super::ws::SocketResource;
+use crate::apps::AppContext;
+use crate::channels::{self, ChannelKey, Inbox, Publication};
+use deno_cli::op_error::OpError;
+use deno_cli::ops::JsonOp;
+use deno_cli::state::State;
+use deno_core::CoreIsolate;
+use deno_core::CoreIsolateState;
+use deno_core::ZeroCopyBuf;
+use futures::future::{poll_fn, FutureExt};
+use serde_derive::Deserialize;
+use serde_json::Value;
+use std::cell::RefCell;
+use std::rc::Rc;
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+
+pub fn init(i: &mut CoreIsolate, s: &State, context: &AppContext) {
+  i.register_op(
+    "op_rws_channel_open",
+    s.stateful_json_op2(op_rws_channel_open),
+  );
+  i.register_op(
+    "op_rws_channel_next",
+    s.stateful_json_op2(op_rws_channel_next),
+  );
+  let subscribe_context = context.clone();
+  i.register_op(
+    "op_rws_channel_subscribe",
+    s.stateful_json_op2(move |isolate_state, state, args, zero_copy| {
+      let context = &subscribe_context;
+      op_rws_channel_subscribe(context, isolate_state, state, args, zero_copy)
+    }),
+  );
+  let unsubscribe_context = context.clone();
+  i.register_op(
+    "op_rws_channel_unsubscribe",
+    s.stateful_json_op2(move |isolate_state, state, args, zero_copy| {
+      let context = &unsubscribe_context;
+      op_rws_channel_unsubscribe(context, isolate_state, state, args, zero_copy)
+    }),
+  );
+  let publish_context = context.clone();
+  i.register_op(
+    "op_rws_channel_publish",
+    s.stateful_json_op(move |state, args, zero_copy| {
+      op_rws_channel_publish(&publish_context, state, args, zero_copy)
+    }),
+  );
+}
+
+/// Channels are those of the isolate's host and app; only the name comes
+/// from JS.
+fn channel_key(context: &AppContext, name: String) -> ChannelKey {
+  ChannelKey {
+    host: Some(context.host.clone()),
+    app: Some(context.app.clone()),
+    name,
+  }
+}
+
+struct InboxResource {
+  id: usize,
+  tx: UnboundedSender<Publication>,
+  messages: Rc<RefCell<UnboundedReceiver<Publication>>>,
+}
+
+#[derive(Deserialize)]
+struct RidArgs {
+  rid: u32,
+}
+
+/// `rid` is an inbox or, with `socket`, a `/ws` connection.
+#[derive(Deserialize)]
+struct SubscribeArgs {
+  rid: u32,
+  #[serde(default)]
+  socket: bool,
+  name: String,
+}
+
+fn op_rws_channel_open(
+  isolate_state: &mut CoreIsolateState,
+  _state: &State,
+  _args: Value,
+  _zero_copy: &mut [ZeroCopyBuf],
+) -> Result<JsonOp, OpError> {
+  let (inbox, tx) = Inbox::new();
+  let resource = InboxResource {
+    id: inbox.id,
+    tx,
+    messages: Rc::new(RefCell::new(inbox.messages)),
+  };
+  let rid = isolate_state
+    .resource_table
+    .borrow_mut()
+    .add("rwsChannelInbox", Box::new(resource));
+  Ok(JsonOp::Sync(json!(rid)))
+}
+
+fn op_rws_channel_next(
+  isolate_state: &mut CoreIsolateState,
+  _state: &State,
+  args: Value,
+  _zero_copy: &mut [ZeroCopyBuf],
+) -> Result<JsonOp, OpError> {
+  let RidArgs { rid } = serde_json::from_value(args)?;
+  let messages = isolate_state
+    .resource_table
+    .borrow()
+    .get::<InboxResource>(rid)
+    .ok_or_else(OpError::bad_resource_id)?
+    .messages
+    .clone();
+
+  let fut = async move {
+    let next = poll_fn(|cx| messages.borrow_mut().poll_recv(cx)).await;
+    Ok(match next {
+      Some(publication) => json!({ "done": false, "value": publication }),
+      None => json!({ "done": true }),
+    })
+  };
+  Ok(JsonOp::Async(fut.boxed_local()))
+}
+
+fn op_rws_channel_subscribe(
+  context: &AppContext,
+  isolate_state: &mut CoreIsolateState,
+  _state: &State,
+  args: Value,
+  _zero_copy: &mut [ZeroCopyBuf],
+) -> Result<JsonOp, OpError> {
+  let SubscribeArgs { rid, socket, name } = serde_json::from_value(args)?;
+  let channel = channel_key(context, name);
+  let table = isolate_state.resource_table.borrow();
+
+  if socket {
+    let socket = table
+      .get::<SocketResource>(rid)
+      .ok_or_else(OpError::bad_resource_id)?;
+    channels::subscribe_socket(&channel, &socket.id, socket.socket.clone());
+  } else {
+    let inbox = table
+      .get::<InboxResource>(rid)
+      .ok_or_else(OpError::bad_resource_id)?;
+    channels::subscribe_inbox(&channel, inbox.id, &inbox.tx);
+  }
+  Ok(JsonOp::Sync(json!(true)))
+}
+
+fn op_rws_channel_unsubscribe(
+  context: &AppContext,
+  isolate_state: &mut CoreIsolateState,
+  _state: &State,
+  args: Value,
+  _zero_copy: &mut [ZeroCopyBuf],
+) -> Result<JsonOp, OpError> {
+  let SubscribeArgs { rid, socket, name } = serde_json::from_value(args)?;
+  let channel = channel_key(context, name);
+  let table = isolate_state.resource_table.borrow();
+
+  if socket {
+    let socket = table
+      .get::<SocketResource>(rid)
+      .ok_or_else(OpError::bad_resource_id)?;
+    channels::unsubscribe_socket(&channel, &socket.id);
+  } else {
+    let inbox = table
+      .get::<InboxResource>(rid)
+      .ok_or_else(OpError::bad_resource_id)?;
+    channels::unsubscribe_inbox(&channel, inbox.id);
+  }
+  Ok(JsonOp::Sync(json!(true)))
+}
+
+fn op_rws_channel_publish(
+  context: &AppContext,
+  _state: &State,
+  args: Value,
+  _zero_copy: &mut [ZeroCopyBuf],
+) -> Result<JsonOp, OpError> {
+  #[derive(Deserialize)]
+  struct PublishArgs {
+    name: String,
+    #[serde(default)]
+    data: Value,
+  }
+  let PublishArgs { name, data } = serde_json::from_value(args)?;
+
+  let channel = channel_key(context, name);
+  Ok(JsonOp::Sync(json!(channels::publish(&channel, data))))
+}
@@ -0,0 +1,221 @@
+use crate::apps::AppContext;
+use crate::components::{self, ComponentInfo, RenderRequest};
+use deno_cli::op_error::OpError;
+use deno_cli::ops::JsonOp;
+use deno_cli::state::State;
+use deno_core::CoreIsolate;
+use deno_core::CoreIsolateState;
+use deno_core::ZeroCopyBuf;
+use futures::future::{poll_fn, FutureExt};
+use serde_derive::Deserialize;
+use serde_json::{Map, Value};
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::time::Duration;
+use tokio::sync::mpsc::UnboundedReceiver;
+use tokio::sync::oneshot;
+
+pub fn init(i: &mut CoreIsolate, s: &State, context: &AppContext) {
+  let register_context = context.clone();
+  i.register_op(
+    "op_rws_component_register",
+    s.stateful_json_op(move |state, args, zero_copy| {
+      op_rws_component_register(&register_context, state, args, zero_copy)
+    }),
+  );
+  i.register_op(
+    "op_rws_component_list",
+    s.stateful_json_op(op_rws_component_list),
+  );
+  i.register_op(
+    "op_rws_component_parse",
+    s.stateful_json_op(op_rws_component_parse),
+  );
+  let render_context = context.clone();
+  i.register_op(
+    "op_rws_component_render",
+    s.stateful_json_op(move |state, args, zero_copy| {
+      op_rws_component_render(&render_context, state, args, zero_copy)
+    }),
+  );
+  let listen_context = context.clone();
+  i.register_op(
+    "op_rws_component_listen",
+    s.stateful_json_op2(move |isolate_state, state, args, zero_copy| {
+      let context = &listen_context;
+      op_rws_component_listen(context, isolate_state, state, args, zero_copy)
+    }),
+  );
+  i.register_op(
+    "op_rws_component_next",
+    s.stateful_json_op2(op_rws_component_next),
+  );
+  i.register_op(
+    "op_rws_component_reply",
+    s.stateful_json_op2(op_rws_component_reply),
+  );
+}
+
+struct ListenerResource(Rc<RefCell<UnboundedReceiver<RenderRequest>>>);
+
+/// A component render waiting for this isolate's answer.
+struct PendingRender(oneshot::Sender<Result<Option<String>, String>>);
+
+/// Components are always registered for the isolate's host and app.
+fn op_rws_component_register(
+  context: &AppContext,
+  _state: &State,
+  args: Value,
+  _zero_copy: &mut [ZeroCopyBuf],
+) -> Result<JsonOp, OpError> {
+  let mut info: ComponentInfo = serde_json::from_value(args)?;
+  info.host = Some(context.host.clone());
+  info.app = Some(context.app.clone());
+  let name = info.name.clone();
+  if !components::register(info) {
+    return Err(OpError::other(format!(
+      "component \"{}\" is registered by another app",
+      name
+    )));
+  }
+  Ok(JsonOp::Sync(json!(true)))
+}
+
+fn op_rws_component_list(
+  _state: &State,
+  _args: Value,
+  _zero_copy: &mut [ZeroCopyBuf],
+) -> Result<JsonOp, OpError> {
+  Ok(JsonOp::Sync(json!(components::list())))
+}
+
+fn op_rws_component_parse(
+  _state: &State,
+  args: Value,
+  _zero_copy: &mut [ZeroCopyBuf],
+) -> Result<JsonOp, OpError> {
+  #[derive(Deserialize)]
+  struct ParseArgs {
+    html: String,
+  }
+  let ParseArgs { html } = serde_json::from_value(args)?;
+
+  Ok(JsonOp::Sync(json!(components::parse_registered(&html))))
+}
+
+/// Renders a component in its app's isolate; null when it isn't available.
+fn op_rws_component_render(
+  context: &AppContext,
+  _state: &State,
+  args: Value,
+  _zero_copy: &mut [ZeroCopyBuf],
+) -> Result<JsonOp, OpError> {
+  #[derive(Deserialize)]
+  struct RenderArgs {
+    name: String,
+    #[serde(default)]
+    args: Map<String, Value>,
+    timeout: u64,
+  }
+  let RenderArgs {
+    name,
+    args,
+    timeout,
+  } = serde_json::from_value(args)?;
+
+  let host = context.host.clone();
+  let fut = async move {
+    let timeout = Duration::from_millis(timeout);
+    let html = components::render(&host, &name, args, timeout)
+      .await
+      .map_err(OpError::other)?;
+    Ok(json!(html))
+  };
+  Ok(JsonOp::Async(fut.boxed_local()))
+}
+
+fn op_rws_component_listen(
+  context: &AppContext,
+  isolate_state: &mut CoreIsolateState,
+  _state: &State,
+  _args: Value,
+  _zero_copy: &mut [ZeroCopyBuf],
+) -> Result<JsonOp, OpError> {
+  let receiver = components::listen(context);
+  let listener = ListenerResource(Rc::new(RefCell::new(receiver)));
+  let rid = isolate_state
+    .resource_table
+    .borrow_mut()
+    .add("rwsComponentListener", Box::new(listener));
+  Ok(JsonOp::Sync(json!(rid)))
+}
+
+fn op_rws_component_next(
+  isolate_state: &mut CoreIsolateState,
+  _state: &State,
+  args: Value,
+  _zero_copy: &mut [ZeroCopyBuf],
+) -> Result<JsonOp, OpError> {
+  #[derive(Deserialize)]
+  struct NextArgs {
+    rid: u32,
+  }
+  let NextArgs { rid } = serde_json::from_value(args)?;
+  let resource_table = isolate_state.resource_table.clone();
+  let listener = resource_table
+    .borrow()
+    .get::<ListenerResource>(rid)
+    .ok_or_else(OpError::bad_resource_id)?
+    .0
+    .clone();
+
+  let fut = async move {
+    let next = poll_fn(|cx| listener.borrow_mut().poll_recv(cx)).await;
+    let request = match next {
+      Some(request) => request,
+      None => return Ok(json!({ "done": true })),
+    };
+    let rid = resource_table
+      .borrow_mut()
+      .add("rwsComponentRender", Box::new(PendingRender(request.reply)));
+    Ok(json!({
+      "done": false,
+      "value": {
+        "rid": rid,
+        "name": request.name,
+        "args": request.args,
+      }
+    }))
+  };
+  Ok(JsonOp::Async(fut.boxed_local()))
+}
+
+/// `html` is null when the component isn't defined in this isolate.
+fn op_rws_component_reply(
+  isolate_state: &mut CoreIsolateState,
+  _state: &State,
+  args: Value,
+  _zero_copy: &mut [ZeroCopyBuf],
+) -> Result<JsonOp, OpError> {
+  #[derive(Deserialize)]
+  struct ReplyArgs {
+    rid: u32,
+    #[serde(default)]
+    html: Option<String>,
+    #[serde(default)]
+    error: Option<String>,
+  }
+  let ReplyArgs { rid, html, error } = serde_json::from_value(args)?;
+
+  let pending = isolate_state
+    .resource_table
+    .borrow_mut()
+    .remove::<PendingRender>(rid)
+    .ok_or_else(OpError::bad_resource_id)?;
+  let reply = match error {
+    Some(error) => Err(error),
+    None => Ok(html),
+  };
+  let _ = pending.0.send(reply);
+  Ok(JsonOp::Sync(json!(true)))
+}
@@ -0,0 +1,43 @@
+use crate::apps::AppContext;
+use crate::logging::{self, LogEntry, LogLevel};
+use deno_cli::op_error::OpError;
+use deno_cli::ops::JsonOp;
+use deno_cli::state::State;
+use deno_core::CoreIsolate;
+use deno_core::ZeroCopyBuf;
+use serde_derive::Deserialize;
+use serde_json::{Map, Value};
+
+pub fn init(i: &mut CoreIsolate, s: &State, context: &AppContext) {
+  let context = context.clone();
+  i.register_op(
+    "op_rws_log",
+    s.stateful_json_op(move |state, args, zero_copy| {
+      op_rws_log(&context, state, args, zero_copy)
+    }),
+  );
+}
+
+/// Entries are tagged with the isolate's app and host, whatever the fields
+/// say.
+fn op_rws_log(
+  context: &AppContext,
+  _state: &State,
+  args: Value,
+  _zero_copy: &mut [ZeroCopyBuf],
+) -> Result<JsonOp, OpError> {
+  #[derive(Deserialize)]
+  struct LogArgs {
+    level: LogLevel,
+    msg: String,
+    #[serde(default)]
+    fields: Map<String, Value>,
+  }
+  let LogArgs { level, msg, fields } = serde_json::from_value(args)?;
+
+  let mut entry = LogEntry::with_fields(level, msg, fields);
+  entry.app = Some(context.app.clone());
+  entry.host = Some(context.host.clone());
+  logging::write(entry);
+  Ok(JsonOp::Sync(json!(true)))
+}
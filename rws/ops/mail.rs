@@ -0,0 +1,28 @@
+use crate::mail;
+use deno_cli::op_error::OpError;
+use deno_cli::ops::JsonOp;
+use deno_cli::state::State;
+use deno_core::CoreIsolate;
+use deno_core::ZeroCopyBuf;
+use futures::future::FutureExt;
+use serde_json::Value;
+
+pub fn init(i: &mut CoreIsolate, s: &State) {
+  i.register_op("op_rws_mail_send", s.stateful_json_op(op_rws_mail_send));
+}
+
+fn op_rws_mail_send(
+  _state: &State,
+  args: Value,
+  _zero_copy: &mut [ZeroCopyBuf],
+) -> Result<JsonOp, OpError> {
+  let message: mail::Message = serde_json::from_value(args)?;
+
+  let fut = async move {
+    let status = mail::send(message)
+      .await
+      .map_err(|e| OpError::other(e.to_string()))?;
+    Ok(json!(status))
+  };
+  Ok(JsonOp::Async(fut.boxed_local()))
+}
@@ -0,0 +1,44 @@
+//! Ops backing the `RWS` JavaScript API.  The JS side lives with the other
+//! Deno ops in `cli/js/ops/rws_*.ts`; the Rust side needs rws state, so it is
+//! registered here on top of the ops `MainWorker` already installs.  Ops
+//! that act for an app take its host and app from the isolate's context
+//! (see `apps`), not from their arguments.
+
+use crate::apps::AppContext;
+use deno_cli::state::State;
+use deno_core::CoreIsolate;
+
+pub mod accounts;
+pub mod apps;
+pub mod channels;
+pub mod components;
+pub mod logging;
+pub mod mail;
+pub mod options;
+pub mod protection;
+pub mod rbac;
+pub mod render;
+pub mod routes;
+pub mod services;
+pub mod ws;
+
+pub fn init(
+  i: &mut CoreIsolate,
+  s: &State,
+  context: &AppContext,
+  installing: bool,
+) {
+  accounts::init(i, s);
+  apps::init(i, s, context, installing);
+  channels::init(i, s, context);
+  components::init(i, s, context);
+  logging::init(i, s, context);
+  mail::init(i, s);
+  options::init(i, s, context);
+  protection::init(i, s);
+  rbac::init(i, s, context);
+  render::init(i, s, context);
+  routes::init(i, s, context);
+  services::init(i, s, context);
+  ws::init(i, s, context);
+}
@@ -0,0 +1,57 @@
+use crate::apps::AppContext;
+use crate::options::{self, OptionsError};
+use deno_cli::op_error::OpError;
+use deno_cli::ops::JsonOp;
+use deno_cli::state::State;
+use deno_core::CoreIsolate;
+use deno_core::ZeroCopyBuf;
+use serde_derive::Deserialize;
+use serde_json::{Map, Value};
+
+pub fn init(i: &mut CoreIsolate, s: &State, context: &AppContext) {
+  let get_context = context.clone();
+  i.register_op(
+    "op_rws_options_get",
+    s.stateful_json_op(move |state, args, zero_copy| {
+      op_rws_options_get(&get_context, state, args, zero_copy)
+    }),
+  );
+  let save_context = context.clone();
+  i.register_op(
+    "op_rws_options_save",
+    s.stateful_json_op(move |state, args, zero_copy| {
+      op_rws_options_save(&save_context, state, args, zero_copy)
+    }),
+  );
+}
+
+fn op_rws_options_get(
+  context: &AppContext,
+  _state: &State,
+  _args: Value,
+  _zero_copy: &mut [ZeroCopyBuf],
+) -> Result<JsonOp, OpError> {
+  let options = options::get(&context.host, &context.app);
+  Ok(JsonOp::Sync(Value::Object(options)))
+}
+
+fn op_rws_options_save(
+  context: &AppContext,
+  _state: &State,
+  args: Value,
+  _zero_copy: &mut [ZeroCopyBuf],
+) -> Result<JsonOp, OpError> {
+  #[derive(Deserialize)]
+  struct SaveArgs {
+    #[serde(default)]
+    options: Map<String, Value>,
+  }
+  let SaveArgs { options } = serde_json::from_value(args)?;
+  let saved = options::save(&context.host, &context.app, options).map_err(
+    |e| match e {
+      OptionsError::Invalid { .. } => OpError::type_error(e.to_string()),
+      OptionsError::Io(_) => OpError::other(e.to_string()),
+    },
+  )?;
+  Ok(JsonOp::Sync(Value::Object(saved)))
+}
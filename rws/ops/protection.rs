@@ -0,0 +1,70 @@
+use crate::protection;
+use deno_cli::op_error::OpError;
+use deno_cli::ops::JsonOp;
+use deno_cli::state::State;
+use deno_core::CoreIsolate;
+use deno_core::ZeroCopyBuf;
+use serde_derive::Deserialize;
+use serde_json::Value;
+
+pub fn init(i: &mut CoreIsolate, s: &State) {
+  i.register_op("op_rws_login_check", s.stateful_json_op(op_rws_login_check));
+  i.register_op(
+    "op_rws_login_failed",
+    s.stateful_json_op(op_rws_login_failed),
+  );
+  i.register_op(
+    "op_rws_login_succeeded",
+    s.stateful_json_op(op_rws_login_succeeded),
+  );
+}
+
+/// Attempts are charged to the account only: an isolate never sees the
+/// client's address, and one taken from JS could lock out anyone's.
+#[derive(Deserialize)]
+struct LoginArgs {
+  #[serde(default)]
+  account: Option<String>,
+}
+
+fn op_rws_login_check(
+  _state: &State,
+  args: Value,
+  _zero_copy: &mut [ZeroCopyBuf],
+) -> Result<JsonOp, OpError> {
+  let LoginArgs { account } = serde_json::from_value(args)?;
+  let account = account
+    .ok_or_else(|| OpError::type_error("account is required".to_string()))?;
+
+  let result = match protection::check_login(None, &account) {
+    Ok(()) => json!({ "allowed": true }),
+    Err(refusal) => json!({
+      "allowed": false,
+      "reason": refusal.to_string(),
+      "retryAfter": refusal.retry_after().map(|d| d.as_millis() as u64),
+    }),
+  };
+  Ok(JsonOp::Sync(result))
+}
+
+fn op_rws_login_failed(
+  _state: &State,
+  args: Value,
+  _zero_copy: &mut [ZeroCopyBuf],
+) -> Result<JsonOp, OpError> {
+  let LoginArgs { account } = serde_json::from_value(args)?;
+  protection::record_failure(None, account.as_deref());
+  Ok(JsonOp::Sync(json!(true)))
+}
+
+fn op_rws_login_succeeded(
+  _state: &State,
+  args: Value,
+  _zero_copy: &mut [ZeroCopyBuf],
+) -> Result<JsonOp, OpError> {
+  let LoginArgs { account } = serde_json::from_value(args)?;
+  if let Some(account) = account {
+    protection::record_success(&account);
+  }
+  Ok(JsonOp::Sync(json!(true)))
+}
@@ -0,0 +1,65 @@
+use crate::apps::AppContext;
+use crate::rbac;
+use deno_cli::op_error::OpError;
+use deno_cli::ops::JsonOp;
+use deno_cli::state::State;
+use deno_core::CoreIsolate;
+use deno_core::ZeroCopyBuf;
+use serde_derive::Deserialize;
+use serde_json::Value;
+
+pub fn init(i: &mut CoreIsolate, s: &State, context: &AppContext) {
+  let can_context = context.clone();
+  i.register_op(
+    "op_rws_can",
+    s.stateful_json_op(move |state, args, zero_copy| {
+      op_rws_can(&can_context, state, args, zero_copy)
+    }),
+  );
+  let permissions_context = context.clone();
+  i.register_op(
+    "op_rws_permissions",
+    s.stateful_json_op(move |state, args, zero_copy| {
+      op_rws_permissions(&permissions_context, state, args, zero_copy)
+    }),
+  );
+}
+
+/// Checks a permission of the isolate's app against the user's roles on
+/// its host.
+fn op_rws_can(
+  context: &AppContext,
+  _state: &State,
+  args: Value,
+  _zero_copy: &mut [ZeroCopyBuf],
+) -> Result<JsonOp, OpError> {
+  #[derive(Deserialize)]
+  #[serde(rename_all = "camelCase")]
+  struct CanArgs {
+    #[serde(default)]
+    user_id: Option<String>,
+    permission: String,
+  }
+  let CanArgs {
+    user_id,
+    permission,
+  } = serde_json::from_value(args)?;
+  let allowed = match user_id {
+    Some(user_id) => {
+      rbac::user_can(&context.host, &user_id, &context.app, &permission)
+    }
+    None => false,
+  };
+  Ok(JsonOp::Sync(json!(allowed)))
+}
+
+/// The permissions declared by the apps on the isolate's host.
+fn op_rws_permissions(
+  context: &AppContext,
+  _state: &State,
+  _args: Value,
+  _zero_copy: &mut [ZeroCopyBuf],
+) -> Result<JsonOp, OpError> {
+  let permissions = rbac::permissions(&context.host);
+  Ok(JsonOp::Sync(json!(permissions)))
+}
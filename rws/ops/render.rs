@@ -0,0 +1,158 @@
+use crate::apps::AppContext;
+use crate::render::{self, Page, PageRequest};
+use deno_cli::op_error::OpError;
+use deno_cli::ops::JsonOp;
+use deno_cli::state::State;
+use deno_core::CoreIsolate;
+use deno_core::CoreIsolateState;
+use deno_core::ZeroCopyBuf;
+use futures::future::{poll_fn, FutureExt};
+use serde_derive::Deserialize;
+use serde_json::Value;
+use std::cell::RefCell;
+use std::rc::Rc;
+use tokio::sync::mpsc::UnboundedReceiver;
+use tokio::sync::oneshot;
+
+pub fn init(i: &mut CoreIsolate, s: &State, context: &AppContext) {
+  let listen_context = context.clone();
+  i.register_op(
+    "op_rws_page_listen",
+    s.stateful_json_op2(move |isolate_state, state, args, zero_copy| {
+      op_rws_page_listen(&listen_context, isolate_state, state, args, zero_copy)
+    }),
+  );
+  i.register_op("op_rws_page_next", s.stateful_json_op2(op_rws_page_next));
+  i.register_op("op_rws_page_reply", s.stateful_json_op2(op_rws_page_reply));
+  let inline_context = context.clone();
+  i.register_op(
+    "op_rws_page_inline",
+    s.stateful_json_op(move |state, args, zero_copy| {
+      op_rws_page_inline(&inline_context, state, args, zero_copy)
+    }),
+  );
+}
+
+struct ListenerResource(Rc<RefCell<UnboundedReceiver<PageRequest>>>);
+
+/// A page waiting for the isolate to render it.
+struct PendingPage(oneshot::Sender<Result<Option<Page>, String>>);
+
+fn op_rws_page_listen(
+  context: &AppContext,
+  isolate_state: &mut CoreIsolateState,
+  _state: &State,
+  _args: Value,
+  _zero_copy: &mut [ZeroCopyBuf],
+) -> Result<JsonOp, OpError> {
+  let receiver = render::listen(context);
+  let listener = ListenerResource(Rc::new(RefCell::new(receiver)));
+  let rid = isolate_state
+    .resource_table
+    .borrow_mut()
+    .add("rwsPageListener", Box::new(listener));
+  Ok(JsonOp::Sync(json!(rid)))
+}
+
+fn op_rws_page_next(
+  isolate_state: &mut CoreIsolateState,
+  _state: &State,
+  args: Value,
+  _zero_copy: &mut [ZeroCopyBuf],
+) -> Result<JsonOp, OpError> {
+  #[derive(Deserialize)]
+  struct NextArgs {
+    rid: u32,
+  }
+  let NextArgs { rid } = serde_json::from_value(args)?;
+  let resource_table = isolate_state.resource_table.clone();
+  let listener = resource_table
+    .borrow()
+    .get::<ListenerResource>(rid)
+    .ok_or_else(OpError::bad_resource_id)?
+    .0
+    .clone();
+
+  let fut = async move {
+    let next = poll_fn(|cx| listener.borrow_mut().poll_recv(cx)).await;
+    let request = match next {
+      Some(request) => request,
+      None => return Ok(json!({ "done": true })),
+    };
+    let rid = resource_table
+      .borrow_mut()
+      .add("rwsPage", Box::new(PendingPage(request.reply)));
+    Ok(json!({
+      "done": false,
+      "value": {
+        "rid": rid,
+        "host": request.host,
+        "app": request.app,
+        "base": request.base,
+        "route": request.route,
+        "query": request.query,
+        "inline": request.inline,
+        "slot": request.slot,
+        "module": request.module,
+        "token": request.token,
+      }
+    }))
+  };
+  Ok(JsonOp::Async(fut.boxed_local()))
+}
+
+/// `page` is null when the app has no view, which serves the plain shell.
+fn op_rws_page_reply(
+  isolate_state: &mut CoreIsolateState,
+  _state: &State,
+  args: Value,
+  _zero_copy: &mut [ZeroCopyBuf],
+) -> Result<JsonOp, OpError> {
+  #[derive(Deserialize)]
+  struct ReplyArgs {
+    rid: u32,
+    #[serde(default)]
+    page: Option<Page>,
+    #[serde(default)]
+    error: Option<String>,
+  }
+  let ReplyArgs { rid, page, error } = serde_json::from_value(args)?;
+
+  let pending = isolate_state
+    .resource_table
+    .borrow_mut()
+    .remove::<PendingPage>(rid)
+    .ok_or_else(OpError::bad_resource_id)?;
+  let reply = match error {
+    Some(error) => Err(error),
+    None => Ok(page),
+  };
+  let _ = pending.0.send(reply);
+  Ok(JsonOp::Sync(json!(true)))
+}
+
+/// A fragment of another app on this host (`RWS.views.inline`).
+fn op_rws_page_inline(
+  context: &AppContext,
+  _state: &State,
+  args: Value,
+  _zero_copy: &mut [ZeroCopyBuf],
+) -> Result<JsonOp, OpError> {
+  #[derive(Deserialize)]
+  struct InlineArgs {
+    app: String,
+    route: String,
+    #[serde(default)]
+    query: String,
+  }
+  let InlineArgs { app, route, query } = serde_json::from_value(args)?;
+
+  let host = context.host.clone();
+  let fut = async move {
+    let body = render::inline(&host, &app, &route, &query)
+      .await
+      .map_err(OpError::other)?;
+    Ok(json!(body))
+  };
+  Ok(JsonOp::Async(fut.boxed_local()))
+}
@@ -0,0 +1,62 @@
+use crate::apps::AppContext;
+use crate::config::RouteMount;
+use crate::routes;
+use deno_cli::op_error::OpError;
+use deno_cli::ops::JsonOp;
+use deno_cli::state::State;
+use deno_core::CoreIsolate;
+use deno_core::ZeroCopyBuf;
+use serde_derive::Deserialize;
+use serde_json::{Map, Value};
+
+pub fn init(i: &mut CoreIsolate, s: &State, context: &AppContext) {
+  let mount_context = context.clone();
+  i.register_op(
+    "op_rws_route_mount",
+    s.stateful_json_op(move |state, args, zero_copy| {
+      op_rws_route_mount(&mount_context, state, args, zero_copy)
+    }),
+  );
+  let url_context = context.clone();
+  i.register_op(
+    "op_rws_route_url",
+    s.stateful_json_op(move |state, args, zero_copy| {
+      op_rws_route_url(&url_context, state, args, zero_copy)
+    }),
+  );
+}
+
+fn op_rws_route_mount(
+  context: &AppContext,
+  _state: &State,
+  args: Value,
+  _zero_copy: &mut [ZeroCopyBuf],
+) -> Result<JsonOp, OpError> {
+  #[derive(Deserialize)]
+  struct MountArgs {
+    mount: RouteMount,
+  }
+  let MountArgs { mount } = serde_json::from_value(args)?;
+  routes::mount(&context.host, &context.app, mount)
+    .map_err(|e| OpError::type_error(e.to_string()))?;
+  Ok(JsonOp::Sync(json!(true)))
+}
+
+fn op_rws_route_url(
+  context: &AppContext,
+  _state: &State,
+  args: Value,
+  _zero_copy: &mut [ZeroCopyBuf],
+) -> Result<JsonOp, OpError> {
+  #[derive(Deserialize)]
+  struct UrlArgs {
+    app: String,
+    route: String,
+    #[serde(default)]
+    args: Map<String, Value>,
+  }
+  let UrlArgs { app, route, args } = serde_json::from_value(args)?;
+  let url = routes::url_for(&context.host, &app, &route, &args)
+    .map_err(|e| OpError::type_error(e.to_string()))?;
+  Ok(JsonOp::Sync(json!(url)))
+}
@@ -0,0 +1,154 @@
+use crate::apps::AppContext;
+use crate::services::{self, Call, ServiceInfo};
+use deno_cli::op_error::OpError;
+use deno_cli::ops::JsonOp;
+use deno_cli::state::State;
+use deno_core::CoreIsolate;
+use deno_core::CoreIsolateState;
+use deno_core::ZeroCopyBuf;
+use futures::future::{poll_fn, FutureExt};
+use serde_derive::Deserialize;
+use serde_json::Value;
+use std::cell::RefCell;
+use std::rc::Rc;
+use tokio::sync::mpsc::UnboundedReceiver;
+use tokio::sync::oneshot;
+
+pub fn init(i: &mut CoreIsolate, s: &State, context: &AppContext) {
+  let register_context = context.clone();
+  i.register_op(
+    "op_rws_service_register",
+    s.stateful_json_op(move |state, args, zero_copy| {
+      op_rws_service_register(&register_context, state, args, zero_copy)
+    }),
+  );
+  let listen_context = context.clone();
+  i.register_op(
+    "op_rws_service_listen",
+    s.stateful_json_op2(move |isolate_state, state, args, zero_copy| {
+      let context = &listen_context;
+      op_rws_service_listen(context, isolate_state, state, args, zero_copy)
+    }),
+  );
+  i.register_op(
+    "op_rws_service_next",
+    s.stateful_json_op2(op_rws_service_next),
+  );
+  i.register_op(
+    "op_rws_service_reply",
+    s.stateful_json_op2(op_rws_service_reply),
+  );
+}
+
+struct ListenerResource(Rc<RefCell<UnboundedReceiver<Call>>>);
+
+/// A `/json` call waiting for the isolate's answer.
+struct PendingCall(oneshot::Sender<Result<Value, String>>);
+
+/// Services are always registered for the isolate's host and app.
+fn op_rws_service_register(
+  context: &AppContext,
+  _state: &State,
+  args: Value,
+  _zero_copy: &mut [ZeroCopyBuf],
+) -> Result<JsonOp, OpError> {
+  let mut info: ServiceInfo = serde_json::from_value(args)?;
+  info.host = Some(context.host.clone());
+  info.app = Some(context.app.clone());
+  let name = info.name.clone();
+  if !services::register(info) {
+    return Err(OpError::other(format!(
+      "service \"{}\" is registered by another app",
+      name
+    )));
+  }
+  Ok(JsonOp::Sync(json!(true)))
+}
+
+fn op_rws_service_listen(
+  context: &AppContext,
+  isolate_state: &mut CoreIsolateState,
+  _state: &State,
+  _args: Value,
+  _zero_copy: &mut [ZeroCopyBuf],
+) -> Result<JsonOp, OpError> {
+  let receiver = services::listen(context);
+  let listener = ListenerResource(Rc::new(RefCell::new(receiver)));
+  let rid = isolate_state
+    .resource_table
+    .borrow_mut()
+    .add("rwsServiceListener", Box::new(listener));
+  Ok(JsonOp::Sync(json!(rid)))
+}
+
+fn op_rws_service_next(
+  isolate_state: &mut CoreIsolateState,
+  _state: &State,
+  args: Value,
+  _zero_copy: &mut [ZeroCopyBuf],
+) -> Result<JsonOp, OpError> {
+  #[derive(Deserialize)]
+  struct NextArgs {
+    rid: u32,
+  }
+  let NextArgs { rid } = serde_json::from_value(args)?;
+  let resource_table = isolate_state.resource_table.clone();
+  let listener = resource_table
+    .borrow()
+    .get::<ListenerResource>(rid)
+    .ok_or_else(OpError::bad_resource_id)?
+    .0
+    .clone();
+
+  let fut = async move {
+    let next = poll_fn(|cx| listener.borrow_mut().poll_recv(cx)).await;
+    let call = match next {
+      Some(call) => call,
+      None => return Ok(json!({ "done": true })),
+    };
+    let rid = resource_table
+      .borrow_mut()
+      .add("rwsServiceCall", Box::new(PendingCall(call.reply)));
+    Ok(json!({
+      "done": false,
+      "value": {
+        "rid": rid,
+        "service": call.service.name,
+        "host": call.host,
+        "args": call.args,
+        "token": call.token,
+      }
+    }))
+  };
+  Ok(JsonOp::Async(fut.boxed_local()))
+}
+
+fn op_rws_service_reply(
+  isolate_state: &mut CoreIsolateState,
+  _state: &State,
+  args: Value,
+  _zero_copy: &mut [ZeroCopyBuf],
+) -> Result<JsonOp, OpError> {
+  #[derive(Deserialize)]
+  struct ReplyArgs {
+    rid: u32,
+    #[serde(default)]
+    result: Value,
+    #[serde(default)]
+    error: Option<String>,
+  }
+  let ReplyArgs { rid, result, error } = serde_json::from_value(args)?;
+
+  let call = isolate_state
+    .resource_table
+    .borrow_mut()
+    .remove::<PendingCall>(rid)
+    .ok_or_else(OpError::bad_resource_id)?;
+  let reply = match error {
+    Some(error) => Err(error),
+    None => Ok(result),
+  };
+  // The client may have gone away meanwhile.
+  let _ = call.0.send(reply);
+  Ok(JsonOp::Sync(json!(true)))
+}
@@ -0,0 +1,197 @@
+use crate::apps::AppContext;
+use crate::websocket::{self, Connection, Outgoing, SocketEvent};
+use actix::Addr;
+use actix_web::web::Bytes;
+use actix_web_actors::ws::{CloseCode, CloseReason};
+use deno_cli::op_error::OpError;
+use deno_cli::ops::JsonOp;
+use deno_cli::state::State;
+use deno_core::CoreIsolate;
+use deno_core::CoreIsolateState;
+use deno_core::ZeroCopyBuf;
+use futures::future::{poll_fn, FutureExt};
+use serde_derive::Deserialize;
+use serde_json::Value;
+use std::cell::RefCell;
+use std::rc::Rc;
+use tokio::sync::mpsc::UnboundedReceiver;
+
+pub fn init(i: &mut CoreIsolate, s: &State, context: &AppContext) {
+  let context = context.clone();
+  i.register_op(
+    "op_rws_ws_listen",
+    s.stateful_json_op2(move |isolate_state, state, args, zero_copy| {
+      op_rws_ws_listen(&context, isolate_state, state, args, zero_copy)
+    }),
+  );
+  i.register_op("op_rws_ws_accept", s.stateful_json_op2(op_rws_ws_accept));
+  i.register_op("op_rws_ws_receive", s.stateful_json_op2(op_rws_ws_receive));
+  i.register_op("op_rws_ws_send", s.stateful_json_op2(op_rws_ws_send));
+  i.register_op("op_rws_ws_close", s.stateful_json_op2(op_rws_ws_close));
+}
+
+struct ListenerResource(Rc<RefCell<UnboundedReceiver<Connection>>>);
+
+/// An accepted connection; `ops::channels` subscribes these to channels.
+pub(super) struct SocketResource {
+  pub(super) id: String,
+  pub(super) socket: Addr<websocket::AppSocket>,
+  events: Rc<RefCell<UnboundedReceiver<SocketEvent>>>,
+}
+
+#[derive(Deserialize)]
+struct RidArgs {
+  rid: u32,
+}
+
+fn op_rws_ws_listen(
+  context: &AppContext,
+  isolate_state: &mut CoreIsolateState,
+  _state: &State,
+  _args: Value,
+  _zero_copy: &mut [ZeroCopyBuf],
+) -> Result<JsonOp, OpError> {
+  let receiver = websocket::listen(context);
+  let listener = ListenerResource(Rc::new(RefCell::new(receiver)));
+  let rid = isolate_state
+    .resource_table
+    .borrow_mut()
+    .add("rwsSocketListener", Box::new(listener));
+  Ok(JsonOp::Sync(json!(rid)))
+}
+
+fn op_rws_ws_accept(
+  isolate_state: &mut CoreIsolateState,
+  _state: &State,
+  args: Value,
+  _zero_copy: &mut [ZeroCopyBuf],
+) -> Result<JsonOp, OpError> {
+  let RidArgs { rid } = serde_json::from_value(args)?;
+  let resource_table = isolate_state.resource_table.clone();
+  let listener = resource_table
+    .borrow()
+    .get::<ListenerResource>(rid)
+    .ok_or_else(OpError::bad_resource_id)?
+    .0
+    .clone();
+
+  let fut = async move {
+    let next = poll_fn(|cx| listener.borrow_mut().poll_recv(cx)).await;
+    let connection = match next {
+      Some(connection) => connection,
+      None => return Ok(json!({ "done": true })),
+    };
+    let socket = SocketResource {
+      id: connection.id.clone(),
+      socket: connection.socket,
+      events: Rc::new(RefCell::new(connection.events)),
+    };
+    let rid = resource_table
+      .borrow_mut()
+      .add("rwsSocket", Box::new(socket));
+    Ok(json!({
+      "done": false,
+      "value": {
+        "rid": rid,
+        "id": connection.id,
+        "host": connection.host,
+        "path": connection.path,
+        "ip": connection.ip,
+        "token": connection.token,
+      }
+    }))
+  };
+  Ok(JsonOp::Async(fut.boxed_local()))
+}
+
+fn op_rws_ws_receive(
+  isolate_state: &mut CoreIsolateState,
+  _state: &State,
+  args: Value,
+  _zero_copy: &mut [ZeroCopyBuf],
+) -> Result<JsonOp, OpError> {
+  let RidArgs { rid } = serde_json::from_value(args)?;
+  let events = isolate_state
+    .resource_table
+    .borrow()
+    .get::<SocketResource>(rid)
+    .ok_or_else(OpError::bad_resource_id)?
+    .events
+    .clone();
+
+  let fut = async move {
+    let event = poll_fn(|cx| events.borrow_mut().poll_recv(cx)).await;
+    let message = match event {
+      Some(SocketEvent::Text(text)) => json!({ "kind": "text", "data": text }),
+      Some(SocketEvent::Binary(bin)) => {
+        json!({ "kind": "binary", "data": base64::encode(&bin) })
+      }
+      Some(SocketEvent::Closed(reason)) => json!({
+        "kind": "close",
+        "code": reason.as_ref().map(|r| u16::from(r.code)),
+        "reason": reason.and_then(|r| r.description),
+      }),
+      None => json!({ "kind": "close", "code": null, "reason": null }),
+    };
+    Ok(message)
+  };
+  Ok(JsonOp::Async(fut.boxed_local()))
+}
+
+/// Sends `text`, or the zero copy buffer as a binary message.
+fn op_rws_ws_send(
+  isolate_state: &mut CoreIsolateState,
+  _state: &State,
+  args: Value,
+  zero_copy: &mut [ZeroCopyBuf],
+) -> Result<JsonOp, OpError> {
+  #[derive(Deserialize)]
+  struct SendArgs {
+    rid: u32,
+    text: Option<String>,
+  }
+  let SendArgs { rid, text } = serde_json::from_value(args)?;
+
+  let message = match (text, zero_copy.first()) {
+    (Some(text), _) => Outgoing::Text(text),
+    (None, Some(buf)) => Outgoing::Binary(Bytes::copy_from_slice(&buf[..])),
+    (None, None) => {
+      return Err(OpError::type_error("nothing to send".to_string()))
+    }
+  };
+  isolate_state
+    .resource_table
+    .borrow()
+    .get::<SocketResource>(rid)
+    .ok_or_else(OpError::bad_resource_id)?
+    .socket
+    .do_send(message);
+  Ok(JsonOp::Sync(json!(true)))
+}
+
+fn op_rws_ws_close(
+  isolate_state: &mut CoreIsolateState,
+  _state: &State,
+  args: Value,
+  _zero_copy: &mut [ZeroCopyBuf],
+) -> Result<JsonOp, OpError> {
+  #[derive(Deserialize)]
+  struct CloseArgs {
+    rid: u32,
+    code: Option<u16>,
+    reason: Option<String>,
+  }
+  let CloseArgs { rid, code, reason } = serde_json::from_value(args)?;
+
+  let socket = isolate_state
+    .resource_table
+    .borrow_mut()
+    .remove::<SocketResource>(rid)
+    .ok_or_else(OpError::bad_resource_id)?;
+  let reason = code.map(|code| CloseReason {
+    code: CloseCode::from(code),
+    description: reason,
+  });
+  socket.socket.do_send(Outgoing::Close(reason));
+  Ok(JsonOp::Sync(json!(true)))
+}
@@ -0,0 +1,303 @@
+//! Per-app, per-host settings (`RWS.getOptions` / `RWS.saveOptions`).
+//!
+//! Each app's options are one JSON object in
+//! `<rws dir>/options/<host>/<app>.json`.  Saves merge into the stored
+//! object (a `null` removes the key) and are checked against the `options`
+//! schema of the app's manifest when it has one.  Every change is published
+//! on the app's `rws:options` channel.  Code running outside an app uses
+//! empty host and app names.
+
+use crate::channels::{self, ChannelKey};
+use crate::config;
+use crate::manifest::{Manifest, OptionSpec};
+use serde_json::{Map, Value};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+/// Channel apps listen on for changes to their options.
+pub const CHANGE_CHANNEL: &str = "rws:options";
+
+lazy_static! {
+  static ref STORE: Mutex<OptionsStore> =
+    Mutex::new(OptionsStore::new(config::rws_dir().join("options")));
+}
+
+#[derive(Debug)]
+pub enum OptionsError {
+  Io(io::Error),
+  /// The value for `key` doesn't match the manifest schema.
+  Invalid {
+    key: String,
+    reason: String,
+  },
+}
+
+impl fmt::Display for OptionsError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      OptionsError::Io(e) => write!(f, "could not store options: {}", e),
+      OptionsError::Invalid { key, reason } => {
+        write!(f, "invalid option \"{}\": {}", key, reason)
+      }
+    }
+  }
+}
+
+impl std::error::Error for OptionsError {}
+
+impl From<io::Error> for OptionsError {
+  fn from(e: io::Error) -> Self {
+    OptionsError::Io(e)
+  }
+}
+
+impl From<serde_json::Error> for OptionsError {
+  fn from(e: serde_json::Error) -> Self {
+    OptionsError::Io(e.into())
+  }
+}
+
+/// The options schema of an installed app, empty when it declares none.
+pub fn schema(host: &str, app: &str) -> Vec<(String, OptionSpec)> {
+  let path = config::app_dir(host, app).join("manifest.json");
+  match Manifest::load(&path) {
+    Ok(manifest) => manifest.option_specs(),
+    Err(_) => Vec::new(),
+  }
+}
+
+fn validate(
+  key: &str,
+  value: &Value,
+  schema: &[(String, OptionSpec)],
+) -> Result<(), OptionsError> {
+  if schema.is_empty() || value.is_null() {
+    return Ok(());
+  }
+  let invalid = |reason: String| OptionsError::Invalid {
+    key: key.to_string(),
+    reason,
+  };
+  let spec = match schema.iter().find(|(k, _)| k == key) {
+    Some((_, spec)) => spec,
+    None => return Err(invalid("not in the app's manifest".to_string())),
+  };
+  if let Some(kind) = spec.kind {
+    if !kind.accepts(value) {
+      return Err(invalid(format!("expected {:?}", kind).to_lowercase()));
+    }
+  }
+  if let Some(one_of) = &spec.one_of {
+    if !one_of.contains(value) {
+      return Err(invalid(format!("must be one of {}", json!(one_of))));
+    }
+  }
+  Ok(())
+}
+
+pub struct OptionsStore {
+  dir: PathBuf,
+}
+
+impl OptionsStore {
+  pub fn new(dir: PathBuf) -> Self {
+    OptionsStore { dir }
+  }
+
+  fn path(&self, host: &str, app: &str) -> PathBuf {
+    self
+      .dir
+      .join(config::file_name(host))
+      .join(format!("{}.json", config::file_name(app)))
+  }
+
+  /// Stored options over the schema defaults.
+  pub fn get(
+    &self,
+    host: &str,
+    app: &str,
+    schema: &[(String, OptionSpec)],
+  ) -> Map<String, Value> {
+    let mut options: Map<String, Value> = schema
+      .iter()
+      .filter_map(|(key, spec)| Some((key.clone(), spec.default.clone()?)))
+      .collect();
+    let stored = fs::read_to_string(self.path(host, app))
+      .ok()
+      .and_then(|text| serde_json::from_str::<Map<String, Value>>(&text).ok());
+    if let Some(stored) = stored {
+      options.extend(stored);
+    }
+    options
+  }
+
+  /// Merges `patch` into the stored options and returns the result.
+  pub fn save(
+    &self,
+    host: &str,
+    app: &str,
+    patch: Map<String, Value>,
+    schema: &[(String, OptionSpec)],
+  ) -> Result<Map<String, Value>, OptionsError> {
+    for (key, value) in &patch {
+      validate(key, value, schema)?;
+    }
+
+    let path = self.path(host, app);
+    let mut stored: Map<String, Value> = match fs::read_to_string(&path) {
+      Ok(text) => serde_json::from_str(&text)?,
+      Err(e) if e.kind() == io::ErrorKind::NotFound => Map::new(),
+      Err(e) => return Err(e.into()),
+    };
+    for (key, value) in patch {
+      if value.is_null() {
+        stored.remove(&key);
+      } else {
+        stored.insert(key, value);
+      }
+    }
+
+    fs::create_dir_all(path.parent().unwrap())?;
+    let tmp = path.with_extension("json.tmp");
+    fs::write(&tmp, serde_json::to_vec_pretty(&stored)?)?;
+    fs::rename(&tmp, &path)?;
+    Ok(self.get(host, app, schema))
+  }
+
+  /// Hosts and apps that have stored options.
+  pub fn list(&self) -> Vec<(String, String)> {
+    let mut found = Vec::new();
+    let hosts = match fs::read_dir(&self.dir) {
+      Ok(hosts) => hosts,
+      Err(_) => return found,
+    };
+    for host in hosts.filter_map(|e| e.ok()) {
+      let host_name = host.file_name().to_string_lossy().into_owned();
+      let apps = match fs::read_dir(host.path()) {
+        Ok(apps) => apps,
+        Err(_) => continue,
+      };
+      for app in apps.filter_map(|e| e.ok()) {
+        let path = app.path();
+        if path.extension().map_or(false, |ext| ext == "json") {
+          let app_name = path.file_stem().unwrap().to_string_lossy();
+          found.push((host_name.clone(), app_name.into_owned()));
+        }
+      }
+    }
+    found.sort();
+    found
+  }
+}
+
+pub fn get(host: &str, app: &str) -> Map<String, Value> {
+  let schema = schema(host, app);
+  STORE.lock().unwrap().get(host, app, &schema)
+}
+
+/// Saves and notifies the app's listeners of the new options.
+pub fn save(
+  host: &str,
+  app: &str,
+  patch: Map<String, Value>,
+) -> Result<Map<String, Value>, OptionsError> {
+  let schema = schema(host, app);
+  let options = STORE.lock().unwrap().save(host, app, patch, &schema)?;
+  channels::publish(
+    &ChannelKey {
+      host: Some(host.to_string()).filter(|h| !h.is_empty()),
+      app: Some(app.to_string()).filter(|a| !a.is_empty()),
+      name: CHANGE_CHANNEL.to_string(),
+    },
+    Value::Object(options.clone()),
+  );
+  Ok(options)
+}
+
+/// Installed apps whose manifest has an `options` schema and apps with
+/// stored options, as (host, app).
+pub fn list() -> Vec<(String, String)> {
+  let mut found = STORE.lock().unwrap().list();
+  for host in &config::get().hosts {
+    let dir = config::rws_dir()
+      .join("apps")
+      .join(config::file_name(&host.name));
+    let apps = match fs::read_dir(dir) {
+      Ok(apps) => apps,
+      Err(_) => continue,
+    };
+    for app in apps.filter_map(|e| e.ok()) {
+      let manifest = match Manifest::load(&app.path().join("manifest.json")) {
+        Ok(manifest) => manifest,
+        Err(_) => continue,
+      };
+      if !manifest.option_specs().is_empty() {
+        let app_name = app.file_name().to_string_lossy().into_owned();
+        found.push((host.name.clone(), app_name));
+      }
+    }
+  }
+  found.sort();
+  found.dedup();
+  found
+}
+
+#[test]
+fn options_store_test() {
+  let dir = tempfile::TempDir::new().unwrap();
+  let store = OptionsStore::new(dir.path().to_path_buf());
+  let schema: Vec<(String, OptionSpec)> = vec![
+    (
+      "color".to_string(),
+      serde_json::from_value(
+        json!({"type": "string", "default": "blue", "enum": ["blue", "red"]}),
+      )
+      .unwrap(),
+    ),
+    (
+      "limit".to_string(),
+      serde_json::from_value(json!({"type": "integer"})).unwrap(),
+    ),
+  ];
+  let patch = |value: Value| value.as_object().unwrap().clone();
+
+  assert_eq!(
+    Value::Object(store.get("example.com", "forum", &schema)),
+    json!({"color": "blue"})
+  );
+
+  let saved = store
+    .save("example.com", "forum", patch(json!({"limit": 10})), &schema)
+    .unwrap();
+  assert_eq!(Value::Object(saved), json!({"color": "blue", "limit": 10}));
+
+  for bad in &[
+    json!({"color": "green"}),
+    json!({"limit": "ten"}),
+    json!({"unknown": true}),
+  ] {
+    let result =
+      store.save("example.com", "forum", patch(bad.clone()), &schema);
+    assert!(matches!(result, Err(OptionsError::Invalid { .. })));
+  }
+
+  // Without a schema anything goes; null removes.
+  store
+    .save("example.com", "shop", patch(json!({"a": [1], "b": 2})), &[])
+    .unwrap();
+  let saved = store
+    .save("example.com", "shop", patch(json!({"b": null})), &[])
+    .unwrap();
+  assert_eq!(Value::Object(saved), json!({"a": [1]}));
+
+  assert_eq!(
+    store.list(),
+    vec![
+      ("example.com".to_string(), "forum".to_string()),
+      ("example.com".to_string(), "shop".to_string())
+    ]
+  );
+}
@@ -0,0 +1,868 @@
+//! `.rwsapp` app packages.
+//!
+//! A package is a gzipped ustar archive, the format `std/archive` reads and
+//! writes, of an app's `manifest.json` and its `bin`, `html_*` and `target`
+//! directories.  The first entry, `rwsapp.json`, lists the SHA-256 of every
+//! other file; a package that doesn't match it is refused before anything
+//! is unpacked.
+//!
+//! Installing checks the manifest against this rws (module API and native
+//! module versions), unpacks the app into `config::app_dir`, runs
+//! `bin/install.ts` in an isolate of its own with `RWS.installing` set (see
+//! `apps`) and mounts the app on the host.  A failing install script puts
+//! the previous version back.  The same script runs again whenever rws
+//! starts the app, so registrations made there last.
+
+use crate::apps::{self, AppContext};
+use crate::config::{self, AppMount, Config, HostConfig};
+use crate::manifest::{Manifest, ManifestError};
+use clap::{App, AppSettings, Arg, SubCommand};
+use deno_cli::colors;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use regex::Regex;
+use ring::digest;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+/// Module API version apps are written against (`api` in the manifest).
+pub const API_VERSION: u32 = 1;
+
+/// Native modules built into this rws and their API versions.  None ship
+/// yet; `rws-db` will be the first (see spec.md).
+pub const NATIVE_MODULES: &[(&str, u64)] = &[];
+
+const INDEX: &str = "rwsapp.json";
+const FORMAT: u32 = 1;
+const BLOCK: usize = 512;
+/// Packages unpacking to more than this are refused.
+const MAX_SIZE: u64 = 1 << 30;
+
+lazy_static! {
+  static ref NAME_RE: Regex = Regex::new(r"^[a-z][a-z0-9_-]*$").unwrap();
+}
+
+#[derive(Debug)]
+pub enum PackageError {
+  Io(io::Error),
+  Manifest(ManifestError),
+  /// Not a package, or its files don't match `rwsapp.json`.
+  Corrupt(String),
+  /// The app can't be packed or installed as it is.
+  Invalid(String),
+  AlreadyInstalled(String),
+  /// Another app is mounted at the path.
+  PathTaken {
+    path: String,
+    app: String,
+  },
+  InstallScript(String),
+}
+
+impl fmt::Display for PackageError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      PackageError::Io(e) => write!(f, "{}", e),
+      PackageError::Manifest(e) => write!(f, "{}", e),
+      PackageError::Corrupt(e) => write!(f, "corrupt package: {}", e),
+      PackageError::Invalid(e) => write!(f, "{}", e),
+      PackageError::AlreadyInstalled(app) => {
+        write!(f, "{} is already installed, use --force to replace it", app)
+      }
+      PackageError::PathTaken { path, app } => {
+        write!(f, "{} is already mounted at {}", app, path)
+      }
+      PackageError::InstallScript(e) => {
+        write!(f, "bin/install.ts failed: {}", e)
+      }
+    }
+  }
+}
+
+impl std::error::Error for PackageError {}
+
+impl From<io::Error> for PackageError {
+  fn from(e: io::Error) -> Self {
+    PackageError::Io(e)
+  }
+}
+
+impl From<ManifestError> for PackageError {
+  fn from(e: ManifestError) -> Self {
+    PackageError::Manifest(e)
+  }
+}
+
+fn corrupt(message: impl ToString) -> PackageError {
+  PackageError::Corrupt(message.to_string())
+}
+
+/// `rwsapp.json`.
+#[derive(Debug, Deserialize, Serialize)]
+struct Index {
+  format: u32,
+  name: String,
+  version: String,
+  /// SHA-256 (hex) of every file by path.
+  files: BTreeMap<String, String>,
+}
+
+fn sha256(bytes: &[u8]) -> String {
+  digest::digest(&digest::SHA256, bytes)
+    .as_ref()
+    .iter()
+    .map(|b| format!("{:02x}", b))
+    .collect()
+}
+
+/// Whether `path` may be in a package: `manifest.json` or a file under
+/// `bin`, `target` or an `html_*` directory, without `.` or `..`.
+fn allowed_path(path: &str) -> bool {
+  if path == "manifest.json" {
+    return true;
+  }
+  let parts: Vec<&str> = path.split('/').collect();
+  let top = parts[0];
+  parts.len() > 1
+    && (top == "bin" || top == "target" || top.starts_with("html_"))
+    && parts
+      .iter()
+      .all(|p| !p.is_empty() && *p != "." && *p != ".." && !p.contains('\\'))
+}
+
+/// Checks what a package needs whatever rws it is installed on.
+fn check_manifest(manifest: &Manifest) -> Result<(), PackageError> {
+  if !NAME_RE.is_match(&manifest.name) {
+    return Err(PackageError::Invalid(format!(
+      "invalid app name {:?}, use lowercase letters, digits, - and _",
+      manifest.name
+    )));
+  }
+  if manifest.version.trim().is_empty() {
+    return Err(PackageError::Invalid("the manifest has no version".into()));
+  }
+  if manifest.route_specs().len() != manifest.nested_routes.len() {
+    return Err(PackageError::Invalid("malformed nested_routes".into()));
+  }
+  Ok(())
+}
+
+/// Checks that this rws provides the module API and native modules the app
+/// was written against.
+pub fn check_platform(manifest: &Manifest) -> Result<(), PackageError> {
+  if manifest.api != API_VERSION {
+    return Err(PackageError::Invalid(format!(
+      "{} needs module API {}, this rws provides {}",
+      manifest.name, manifest.api, API_VERSION
+    )));
+  }
+  for (module, version) in &manifest.native_dependencies {
+    let version = version.as_u64().ok_or_else(|| {
+      PackageError::Invalid(format!("{} has no API version", module))
+    })?;
+    match NATIVE_MODULES.iter().find(|(name, _)| name == module) {
+      Some((_, provided)) if *provided == version => {}
+      Some((_, provided)) => {
+        return Err(PackageError::Invalid(format!(
+          "{} needs {} API {}, this rws provides {}",
+          manifest.name, module, version, provided
+        )))
+      }
+      None => {
+        return Err(PackageError::Invalid(format!(
+          "{} needs the native module {}, which this rws doesn't provide",
+          manifest.name, module
+        )))
+      }
+    }
+  }
+  Ok(())
+}
+
+fn octal(field: &mut [u8], value: u64) {
+  let text = format!("{:0width$o}\0", value, width = field.len() - 1);
+  field.copy_from_slice(text.as_bytes());
+}
+
+fn parse_octal(field: &[u8]) -> Result<u64, PackageError> {
+  let text = std::str::from_utf8(field).map_err(corrupt)?;
+  let text = text.trim_matches(|c| c == '\0' || c == ' ');
+  u64::from_str_radix(text, 8).map_err(|_| corrupt("bad header number"))
+}
+
+fn text(field: &[u8]) -> Result<&str, PackageError> {
+  let end = field.iter().position(|b| *b == 0).unwrap_or(field.len());
+  std::str::from_utf8(&field[..end]).map_err(corrupt)
+}
+
+fn checksum(block: &[u8; BLOCK]) -> u64 {
+  // The checksum field itself counts as spaces.
+  block
+    .iter()
+    .enumerate()
+    .map(|(i, b)| {
+      if (148..156).contains(&i) {
+        32
+      } else {
+        u64::from(*b)
+      }
+    })
+    .sum()
+}
+
+/// A ustar header.  Paths longer than 100 bytes are split into prefix and
+/// name at a `/`.
+fn header(path: &str, size: u64) -> Result<[u8; BLOCK], PackageError> {
+  let (prefix, name) = if path.len() <= 100 {
+    ("", path)
+  } else {
+    path
+      .match_indices('/')
+      .map(|(i, _)| (&path[..i], &path[i + 1..]))
+      .find(|(prefix, name)| prefix.len() <= 155 && name.len() <= 100)
+      .ok_or_else(|| PackageError::Invalid(format!("{} is too long", path)))?
+  };
+  let mut block = [0u8; BLOCK];
+  block[..name.len()].copy_from_slice(name.as_bytes());
+  octal(&mut block[100..108], 0o644);
+  octal(&mut block[108..116], 0);
+  octal(&mut block[116..124], 0);
+  octal(&mut block[124..136], size);
+  // No modification time, so packing the same files gives the same package.
+  octal(&mut block[136..148], 0);
+  block[156] = b'0';
+  block[257..263].copy_from_slice(b"ustar\0");
+  block[263..265].copy_from_slice(b"00");
+  block[345..345 + prefix.len()].copy_from_slice(prefix.as_bytes());
+  let sum = format!("{:06o}\0 ", checksum(&block));
+  block[148..156].copy_from_slice(sum.as_bytes());
+  Ok(block)
+}
+
+/// Writes `files` as a package, the index first.
+fn write_package<W: Write>(
+  manifest: &Manifest,
+  files: &[(String, Vec<u8>)],
+  writer: W,
+) -> Result<W, PackageError> {
+  let index = Index {
+    format: FORMAT,
+    name: manifest.name.clone(),
+    version: manifest.version.clone(),
+    files: files
+      .iter()
+      .map(|(path, data)| (path.clone(), sha256(data)))
+      .collect(),
+  };
+  let index = serde_json::to_vec_pretty(&index).map_err(io::Error::from)?;
+  let mut gz = GzEncoder::new(writer, Compression::best());
+  let entries = std::iter::once((INDEX, &index))
+    .chain(files.iter().map(|(path, data)| (path.as_str(), data)));
+  for (path, data) in entries {
+    gz.write_all(&header(path, data.len() as u64)?)?;
+    gz.write_all(data)?;
+    let padding = (BLOCK - data.len() % BLOCK) % BLOCK;
+    gz.write_all(&vec![0; padding])?;
+  }
+  gz.write_all(&[0; 2 * BLOCK])?;
+  Ok(gz.finish()?)
+}
+
+/// The regular files of a ustar archive in order.  Directory entries are
+/// skipped, anything else is refused.
+fn read_entries<R: Read>(
+  mut reader: R,
+) -> Result<Vec<(String, Vec<u8>)>, PackageError> {
+  let mut entries = Vec::new();
+  let mut total = 0;
+  loop {
+    let mut block = [0u8; BLOCK];
+    reader.read_exact(&mut block).map_err(corrupt)?;
+    if block.iter().all(|b| *b == 0) {
+      return Ok(entries);
+    }
+    if parse_octal(&block[148..156])? != checksum(&block) {
+      return Err(corrupt("bad header checksum"));
+    }
+    let name = text(&block[..100])?;
+    let prefix = text(&block[345..500])?;
+    let path = if prefix.is_empty() {
+      name.to_string()
+    } else {
+      format!("{}/{}", prefix, name)
+    };
+    let size = parse_octal(&block[124..136])?;
+    total += size;
+    if total > MAX_SIZE {
+      return Err(corrupt("package is too large"));
+    }
+    let mut data = vec![0; size as usize];
+    reader.read_exact(&mut data).map_err(corrupt)?;
+    let padding = (BLOCK - data.len() % BLOCK) % BLOCK;
+    reader.read_exact(&mut vec![0; padding]).map_err(corrupt)?;
+    match block[156] {
+      b'0' | 0 => entries.push((path, data)),
+      b'5' => {}
+      _ => return Err(corrupt(format!("{} is not a regular file", path))),
+    }
+  }
+}
+
+/// A package checked against its index.
+pub struct Package {
+  pub manifest: Manifest,
+  files: Vec<(String, Vec<u8>)>,
+}
+
+impl Package {
+  pub fn read(bytes: &[u8]) -> Result<Package, PackageError> {
+    let mut entries = read_entries(GzDecoder::new(bytes))?.into_iter();
+    let index: Index = match entries.next() {
+      Some((path, data)) if path == INDEX => {
+        serde_json::from_slice(&data).map_err(corrupt)?
+      }
+      _ => return Err(corrupt(format!("{} is missing", INDEX))),
+    };
+    if index.format != FORMAT {
+      return Err(corrupt(format!("unknown format {}", index.format)));
+    }
+
+    let mut seen = HashSet::new();
+    let mut files = Vec::new();
+    for (path, data) in entries {
+      if !allowed_path(&path) || !seen.insert(path.clone()) {
+        return Err(corrupt(format!("unexpected file {}", path)));
+      }
+      if index.files.get(&path) != Some(&sha256(&data)) {
+        return Err(corrupt(format!("checksum mismatch for {}", path)));
+      }
+      files.push((path, data));
+    }
+    if let Some(path) = index.files.keys().find(|p| !seen.contains(*p)) {
+      return Err(corrupt(format!("{} is missing", path)));
+    }
+
+    let manifest = match files.iter().find(|(p, _)| p == "manifest.json") {
+      Some((_, data)) => Manifest::parse(&String::from_utf8_lossy(data))?,
+      None => return Err(corrupt("manifest.json is missing")),
+    };
+    if manifest.name != index.name || manifest.version != index.version {
+      return Err(corrupt("manifest.json doesn't match the index"));
+    }
+    check_manifest(&manifest)?;
+    Ok(Package { manifest, files })
+  }
+
+  pub fn open(path: &Path) -> Result<Package, PackageError> {
+    Package::read(&fs::read(path)?)
+  }
+
+  fn unpack(&self, dir: &Path) -> io::Result<()> {
+    for (path, data) in &self.files {
+      let file = dir.join(path);
+      fs::create_dir_all(file.parent().unwrap())?;
+      fs::write(file, data)?;
+    }
+    Ok(())
+  }
+}
+
+/// Adds the files under `dir` to `files` as `<prefix>/<name>`, skipping
+/// dot files.
+fn collect(
+  dir: &Path,
+  prefix: &str,
+  files: &mut Vec<(String, PathBuf)>,
+) -> io::Result<()> {
+  for entry in fs::read_dir(dir)? {
+    let entry = entry?;
+    let name = entry.file_name().to_string_lossy().into_owned();
+    if name.starts_with('.') {
+      continue;
+    }
+    let path = format!("{}/{}", prefix, name);
+    if entry.file_type()?.is_dir() {
+      collect(&entry.path(), &path, files)?;
+    } else {
+      files.push((path, entry.path()));
+    }
+  }
+  Ok(())
+}
+
+pub struct Packed {
+  pub path: PathBuf,
+  pub files: usize,
+  /// SHA-256 of the package file.
+  pub sha256: String,
+}
+
+/// Packs the app in `dir`, by default into `<name>-<version>.rwsapp`.
+pub fn pack(dir: &Path, out: Option<PathBuf>) -> Result<Packed, PackageError> {
+  let manifest_path = dir.join("manifest.json");
+  let manifest = Manifest::load(&manifest_path)?;
+  check_manifest(&manifest)?;
+
+  let mut paths = vec![("manifest.json".to_string(), manifest_path)];
+  for entry in fs::read_dir(dir)? {
+    let entry = entry?;
+    let name = entry.file_name().to_string_lossy().into_owned();
+    let packed = name == "bin" || name == "target" || name.starts_with("html_");
+    if packed && entry.file_type()?.is_dir() {
+      collect(&entry.path(), &name, &mut paths)?;
+    }
+  }
+  paths.sort();
+
+  let mut files = Vec::new();
+  let mut total = 0;
+  for (name, path) in paths {
+    let data = fs::read(path)?;
+    total += data.len() as u64;
+    if total > MAX_SIZE {
+      return Err(PackageError::Invalid("the app is too large".into()));
+    }
+    files.push((name, data));
+  }
+
+  let path = out.unwrap_or_else(|| {
+    PathBuf::from(format!("{}-{}.rwsapp", manifest.name, manifest.version))
+  });
+  let bytes = write_package(&manifest, &files, Vec::new())?;
+  let tmp = path.with_extension("rwsapp.tmp");
+  fs::write(&tmp, &bytes)?;
+  fs::rename(&tmp, &path)?;
+  Ok(Packed {
+    path,
+    files: files.len(),
+    sha256: sha256(&bytes),
+  })
+}
+
+pub struct InstallOptions {
+  pub host: String,
+  /// Where to mount the app; defaults to where it is already mounted, or
+  /// "/".
+  pub path: Option<String>,
+  /// Replace an installed app of the same name.
+  pub force: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Installed {
+  pub app: String,
+  pub version: String,
+  pub host: String,
+  pub path: String,
+  /// Version that was replaced.
+  pub previous: Option<String>,
+}
+
+/// Mounts `app` on `host` in `config`, adding the host if needed, and
+/// returns the path it is mounted at.
+fn mount(
+  config: &mut Config,
+  host: &str,
+  app: &str,
+  path: Option<&str>,
+) -> Result<String, PackageError> {
+  let index = match config.hosts.iter().position(|h| h.matches(host)) {
+    Some(index) => index,
+    None => {
+      let new: HostConfig = serde_json::from_value(json!({ "name": host }))
+        .map_err(io::Error::from)?;
+      config.hosts.push(new);
+      config.hosts.len() - 1
+    }
+  };
+  let host = &mut config.hosts[index];
+  let existing = host.apps.iter().position(|m| m.app == app);
+  let path = match (path, existing) {
+    (Some(path), _) => path.to_string(),
+    (None, Some(i)) => host.apps[i].path.clone(),
+    (None, None) => "/".to_string(),
+  };
+  let path = format!("/{}", path.trim_matches('/'));
+  let taken = host.apps.iter().find(|m| m.app != app && m.path == path);
+  if let Some(other) = taken {
+    return Err(PackageError::PathTaken {
+      path,
+      app: other.app.clone(),
+    });
+  }
+  match existing {
+    Some(i) => host.apps[i].path = path.clone(),
+    None => host.apps.push(AppMount {
+      app: app.to_string(),
+      path: path.clone(),
+      docs: None,
+    }),
+  }
+  Ok(path)
+}
+
+/// Moves `from` to `to`, replacing it.
+fn replace(from: &Path, to: &Path) -> io::Result<()> {
+  if to.exists() {
+    fs::remove_dir_all(to)?;
+  }
+  fs::rename(from, to)
+}
+
+pub fn install(
+  package: &Package,
+  options: &InstallOptions,
+) -> Result<Installed, PackageError> {
+  let manifest = &package.manifest;
+  check_platform(manifest)?;
+  let mut config = (*config::get()).clone();
+  let host = config
+    .host(&options.host)
+    .map_or_else(|| options.host.clone(), |h| h.name.clone());
+  let path =
+    mount(&mut config, &host, &manifest.name, options.path.as_deref())?;
+
+  let dir = config::app_dir(&host, &manifest.name);
+  let installed = dir.exists();
+  if installed && !options.force {
+    return Err(PackageError::AlreadyInstalled(manifest.name.clone()));
+  }
+  let previous = if installed {
+    Manifest::load(&dir.join("manifest.json"))
+      .ok()
+      .map(|m| m.version)
+  } else {
+    None
+  };
+
+  let parent = dir.parent().unwrap();
+  let name = config::file_name(&manifest.name);
+  let staging = parent.join(format!(".{}.installing", name));
+  let backup = parent.join(format!(".{}.previous", name));
+  if staging.exists() {
+    fs::remove_dir_all(&staging)?;
+  }
+  fs::create_dir_all(&staging)?;
+  package.unpack(&staging)?;
+  if installed {
+    replace(&dir, &backup)?;
+  }
+  fs::rename(&staging, &dir)?;
+
+  let script = dir.join("bin").join("install.ts");
+  let result = if script.exists() {
+    let context = AppContext::new(&host, &manifest.name);
+    apps::run_isolate(&context, &dir, None)
+      .map_err(|e| PackageError::InstallScript(e.to_string()))
+  } else {
+    Ok(())
+  };
+  let result =
+    result.and_then(|()| config::save(config).map_err(PackageError::from));
+  if let Err(e) = result {
+    fs::remove_dir_all(&dir)?;
+    if installed {
+      fs::rename(&backup, &dir)?;
+    }
+    return Err(e);
+  }
+  if installed {
+    fs::remove_dir_all(&backup)?;
+  }
+
+  Ok(Installed {
+    app: manifest.name.clone(),
+    version: manifest.version.clone(),
+    host,
+    path,
+    previous,
+  })
+}
+
+fn fail(e: PackageError) -> i32 {
+  eprintln!("{}: {}", colors::red_bold("error"), e);
+  1
+}
+
+/// `rws app pack`; returns the exit code.
+pub fn pack_command(dir: &Path, out: Option<PathBuf>) -> i32 {
+  match pack(dir, out) {
+    Ok(packed) => {
+      println!(
+        "{} {} ({} files)\nsha256 {}",
+        colors::green("Packed"),
+        packed.path.display(),
+        packed.files,
+        packed.sha256
+      );
+      0
+    }
+    Err(e) => fail(e),
+  }
+}
+
+/// `rws app install`; returns the exit code.
+pub fn install_command(file: &Path, options: InstallOptions) -> i32 {
+  let result =
+    Package::open(file).and_then(|package| install(&package, &options));
+  match result {
+    Ok(installed) => {
+      println!(
+        "{} {} {} on {} at {}",
+        colors::green("Installed"),
+        installed.app,
+        installed.version,
+        installed.host,
+        installed.path
+      );
+      0
+    }
+    Err(e) => fail(e),
+  }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum AppCommand {
+  Pack {
+    dir: PathBuf,
+    out_file: Option<PathBuf>,
+  },
+  Install {
+    file: PathBuf,
+    host: String,
+    path: Option<String>,
+    force: bool,
+  },
+}
+
+fn app_subcommand<'a, 'b>() -> App<'a, 'b> {
+  SubCommand::with_name("app")
+    .setting(AppSettings::SubcommandRequiredElseHelp)
+    .about("Package and install Reach apps")
+    .subcommand(
+      SubCommand::with_name("pack")
+        .about("Build an .rwsapp package")
+        .long_about(
+          "Packs an app's manifest.json, bin, html_* and target directories
+into a checksummed .rwsapp package.
+  rws app pack ./my-app
+
+The package is written to <name>-<version>.rwsapp unless -o/--output is given:
+  rws app pack ./my-app -o my-app.rwsapp",
+        )
+        .arg(Arg::with_name("dir").takes_value(true).required(false))
+        .arg(
+          Arg::with_name("output")
+            .long("output")
+            .short("o")
+            .help("Package file to write")
+            .takes_value(true),
+        ),
+    )
+    .subcommand(
+      SubCommand::with_name("install")
+        .about("Install an .rwsapp package on a host")
+        .long_about(
+          "Checks the package and its manifest, unpacks it, runs bin/install.ts
+and mounts the app on the host.
+  rws app install my-app-1.0.0.rwsapp --host example.com
+
+The app is mounted at / unless --path is given:
+  rws app install forum.rwsapp --host example.com --path /forum",
+        )
+        .arg(Arg::with_name("file").takes_value(true).required(true))
+        .arg(
+          Arg::with_name("host")
+            .long("host")
+            .help("Host to install the app on")
+            .takes_value(true)
+            .required(true),
+        )
+        .arg(
+          Arg::with_name("path")
+            .long("path")
+            .help("Path to mount the app at")
+            .takes_value(true),
+        )
+        .arg(
+          Arg::with_name("force")
+            .long("force")
+            .short("f")
+            .help("Replace an installed app of the same name")
+            .takes_value(false),
+        ),
+    )
+}
+
+/// Parses `rws app ...`; `Ok(None)` when `args` are another command.  The
+/// app commands are parsed here, before the deno flags see the arguments,
+/// so they stay out of the deno CLI.
+pub fn parse_app_command(args: &[String]) -> clap::Result<Option<AppCommand>> {
+  if args.get(1).map(String::as_str) != Some("app") {
+    return Ok(None);
+  }
+  let matches = App::new("rws")
+    .bin_name("rws")
+    .subcommand(app_subcommand())
+    .get_matches_from_safe(args)?;
+  let matches = matches.subcommand_matches("app").unwrap();
+  let command = if let Some(m) = matches.subcommand_matches("pack") {
+    AppCommand::Pack {
+      dir: PathBuf::from(m.value_of("dir").unwrap_or(".")),
+      out_file: m.value_of("output").map(PathBuf::from),
+    }
+  } else {
+    let m = matches.subcommand_matches("install").unwrap();
+    AppCommand::Install {
+      file: PathBuf::from(m.value_of("file").unwrap()),
+      host: m.value_of("host").unwrap().to_string(),
+      path: m.value_of("path").map(String::from),
+      force: m.is_present("force"),
+    }
+  };
+  Ok(Some(command))
+}
+
+/// Runs `rws app ...`; returns its exit code, or `None` when `args` are
+/// another command.
+pub fn app_command(args: &[String]) -> Option<i32> {
+  let command = match parse_app_command(args) {
+    Ok(command) => command?,
+    Err(e) => e.exit(),
+  };
+  Some(match command {
+    AppCommand::Pack { dir, out_file } => pack_command(&dir, out_file),
+    AppCommand::Install {
+      file,
+      host,
+      path,
+      force,
+    } => install_command(&file, InstallOptions { host, path, force }),
+  })
+}
+
+#[test]
+fn package_test() {
+  let manifest = Manifest::parse(
+    r#"{"api": 1, "name": "forum", "version": "1",
+        "native_dependencies": {}}"#,
+  )
+  .unwrap();
+  let long = format!("target/client/{}/index.js", "a".repeat(120));
+  let file = |path: &str, data: &[u8]| (path.to_string(), data.to_vec());
+  let files = vec![
+    file(
+      "manifest.json",
+      br#"{"api": 1, "name": "forum", "version": "1"}"#,
+    ),
+    file("bin/install.ts", b"export default () => {};"),
+    file(&long, &[7; 1000]),
+  ];
+  let bytes = write_package(&manifest, &files, Vec::new()).unwrap();
+  assert_eq!(bytes, write_package(&manifest, &files, Vec::new()).unwrap());
+  let package = Package::read(&bytes).unwrap();
+  assert_eq!(package.manifest.name, "forum");
+  assert_eq!(package.files, files);
+
+  let dir = tempfile::TempDir::new().unwrap();
+  package.unpack(dir.path()).unwrap();
+  assert_eq!(fs::read(dir.path().join(&long)).unwrap(), vec![7; 1000]);
+
+  // A file that doesn't match the index.
+  let mut entries = read_entries(GzDecoder::new(&bytes[..])).unwrap();
+  entries[2].1 = b"export default () => { evil(); };".to_vec();
+  let mut tar = Vec::new();
+  for (path, data) in &entries {
+    tar.extend_from_slice(&header(path, data.len() as u64).unwrap());
+    tar.extend_from_slice(data);
+    tar.resize(tar.len() + (BLOCK - data.len() % BLOCK) % BLOCK, 0);
+  }
+  tar.resize(tar.len() + 2 * BLOCK, 0);
+  let mut gz = GzEncoder::new(Vec::new(), Compression::fast());
+  gz.write_all(&tar).unwrap();
+  let tampered = gz.finish().unwrap();
+  assert!(matches!(
+    Package::read(&tampered),
+    Err(PackageError::Corrupt(_))
+  ));
+  assert!(Package::read(b"not a package").is_err());
+
+  assert!(allowed_path("html_public/views/index.tsx"));
+  assert!(!allowed_path("bin/../../etc/passwd"));
+  assert!(!allowed_path("/etc/passwd"));
+  assert!(!allowed_path("src/main.rs"));
+  assert!(!allowed_path("bin"));
+
+  assert!(check_platform(&manifest).is_ok());
+  let db = Manifest::parse(
+    r#"{"api": 1, "name": "forum", "version": "1",
+        "native_dependencies": {"rws-db": 1}}"#,
+  )
+  .unwrap();
+  assert!(check_platform(&db).is_err());
+  let old =
+    Manifest::parse(r#"{"api": 0, "name": "forum", "version": "1"}"#).unwrap();
+  assert!(check_platform(&old).is_err());
+
+  let mut config = Config::default();
+  let path = mount(&mut config, "example.com", "forum", Some("/forum/"));
+  assert_eq!(path.unwrap(), "/forum");
+  assert_eq!(config.hosts.len(), 1);
+  let path = mount(&mut config, "example.com", "forum", None);
+  assert_eq!(path.unwrap(), "/forum");
+  assert!(matches!(
+    mount(&mut config, "example.com", "shop", Some("/forum")),
+    Err(PackageError::PathTaken { .. })
+  ));
+  assert_eq!(
+    mount(&mut config, "example.com", "shop", None).unwrap(),
+    "/"
+  );
+  assert_eq!(config.hosts[0].apps.len(), 2);
+
+  let args = |args: &[&str]| {
+    let args: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+    parse_app_command(&args)
+  };
+  assert_eq!(args(&["rws", "run", "app"]).unwrap(), None);
+  assert_eq!(
+    args(&["rws", "app", "pack"]).unwrap(),
+    Some(AppCommand::Pack {
+      dir: PathBuf::from("."),
+      out_file: None,
+    })
+  );
+  assert_eq!(
+    args(&["rws", "app", "pack", "my-app", "-o", "a.rwsapp"]).unwrap(),
+    Some(AppCommand::Pack {
+      dir: PathBuf::from("my-app"),
+      out_file: Some(PathBuf::from("a.rwsapp")),
+    })
+  );
+  assert_eq!(
+    args(&[
+      "rws",
+      "app",
+      "install",
+      "forum.rwsapp",
+      "--host",
+      "example.com",
+      "--path",
+      "/forum",
+      "-f",
+    ])
+    .unwrap(),
+    Some(AppCommand::Install {
+      file: PathBuf::from("forum.rwsapp"),
+      host: "example.com".to_string(),
+      path: Some("/forum".to_string()),
+      force: true,
+    })
+  );
+  assert!(args(&["rws", "app", "install", "a.rwsapp"]).is_err());
+}
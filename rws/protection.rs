@@ -0,0 +1,578 @@
+//! Rate limiting and brute force protection.
+//!
+//! Every request is charged against a token bucket for its IP, and login
+//! attempts against one for the account.  Repeated failed logins lock the
+//! IP and the account out for a time that doubles with each further
+//! failure; logins reported by apps only count against the account, as
+//! their isolates don't see client addresses.  Addresses in `allow` skip
+//! the limits and lockouts of their own IP but not those of the accounts
+//! they log in to; addresses in `deny` are always refused.  Changes to the
+//! settings apply straight away.
+
+use crate::config::{self, ProtectionConfig};
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::StatusCode;
+use actix_web::{Error, HttpResponse, ResponseError};
+use futures::future::{ok, Either, Ready};
+use serde_derive::Serialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::net::IpAddr;
+use std::sync::{Mutex, MutexGuard};
+use std::task::{Context, Poll};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+lazy_static! {
+  static ref GUARD: Mutex<Guard> =
+    Mutex::new(Guard::new(config::get().protection.clone()));
+}
+
+/// The guard, brought up to date with the current settings.
+fn guard() -> MutexGuard<'static, Guard> {
+  let mut guard = GUARD.lock().unwrap();
+  let config = config::get();
+  if guard.config != config.protection {
+    guard.configure(config.protection.clone());
+  }
+  guard
+}
+
+/// An address range such as "10.0.0.0/8"; a bare address matches itself.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Cidr {
+  addr: IpAddr,
+  bits: u8,
+}
+
+impl Cidr {
+  pub fn parse(s: &str) -> Option<Cidr> {
+    let mut parts = s.trim().splitn(2, '/');
+    let addr: IpAddr = parts.next()?.parse().ok()?;
+    let max = if addr.is_ipv4() { 32 } else { 128 };
+    let bits = match parts.next() {
+      Some(bits) => bits.parse().ok().filter(|b| *b <= max)?,
+      None => max,
+    };
+    Some(Cidr { addr, bits })
+  }
+
+  pub fn contains(&self, ip: IpAddr) -> bool {
+    match (self.addr, ip) {
+      (IpAddr::V4(net), IpAddr::V4(ip)) => {
+        let mask = u32::max_value()
+          .checked_shl(32 - u32::from(self.bits))
+          .unwrap_or(0);
+        u32::from(net) & mask == u32::from(ip) & mask
+      }
+      (IpAddr::V6(net), IpAddr::V6(ip)) => {
+        let mask = u128::max_value()
+          .checked_shl(128 - u32::from(self.bits))
+          .unwrap_or(0);
+        u128::from(net) & mask == u128::from(ip) & mask
+      }
+      _ => false,
+    }
+  }
+}
+
+fn parse_list(list: &[String]) -> Vec<Cidr> {
+  list
+    .iter()
+    .filter_map(|s| {
+      let cidr = Cidr::parse(s);
+      if cidr.is_none() {
+        warn!("ignoring invalid address range {:?}", s);
+      }
+      cidr
+    })
+    .collect()
+}
+
+struct TokenBucket {
+  tokens: f64,
+  updated: SystemTime,
+}
+
+impl TokenBucket {
+  fn new(burst: f64, now: SystemTime) -> Self {
+    TokenBucket {
+      tokens: burst,
+      updated: now,
+    }
+  }
+
+  fn refill(&mut self, rate: f64, burst: f64, now: SystemTime) {
+    let elapsed = now
+      .duration_since(self.updated)
+      .unwrap_or_default()
+      .as_secs_f64();
+    self.tokens = (self.tokens + elapsed * rate).min(burst);
+    self.updated = now;
+  }
+
+  /// Takes a token, or returns how long until one is available.
+  fn take(
+    &mut self,
+    rate: f64,
+    burst: f64,
+    now: SystemTime,
+  ) -> Result<(), Duration> {
+    self.refill(rate, burst, now);
+    if self.tokens >= 1.0 {
+      self.tokens -= 1.0;
+      Ok(())
+    } else if rate > 0.0 {
+      Err(Duration::from_secs_f64((1.0 - self.tokens) / rate))
+    } else {
+      Err(Duration::from_secs(u64::from(u32::max_value())))
+    }
+  }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+enum Key {
+  Ip(IpAddr),
+  Account(String),
+}
+
+struct Failures {
+  count: u32,
+  last: SystemTime,
+  locked_until: Option<SystemTime>,
+}
+
+/// Why a request or login was refused.
+#[derive(Debug, PartialEq)]
+pub enum Refusal {
+  /// The address is on the deny list.
+  Denied,
+  /// Too many requests; try again after the duration.
+  RateLimited(Duration),
+  /// Locked out after failed logins; try again after the duration.
+  LockedOut(Duration),
+}
+
+impl Refusal {
+  pub fn retry_after(&self) -> Option<Duration> {
+    match self {
+      Refusal::Denied => None,
+      Refusal::RateLimited(after) | Refusal::LockedOut(after) => Some(*after),
+    }
+  }
+}
+
+impl fmt::Display for Refusal {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Refusal::Denied => write!(f, "address denied"),
+      Refusal::RateLimited(_) => write!(f, "too many requests"),
+      Refusal::LockedOut(_) => write!(f, "locked out after failed logins"),
+    }
+  }
+}
+
+impl ResponseError for Refusal {
+  fn status_code(&self) -> StatusCode {
+    match self {
+      Refusal::Denied => StatusCode::FORBIDDEN,
+      _ => StatusCode::TOO_MANY_REQUESTS,
+    }
+  }
+
+  fn error_response(&self) -> HttpResponse {
+    let mut res = HttpResponse::build(self.status_code());
+    if let Some(after) = self.retry_after() {
+      // Round up so clients never retry early.
+      let secs = after.as_secs() + u64::from(after.subsec_nanos() > 0);
+      res.header("retry-after", secs.to_string());
+    }
+    res.body(self.to_string())
+  }
+}
+
+/// A lockout, as listed in the control panel.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Ban {
+  /// "ip" or "account".
+  pub kind: &'static str,
+  pub key: String,
+  pub failures: u32,
+  /// Milliseconds since the unix epoch.
+  pub until: u64,
+}
+
+fn millis(time: SystemTime) -> u64 {
+  time
+    .duration_since(UNIX_EPOCH)
+    .unwrap_or_default()
+    .as_millis() as u64
+}
+
+pub struct Guard {
+  config: ProtectionConfig,
+  allow: Vec<Cidr>,
+  deny: Vec<Cidr>,
+  ip_buckets: HashMap<IpAddr, TokenBucket>,
+  account_buckets: HashMap<String, TokenBucket>,
+  failures: HashMap<Key, Failures>,
+}
+
+impl Guard {
+  pub fn new(config: ProtectionConfig) -> Self {
+    Guard {
+      allow: parse_list(&config.allow),
+      deny: parse_list(&config.deny),
+      config,
+      ip_buckets: HashMap::new(),
+      account_buckets: HashMap::new(),
+      failures: HashMap::new(),
+    }
+  }
+
+  /// Switches to new settings, keeping buckets and failures.
+  pub fn configure(&mut self, config: ProtectionConfig) {
+    self.allow = parse_list(&config.allow);
+    self.deny = parse_list(&config.deny);
+    self.config = config;
+  }
+
+  fn allowed(&self, ip: IpAddr) -> bool {
+    self.allow.iter().any(|cidr| cidr.contains(ip))
+  }
+
+  fn locked(&self, key: &Key, now: SystemTime) -> Result<(), Refusal> {
+    let until = self.failures.get(key).and_then(|f| f.locked_until);
+    match until.and_then(|until| until.duration_since(now).ok()) {
+      Some(left) => Err(Refusal::LockedOut(left)),
+      None => Ok(()),
+    }
+  }
+
+  /// Charges one request to `ip`.
+  pub fn check_request(
+    &mut self,
+    ip: IpAddr,
+    now: SystemTime,
+  ) -> Result<(), Refusal> {
+    if self.deny.iter().any(|cidr| cidr.contains(ip)) {
+      return Err(Refusal::Denied);
+    }
+    if self.allowed(ip) {
+      return Ok(());
+    }
+    self.locked(&Key::Ip(ip), now)?;
+
+    let (rate, burst) = (self.config.ip_rate, self.config.ip_burst);
+    self
+      .ip_buckets
+      .entry(ip)
+      .or_insert_with(|| TokenBucket::new(burst, now))
+      .take(rate, burst, now)
+      .map_err(Refusal::RateLimited)
+  }
+
+  /// Charges one login attempt to `account`; apps call this before checking
+  /// the password.  The account is limited whatever address the attempt
+  /// comes from.
+  pub fn check_login(
+    &mut self,
+    ip: Option<IpAddr>,
+    account: &str,
+    now: SystemTime,
+  ) -> Result<(), Refusal> {
+    if let Some(ip) = ip.filter(|ip| !self.allowed(*ip)) {
+      self.locked(&Key::Ip(ip), now)?;
+    }
+    self.locked(&Key::Account(account.to_string()), now)?;
+
+    let (rate, burst) = (self.config.account_rate, self.config.account_burst);
+    self
+      .account_buckets
+      .entry(account.to_string())
+      .or_insert_with(|| TokenBucket::new(burst, now))
+      .take(rate, burst, now)
+      .map_err(Refusal::RateLimited)
+  }
+
+  fn fail(&mut self, key: Key, now: SystemTime) {
+    let forget_after = Duration::from_secs(self.config.max_lockout_secs);
+    let failures = self.failures.entry(key).or_insert(Failures {
+      count: 0,
+      last: now,
+      locked_until: None,
+    });
+    if now.duration_since(failures.last).unwrap_or_default() > forget_after {
+      failures.count = 0;
+    }
+    failures.count += 1;
+    failures.last = now;
+
+    if failures.count >= self.config.max_failures {
+      let doublings = (failures.count - self.config.max_failures).min(32);
+      let secs = self
+        .config
+        .lockout_secs
+        .saturating_mul(1 << doublings)
+        .min(self.config.max_lockout_secs);
+      failures.locked_until = Some(now + Duration::from_secs(secs));
+    }
+  }
+
+  /// Records a failed login from `ip` and/or for `account`.
+  pub fn record_failure(
+    &mut self,
+    ip: Option<IpAddr>,
+    account: Option<&str>,
+    now: SystemTime,
+  ) {
+    if let Some(ip) = ip {
+      if !self.allowed(ip) {
+        self.fail(Key::Ip(ip), now);
+      }
+    }
+    if let Some(account) = account {
+      self.fail(Key::Account(account.to_string()), now);
+    }
+  }
+
+  /// Forgets the failures of an account after a successful login.  The IP's
+  /// failures stay, so one good account doesn't clear a scan of others.
+  pub fn record_success(&mut self, account: &str) {
+    self.failures.remove(&Key::Account(account.to_string()));
+  }
+
+  pub fn bans(&self, now: SystemTime) -> Vec<Ban> {
+    let mut bans: Vec<Ban> = self
+      .failures
+      .iter()
+      .filter_map(|(key, failures)| {
+        let until = failures.locked_until.filter(|until| *until > now)?;
+        let (kind, key) = match key {
+          Key::Ip(ip) => ("ip", ip.to_string()),
+          Key::Account(account) => ("account", account.clone()),
+        };
+        Some(Ban {
+          kind,
+          key,
+          failures: failures.count,
+          until: millis(until),
+        })
+      })
+      .collect();
+    bans.sort_by(|a, b| b.until.cmp(&a.until));
+    bans
+  }
+
+  /// Lifts a lockout; returns false when there was none.
+  pub fn unban(&mut self, kind: &str, key: &str) -> bool {
+    let key = match kind {
+      "ip" => match key.parse() {
+        Ok(ip) => Key::Ip(ip),
+        Err(_) => return false,
+      },
+      "account" => Key::Account(key.to_string()),
+      _ => return false,
+    };
+    self.failures.remove(&key).is_some()
+  }
+
+  /// Drops state that no longer affects any decision.
+  fn prune(&mut self, now: SystemTime) {
+    let config = &self.config;
+    let idle = |bucket: &mut TokenBucket, rate: f64, burst: f64| {
+      bucket.refill(rate, burst, now);
+      bucket.tokens >= burst
+    };
+    self
+      .ip_buckets
+      .retain(|_, b| !idle(b, config.ip_rate, config.ip_burst));
+    self
+      .account_buckets
+      .retain(|_, b| !idle(b, config.account_rate, config.account_burst));
+
+    let forget_after = Duration::from_secs(config.max_lockout_secs);
+    self.failures.retain(|_, f| {
+      f.locked_until.map_or(false, |until| until > now)
+        || now.duration_since(f.last).unwrap_or_default() <= forget_after
+    });
+  }
+}
+
+pub fn check_login(ip: Option<IpAddr>, account: &str) -> Result<(), Refusal> {
+  guard().check_login(ip, account, SystemTime::now())
+}
+
+pub fn record_failure(ip: Option<IpAddr>, account: Option<&str>) {
+  guard().record_failure(ip, account, SystemTime::now());
+}
+
+pub fn record_success(account: &str) {
+  guard().record_success(account);
+}
+
+pub fn bans() -> Vec<Ban> {
+  guard().bans(SystemTime::now())
+}
+
+pub fn unban(kind: &str, key: &str) -> bool {
+  guard().unban(kind, key)
+}
+
+/// Periodically drops idle buckets and expired failures.
+pub async fn run() {
+  loop {
+    tokio::time::delay_for(PRUNE_INTERVAL).await;
+    guard().prune(SystemTime::now());
+  }
+}
+
+/// Middleware refusing requests from denied, locked out or rate limited
+/// addresses before they reach an app.
+pub struct Protection;
+
+impl<S, B> Transform<S> for Protection
+where
+  S: Service<
+    Request = ServiceRequest,
+    Response = ServiceResponse<B>,
+    Error = Error,
+  >,
+  S::Future: 'static,
+{
+  type Request = ServiceRequest;
+  type Response = ServiceResponse<B>;
+  type Error = Error;
+  type InitError = ();
+  type Transform = ProtectionMiddleware<S>;
+  type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+  fn new_transform(&self, service: S) -> Self::Future {
+    ok(ProtectionMiddleware { service })
+  }
+}
+
+pub struct ProtectionMiddleware<S> {
+  service: S,
+}
+
+impl<S, B> Service for ProtectionMiddleware<S>
+where
+  S: Service<
+    Request = ServiceRequest,
+    Response = ServiceResponse<B>,
+    Error = Error,
+  >,
+  S::Future: 'static,
+{
+  type Request = ServiceRequest;
+  type Response = ServiceResponse<B>;
+  type Error = Error;
+  type Future = Either<S::Future, Ready<Result<Self::Response, Self::Error>>>;
+
+  fn poll_ready(&mut self, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+    self.service.poll_ready(cx)
+  }
+
+  fn call(&mut self, req: ServiceRequest) -> Self::Future {
+    let checked = match req.peer_addr() {
+      Some(addr) => guard().check_request(addr.ip(), SystemTime::now()),
+      None => Ok(()),
+    };
+    match checked {
+      Ok(()) => Either::Left(self.service.call(req)),
+      Err(refusal) => Either::Right(ok(req.error_response(refusal))),
+    }
+  }
+}
+
+#[test]
+fn protection_test() {
+  let ip: IpAddr = "203.0.113.7".parse().unwrap();
+  let local: IpAddr = "127.0.0.1".parse().unwrap();
+  let start = UNIX_EPOCH + Duration::from_secs(1_000_000);
+  let at = |secs: u64| start + Duration::from_secs(secs);
+
+  let cidr = Cidr::parse("10.1.0.0/16").unwrap();
+  assert!(cidr.contains("10.1.200.3".parse().unwrap()));
+  assert!(!cidr.contains("10.2.0.1".parse().unwrap()));
+  assert!(Cidr::parse("fe80::/10")
+    .unwrap()
+    .contains("fe80::1".parse().unwrap()));
+  assert_eq!(Cidr::parse("10.0.0.0/33"), None);
+
+  let mut guard = Guard::new(ProtectionConfig {
+    ip_rate: 1.0,
+    ip_burst: 3.0,
+    deny: vec!["198.51.100.0/24".to_string()],
+    ..ProtectionConfig::default()
+  });
+
+  // Burst, then one request per second.
+  for _ in 0..3 {
+    assert_eq!(guard.check_request(ip, at(0)), Ok(()));
+  }
+  assert_eq!(
+    guard.check_request(ip, at(0)),
+    Err(Refusal::RateLimited(Duration::from_secs(1)))
+  );
+  assert_eq!(guard.check_request(ip, at(1)), Ok(()));
+  assert_eq!(
+    guard.check_request("198.51.100.9".parse().unwrap(), at(1)),
+    Err(Refusal::Denied)
+  );
+  for _ in 0..10 {
+    assert_eq!(guard.check_request(local, at(1)), Ok(()));
+  }
+
+  // Five failures lock out for 60s, the sixth for 120s.
+  for _ in 0..5 {
+    guard.record_failure(Some(ip), Some("alice"), at(10));
+  }
+  assert_eq!(
+    guard.check_login(None, "alice", at(20)),
+    Err(Refusal::LockedOut(Duration::from_secs(50)))
+  );
+  assert_eq!(
+    guard.check_request(ip, at(20)),
+    Err(Refusal::LockedOut(Duration::from_secs(50)))
+  );
+  guard.record_failure(Some(ip), Some("alice"), at(80));
+  assert_eq!(
+    guard.check_request(ip, at(100)),
+    Err(Refusal::LockedOut(Duration::from_secs(100)))
+  );
+
+  let bans = guard.bans(at(100));
+  assert_eq!(bans.len(), 2);
+  assert!(bans.iter().all(|b| b.failures == 6));
+
+  assert!(guard.unban("ip", "203.0.113.7"));
+  assert_eq!(guard.check_request(ip, at(100)), Ok(()));
+  guard.record_success("alice");
+  assert_eq!(guard.check_login(Some(ip), "alice", at(100)), Ok(()));
+  assert!(guard.bans(at(100)).is_empty());
+
+  // An allowed address still can't get past an account's lockout.
+  for _ in 0..5 {
+    guard.record_failure(Some(local), Some("bob"), at(200));
+  }
+  assert_eq!(
+    guard.check_login(Some(local), "bob", at(200)),
+    Err(Refusal::LockedOut(Duration::from_secs(60)))
+  );
+  assert_eq!(guard.check_request(local, at(200)), Ok(()));
+
+  // New settings apply to what is already tracked.
+  guard.configure(ProtectionConfig {
+    deny: vec![ip.to_string()],
+    ..ProtectionConfig::default()
+  });
+  assert_eq!(guard.check_request(ip, at(200)), Err(Refusal::Denied));
+  assert_eq!(
+    guard.check_login(None, "bob", at(230)),
+    Err(Refusal::LockedOut(Duration::from_secs(30)))
+  );
+}
@@ -0,0 +1,472 @@
+//! Reverse proxy routes per virtual host.
+//!
+//! Requests whose path falls under a host's `proxy` prefix are streamed to
+//! one of the route's upstreams (round-robin, skipping ones that fail their
+//! health check) without going through JS.  WebSocket upgrades are bridged
+//! frame by frame.  The routes follow config.json as it is saved; routes
+//! that didn't change keep the health of their upstreams.
+
+use crate::config::{self, Config, ProxyRoute};
+use actix::io::{SinkWrite, WriteHandler};
+use actix::prelude::*;
+use actix_codec::Framed;
+use actix_web::client::{BoxedSocket, Client};
+use actix_web::http::header;
+use actix_web::{web, HttpRequest, HttpResponse};
+use actix_web_actors::ws;
+use futures::stream::{SplitSink, StreamExt};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+const HEALTH_INTERVAL: Duration = Duration::from_secs(10);
+const HEALTH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Headers that only apply to a single connection and must not be forwarded.
+const HOP_BY_HOP: &[&str] = &[
+  "connection",
+  "keep-alive",
+  "proxy-authenticate",
+  "proxy-authorization",
+  "te",
+  "trailers",
+  "transfer-encoding",
+  "upgrade",
+];
+
+/// Handshake headers the websocket client makes up itself for the upstream.
+const WEBSOCKET_HANDSHAKE: &[&str] = &[
+  "sec-websocket-accept",
+  "sec-websocket-extensions",
+  "sec-websocket-key",
+  "sec-websocket-version",
+];
+
+lazy_static! {
+  /// The routes, with the config they were built from.
+  static ref TABLE: RwLock<Option<(Arc<Config>, Arc<Vec<HostRoutes>>)>> =
+    RwLock::new(None);
+}
+
+pub struct Upstream {
+  pub url: String,
+  healthy: AtomicBool,
+}
+
+impl Upstream {
+  pub fn is_healthy(&self) -> bool {
+    self.healthy.load(Ordering::Relaxed)
+  }
+
+  fn set_healthy(&self, healthy: bool) {
+    if self.healthy.swap(healthy, Ordering::Relaxed) != healthy {
+      if healthy {
+        info!("upstream {} is back up", self.url);
+      } else {
+        warn!("upstream {} is down", self.url);
+      }
+    }
+  }
+}
+
+pub struct Route {
+  config: ProxyRoute,
+  pub prefix: String,
+  pub upstreams: Vec<Upstream>,
+  strip_prefix: bool,
+  health_check: Option<String>,
+  next: AtomicUsize,
+}
+
+impl Route {
+  fn new(config: &ProxyRoute) -> Self {
+    Route {
+      config: config.clone(),
+      prefix: config.prefix.trim_end_matches('/').to_string(),
+      upstreams: config
+        .upstreams
+        .iter()
+        .map(|url| Upstream {
+          url: url.trim_end_matches('/').to_string(),
+          healthy: AtomicBool::new(true),
+        })
+        .collect(),
+      strip_prefix: config.strip_prefix,
+      health_check: config.health_check.clone(),
+      next: AtomicUsize::new(0),
+    }
+  }
+
+  /// True when `path` is the prefix itself or below it.
+  fn matches(&self, path: &str) -> bool {
+    path.starts_with(&self.prefix)
+      && (path.len() == self.prefix.len()
+        || path[self.prefix.len()..].starts_with('/'))
+  }
+
+  /// Next healthy upstream in round-robin order.
+  fn next_upstream(&self) -> Option<&Upstream> {
+    let count = self.upstreams.len();
+    let start = self.next.fetch_add(1, Ordering::Relaxed);
+    (0..count)
+      .map(|i| &self.upstreams[(start + i) % count])
+      .find(|upstream| upstream.is_healthy())
+  }
+
+  fn upstream_url(&self, upstream: &Upstream, path_and_query: &str) -> String {
+    let rest = if self.strip_prefix {
+      &path_and_query[self.prefix.len()..]
+    } else {
+      path_and_query
+    };
+    if rest.starts_with('/') {
+      format!("{}{}", upstream.url, rest)
+    } else {
+      format!("{}/{}", upstream.url, rest)
+    }
+  }
+}
+
+struct HostRoutes {
+  host: config::HostConfig,
+  /// Longest prefix first, so the most specific route wins.
+  routes: Vec<Arc<Route>>,
+}
+
+impl HostRoutes {
+  /// Routes of `config`, reusing those of `old` that are the same.
+  fn from_config(config: &Config, old: &[HostRoutes]) -> Vec<HostRoutes> {
+    let route = |route: &ProxyRoute| {
+      old
+        .iter()
+        .flat_map(|host| host.routes.iter())
+        .find(|known| known.config == *route)
+        .cloned()
+        .unwrap_or_else(|| Arc::new(Route::new(route)))
+    };
+    config
+      .hosts
+      .iter()
+      .filter(|host| !host.proxy.is_empty())
+      .map(|host| {
+        let mut routes: Vec<Arc<Route>> =
+          host.proxy.iter().map(&route).collect();
+        routes.sort_by(|a, b| b.prefix.len().cmp(&a.prefix.len()));
+        HostRoutes {
+          host: host.clone(),
+          routes,
+        }
+      })
+      .collect()
+  }
+}
+
+/// The routes of the current config.
+fn table() -> Arc<Vec<HostRoutes>> {
+  let config = config::get();
+  if let Some((built_from, routes)) = &*TABLE.read().unwrap() {
+    if Arc::ptr_eq(built_from, &config) {
+      return routes.clone();
+    }
+  }
+  let mut table = TABLE.write().unwrap();
+  let routes = {
+    let old: &[HostRoutes] = match &*table {
+      Some((_, old)) => old,
+      None => &[],
+    };
+    Arc::new(HostRoutes::from_config(&config, old))
+  };
+  *table = Some((config, routes.clone()));
+  routes
+}
+
+/// The proxy route for this request, if its host has one for its path.
+pub fn route_for(req: &HttpRequest) -> Option<Arc<Route>> {
+  let info = req.connection_info();
+  let table = table();
+  let host = table.iter().find(|h| h.host.matches(info.host()))?;
+  host
+    .routes
+    .iter()
+    .find(|route| route.matches(req.path()))
+    .cloned()
+}
+
+fn is_websocket(req: &HttpRequest) -> bool {
+  req
+    .headers()
+    .get(header::UPGRADE)
+    .and_then(|v| v.to_str().ok())
+    .map_or(false, |v| v.eq_ignore_ascii_case("websocket"))
+}
+
+fn forwarded_for(req: &HttpRequest) -> String {
+  let peer = req
+    .peer_addr()
+    .map(|addr| addr.ip().to_string())
+    .unwrap_or_default();
+  match req
+    .headers()
+    .get("x-forwarded-for")
+    .and_then(|v| v.to_str().ok())
+  {
+    Some(previous) if !peer.is_empty() => format!("{}, {}", previous, peer),
+    Some(previous) => previous.to_string(),
+    None => peer,
+  }
+}
+
+/// Sends the request to an upstream of `route` and streams the reply back.
+pub async fn forward(
+  route: Arc<Route>,
+  req: HttpRequest,
+  body: web::Payload,
+) -> HttpResponse {
+  let upstream = match route.next_upstream() {
+    Some(upstream) => upstream,
+    None => {
+      return HttpResponse::ServiceUnavailable().body("no healthy upstream")
+    }
+  };
+  let path_and_query = req
+    .uri()
+    .path_and_query()
+    .map(|p| p.as_str())
+    .unwrap_or("/");
+  let url = route.upstream_url(upstream, path_and_query);
+
+  if is_websocket(&req) {
+    return forward_websocket(url, req, body).await;
+  }
+
+  let info = req.connection_info().clone();
+  let mut forwarded = Client::default()
+    .request_from(url.as_str(), req.head())
+    .no_decompress()
+    .set_header("x-forwarded-for", forwarded_for(&req))
+    .set_header("x-forwarded-host", info.host())
+    .set_header("x-forwarded-proto", info.scheme());
+  for name in HOP_BY_HOP {
+    forwarded.headers_mut().remove(*name);
+  }
+
+  match forwarded.send_stream(body).await {
+    Ok(res) => {
+      let mut reply = HttpResponse::build(res.status());
+      for (name, value) in res.headers() {
+        if !HOP_BY_HOP.contains(&name.as_str()) {
+          reply.header(name.clone(), value.clone());
+        }
+      }
+      reply.streaming(res)
+    }
+    Err(e) => {
+      warn!("proxy to {} failed: {}", url, e);
+      HttpResponse::BadGateway().body("upstream unavailable")
+    }
+  }
+}
+
+type UpstreamConnection = Framed<BoxedSocket, ws::Codec>;
+type UpstreamSink = SplitSink<UpstreamConnection, ws::Message>;
+
+/// Relays frames between a browser websocket and the upstream one.
+struct WebSocketBridge {
+  upstream: Option<SinkWrite<ws::Message, UpstreamSink>>,
+  pending: Option<UpstreamConnection>,
+}
+
+impl Actor for WebSocketBridge {
+  type Context = ws::WebsocketContext<Self>;
+
+  fn started(&mut self, ctx: &mut Self::Context) {
+    if let Some(framed) = self.pending.take() {
+      let (sink, stream) = framed.split();
+      ctx.add_stream(stream);
+      self.upstream = Some(SinkWrite::new(sink, ctx));
+    }
+  }
+}
+
+impl WebSocketBridge {
+  fn send_upstream(&mut self, msg: ws::Message) {
+    if let Some(upstream) = self.upstream.as_mut() {
+      let _ = upstream.write(msg);
+    }
+  }
+}
+
+/// Browser -> upstream
+impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for WebSocketBridge {
+  fn handle(
+    &mut self,
+    msg: Result<ws::Message, ws::ProtocolError>,
+    ctx: &mut Self::Context,
+  ) {
+    match msg {
+      Ok(ws::Message::Close(reason)) => {
+        self.send_upstream(ws::Message::Close(reason));
+        ctx.stop();
+      }
+      Ok(msg) => self.send_upstream(msg),
+      Err(_) => ctx.stop(),
+    }
+  }
+}
+
+/// Upstream -> browser
+impl StreamHandler<Result<ws::Frame, ws::ProtocolError>> for WebSocketBridge {
+  fn handle(
+    &mut self,
+    frame: Result<ws::Frame, ws::ProtocolError>,
+    ctx: &mut Self::Context,
+  ) {
+    match frame {
+      Ok(ws::Frame::Text(text)) => {
+        ctx.text(String::from_utf8_lossy(&text).into_owned())
+      }
+      Ok(ws::Frame::Binary(bin)) => ctx.binary(bin),
+      Ok(ws::Frame::Ping(msg)) => ctx.ping(&msg),
+      Ok(ws::Frame::Pong(msg)) => ctx.pong(&msg),
+      Ok(ws::Frame::Close(reason)) => {
+        ctx.close(reason);
+        ctx.stop();
+      }
+      Ok(ws::Frame::Continuation(item)) => {
+        ctx.write_raw(ws::Message::Continuation(item))
+      }
+      Err(_) => ctx.stop(),
+    }
+  }
+
+  fn finished(&mut self, ctx: &mut Self::Context) {
+    ctx.stop();
+  }
+}
+
+impl WriteHandler<ws::ProtocolError> for WebSocketBridge {}
+
+/// The request headers a proxied websocket takes to the upstream, as a
+/// forwarded request would: all but the hop-by-hop and handshake ones.
+fn websocket_headers(
+  req: &HttpRequest,
+) -> Vec<(header::HeaderName, header::HeaderValue)> {
+  req
+    .headers()
+    .iter()
+    .filter(|(name, _)| {
+      let name = name.as_str();
+      !HOP_BY_HOP.contains(&name) && !WEBSOCKET_HANDSHAKE.contains(&name)
+    })
+    .map(|(name, value)| (name.clone(), value.clone()))
+    .collect()
+}
+
+async fn forward_websocket(
+  url: String,
+  req: HttpRequest,
+  body: web::Payload,
+) -> HttpResponse {
+  let ws_url = if url.starts_with("https://") {
+    url.replacen("https://", "wss://", 1)
+  } else {
+    url.replacen("http://", "ws://", 1)
+  };
+
+  let mut upstream = Client::default().ws(ws_url.as_str());
+  for (name, value) in websocket_headers(&req) {
+    upstream = upstream.header(name, value);
+  }
+  let info = req.connection_info().clone();
+  let upstream = upstream
+    .set_header("x-forwarded-for", forwarded_for(&req))
+    .set_header("x-forwarded-host", info.host())
+    .set_header("x-forwarded-proto", info.scheme());
+  let framed = match upstream.connect().await {
+    Ok((_, framed)) => framed,
+    Err(e) => {
+      warn!("websocket proxy to {} failed: {}", ws_url, e);
+      return HttpResponse::BadGateway().body("upstream unavailable");
+    }
+  };
+
+  let bridge = WebSocketBridge {
+    upstream: None,
+    pending: Some(framed),
+  };
+  ws::start(bridge, &req, body).unwrap_or_else(|e| HttpResponse::from_error(e))
+}
+
+/// Polls every route's health check for the life of the process.
+pub async fn run_health_checks() {
+  let client = Client::build().timeout(HEALTH_TIMEOUT).finish();
+  loop {
+    let routes: Vec<Arc<Route>> = table()
+      .iter()
+      .flat_map(|host| host.routes.iter())
+      .filter(|route| route.health_check.is_some())
+      .cloned()
+      .collect();
+    for route in &routes {
+      let path = route.health_check.as_ref().unwrap();
+      for upstream in &route.upstreams {
+        let url = format!("{}{}", upstream.url, path);
+        let healthy = match client.get(url).send().await {
+          Ok(res) => res.status().is_success(),
+          Err(_) => false,
+        };
+        upstream.set_healthy(healthy);
+      }
+    }
+    tokio::time::delay_for(HEALTH_INTERVAL).await;
+  }
+}
+
+#[test]
+fn proxy_route_test() {
+  let route = Route::new(&ProxyRoute {
+    prefix: "/api/".to_string(),
+    upstreams: vec![
+      "http://127.0.0.1:3000/".to_string(),
+      "http://127.0.0.1:3001".to_string(),
+    ],
+    strip_prefix: true,
+    health_check: None,
+  });
+
+  assert!(route.matches("/api"));
+  assert!(route.matches("/api/users"));
+  assert!(!route.matches("/apis"));
+
+  let first = route.next_upstream().unwrap();
+  assert_eq!(
+    route.upstream_url(first, "/api/users?id=1"),
+    "http://127.0.0.1:3000/users?id=1"
+  );
+  assert_eq!(route.next_upstream().unwrap().url, "http://127.0.0.1:3001");
+
+  route.upstreams[0].set_healthy(false);
+  assert_eq!(route.next_upstream().unwrap().url, "http://127.0.0.1:3001");
+  assert_eq!(route.next_upstream().unwrap().url, "http://127.0.0.1:3001");
+  assert_eq!(route.upstream_url(first, "/api"), "http://127.0.0.1:3000/");
+}
+
+#[test]
+fn websocket_headers_test() {
+  let req = actix_web::test::TestRequest::get()
+    .header("cookie", "rws_session=abc")
+    .header("authorization", "Bearer xyz")
+    .header("sec-websocket-protocol", "chat")
+    .header("sec-websocket-key", "dGhlIHNhbXBsZSBub25jZQ==")
+    .header("sec-websocket-version", "13")
+    .header("connection", "Upgrade")
+    .header("upgrade", "websocket")
+    .to_http_request();
+  let mut names: Vec<String> = websocket_headers(&req)
+    .into_iter()
+    .map(|(name, _)| name.as_str().to_string())
+    .collect();
+  names.sort();
+  assert_eq!(names, ["authorization", "cookie", "sec-websocket-protocol"]);
+}
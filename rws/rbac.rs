@@ -0,0 +1,352 @@
+//! Role based access control.
+//!
+//! Apps declare permissions in the `permissions` of their manifest.  Admins
+//! build roles for a host out of them and assign users to roles on that
+//! host.  Inside a role a permission is written `<app>.<name>`; `<app>.*`
+//! grants all of an app's permissions and `*` everything on the host.
+//!
+//! Each host's roles and members are kept in `<rws dir>/rbac/<host>.json`.
+
+use crate::accounts::{self, Token};
+use crate::config;
+use crate::manifest::Manifest;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+lazy_static! {
+  static ref STORE: Mutex<RbacStore> =
+    Mutex::new(RbacStore::new(config::rws_dir().join("rbac")));
+}
+
+#[derive(Debug)]
+pub enum RbacError {
+  Io(io::Error),
+  Invalid(String),
+}
+
+impl fmt::Display for RbacError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      RbacError::Io(e) => write!(f, "could not store roles: {}", e),
+      RbacError::Invalid(reason) => f.write_str(reason),
+    }
+  }
+}
+
+impl std::error::Error for RbacError {}
+
+impl From<io::Error> for RbacError {
+  fn from(e: io::Error) -> Self {
+    RbacError::Io(e)
+  }
+}
+
+impl From<serde_json::Error> for RbacError {
+  fn from(e: serde_json::Error) -> Self {
+    RbacError::Io(e.into())
+  }
+}
+
+/// A permission declared by an installed app.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct PermissionInfo {
+  pub app: String,
+  pub name: String,
+  pub title: String,
+  pub desc: String,
+}
+
+impl PermissionInfo {
+  /// The name roles use, `<app>.<name>`.
+  pub fn key(&self) -> String {
+    format!("{}.{}", self.app, self.name)
+  }
+}
+
+/// Permissions declared by the apps installed on `host`.
+pub fn permissions(host: &str) -> Vec<PermissionInfo> {
+  let dir = config::rws_dir().join("apps").join(config::file_name(host));
+  let mut found = Vec::new();
+  let apps = match fs::read_dir(dir) {
+    Ok(apps) => apps,
+    Err(_) => return found,
+  };
+  for app in apps.filter_map(|e| e.ok()) {
+    let app_name = app.file_name().to_string_lossy().into_owned();
+    let manifest = match Manifest::load(&app.path().join("manifest.json")) {
+      Ok(manifest) => manifest,
+      Err(_) => continue,
+    };
+    for (name, spec) in manifest.permission_specs() {
+      found.push(PermissionInfo {
+        app: app_name.clone(),
+        name,
+        title: spec.title,
+        desc: spec.desc,
+      });
+    }
+  }
+  found.sort_by(|a, b| a.key().cmp(&b.key()));
+  found
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Role {
+  pub title: String,
+  pub permissions: BTreeSet<String>,
+}
+
+impl Role {
+  fn grants(&self, app: &str, permission: &str) -> bool {
+    self.permissions.iter().any(|p| {
+      if p == "*" {
+        return true;
+      }
+      match p.find('.') {
+        Some(dot) => {
+          let name = &p[dot + 1..];
+          &p[..dot] == app && (name == "*" || name == permission)
+        }
+        None => false,
+      }
+    })
+  }
+}
+
+/// Roles and role assignments of one host.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct HostPolicy {
+  pub roles: BTreeMap<String, Role>,
+  /// Role names by user id.
+  pub members: BTreeMap<String, BTreeSet<String>>,
+}
+
+impl HostPolicy {
+  pub fn can(&self, user_id: &str, app: &str, permission: &str) -> bool {
+    let roles = match self.members.get(user_id) {
+      Some(roles) => roles,
+      None => return false,
+    };
+    roles
+      .iter()
+      .filter_map(|name| self.roles.get(name))
+      .any(|role| role.grants(app, permission))
+  }
+}
+
+pub struct RbacStore {
+  dir: PathBuf,
+  hosts: HashMap<String, HostPolicy>,
+}
+
+impl RbacStore {
+  pub fn new(dir: PathBuf) -> Self {
+    RbacStore {
+      dir,
+      hosts: HashMap::new(),
+    }
+  }
+
+  fn path(&self, host: &str) -> PathBuf {
+    self.dir.join(format!("{}.json", config::file_name(host)))
+  }
+
+  pub fn policy(&mut self, host: &str) -> &mut HostPolicy {
+    if !self.hosts.contains_key(host) {
+      let path = self.path(host);
+      let policy = match fs::read_to_string(&path) {
+        Ok(text) => serde_json::from_str(&text).unwrap_or_else(|e| {
+          error!("invalid {}: {}", path.display(), e);
+          HostPolicy::default()
+        }),
+        Err(_) => HostPolicy::default(),
+      };
+      self.hosts.insert(host.to_string(), policy);
+    }
+    self.hosts.get_mut(host).unwrap()
+  }
+
+  fn persist(&self, host: &str) -> Result<(), RbacError> {
+    let path = self.path(host);
+    fs::create_dir_all(&self.dir)?;
+    let tmp = path.with_extension("json.tmp");
+    fs::write(&tmp, serde_json::to_vec_pretty(&self.hosts[host])?)?;
+    fs::rename(&tmp, &path)?;
+    Ok(())
+  }
+
+  /// Creates or replaces a role.  Its permissions must be declared by an
+  /// app in `declared` or be wildcards.
+  pub fn save_role(
+    &mut self,
+    host: &str,
+    name: &str,
+    role: Role,
+    declared: &[PermissionInfo],
+  ) -> Result<(), RbacError> {
+    if name.is_empty() {
+      return Err(RbacError::Invalid("role name is empty".to_string()));
+    }
+    for permission in &role.permissions {
+      let known = permission == "*"
+        || permission.ends_with(".*")
+        || declared.iter().any(|p| &p.key() == permission);
+      if !known {
+        return Err(RbacError::Invalid(format!(
+          "unknown permission \"{}\"",
+          permission
+        )));
+      }
+    }
+    self.policy(host).roles.insert(name.to_string(), role);
+    self.persist(host)
+  }
+
+  /// Deletes a role and takes it away from its members.
+  pub fn delete_role(
+    &mut self,
+    host: &str,
+    name: &str,
+  ) -> Result<bool, RbacError> {
+    let policy = self.policy(host);
+    if policy.roles.remove(name).is_none() {
+      return Ok(false);
+    }
+    for roles in policy.members.values_mut() {
+      roles.remove(name);
+    }
+    policy.members.retain(|_, roles| !roles.is_empty());
+    self.persist(host)?;
+    Ok(true)
+  }
+
+  /// Sets the roles `user_id` has on `host`; an empty set removes the user.
+  pub fn assign(
+    &mut self,
+    host: &str,
+    user_id: &str,
+    roles: BTreeSet<String>,
+  ) -> Result<(), RbacError> {
+    let policy = self.policy(host);
+    let missing = roles.iter().find(|r| !policy.roles.contains_key(*r));
+    if let Some(missing) = missing {
+      return Err(RbacError::Invalid(format!("unknown role \"{}\"", missing)));
+    }
+    if roles.is_empty() {
+      policy.members.remove(user_id);
+    } else {
+      policy.members.insert(user_id.to_string(), roles);
+    }
+    self.persist(host)
+  }
+}
+
+/// Whether the session may use `app`'s `permission` on `host`.  Signed out
+/// visitors and disabled users have no permissions.
+pub fn can(
+  host: &str,
+  token: Option<&Token>,
+  app: &str,
+  permission: &str,
+) -> bool {
+  let user_id = match token {
+    Some(token) => &token.user.id,
+    None => return false,
+  };
+  user_can(host, user_id, app, permission)
+}
+
+pub fn user_can(
+  host: &str,
+  user_id: &str,
+  app: &str,
+  permission: &str,
+) -> bool {
+  match accounts::user(user_id) {
+    Some(user) if !user.disabled => {}
+    _ => return false,
+  }
+  STORE
+    .lock()
+    .unwrap()
+    .policy(host)
+    .can(user_id, app, permission)
+}
+
+pub fn policy(host: &str) -> HostPolicy {
+  STORE.lock().unwrap().policy(host).clone()
+}
+
+pub fn save_role(host: &str, name: &str, role: Role) -> Result<(), RbacError> {
+  let declared = permissions(host);
+  STORE.lock().unwrap().save_role(host, name, role, &declared)
+}
+
+pub fn delete_role(host: &str, name: &str) -> Result<bool, RbacError> {
+  STORE.lock().unwrap().delete_role(host, name)
+}
+
+pub fn assign(
+  host: &str,
+  user_id: &str,
+  roles: BTreeSet<String>,
+) -> Result<(), RbacError> {
+  STORE.lock().unwrap().assign(host, user_id, roles)
+}
+
+#[test]
+fn rbac_test() {
+  let dir = tempfile::TempDir::new().unwrap();
+  let mut store = RbacStore::new(dir.path().to_path_buf());
+  let declared = vec![PermissionInfo {
+    app: "forum".to_string(),
+    name: "can_edit_own_posts".to_string(),
+    title: "Edit Own Posts".to_string(),
+    desc: String::new(),
+  }];
+  let role = |permissions: &[&str]| Role {
+    title: String::new(),
+    permissions: permissions.iter().map(|p| p.to_string()).collect(),
+  };
+  let set = |names: &[&str]| names.iter().map(|n| n.to_string()).collect();
+
+  let editor = role(&["forum.can_edit_own_posts"]);
+  store
+    .save_role("a.com", "editor", editor, &declared)
+    .unwrap();
+  store
+    .save_role("a.com", "owner", role(&["*"]), &declared)
+    .unwrap();
+  assert!(matches!(
+    store.save_role("a.com", "bad", role(&["forum.can_fly"]), &declared),
+    Err(RbacError::Invalid(_))
+  ));
+  assert!(store.assign("a.com", "u1", set(&["nobody"])).is_err());
+
+  store.assign("a.com", "u1", set(&["editor"])).unwrap();
+  store.assign("a.com", "u2", set(&["owner"])).unwrap();
+
+  let policy = store.policy("a.com").clone();
+  assert!(policy.can("u1", "forum", "can_edit_own_posts"));
+  assert!(!policy.can("u1", "forum", "can_view_something"));
+  assert!(!policy.can("u1", "shop", "can_edit_own_posts"));
+  assert!(policy.can("u2", "shop", "anything"));
+  assert!(!policy.can("u3", "forum", "can_edit_own_posts"));
+  // Roles are per host.
+  assert!(!store.policy("b.com").can("u2", "shop", "anything"));
+
+  // Survives a reload; deleting a role drops it from its members.
+  let mut store = RbacStore::new(dir.path().to_path_buf());
+  assert!(store
+    .policy("a.com")
+    .can("u1", "forum", "can_edit_own_posts"));
+  assert!(store.delete_role("a.com", "editor").unwrap());
+  assert!(!store.policy("a.com").members.contains_key("u1"));
+}
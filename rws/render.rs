@@ -0,0 +1,788 @@
+//! Serving apps' pages according to their manifest `render_type`.
+//!
+//! Apps are mounted on a host at a path (`hosts[].apps` in config.json).
+//! Below the mount, the files of `target/client/html_public` are served at
+//! `/`, `html_public/static` at `/static` and `html_public/node_modules` at
+//! `/node_modules`.  Any other GET is a route of the app:
+//!
+//! - `single_page` answers with the RequireJS shell, which loads
+//!   `views/index.js` and leaves routing to the client.
+//! - `multi_page` asks the app's isolate (see `apps`) to render the route
+//!   and inlines the result in the shell.  Without a view set up in the
+//!   isolate it falls back to the bare shell.
+//! - `inline` answers with the fragment the isolate renders, for embedding
+//!   in other apps' pages (`RWS.views.inline` gets the same through `inline`).
+//! - `control_panel` apps aren't served on their host; the dashboard mounts
+//!   their `html_admin` views as pages (see `control_panel`).
+//!
+//! Apps with a server build of their React views,
+//! `target/server/html_*/views/index.js`, are rendered in the isolate for
+//! both `single_page` and `multi_page`: the AMD module is loaded with its
+//! `node_modules` and rendered with react-dom/server, and the client
+//! hydrates from `RWS_PAGE.data`.  Renders that take longer than
+//! `render.timeout_ms` fall back to the bare shell, and an isolate still
+//! stuck in synchronous code then is terminated and restarted (see
+//! `apps::terminate`).  Pages rendered for signed out visitors are cached
+//! per route for `render.cache_secs`.
+//!
+//! Library packages in `node_modules` that have a `lib.js` are loaded up
+//! front by script tags checked against their SRI hash (see `libs`); with
+//! `dev` set in config.json their unminified builds are served instead.
+//!
+//! Paths matching a nested route mount (see `routes`) are served by the
+//! route's app, with the slot and its arguments passed along as `slot`.
+//! Such pages load their files from `/.rws/apps/<app>/`, where every app
+//! installed on the host has its files served as well.
+
+use crate::accounts::{self, Token};
+use crate::apps::{self, AppContext, Listeners};
+use crate::config::{self, AppMount, HostConfig};
+use crate::libs::{self, Library};
+use crate::manifest::{Manifest, RenderType};
+use crate::routes::{self, Resolved, RouteError};
+use actix_files::NamedFile;
+use actix_web::http::Method;
+use actix_web::{HttpRequest, HttpResponse};
+use serde_derive::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, UNIX_EPOCH};
+use tokio::sync::{mpsc, oneshot};
+
+/// RequireJS, served to every app at `/.rws/require.js`.
+const REQUIRE_JS: &str = include_str!("../control-panel/src/require.js");
+
+/// Where the files of the apps installed on a host are served, by app name.
+const APP_FILES: &str = "/.rws/apps/";
+
+/// Host, app, route, query and whether the page is inline.
+type CacheKey = (String, String, String, String, bool);
+
+struct CachedPage {
+  page: Page,
+  /// Version of the server build the page was rendered with.
+  version: Option<u64>,
+  expires: Instant,
+}
+
+lazy_static! {
+  static ref CACHE: Mutex<HashMap<CacheKey, CachedPage>> =
+    Mutex::new(HashMap::new());
+  static ref PAGES: Listeners<PageRequest> = Listeners::new();
+}
+
+/// An app mounted on a host, matched against a request path.
+#[derive(Clone, Debug)]
+pub struct Mount {
+  pub host: String,
+  pub app: String,
+  /// Mount path without the trailing slash, "" at the root.
+  pub base: String,
+  /// Path the app's files are served at, without the trailing slash: the
+  /// mount path, or the app's directory under `/.rws/apps` when the path
+  /// isn't the app's (nested routes) or only serves its files.
+  pub files: String,
+  /// The rest of the path, always starting with "/".
+  pub route: String,
+  pub manifest: Manifest,
+  pub dir: PathBuf,
+  /// The nested route the path was resolved to, if any.
+  pub slot: Option<Resolved>,
+}
+
+impl Mount {
+  /// The `html_*` directory pages come from.
+  pub fn section(&self) -> &'static str {
+    section(self.manifest.render_type)
+  }
+}
+
+pub fn section(render_type: RenderType) -> &'static str {
+  match render_type {
+    RenderType::ControlPanel => "html_admin",
+    _ => "html_public",
+  }
+}
+
+fn trim_base(path: &str) -> &str {
+  path.trim_end_matches('/')
+}
+
+/// Finds the app mounted at the longest prefix of `path` on `host`.
+pub fn find_mount<'a>(
+  hosts: &'a [HostConfig],
+  host: &str,
+  path: &str,
+) -> Option<(&'a HostConfig, &'a AppMount, String)> {
+  let host_config = hosts.iter().find(|h| h.matches(host))?;
+  host_config
+    .apps
+    .iter()
+    .filter_map(|mount| {
+      let base = trim_base(&mount.path);
+      if !path.starts_with(base) {
+        return None;
+      }
+      let rest = &path[base.len()..];
+      if rest.is_empty() || rest.starts_with('/') {
+        let route = if rest.is_empty() { "/" } else { rest };
+        Some((mount, base.len(), route.to_string()))
+      } else {
+        None
+      }
+    })
+    .max_by_key(|(_, len, _)| *len)
+    .map(|(mount, _, route)| (host_config, mount, route))
+}
+
+fn load_manifest(host: &str, app: &str) -> Option<(Manifest, PathBuf)> {
+  let dir = config::app_dir(host, app);
+  match Manifest::load(&dir.join("manifest.json")) {
+    Ok(manifest) => Some((manifest, dir)),
+    Err(e) => {
+      warn!("app {} on {}: {}", app, host, e);
+      None
+    }
+  }
+}
+
+/// The app serving a request.  Nested route mounts take precedence over
+/// app mounts; `Some(Err)` is a route mount whose arguments didn't fit.
+pub fn mount_for(req: &HttpRequest) -> Option<Result<Mount, RouteError>> {
+  let config = config::get();
+  let host = req.connection_info().host().to_string();
+  let host_config = config.host(&host)?;
+
+  if req.path().starts_with(APP_FILES) {
+    let rest = &req.path()[APP_FILES.len()..];
+    let (app, route) = match rest.find('/') {
+      Some(slash) => (&rest[..slash], &rest[slash..]),
+      None => (rest, "/"),
+    };
+    let (manifest, dir) = load_manifest(&host_config.name, app)?;
+    let files = format!("{}{}", APP_FILES, app);
+    return Some(Ok(Mount {
+      host: host_config.name.clone(),
+      app: app.to_string(),
+      base: files.clone(),
+      files,
+      route: route.to_string(),
+      manifest,
+      dir,
+      slot: None,
+    }));
+  }
+
+  match routes::resolve(&host_config.name, req.path()) {
+    Some(Ok(slot)) => {
+      let (manifest, dir) = load_manifest(&host_config.name, &slot.app)?;
+      return Some(Ok(Mount {
+        host: host_config.name.clone(),
+        app: slot.app.clone(),
+        base: String::new(),
+        files: format!("{}{}", APP_FILES, slot.app),
+        route: req.path().to_string(),
+        manifest,
+        dir,
+        slot: Some(slot),
+      }));
+    }
+    Some(Err(e)) => return Some(Err(e)),
+    None => {}
+  }
+
+  let (host_config, mount, route) =
+    find_mount(&config.hosts, &host, req.path())?;
+  let (manifest, dir) = load_manifest(&host_config.name, &mount.app)?;
+  Some(Ok(Mount {
+    host: host_config.name.clone(),
+    app: mount.app.clone(),
+    base: trim_base(&mount.path).to_string(),
+    files: trim_base(&mount.path).to_string(),
+    route,
+    manifest,
+    dir,
+    slot: None,
+  }))
+}
+
+/// The file a route names, if there is one.  Paths can't leave the app.
+pub fn static_file(dir: &Path, section: &str, route: &str) -> Option<PathBuf> {
+  let relative = Path::new(route.trim_start_matches('/'));
+  if relative
+    .components()
+    .any(|c| !matches!(c, Component::Normal(_)))
+  {
+    return None;
+  }
+  let path = match relative.components().next() {
+    Some(Component::Normal(first)) if first == "static" => {
+      dir.join(section).join(relative)
+    }
+    Some(Component::Normal(first)) if first == "node_modules" => {
+      dir.join(section).join(relative)
+    }
+    Some(_) => {
+      let client = dir.join("target").join("client");
+      client.join(section).join(relative)
+    }
+    None => return None,
+  };
+  if path.is_file() {
+    Some(path)
+  } else {
+    None
+  }
+}
+
+/// RequireJS `paths` for the packages in the section's `node_modules`.
+pub fn require_paths(dir: &Path, section: &str) -> Map<String, Value> {
+  let mut paths = Map::new();
+  if let Ok(entries) = fs::read_dir(dir.join(section).join("node_modules")) {
+    for entry in entries.filter_map(|e| e.ok()) {
+      if entry.path().is_dir() {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let path = format!("node_modules/{}/index", name);
+        paths.insert(name, Value::String(path));
+      }
+    }
+  }
+  paths
+}
+
+/// The server build of an app's views.
+#[derive(Clone, Debug, Serialize)]
+pub struct ServerModule {
+  /// `target/server/<section>/views/index.js`.
+  pub path: PathBuf,
+  /// The section directory; other modules resolve against its
+  /// `node_modules`.
+  pub root: PathBuf,
+  /// Modification time of the build in milliseconds.  The isolate reloads
+  /// the module when it changes.
+  pub version: u64,
+}
+
+pub fn server_module(dir: &Path, section: &str) -> Option<ServerModule> {
+  let path = dir
+    .join("target")
+    .join("server")
+    .join(section)
+    .join("views")
+    .join("index.js");
+  let modified = fs::metadata(&path).ok()?.modified().ok()?;
+  let version = modified.duration_since(UNIX_EPOCH).ok()?.as_millis() as u64;
+  Some(ServerModule {
+    path,
+    root: dir.join(section),
+    version,
+  })
+}
+
+/// JSON that is safe inside a `<script>` element.
+pub fn script_json(value: &Value) -> String {
+  value
+    .to_string()
+    .replace('<', "\\u003c")
+    .replace('>', "\\u003e")
+    .replace('&', "\\u0026")
+}
+
+pub fn escape_html(text: &str) -> String {
+  text
+    .replace('&', "&amp;")
+    .replace('<', "&lt;")
+    .replace('>', "&gt;")
+    .replace('"', "&quot;")
+}
+
+/// What the isolate renders for a route.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Page {
+  /// HTML placed in `<body>`, or the whole fragment for inline apps.
+  pub body: String,
+  /// Extra tags for `<head>`.
+  pub head: String,
+  /// Handed to the client as `window.RWS_PAGE.data`, e.g. for hydration.
+  pub data: Value,
+  pub status: Option<u16>,
+  /// Keeps the page out of the render cache.
+  pub nocache: bool,
+}
+
+/// The library packages of the mount's section (see `libs`), dependencies
+/// first.  When they don't resolve the page gets them in directory order
+/// and the problem is logged.
+fn libraries(mount: &Mount) -> Vec<Arc<Library>> {
+  let node_modules = mount.dir.join(mount.section()).join("node_modules");
+  let found = libs::load(&node_modules);
+  libs::resolve(&found).unwrap_or_else(|e| {
+    error!("{}: {}", node_modules.display(), e);
+    found
+  })
+}
+
+/// The HTML document of a page.  The client side entry point,
+/// `views/index.js`, is called as `main(document.body, base, route)`.
+pub fn shell(mount: &Mount, page: Option<&Page>) -> String {
+  let files = format!("{}/", mount.files);
+  let dev = config::get().dev;
+  let libraries = libraries(mount);
+  let mut paths = require_paths(&mount.dir, mount.section());
+  let (lib_paths, map) = libs::require_config(&libraries, dev);
+  for dir in map.keys() {
+    paths.remove(dir);
+  }
+  paths.extend(lib_paths);
+  let config = json!({
+    "baseUrl": files,
+    "paths": paths,
+    "map": {"*": map},
+  });
+  let state = json!({
+    "app": mount.app,
+    "base": mount.base,
+    "route": mount.route,
+    "slot": mount.slot,
+    // Whether the body was rendered on the server and can be hydrated.
+    "rendered": page.is_some(),
+    "data": page.map(|p| p.data.clone()).unwrap_or(Value::Null),
+  });
+  format!(
+    r#"<!DOCTYPE html>
+<html>
+  <head>
+    <meta charset="utf-8">
+    <meta name="viewport" content="width=device-width, initial-scale=1.0">
+    <title>{title}</title>
+    {head}
+    <script src="/.rws/require.js"></script>
+    <script>
+      window.RWS_PAGE = {state};
+      requirejs.config({config});
+    </script>
+    {libs}
+  </head>
+  <body>{body}
+    <script>
+      require(["views/index"], function (view) {{
+        var main = view.default || view;
+        main(document.body, RWS_PAGE.base, RWS_PAGE.route);
+      }});
+    </script>
+  </body>
+</html>
+"#,
+    title = escape_html(&mount.manifest.name),
+    head = page.map(|p| p.head.as_str()).unwrap_or(""),
+    state = script_json(&state),
+    config = script_json(&config),
+    libs = libs::tags(&libraries, &files, dev),
+    body = page.map(|p| p.body.as_str()).unwrap_or(""),
+  )
+}
+
+/// A route for the isolate to render.
+pub struct PageRequest {
+  pub host: String,
+  pub app: String,
+  pub base: String,
+  pub route: String,
+  pub query: String,
+  /// Render a fragment rather than a document body.
+  pub inline: bool,
+  /// The nested route being rendered, if any.
+  pub slot: Option<Resolved>,
+  /// Rendered when the app has no view set up in the isolate.
+  pub module: Option<ServerModule>,
+  pub token: Option<Token>,
+  /// `None` when the app has no view in the isolate.
+  pub reply: oneshot::Sender<Result<Option<Page>, String>>,
+}
+
+/// Starts taking the page renders of an app; a later call replaces the
+/// earlier listener.
+pub fn listen(context: &AppContext) -> mpsc::UnboundedReceiver<PageRequest> {
+  PAGES.listen(context)
+}
+
+#[derive(Debug)]
+enum RenderError {
+  Timeout,
+  Failed(String),
+}
+
+fn cached(key: &CacheKey, version: Option<u64>) -> Option<Page> {
+  let cache = CACHE.lock().unwrap();
+  let entry = cache.get(key)?;
+  if entry.version == version && entry.expires > Instant::now() {
+    Some(entry.page.clone())
+  } else {
+    None
+  }
+}
+
+fn store(key: CacheKey, page: &Page, version: Option<u64>) {
+  let config = &config::get().render;
+  if config.cache_secs == 0 || page.nocache || page.status.unwrap_or(200) != 200
+  {
+    return;
+  }
+  let mut cache = CACHE.lock().unwrap();
+  if cache.len() >= config.cache_entries {
+    let now = Instant::now();
+    cache.retain(|_, entry| entry.expires > now);
+    if cache.len() >= config.cache_entries {
+      cache.clear();
+    }
+  }
+  let entry = CachedPage {
+    page: page.clone(),
+    version,
+    expires: Instant::now() + Duration::from_secs(config.cache_secs),
+  };
+  cache.insert(key, entry);
+}
+
+async fn render(
+  mount: &Mount,
+  token: Option<Token>,
+  query: &str,
+  inline: bool,
+) -> Result<Option<Page>, RenderError> {
+  let module = server_module(&mount.dir, mount.section());
+  let version = module.as_ref().map(|m| m.version);
+  let key = (
+    mount.host.clone(),
+    mount.app.clone(),
+    mount.route.clone(),
+    query.to_string(),
+    inline,
+  );
+  // Pages of signed in users may be personal.
+  if token.is_none() {
+    if let Some(page) = cached(&key, version) {
+      return Ok(Some(page));
+    }
+  }
+
+  let (reply, result) = oneshot::channel();
+  let request = PageRequest {
+    host: mount.host.clone(),
+    app: mount.app.clone(),
+    base: mount.base.clone(),
+    route: mount.route.clone(),
+    query: query.to_string(),
+    inline,
+    slot: mount.slot.clone(),
+    module,
+    token: token.clone(),
+    reply,
+  };
+  let context = AppContext::new(&mount.host, &mount.app);
+  if PAGES.send(&context, request).is_err() {
+    return Ok(None);
+  }
+
+  let timeout = Duration::from_millis(config::get().render.timeout_ms);
+  let page = match tokio::time::timeout(timeout, result).await {
+    Ok(Ok(Ok(page))) => page,
+    Ok(Ok(Err(e))) => return Err(RenderError::Failed(e)),
+    Ok(Err(_)) => {
+      return Err(RenderError::Failed("renderer went away".to_string()))
+    }
+    Err(_) => {
+      if stuck(&context).await {
+        warn!("{} on {} is stuck, terminating it", mount.app, mount.host);
+        apps::terminate(&context);
+      }
+      return Err(RenderError::Timeout);
+    }
+  };
+  if let (Some(page), None) = (&page, &token) {
+    store(key, page, version);
+  }
+  Ok(page)
+}
+
+/// Whether the isolate of `context` is stuck, say in a view looping in
+/// `renderToString`; it is probed with a render for no app.
+async fn stuck(context: &AppContext) -> bool {
+  let (reply, result) = oneshot::channel();
+  let probe = PageRequest {
+    host: context.host.clone(),
+    app: String::new(),
+    base: String::new(),
+    route: String::new(),
+    query: String::new(),
+    inline: false,
+    slot: None,
+    module: None,
+    token: None,
+    reply,
+  };
+  PAGES.stuck(context, probe, result).await
+}
+
+/// Renders a page to inline in the shell; the shell alone is served when
+/// that fails.
+async fn render_page(mount: &Mount, req: &HttpRequest) -> Option<Page> {
+  let token = accounts::request_session(req);
+  match render(mount, token, req.query_string(), false).await {
+    Ok(page) => page,
+    Err(RenderError::Timeout) => {
+      warn!("rendering {}{} timed out", mount.base, mount.route);
+      None
+    }
+    Err(RenderError::Failed(e)) => {
+      error!("rendering {}{} failed: {}", mount.base, mount.route, e);
+      None
+    }
+  }
+}
+
+pub fn html(status: Option<u16>, body: String) -> HttpResponse {
+  let status = status
+    .and_then(|s| actix_web::http::StatusCode::from_u16(s).ok())
+    .unwrap_or(actix_web::http::StatusCode::OK);
+  HttpResponse::build(status)
+    .content_type("text/html; charset=utf-8")
+    .body(body)
+}
+
+/// Answers a request for a mounted app.
+pub async fn serve(mount: Mount, req: HttpRequest) -> HttpResponse {
+  let render_type = mount.manifest.render_type;
+  if render_type == RenderType::ControlPanel {
+    return HttpResponse::NotFound().finish();
+  }
+  if req.method() != Method::GET && req.method() != Method::HEAD {
+    return HttpResponse::MethodNotAllowed().finish();
+  }
+
+  if let Some(path) = static_file(&mount.dir, mount.section(), &mount.route) {
+    return match NamedFile::open(path) {
+      Ok(file) => file
+        .use_last_modified(true)
+        .into_response(&req)
+        .unwrap_or_else(HttpResponse::from_error),
+      Err(_) => HttpResponse::NotFound().finish(),
+    };
+  }
+  if mount.base.starts_with(APP_FILES) {
+    return HttpResponse::NotFound().finish();
+  }
+
+  match render_type {
+    RenderType::SinglePage | RenderType::MultiPage => {
+      // Single page apps are only rendered here from a server build.
+      let page = if render_type == RenderType::MultiPage
+        || server_module(&mount.dir, mount.section()).is_some()
+      {
+        render_page(&mount, &req).await
+      } else {
+        None
+      };
+      match page {
+        Some(page) => html(page.status, shell(&mount, Some(&page))),
+        None => html(None, shell(&mount, None)),
+      }
+    }
+    RenderType::Inline => {
+      let token = accounts::request_session(&req);
+      inline_response(&mount, token, req.query_string()).await
+    }
+    RenderType::ControlPanel => unreachable!(),
+  }
+}
+
+async fn inline_response(
+  mount: &Mount,
+  token: Option<Token>,
+  query: &str,
+) -> HttpResponse {
+  match render(mount, token, query, true).await {
+    Ok(Some(page)) => html(page.status, page.body),
+    Ok(None) => HttpResponse::NotFound().finish(),
+    Err(RenderError::Timeout) => HttpResponse::GatewayTimeout().finish(),
+    Err(RenderError::Failed(e)) => {
+      error!("rendering {}{} failed: {}", mount.base, mount.route, e);
+      HttpResponse::InternalServerError().finish()
+    }
+  }
+}
+
+/// `app` as mounted on `host`, at `route`.
+fn app_mount(host: &str, app: &str, route: &str) -> Option<Mount> {
+  let config = config::get();
+  let host_config = config.host(host)?;
+  let mount = host_config.apps.iter().find(|m| m.app == app)?;
+  let (manifest, dir) = load_manifest(&host_config.name, app)?;
+  Some(Mount {
+    host: host_config.name.clone(),
+    app: app.to_string(),
+    base: trim_base(&mount.path).to_string(),
+    files: trim_base(&mount.path).to_string(),
+    route: route.to_string(),
+    manifest,
+    dir,
+    slot: None,
+  })
+}
+
+/// The fragment `app` renders for `route`, for `RWS.views.inline` in
+/// another app's isolate.  It is rendered for a signed out visitor, and is
+/// empty when `app` isn't mounted on `host` or has nothing to show.
+pub async fn inline(
+  host: &str,
+  app: &str,
+  route: &str,
+  query: &str,
+) -> Result<String, String> {
+  let mount = match app_mount(host, app, route) {
+    Some(mount) => mount,
+    None => return Ok(String::new()),
+  };
+  match render(&mount, None, query, true).await {
+    Ok(page) => Ok(page.map(|p| p.body).unwrap_or_default()),
+    Err(RenderError::Timeout) => Err(format!("rendering {} timed out", app)),
+    Err(RenderError::Failed(e)) => Err(e),
+  }
+}
+
+pub async fn require_js() -> HttpResponse {
+  HttpResponse::Ok()
+    .content_type("application/javascript")
+    .header("cache-control", "public, max-age=86400")
+    .body(REQUIRE_JS)
+}
+
+#[test]
+fn render_mount_test() {
+  let hosts: Vec<HostConfig> = serde_json::from_value(json!([{
+    "name": "example.com",
+    "apps": [
+      {"app": "site"},
+      {"app": "shop", "path": "/shop/"},
+    ],
+  }]))
+  .unwrap();
+  let find = |path: &str| {
+    find_mount(&hosts, "example.com:8080", path)
+      .map(|(_, mount, route)| (mount.app.as_str(), route))
+  };
+  assert_eq!(find("/"), Some(("site", "/".to_string())));
+  assert_eq!(find("/about"), Some(("site", "/about".to_string())));
+  assert_eq!(find("/shop"), Some(("shop", "/".to_string())));
+  assert_eq!(find("/shop/cart"), Some(("shop", "/cart".to_string())));
+  assert_eq!(find("/shopping"), Some(("site", "/shopping".to_string())));
+  assert!(find_mount(&hosts, "other.com", "/").is_none());
+
+  let dir = tempfile::TempDir::new().unwrap();
+  let client = dir.path().join("target/client/html_public/views");
+  fs::create_dir_all(&client).unwrap();
+  fs::write(client.join("index.js"), "").unwrap();
+  fs::create_dir_all(dir.path().join("html_public/node_modules/fb::react"))
+    .unwrap();
+  assert!(static_file(dir.path(), "html_public", "/views/index.js").is_some());
+  assert!(static_file(dir.path(), "html_public", "/views/other.js").is_none());
+  assert!(
+    static_file(dir.path(), "html_public", "/views/../views/index.js")
+      .is_none()
+  );
+  assert_eq!(
+    Value::Object(require_paths(dir.path(), "html_public")),
+    json!({"fb::react": "node_modules/fb::react/index"})
+  );
+  assert_eq!(script_json(&json!("</script>")), r#""\u003c/script\u003e""#);
+
+  assert!(server_module(dir.path(), "html_public").is_none());
+  let server = dir.path().join("target/server/html_public/views");
+  fs::create_dir_all(&server).unwrap();
+  fs::write(server.join("index.js"), "").unwrap();
+  let module = server_module(dir.path(), "html_public").unwrap();
+  assert_eq!(module.root, dir.path().join("html_public"));
+  assert!(module.version > 0);
+}
+
+#[test]
+fn render_test() {
+  let dir = tempfile::TempDir::new().unwrap();
+  let write = |path: &str, code: &str| {
+    let path = dir.path().join(path);
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::write(path, code).unwrap();
+  };
+  write(
+    "target/server/html_public/views/index.js",
+    r#"define(["exports", "fb::react"], function (exports, React) {
+      exports.default = function (props) {
+        return React.createElement("main", null, "route " + props.route);
+      };
+    });"#,
+  );
+  write(
+    "html_public/node_modules/fb::react/index.js",
+    r#"define([], function () {
+      return {
+        createElement: function (type, props, text) {
+          if (typeof type == "function") return type(props);
+          return { type: type, text: text };
+        },
+      };
+    });"#,
+  );
+  write(
+    "html_public/node_modules/fb::react-dom/server.js",
+    r#"define([], function () {
+      return {
+        renderToString: function (e) {
+          return "<" + e.type + ">" + e.text + "</" + e.type + ">";
+        },
+      };
+    });"#,
+  );
+
+  let mount = Mount {
+    host: "render.test".to_string(),
+    app: "site".to_string(),
+    base: String::new(),
+    files: String::new(),
+    route: "/about".to_string(),
+    manifest: Manifest {
+      name: "site".to_string(),
+      render_type: RenderType::MultiPage,
+      ..Manifest::default()
+    },
+    dir: dir.path().to_path_buf(),
+    slot: None,
+  };
+  let context = AppContext::new(&mount.host, &mount.app);
+  let mut app = crate::apps::start(context, mount.dir.clone());
+
+  let mut runtime = tokio::runtime::Builder::new()
+    .basic_scheduler()
+    .enable_all()
+    .build()
+    .unwrap();
+  let page = runtime.block_on(async {
+    // Until the isolate is up and listening.
+    for _ in 0..100 {
+      if let Some(page) = render(&mount, None, "", false).await.unwrap() {
+        return page;
+      }
+      tokio::time::delay_for(Duration::from_millis(100)).await;
+    }
+    panic!("the isolate never took the page");
+  });
+  app.stop();
+
+  assert_eq!(page.body, "<main>route /about</main>");
+  let html = shell(&mount, Some(&page));
+  assert!(html.contains("<body><main>route /about</main>"));
+  assert!(html.contains(r#""rendered":true"#));
+}
@@ -0,0 +1,460 @@
+//! Nested routes.
+//!
+//! Apps declare named route slots with typed arguments in the manifest's
+//! `nested_routes`:
+//!
+//! ```text
+//! "nested_routes": {"main-page": {"args": ["template:string"]}}
+//! ```
+//!
+//! A slot is mounted at a path pattern, by the host (`hosts[].routes` in
+//! config.json) or by another app at runtime (`RWS.routes.mount`).  `:name`
+//! segments of the pattern take arguments from the URL and `args` of the
+//! mount fix the others; together they must supply exactly the slot's
+//! arguments, each of its declared type.  `url_for` goes the other way.
+//!
+//! The host's mounts apply to every path.  An app can only mount slots
+//! under its own mount path, and its mounts only apply to paths that belong
+//! to it, so it can't take over another app's pages.
+
+use crate::config::{self, RouteMount};
+use crate::manifest::{Manifest, OptionType, RouteArg, RouteSpec};
+use crate::render;
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet};
+use serde_derive::Serialize;
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::RwLock;
+
+/// Characters escaped in a path segment.
+const SEGMENT: &AsciiSet = &percent_encoding::NON_ALPHANUMERIC
+  .remove(b'-')
+  .remove(b'_')
+  .remove(b'.')
+  .remove(b'~');
+
+lazy_static! {
+  /// Mounts made at runtime, by host, with the app that made them.
+  static ref MOUNTS: RwLock<HashMap<String, Vec<(String, RouteMount)>>> =
+    RwLock::new(HashMap::new());
+}
+
+#[derive(Debug, PartialEq)]
+pub enum RouteError {
+  UnknownRoute {
+    app: String,
+    route: String,
+  },
+  MissingArg(String),
+  UnknownArg(String),
+  BadArg {
+    name: String,
+    expected: OptionType,
+  },
+  /// No mount of the route matches the arguments.
+  NotMounted {
+    app: String,
+    route: String,
+  },
+  /// An app tried to mount a slot outside its own mount path.
+  OutsideApp {
+    app: String,
+    path: String,
+  },
+}
+
+impl fmt::Display for RouteError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      RouteError::UnknownRoute { app, route } => {
+        write!(f, "{} has no nested route \"{}\"", app, route)
+      }
+      RouteError::MissingArg(name) => {
+        write!(f, "missing argument \"{}\"", name)
+      }
+      RouteError::UnknownArg(name) => {
+        write!(f, "unknown argument \"{}\"", name)
+      }
+      RouteError::BadArg { name, expected } => {
+        let kind = format!("{:?}", expected).to_lowercase();
+        write!(f, "argument \"{}\" must be {}", name, kind)
+      }
+      RouteError::NotMounted { app, route } => {
+        write!(f, "route \"{}\" of {} is not mounted", route, app)
+      }
+      RouteError::OutsideApp { app, path } => {
+        write!(f, "{} can't mount routes at {}", app, path)
+      }
+    }
+  }
+}
+
+impl std::error::Error for RouteError {}
+
+/// A request path matched to a route slot.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Resolved {
+  pub app: String,
+  pub route: String,
+  pub args: Map<String, Value>,
+}
+
+/// Parses a URL segment as an argument of the given type.
+fn coerce(arg: &RouteArg, text: &str) -> Result<Value, RouteError> {
+  let bad = || RouteError::BadArg {
+    name: arg.name.clone(),
+    expected: arg.kind,
+  };
+  let value = match arg.kind {
+    OptionType::String => Value::String(text.to_string()),
+    OptionType::Integer => json!(text.parse::<i64>().map_err(|_| bad())?),
+    OptionType::Number => {
+      let n = text.parse::<f64>().map_err(|_| bad())?;
+      json!(serde_json::Number::from_f64(n).ok_or_else(bad)?)
+    }
+    OptionType::Boolean => match text {
+      "true" => Value::Bool(true),
+      "false" => Value::Bool(false),
+      _ => return Err(bad()),
+    },
+    OptionType::Object | OptionType::Array => {
+      let value: Value = serde_json::from_str(text).map_err(|_| bad())?;
+      if !arg.kind.accepts(&value) {
+        return Err(bad());
+      }
+      value
+    }
+  };
+  Ok(value)
+}
+
+/// Checks that `args` are exactly the slot's arguments, each of its type.
+pub fn check_args(
+  spec: &RouteSpec,
+  args: &Map<String, Value>,
+) -> Result<(), RouteError> {
+  for arg in &spec.args {
+    match args.get(&arg.name) {
+      Some(value) if arg.kind.accepts(value) => {}
+      Some(_) => {
+        return Err(RouteError::BadArg {
+          name: arg.name.clone(),
+          expected: arg.kind,
+        })
+      }
+      None => return Err(RouteError::MissingArg(arg.name.clone())),
+    }
+  }
+  match args
+    .keys()
+    .find(|k| !spec.args.iter().any(|a| &a.name == *k))
+  {
+    Some(extra) => Err(RouteError::UnknownArg(extra.clone())),
+    None => Ok(()),
+  }
+}
+
+fn segments(path: &str) -> Vec<&str> {
+  path.split('/').filter(|s| !s.is_empty()).collect()
+}
+
+/// The `:name` captures of `pattern` in `path`, percent-decoded.
+fn captures(pattern: &str, path: &str) -> Option<Vec<(String, String)>> {
+  let pattern = segments(pattern);
+  let path = segments(path);
+  if pattern.len() != path.len() {
+    return None;
+  }
+  let mut found = Vec::new();
+  for (expected, actual) in pattern.iter().zip(path) {
+    if expected.starts_with(':') {
+      let value = percent_decode_str(actual).decode_utf8().ok()?;
+      found.push((expected[1..].to_string(), value.into_owned()));
+    } else if *expected != actual {
+      return None;
+    }
+  }
+  Some(found)
+}
+
+/// Checks that a mount supplies the slot's arguments.
+pub fn check_mount(
+  mount: &RouteMount,
+  spec: &RouteSpec,
+) -> Result<(), RouteError> {
+  let mut args = mount.args.clone();
+  for segment in segments(&mount.path) {
+    if segment.starts_with(':') {
+      let name = &segment[1..];
+      let arg = spec
+        .args
+        .iter()
+        .find(|a| a.name == name)
+        .ok_or_else(|| RouteError::UnknownArg(name.to_string()))?;
+      // Stands in for the value the URL will carry.
+      let sample = match arg.kind {
+        OptionType::String => json!(""),
+        OptionType::Integer | OptionType::Number => json!(0),
+        OptionType::Boolean => json!(false),
+        OptionType::Object => json!({}),
+        OptionType::Array => json!([]),
+      };
+      args.insert(name.to_string(), sample);
+    }
+  }
+  check_args(spec, &args)
+}
+
+/// Matches `path` against `mounts`.  `Some(Err)` means a mount's pattern
+/// matched but the URL's arguments didn't fit the slot.
+pub fn resolve_in<F>(
+  mounts: &[RouteMount],
+  path: &str,
+  spec_of: F,
+) -> Option<Result<Resolved, RouteError>>
+where
+  F: Fn(&str, &str) -> Option<RouteSpec>,
+{
+  for mount in mounts {
+    let captured = match captures(&mount.path, path) {
+      Some(captured) => captured,
+      None => continue,
+    };
+    let spec = match spec_of(&mount.app, &mount.route) {
+      Some(spec) => spec,
+      None => continue,
+    };
+    let mut args = mount.args.clone();
+    for (name, text) in captured {
+      let arg = match spec.args.iter().find(|a| a.name == name) {
+        Some(arg) => arg,
+        None => return Some(Err(RouteError::UnknownArg(name))),
+      };
+      match coerce(arg, &text) {
+        Ok(value) => args.insert(name, value),
+        Err(e) => return Some(Err(e)),
+      };
+    }
+    return Some(check_args(&spec, &args).map(|()| Resolved {
+      app: mount.app.clone(),
+      route: mount.route.clone(),
+      args,
+    }));
+  }
+  None
+}
+
+fn segment_text(value: &Value) -> String {
+  match value {
+    Value::String(s) => s.clone(),
+    other => other.to_string(),
+  }
+}
+
+/// The path of the first mount of `app`'s `route` that fits `args`.
+pub fn url_in(
+  mounts: &[RouteMount],
+  app: &str,
+  route: &str,
+  spec: &RouteSpec,
+  args: &Map<String, Value>,
+) -> Result<String, RouteError> {
+  check_args(spec, args)?;
+  let not_mounted = || RouteError::NotMounted {
+    app: app.to_string(),
+    route: route.to_string(),
+  };
+  mounts
+    .iter()
+    .filter(|m| m.app == app && m.route == route)
+    .find(|m| m.args.iter().all(|(k, v)| args.get(k) == Some(v)))
+    .map(|mount| {
+      let path: Vec<String> = segments(&mount.path)
+        .into_iter()
+        .map(|segment| {
+          if segment.starts_with(':') {
+            let text = segment_text(&args[&segment[1..]]);
+            utf8_percent_encode(&text, SEGMENT).to_string()
+          } else {
+            segment.to_string()
+          }
+        })
+        .collect();
+      format!("/{}", path.join("/"))
+    })
+    .ok_or_else(not_mounted)
+}
+
+/// A route slot of an app installed on `host`.
+pub fn spec(host: &str, app: &str, route: &str) -> Option<RouteSpec> {
+  let path = config::app_dir(host, app).join("manifest.json");
+  let manifest = Manifest::load(&path).ok()?;
+  manifest.route_specs().into_iter().find(|s| s.name == route)
+}
+
+/// The host's own mounts followed by those made at runtime.
+pub fn mounts(host: &str) -> Vec<RouteMount> {
+  let config = config::get();
+  let mut mounts = match config.host(host) {
+    Some(host_config) => host_config.routes.clone(),
+    None => Vec::new(),
+  };
+  if let Some(more) = MOUNTS.read().unwrap().get(host) {
+    mounts.extend(more.iter().map(|(_, mount)| mount.clone()));
+  }
+  mounts
+}
+
+/// The app mounted at `path` on `host`.
+fn app_at(host: &str, path: &str) -> Option<String> {
+  let config = config::get();
+  render::find_mount(&config.hosts, host, path)
+    .map(|(_, mount, _)| mount.app.clone())
+}
+
+/// The part of a path pattern before its first `:name` segment.
+fn fixed_prefix(pattern: &str) -> String {
+  let fixed: Vec<&str> = pattern
+    .split('/')
+    .take_while(|segment| !segment.starts_with(':'))
+    .collect();
+  let prefix = fixed.join("/");
+  if prefix.is_empty() {
+    "/".to_string()
+  } else {
+    prefix
+  }
+}
+
+/// Mounts a route at runtime for `owner`, the app making the mount, after
+/// checking it against the route app's manifest.
+pub fn mount(
+  host: &str,
+  owner: &str,
+  mount: RouteMount,
+) -> Result<(), RouteError> {
+  if app_at(host, &fixed_prefix(&mount.path)).as_deref() != Some(owner) {
+    return Err(RouteError::OutsideApp {
+      app: owner.to_string(),
+      path: mount.path,
+    });
+  }
+  let spec = spec(host, &mount.app, &mount.route).ok_or_else(|| {
+    RouteError::UnknownRoute {
+      app: mount.app.clone(),
+      route: mount.route.clone(),
+    }
+  })?;
+  check_mount(&mount, &spec)?;
+  let mut all = MOUNTS.write().unwrap();
+  let list = all.entry(host.to_string()).or_default();
+  list.retain(|(_, m)| m.path != mount.path);
+  list.push((owner.to_string(), mount));
+  Ok(())
+}
+
+/// The host's mounts and those the app `path` belongs to made at runtime.
+pub fn resolve(host: &str, path: &str) -> Option<Result<Resolved, RouteError>> {
+  let config = config::get();
+  let mut mounts = match config.host(host) {
+    Some(host_config) => host_config.routes.clone(),
+    None => Vec::new(),
+  };
+  if let (Some(owner), Some(more)) =
+    (app_at(host, path), MOUNTS.read().unwrap().get(host))
+  {
+    let owned = more.iter().filter(|(app, _)| *app == owner);
+    mounts.extend(owned.map(|(_, mount)| mount.clone()));
+  }
+  resolve_in(&mounts, path, |app, route| spec(host, app, route))
+}
+
+pub fn url_for(
+  host: &str,
+  app: &str,
+  route: &str,
+  args: &Map<String, Value>,
+) -> Result<String, RouteError> {
+  let spec =
+    spec(host, app, route).ok_or_else(|| RouteError::UnknownRoute {
+      app: app.to_string(),
+      route: route.to_string(),
+    })?;
+  url_in(&mounts(host), app, route, &spec, args)
+}
+
+#[test]
+fn routes_test() {
+  let spec = RouteSpec {
+    name: "main-page".to_string(),
+    args: vec![
+      RouteArg::parse("template:string").unwrap(),
+      RouteArg::parse("page:integer").unwrap(),
+    ],
+  };
+  let mounts: Vec<RouteMount> = serde_json::from_value(json!([
+    {"path": "/pages/:template/:page", "app": "my-app", "route": "main-page"},
+    {"path": "/home", "app": "my-app", "route": "main-page",
+     "args": {"template": "home", "page": 1}},
+  ]))
+  .unwrap();
+  let spec_of = |app: &str, route: &str| {
+    if app == "my-app" && route == "main-page" {
+      Some(spec.clone())
+    } else {
+      None
+    }
+  };
+  let args = |value: Value| value.as_object().unwrap().clone();
+
+  assert_eq!(
+    resolve_in(&mounts, "/pages/a%20b/2", spec_of),
+    Some(Ok(Resolved {
+      app: "my-app".to_string(),
+      route: "main-page".to_string(),
+      args: args(json!({"template": "a b", "page": 2})),
+    }))
+  );
+  assert_eq!(
+    resolve_in(&mounts, "/home/", spec_of)
+      .unwrap()
+      .unwrap()
+      .args,
+    args(json!({"template": "home", "page": 1}))
+  );
+  assert_eq!(
+    resolve_in(&mounts, "/pages/x/two", spec_of),
+    Some(Err(RouteError::BadArg {
+      name: "page".to_string(),
+      expected: OptionType::Integer,
+    }))
+  );
+  assert_eq!(resolve_in(&mounts, "/pages/x", spec_of), None);
+
+  let url =
+    |value: Value| url_in(&mounts, "my-app", "main-page", &spec, &args(value));
+  assert_eq!(
+    url(json!({"template": "a b", "page": 3})),
+    Ok("/pages/a%20b/3".to_string())
+  );
+  assert_eq!(
+    url(json!({"template": "home", "page": 1})),
+    Ok("/pages/home/1".to_string())
+  );
+  assert_eq!(
+    url(json!({"template": "x"})),
+    Err(RouteError::MissingArg("page".to_string()))
+  );
+
+  let bad: RouteMount = serde_json::from_value(
+    json!({"path": "/p/:nope", "app": "my-app", "route": "main-page"}),
+  )
+  .unwrap();
+  assert_eq!(
+    check_mount(&bad, &spec),
+    Err(RouteError::UnknownArg("nope".to_string()))
+  );
+  assert!(check_mount(&mounts[0], &spec).is_ok());
+  assert!(check_mount(&mounts[1], &spec).is_ok());
+}
@@ -0,0 +1,360 @@
+//! In-process full-text index used for component search.
+//!
+//! Documents are tokenized into lowercase words, stored in an inverted index
+//! with word positions, and ranked with rules modeled on MeiliSearch's
+//! defaults: number of matched query words, then typo count, then word
+//! proximity, then BM25 relevance.  Nothing here talks to an external daemon.
+
+use serde_json::Value;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+const BM25_K1: f64 = 1.2;
+const BM25_B: f64 = 0.75;
+
+/// Splits text into lowercase alphanumeric words.
+pub fn tokenize(text: &str) -> Vec<String> {
+  text
+    .split(|c: char| !c.is_alphanumeric())
+    .filter(|word| !word.is_empty())
+    .map(|word| word.to_lowercase())
+    .collect()
+}
+
+/// Number of typos tolerated for a query word, following MeiliSearch:
+/// none below 5 characters, one below 9, two otherwise.
+fn allowed_typos(word: &str) -> usize {
+  match word.chars().count() {
+    0..=4 => 0,
+    5..=8 => 1,
+    _ => 2,
+  }
+}
+
+/// Levenshtein distance between `a` and `b`, or `None` once it exceeds `max`.
+fn bounded_distance(a: &str, b: &str, max: usize) -> Option<usize> {
+  let a: Vec<char> = a.chars().collect();
+  let b: Vec<char> = b.chars().collect();
+  if (a.len() as isize - b.len() as isize).abs() as usize > max {
+    return None;
+  }
+
+  let mut prev: Vec<usize> = (0..=b.len()).collect();
+  for i in 1..=a.len() {
+    let mut row = vec![i; b.len() + 1];
+    let mut row_min = row[0];
+    for j in 1..=b.len() {
+      let cost = if a[i - 1] == b[j - 1] { 0 } else { 1 };
+      row[j] = (prev[j] + 1).min(row[j - 1] + 1).min(prev[j - 1] + cost);
+      row_min = row_min.min(row[j]);
+    }
+    if row_min > max {
+      return None;
+    }
+    prev = row;
+  }
+
+  let distance = prev[b.len()];
+  if distance <= max {
+    Some(distance)
+  } else {
+    None
+  }
+}
+
+/// Collects every string found in a component value, in document order.
+fn collect_text(value: &Value, out: &mut String) {
+  match value {
+    Value::String(s) => {
+      out.push_str(s);
+      out.push(' ');
+    }
+    Value::Array(items) => items.iter().for_each(|v| collect_text(v, out)),
+    Value::Object(map) => map.values().for_each(|v| collect_text(v, out)),
+    Value::Number(n) => {
+      out.push_str(&n.to_string());
+      out.push(' ');
+    }
+    _ => {}
+  }
+}
+
+#[derive(Debug, Clone)]
+pub struct SearchOptions {
+  pub limit: usize,
+  pub offset: usize,
+  /// Match query words that are a small edit distance away.
+  pub typo_tolerance: bool,
+  /// Treat the last query word as a prefix, for search-as-you-type.
+  pub prefix_last: bool,
+}
+
+impl Default for SearchOptions {
+  fn default() -> Self {
+    SearchOptions {
+      limit: 20,
+      offset: 0,
+      typo_tolerance: true,
+      prefix_last: true,
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchHit {
+  pub id: String,
+  pub score: f64,
+}
+
+#[derive(Default)]
+struct Document {
+  length: usize,
+  terms: HashSet<String>,
+}
+
+/// Ranking key for a candidate document; compared field by field.
+struct Rank {
+  matched: usize,
+  typos: usize,
+  proximity: usize,
+  relevance: f64,
+}
+
+impl Rank {
+  fn cmp(&self, other: &Rank) -> Ordering {
+    other
+      .matched
+      .cmp(&self.matched)
+      .then(self.typos.cmp(&other.typos))
+      .then(self.proximity.cmp(&other.proximity))
+      .then(
+        other
+          .relevance
+          .partial_cmp(&self.relevance)
+          .unwrap_or(Ordering::Equal),
+      )
+  }
+}
+
+#[derive(Default)]
+pub struct SearchIndex {
+  /// word -> document id -> positions of the word in that document
+  postings: BTreeMap<String, HashMap<String, Vec<usize>>>,
+  docs: HashMap<String, Document>,
+  total_length: usize,
+}
+
+impl SearchIndex {
+  pub fn new() -> Self {
+    SearchIndex::default()
+  }
+
+  pub fn len(&self) -> usize {
+    self.docs.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.docs.is_empty()
+  }
+
+  /// Indexes the text of `value` under `id`, replacing any previous version.
+  pub fn upsert(&mut self, id: &str, value: &Value) {
+    let mut text = String::new();
+    collect_text(value, &mut text);
+    self.upsert_text(id, &text);
+  }
+
+  pub fn upsert_text(&mut self, id: &str, text: &str) {
+    self.remove(id);
+
+    let words = tokenize(text);
+    let mut doc = Document {
+      length: words.len(),
+      terms: HashSet::new(),
+    };
+    for (position, word) in words.into_iter().enumerate() {
+      self
+        .postings
+        .entry(word.clone())
+        .or_insert_with(HashMap::new)
+        .entry(id.to_string())
+        .or_insert_with(Vec::new)
+        .push(position);
+      doc.terms.insert(word);
+    }
+    self.total_length += doc.length;
+    self.docs.insert(id.to_string(), doc);
+  }
+
+  pub fn remove(&mut self, id: &str) -> bool {
+    let doc = match self.docs.remove(id) {
+      Some(doc) => doc,
+      None => return false,
+    };
+    self.total_length -= doc.length;
+    for term in doc.terms {
+      let now_empty = match self.postings.get_mut(&term) {
+        Some(docs) => {
+          docs.remove(id);
+          docs.is_empty()
+        }
+        None => false,
+      };
+      if now_empty {
+        self.postings.remove(&term);
+      }
+    }
+    true
+  }
+
+  /// Indexed words that match `word`, with the number of typos each costs.
+  fn expand(
+    &self,
+    word: &str,
+    is_last: bool,
+    opts: &SearchOptions,
+  ) -> Vec<(&String, usize)> {
+    let max_typos = if opts.typo_tolerance {
+      allowed_typos(word)
+    } else {
+      0
+    };
+    let mut found = Vec::new();
+
+    if max_typos == 0 && !(is_last && opts.prefix_last) {
+      if let Some((term, _)) = self.postings.get_key_value(word) {
+        found.push((term, 0));
+      }
+      return found;
+    }
+
+    for term in self.postings.keys() {
+      if is_last && opts.prefix_last && term.starts_with(word) {
+        found.push((term, 0));
+      } else if let Some(typos) = bounded_distance(word, term, max_typos) {
+        found.push((term, typos));
+      }
+    }
+    found
+  }
+
+  fn idf(&self, term: &str) -> f64 {
+    let n = self.docs.len() as f64;
+    let df = self.postings.get(term).map(|d| d.len()).unwrap_or(0) as f64;
+    ((n - df + 0.5) / (df + 0.5) + 1.0).ln()
+  }
+
+  pub fn search(&self, query: &str, opts: &SearchOptions) -> Vec<SearchHit> {
+    let words = tokenize(query);
+    if words.is_empty() || self.docs.is_empty() {
+      return Vec::new();
+    }
+    let avg_length = self.total_length as f64 / self.docs.len() as f64;
+
+    // document id -> per query word: (typos, positions, bm25 contribution)
+    let mut candidates: HashMap<
+      &String,
+      Vec<Option<(usize, Vec<usize>, f64)>>,
+    > = HashMap::new();
+
+    for (i, word) in words.iter().enumerate() {
+      let is_last = i + 1 == words.len();
+      for (term, typos) in self.expand(word, is_last, opts) {
+        let idf = self.idf(term);
+        for (id, positions) in &self.postings[term] {
+          let doc_length = self.docs[id].length as f64;
+          let tf = positions.len() as f64;
+          let bm25 = idf * (tf * (BM25_K1 + 1.0))
+            / (tf
+              + BM25_K1 * (1.0 - BM25_B + BM25_B * doc_length / avg_length));
+
+          let slots = candidates
+            .entry(id)
+            .or_insert_with(|| vec![None; words.len()]);
+          let better = match &slots[i] {
+            Some((best_typos, _, best_bm25)) => {
+              typos < *best_typos || (typos == *best_typos && bm25 > *best_bm25)
+            }
+            None => true,
+          };
+          if better {
+            slots[i] = Some((typos, positions.clone(), bm25));
+          }
+        }
+      }
+    }
+
+    let mut ranked: Vec<(Rank, &String)> = candidates
+      .into_iter()
+      .map(|(id, slots)| {
+        let matched: Vec<&(usize, Vec<usize>, f64)> =
+          slots.iter().filter_map(|s| s.as_ref()).collect();
+        let rank = Rank {
+          matched: matched.len(),
+          typos: matched.iter().map(|m| m.0).sum(),
+          proximity: proximity(&matched),
+          relevance: matched.iter().map(|m| m.2).sum(),
+        };
+        (rank, id)
+      })
+      .collect();
+    ranked.sort_by(|a, b| a.0.cmp(&b.0).then(a.1.cmp(b.1)));
+
+    ranked
+      .into_iter()
+      .skip(opts.offset)
+      .take(opts.limit)
+      .map(|(rank, id)| SearchHit {
+        id: id.clone(),
+        score: rank.relevance,
+      })
+      .collect()
+  }
+}
+
+/// Sum of the smallest gaps between consecutive matched query words.
+fn proximity(matched: &[&(usize, Vec<usize>, f64)]) -> usize {
+  matched
+    .windows(2)
+    .map(|pair| {
+      let mut best = usize::max_value();
+      for a in &pair[0].1 {
+        for b in &pair[1].1 {
+          let gap = if b > a { b - a } else { a - b + 1 };
+          best = best.min(gap);
+        }
+      }
+      best.min(8)
+    })
+    .sum()
+}
+
+#[test]
+fn search_index_test() {
+  let mut index = SearchIndex::new();
+  index.upsert("1", &json!({"title": "Rust web server", "body": "fast"}));
+  index.upsert("2", &json!({"title": "Deno runtime", "tags": ["web"]}));
+  index.upsert("3", &json!({"title": "Web server written in Rust"}));
+
+  let opts = SearchOptions::default();
+  let ids = |hits: Vec<SearchHit>| -> Vec<String> {
+    hits.into_iter().map(|h| h.id).collect()
+  };
+
+  assert_eq!(ids(index.search("rust server", &opts)), vec!["1", "3"]);
+  // "servr" is one typo away from "server".
+  assert_eq!(ids(index.search("web servr", &opts)), vec!["1", "3", "2"]);
+  // Prefix match on the last word.
+  assert_eq!(ids(index.search("runt", &opts)), vec!["2"]);
+
+  let strict = SearchOptions {
+    typo_tolerance: false,
+    prefix_last: false,
+    ..SearchOptions::default()
+  };
+  assert!(index.search("servr", &strict).is_empty());
+
+  assert!(index.remove("1"));
+  assert_eq!(ids(index.search("rust", &opts)), vec!["3"]);
+  assert_eq!(index.len(), 2);
+}
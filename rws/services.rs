@@ -0,0 +1,238 @@
+//! Service RPC.
+//!
+//! Apps register named services (`RWS.addService`), optionally guarded by
+//! one of their manifest permissions.  Browsers call them with
+//!
+//! ```text
+//! POST /json
+//! {"service": "get_user_by_id", "args": {"id": 22}}
+//! ```
+//!
+//! and get back `{"result": ...}` or `{"error": "..."}`.  The permission is
+//! checked here, before the call reaches the isolate; the isolate gets the
+//! caller's session as `token`.  Calls are handed to the isolate of the app
+//! that registered the service (see `apps`).  A name belongs to the first
+//! app that registers it on a host.
+
+use crate::accounts::{self, Token};
+use crate::apps::{AppContext, Listeners};
+use crate::config;
+use crate::rbac;
+use actix_web::dev::HttpResponseBuilder;
+use actix_web::{web, HttpRequest, HttpResponse};
+use serde_derive::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::RwLock;
+use tokio::sync::{mpsc, oneshot};
+
+/// Host (or `None` for every host) and name.
+type ServiceKey = (Option<String>, String);
+
+lazy_static! {
+  static ref REGISTRY: RwLock<HashMap<ServiceKey, ServiceInfo>> =
+    RwLock::new(HashMap::new());
+  static ref CALLS: Listeners<Call> = Listeners::new();
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ServiceInfo {
+  pub name: String,
+  /// Host the service is served on; `None` serves it on every host.
+  #[serde(default)]
+  pub host: Option<String>,
+  #[serde(default)]
+  pub app: Option<String>,
+  /// Manifest permission of `app` callers need.
+  #[serde(default)]
+  pub permission: Option<String>,
+}
+
+/// Registers `info`, replacing an earlier registration of the same name by
+/// the same app; returns false when another app owns the name.
+pub fn register(info: ServiceInfo) -> bool {
+  let key = (info.host.clone(), info.name.clone());
+  let mut registry = REGISTRY.write().unwrap();
+  if let Some(existing) = registry.get(&key) {
+    if existing.app != info.app {
+      warn!(
+        "service {} is already registered by {:?}",
+        info.name, existing.app
+      );
+      return false;
+    }
+  }
+  registry.insert(key, info);
+  true
+}
+
+/// The service `name` as seen from `host`.
+pub fn get(host: &str, name: &str) -> Option<ServiceInfo> {
+  let registry = REGISTRY.read().unwrap();
+  registry
+    .get(&(Some(host.to_string()), name.to_string()))
+    .or_else(|| registry.get(&(None, name.to_string())))
+    .cloned()
+}
+
+pub fn list() -> Vec<ServiceInfo> {
+  let mut list: Vec<ServiceInfo> =
+    REGISTRY.read().unwrap().values().cloned().collect();
+  list.sort_by(|a, b| (&a.host, &a.name).cmp(&(&b.host, &b.name)));
+  list
+}
+
+/// Whether `token` may call `service` on `host`.
+pub fn allowed(
+  service: &ServiceInfo,
+  host: &str,
+  token: Option<&Token>,
+) -> bool {
+  match &service.permission {
+    Some(permission) => {
+      let app = service.app.as_deref().unwrap_or("");
+      rbac::can(host, token, app, permission)
+    }
+    None => true,
+  }
+}
+
+/// A call on its way to the isolate.
+pub struct Call {
+  pub service: ServiceInfo,
+  pub host: String,
+  pub args: Value,
+  pub token: Option<Token>,
+  pub reply: oneshot::Sender<Result<Value, String>>,
+}
+
+/// Starts taking the calls of an app's services; a later call replaces the
+/// earlier listener.
+pub fn listen(context: &AppContext) -> mpsc::UnboundedReceiver<Call> {
+  CALLS.listen(context)
+}
+
+#[derive(Deserialize)]
+pub struct CallRequest {
+  service: String,
+  #[serde(default)]
+  args: Value,
+}
+
+fn error(mut response: HttpResponseBuilder, message: &str) -> HttpResponse {
+  response.json(json!({ "error": message }))
+}
+
+pub async fn handle(
+  req: HttpRequest,
+  body: web::Json<CallRequest>,
+) -> HttpResponse {
+  let host = req.connection_info().host().to_string();
+  let host = match config::get().host(&host) {
+    Some(host_config) => host_config.name.clone(),
+    None => return error(HttpResponse::NotFound(), "unknown host"),
+  };
+  let token = accounts::request_session(&req);
+  call(&host, token, body.into_inner()).await
+}
+
+/// Makes a call on `host`, the name of a configured host.
+async fn call(
+  host: &str,
+  token: Option<Token>,
+  request: CallRequest,
+) -> HttpResponse {
+  let CallRequest { service, args } = request;
+  let service = match get(host, &service) {
+    Some(service) => service,
+    None => return error(HttpResponse::NotFound(), "unknown service"),
+  };
+
+  if !allowed(&service, host, token.as_ref()) {
+    return match token {
+      None => error(HttpResponse::Unauthorized(), "sign in required"),
+      Some(_) => error(HttpResponse::Forbidden(), "permission denied"),
+    };
+  }
+
+  let context = AppContext::new(
+    service.host.as_deref().unwrap_or(host),
+    service.app.as_deref().unwrap_or(""),
+  );
+  let (reply, result) = oneshot::channel();
+  let call = Call {
+    service,
+    host: host.to_string(),
+    args,
+    token,
+    reply,
+  };
+  if CALLS.send(&context, call).is_err() {
+    let unavailable = HttpResponse::ServiceUnavailable();
+    return error(unavailable, "no app is serving calls");
+  }
+
+  match result.await {
+    Ok(Ok(result)) => HttpResponse::Ok().json(json!({ "result": result })),
+    Ok(Err(message)) => error(HttpResponse::InternalServerError(), &message),
+    Err(_) => error(HttpResponse::InternalServerError(), "service went away"),
+  }
+}
+
+#[test]
+fn services_test() {
+  use actix_web::body::Body;
+  use actix_web::http::StatusCode;
+  use actix_web::{test, App};
+
+  let echo = |app: &str| ServiceInfo {
+    name: "echo".to_string(),
+    host: Some("services.test".to_string()),
+    app: Some(app.to_string()),
+    permission: None,
+  };
+  assert!(register(echo("owner")));
+  assert!(register(echo("owner")));
+  assert!(!register(echo("other")));
+  let service = get("services.test", "echo").unwrap();
+  assert_eq!(service.app.as_deref(), Some("owner"));
+  assert!(get("other.test", "echo").is_none());
+
+  let mut system = actix_rt::System::new("services_test");
+  system.block_on(async {
+    let mut calls = listen(&AppContext::new("services.test", "owner"));
+    actix_rt::spawn(async move {
+      while let Some(call) = calls.recv().await {
+        assert_eq!(call.host, "services.test");
+        let _ = call.reply.send(Ok(call.args));
+      }
+    });
+    let request = |service: &str| CallRequest {
+      service: service.to_string(),
+      args: json!({ "id": 22 }),
+    };
+
+    let response = call("services.test", None, request("echo")).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = match response.body().as_ref() {
+      Some(Body::Bytes(bytes)) => serde_json::from_slice::<Value>(bytes),
+      _ => panic!("no JSON body"),
+    };
+    assert_eq!(body.unwrap(), json!({ "result": { "id": 22 } }));
+
+    let response = call("services.test", None, request("nope")).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    // The host has to be in config.json.
+    let route = web::post().to(handle);
+    let mut app = test::init_service(App::new().route("/json", route)).await;
+    let req = test::TestRequest::post()
+      .uri("/json")
+      .header("host", "unknown.test")
+      .set_json(&json!({ "service": "echo" }))
+      .to_request();
+    let response = test::call_service(&mut app, req).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+  });
+}
@@ -0,0 +1,373 @@
+//! The `/ws` endpoint every host serves for app websockets.
+//!
+//! Each upgraded connection becomes an `AppSocket` actor on the worker thread
+//! that accepted it, and is handed to the isolate of one app through
+//! `CONNECTIONS`: the app named by the `app` query parameter, or else the
+//! one mounted at the root of the host.  Apps see it as a `Connection` with
+//! a stream of incoming messages and an address to send on (see
+//! `ops::ws`).  Pings are answered here, fragmented messages put back
+//! together and idle peers dropped, so app code only deals with whole
+//! messages.  Browsers may only connect from pages on the host's own
+//! domains, as the session cookie would otherwise let any site in.
+
+use crate::accounts::{self, Token};
+use crate::apps::{AppContext, Listeners};
+use crate::config::{self, HostConfig};
+use actix::prelude::*;
+use actix_http::ws::Item;
+use actix_web::http::header;
+use actix_web::{web, Error, HttpRequest, HttpResponse};
+use actix_web_actors::ws;
+use serde_derive::Deserialize;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(15);
+/// Largest message put together from fragments.
+const MAX_MESSAGE_SIZE: usize = 1 << 20;
+
+lazy_static! {
+  static ref CONNECTIONS: Listeners<Connection> = Listeners::new();
+}
+
+/// Starts taking the connections of an app; a later call replaces the
+/// earlier listener.
+pub fn listen(context: &AppContext) -> mpsc::UnboundedReceiver<Connection> {
+  CONNECTIONS.listen(context)
+}
+
+/// Something the peer sent, or the end of the connection.
+#[derive(Debug)]
+pub enum SocketEvent {
+  Text(String),
+  Binary(web::Bytes),
+  Closed(Option<ws::CloseReason>),
+}
+
+/// Sent to an `AppSocket` to write to its peer.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub enum Outgoing {
+  Text(String),
+  Binary(web::Bytes),
+  Close(Option<ws::CloseReason>),
+}
+
+/// A new connection, as handed to the isolate.
+pub struct Connection {
+  pub id: String,
+  pub host: String,
+  pub path: String,
+  pub ip: Option<String>,
+  /// Session of the upgrade request's cookie or bearer token.
+  pub token: Option<Token>,
+  pub socket: Addr<AppSocket>,
+  pub events: mpsc::UnboundedReceiver<SocketEvent>,
+}
+
+pub struct AppSocket {
+  id: String,
+  context: AppContext,
+  host: String,
+  path: String,
+  ip: Option<String>,
+  token: Option<Token>,
+  events: Option<mpsc::UnboundedSender<SocketEvent>>,
+  /// The message being received in fragments: whether it is text, and the
+  /// data so far.
+  fragments: Option<(bool, Vec<u8>)>,
+  last_seen: Instant,
+}
+
+impl AppSocket {
+  fn new(req: &HttpRequest, context: AppContext) -> Self {
+    AppSocket {
+      id: format!("{:016x}", rand::random::<u64>()),
+      context,
+      host: req.connection_info().host().to_string(),
+      path: req.uri().to_string(),
+      ip: req.peer_addr().map(|addr| addr.ip().to_string()),
+      token: accounts::request_session(req),
+      events: None,
+      fragments: None,
+      last_seen: Instant::now(),
+    }
+  }
+
+  fn emit(&mut self, event: SocketEvent, ctx: &mut ws::WebsocketContext<Self>) {
+    let delivered = match &self.events {
+      Some(events) => events.send(event).is_ok(),
+      None => false,
+    };
+    if !delivered {
+      // The app dropped the connection without closing it.
+      close(ws::CloseCode::Away, ctx);
+    }
+  }
+
+  /// Collects the fragments of a message and emits it after the last one.
+  fn fragment(&mut self, item: Item, ctx: &mut ws::WebsocketContext<Self>) {
+    let (data, last) = match item {
+      Item::FirstText(_) | Item::FirstBinary(_) if self.fragments.is_some() => {
+        return close(ws::CloseCode::Protocol, ctx);
+      }
+      Item::FirstText(data) => {
+        self.fragments = Some((true, vec![]));
+        (data, false)
+      }
+      Item::FirstBinary(data) => {
+        self.fragments = Some((false, vec![]));
+        (data, false)
+      }
+      Item::Continue(data) => (data, false),
+      Item::Last(data) => (data, true),
+    };
+    let buffer = match &mut self.fragments {
+      Some((_, buffer)) => buffer,
+      None => return close(ws::CloseCode::Protocol, ctx),
+    };
+    if buffer.len() + data.len() > MAX_MESSAGE_SIZE {
+      return close(ws::CloseCode::Size, ctx);
+    }
+    buffer.extend_from_slice(&data);
+    if !last {
+      return;
+    }
+    match self.fragments.take() {
+      Some((true, buffer)) => match String::from_utf8(buffer) {
+        Ok(text) => self.emit(SocketEvent::Text(text), ctx),
+        Err(_) => close(ws::CloseCode::Invalid, ctx),
+      },
+      Some((false, buffer)) => {
+        self.emit(SocketEvent::Binary(web::Bytes::from(buffer)), ctx)
+      }
+      None => {}
+    }
+  }
+}
+
+fn close(code: ws::CloseCode, ctx: &mut ws::WebsocketContext<AppSocket>) {
+  ctx.close(Some(code.into()));
+  ctx.stop();
+}
+
+impl Actor for AppSocket {
+  type Context = ws::WebsocketContext<Self>;
+
+  fn started(&mut self, ctx: &mut Self::Context) {
+    let (tx, rx) = mpsc::unbounded_channel();
+    let connection = Connection {
+      id: self.id.clone(),
+      host: self.host.clone(),
+      path: self.path.clone(),
+      ip: self.ip.clone(),
+      token: self.token.clone(),
+      socket: ctx.address(),
+      events: rx,
+    };
+    if CONNECTIONS.send(&self.context, connection).is_err() {
+      ctx.close(Some(ws::CloseReason {
+        code: ws::CloseCode::Again,
+        description: Some("no app is accepting connections".to_string()),
+      }));
+      ctx.stop();
+      return;
+    }
+    self.events = Some(tx);
+
+    ctx.run_interval(HEARTBEAT_INTERVAL, |act, ctx| {
+      if Instant::now().duration_since(act.last_seen) > CLIENT_TIMEOUT {
+        ctx.stop();
+      } else {
+        ctx.ping(b"");
+      }
+    });
+  }
+
+  fn stopped(&mut self, _: &mut Self::Context) {
+    if let Some(events) = self.events.take() {
+      let _ = events.send(SocketEvent::Closed(None));
+    }
+  }
+}
+
+impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for AppSocket {
+  fn handle(
+    &mut self,
+    msg: Result<ws::Message, ws::ProtocolError>,
+    ctx: &mut Self::Context,
+  ) {
+    self.last_seen = Instant::now();
+    match msg {
+      Ok(ws::Message::Ping(msg)) => ctx.pong(&msg),
+      Ok(ws::Message::Pong(_)) => {}
+      Ok(ws::Message::Text(text)) => self.emit(SocketEvent::Text(text), ctx),
+      Ok(ws::Message::Binary(bin)) => self.emit(SocketEvent::Binary(bin), ctx),
+      Ok(ws::Message::Close(reason)) => {
+        if let Some(events) = self.events.take() {
+          let _ = events.send(SocketEvent::Closed(reason.clone()));
+        }
+        ctx.close(reason);
+        ctx.stop();
+      }
+      Ok(ws::Message::Continuation(item)) => self.fragment(item, ctx),
+      Ok(ws::Message::Nop) => {}
+      Err(e) => {
+        warn!("websocket {} failed: {}", self.id, e);
+        ctx.stop();
+      }
+    }
+  }
+}
+
+impl Handler<Outgoing> for AppSocket {
+  type Result = ();
+
+  fn handle(&mut self, msg: Outgoing, ctx: &mut Self::Context) {
+    match msg {
+      Outgoing::Text(text) => ctx.text(text),
+      Outgoing::Binary(bin) => ctx.binary(bin),
+      Outgoing::Close(reason) => {
+        ctx.close(reason);
+        ctx.stop();
+      }
+    }
+  }
+}
+
+/// Whether a page from `origin` may connect to `host`.  Only browsers send
+/// an Origin, and they send "null" for pages without one.
+fn origin_allowed(host: &HostConfig, origin: Option<&str>) -> bool {
+  let origin = match origin {
+    Some(origin) => origin,
+    None => return true,
+  };
+  let authority = match origin.splitn(2, "://").nth(1) {
+    Some(authority) => authority,
+    None => return false,
+  };
+  let domain = authority.rsplitn(2, ':').last().unwrap_or(authority);
+  host
+    .domains()
+    .iter()
+    .any(|d| d.eq_ignore_ascii_case(domain))
+}
+
+#[derive(Deserialize)]
+pub struct SocketQuery {
+  #[serde(default)]
+  app: Option<String>,
+}
+
+pub async fn index(
+  req: HttpRequest,
+  query: web::Query<SocketQuery>,
+  stream: web::Payload,
+) -> Result<HttpResponse, Error> {
+  let config = config::get();
+  let host = match config.host(req.connection_info().host()) {
+    Some(host) => host,
+    None => return Ok(HttpResponse::NotFound().finish()),
+  };
+  let origin = req.headers().get(header::ORIGIN);
+  if !origin_allowed(host, origin.map(|o| o.to_str().unwrap_or(""))) {
+    return Ok(HttpResponse::Forbidden().finish());
+  }
+  let app = match &query.app {
+    Some(app) => host.apps.iter().find(|m| &m.app == app),
+    None => host
+      .apps
+      .iter()
+      .find(|m| m.path.trim_end_matches('/') == ""),
+  };
+  let context = match app {
+    Some(mount) => AppContext::new(&host.name, &mount.app),
+    None => return Ok(HttpResponse::NotFound().finish()),
+  };
+  ws::start(AppSocket::new(&req, context), &req, stream)
+}
+
+#[test]
+fn websocket_test() {
+  use actix_http::ws::{Frame, Message};
+  use actix_web::{test, App};
+  use futures::{SinkExt, StreamExt};
+
+  let host: HostConfig = serde_json::from_value(json!({
+    "name": "ws.test",
+    "aliases": ["www.ws.test"],
+  }))
+  .unwrap();
+  assert!(origin_allowed(&host, None));
+  assert!(origin_allowed(&host, Some("https://ws.test")));
+  assert!(origin_allowed(&host, Some("http://WWW.ws.test:8080")));
+  assert!(!origin_allowed(&host, Some("https://evil.test")));
+  assert!(!origin_allowed(&host, Some("https://ws.test.evil.test")));
+  assert!(!origin_allowed(&host, Some("null")));
+
+  let mut system = actix_rt::System::new("websocket_test");
+  system.block_on(async {
+    let context = AppContext::new("ws.test", "echo");
+    let mut connections = listen(&context);
+    actix_rt::spawn(async move {
+      while let Some(mut connection) = connections.recv().await {
+        while let Some(event) = connection.events.recv().await {
+          let reply = match event {
+            SocketEvent::Text(text) => Outgoing::Text(text),
+            SocketEvent::Binary(bin) => Outgoing::Binary(bin),
+            SocketEvent::Closed(_) => break,
+          };
+          connection.socket.do_send(reply);
+        }
+      }
+    });
+    let mut server = test::start(move || {
+      let context = context.clone();
+      App::new().route(
+        "/ws",
+        web::get().to(move |req: HttpRequest, stream: web::Payload| {
+          let socket = AppSocket::new(&req, context.clone());
+          async move { ws::start(socket, &req, stream) }
+        }),
+      )
+    });
+    let mut client = server.ws_at("/ws").await.unwrap();
+    let bytes = web::Bytes::from_static;
+
+    client.send(Message::Ping(bytes(b"p"))).await.unwrap();
+    let frame = client.next().await.unwrap().unwrap();
+    assert_eq!(frame, Frame::Pong(bytes(b"p")));
+
+    client
+      .send(Message::Text("hello".to_string()))
+      .await
+      .unwrap();
+    let frame = client.next().await.unwrap().unwrap();
+    assert_eq!(frame, Frame::Text(bytes(b"hello")));
+
+    // Fragments reach the app as one message.
+    let parts = vec![
+      Item::FirstText(bytes(b"he")),
+      Item::Continue(bytes(b"ll")),
+      Item::Last(bytes(b"o")),
+      Item::FirstBinary(bytes(b"\x01")),
+      Item::Last(bytes(b"\x02")),
+    ];
+    for item in parts {
+      client.send(Message::Continuation(item)).await.unwrap();
+    }
+    let frame = client.next().await.unwrap().unwrap();
+    assert_eq!(frame, Frame::Text(bytes(b"hello")));
+    let frame = client.next().await.unwrap().unwrap();
+    assert_eq!(frame, Frame::Binary(bytes(b"\x01\x02")));
+
+    let reason = ws::CloseReason::from(ws::CloseCode::Normal);
+    client
+      .send(Message::Close(Some(reason.clone())))
+      .await
+      .unwrap();
+    let frame = client.next().await.unwrap().unwrap();
+    assert_eq!(frame, Frame::Close(Some(reason)));
+  });
+}